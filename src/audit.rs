@@ -0,0 +1,526 @@
+use std::cell::RefCell;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, warn};
+
+use crate::auth;
+use crate::changes::Operation;
+use crate::listener::Addr;
+use crate::read_only::is_read;
+
+/// File of the audit log, in the data directory.
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// Number of rotated files kept, `audit.jsonl.1` being the most recent.
+const ROTATED_FILES: usize = 5;
+
+/// Entry of the audit log. Contacts are only named by their UID, never by
+/// their content.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    /// Authenticated user, unset without authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub ip: IpAddr,
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub book: Option<String>,
+    /// UID of the changed contact, unset when the request changed none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<Operation>,
+    /// Status code of the response.
+    pub status: u16,
+}
+
+tokio::task_local! {
+    /// Contacts changed by the request being handled, as book, UID and
+    /// operation.
+    static CHANGES: RefCell<Vec<(String, String, Operation)>>;
+}
+
+/// Notes a change of a contact for the audit entry of the request being
+/// handled. Changes made outside of a request are not audited.
+pub fn note(book: &str, id: &str, op: Operation) {
+    let _ = CHANGES.try_with(|changes| {
+        changes
+            .borrow_mut()
+            .push((book.to_string(), id.to_string(), op))
+    });
+}
+
+/// Append-only audit log, kept in `audit.jsonl` in the data directory and
+/// rotated by size. Entries are queued and written by [`write`], away from the
+/// requests.
+pub struct AuditLog {
+    path: PathBuf,
+    /// User allowed to read the log, when authentication is on.
+    admin: Option<String>,
+    sender: mpsc::UnboundedSender<Entry>,
+}
+
+impl AuditLog {
+    /// Opens the log of `dir`, returning the queue for [`write`] to drain.
+    pub fn new(dir: &Path, admin: Option<String>) -> (Self, mpsc::UnboundedReceiver<Entry>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let log = AuditLog {
+            path: dir.join(AUDIT_FILE),
+            admin,
+            sender,
+        };
+
+        (log, receiver)
+    }
+
+    /// Whether the client can read the log: anyone without authentication,
+    /// else only the administrator.
+    pub fn readable(&self) -> bool {
+        match auth::user() {
+            Some(user) => self.admin.as_ref() == Some(&user),
+            None => true,
+        }
+    }
+
+    /// Reads the written entries, oldest first, from `since` on and
+    /// optionally limited to a user or a contact.
+    pub async fn read(
+        &self,
+        since: Option<DateTime<Utc>>,
+        user: Option<&str>,
+        id: Option<&str>,
+    ) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let files = (1..=ROTATED_FILES)
+            .rev()
+            .map(|n| rotated(&self.path, n))
+            .chain([self.path.clone()]);
+        for file in files {
+            let content = match fs::read_to_string(&file).await {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            for line in content.lines().filter(|line| !line.is_empty()) {
+                let entry: Entry = match serde_json::from_str(line) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn!("skipped invalid line of {}: {}", file.display(), e);
+                        continue;
+                    }
+                };
+
+                if since.is_some_and(|since| entry.time < since)
+                    || user.is_some_and(|user| entry.user.as_deref() != Some(user))
+                    || id.is_some_and(|id| entry.id.as_deref() != Some(id))
+                {
+                    continue;
+                }
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Middleware queueing an audit entry for every request that can change
+/// something, one per contact it changed.
+pub async fn record(
+    State(log): State<Arc<AuditLog>>,
+    ConnectInfo(addr): ConnectInfo<Addr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_read(request.method(), request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let (response, changes) = CHANGES
+        .scope(RefCell::new(Vec::new()), async {
+            let response = next.run(request).await;
+            (response, CHANGES.with(RefCell::take))
+        })
+        .await;
+
+    let entry = Entry {
+        time: Utc::now(),
+        user: auth::user(),
+        ip: addr.ip(),
+        method,
+        path,
+        book: None,
+        id: None,
+        operation: None,
+        status: response.status().as_u16(),
+    };
+    if changes.is_empty() {
+        let _ = log.sender.send(entry);
+    } else {
+        for (book, id, op) in changes {
+            let _ = log.sender.send(Entry {
+                book: Some(book),
+                id: Some(id),
+                operation: Some(op),
+                ..entry.clone()
+            });
+        }
+    }
+
+    response
+}
+
+/// Appends the queued entries to the log until `stopping` is set, then writes
+/// the ones still queued. The log is rotated once above `max_size` bytes.
+pub async fn write(
+    dir: PathBuf,
+    mut receiver: mpsc::UnboundedReceiver<Entry>,
+    max_size: u64,
+    mut stopping: watch::Receiver<bool>,
+) {
+    let path = dir.join(AUDIT_FILE);
+    loop {
+        let entry = tokio::select! {
+            biased;
+            entry = receiver.recv() => entry,
+            _ = stopping.wait_for(|&stop| stop) => None,
+        };
+        let Some(entry) = entry else {
+            break;
+        };
+
+        let mut entries = vec![entry];
+        while let Ok(entry) = receiver.try_recv() {
+            entries.push(entry);
+        }
+        if let Err(e) = append(&path, &entries, max_size).await {
+            error!("failed to write the audit log: {}", e);
+        }
+    }
+
+    let mut entries = Vec::new();
+    while let Ok(entry) = receiver.try_recv() {
+        entries.push(entry);
+    }
+    if !entries.is_empty() {
+        if let Err(e) = append(&path, &entries, max_size).await {
+            error!("failed to write the audit log: {}", e);
+        }
+    }
+}
+
+async fn append(path: &Path, entries: &[Entry], max_size: u64) -> io::Result<()> {
+    let mut size = match fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+
+    let mut lines = Vec::new();
+    for entry in entries {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+
+        if size + (lines.len() + line.len()) as u64 > max_size && size + lines.len() as u64 > 0 {
+            write_lines(path, &lines).await?;
+            rotate(path).await?;
+            size = 0;
+            lines.clear();
+        }
+        lines.extend(line);
+    }
+
+    write_lines(path, &lines).await
+}
+
+async fn write_lines(path: &Path, lines: &[u8]) -> io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(lines).await?;
+    file.flush().await
+}
+
+/// Shifts the rotated files by one, dropping the oldest, and moves the log in
+/// place of the most recent.
+async fn rotate(path: &Path) -> io::Result<()> {
+    for n in (1..ROTATED_FILES).rev() {
+        match fs::rename(rotated(path, n), rotated(path, n + 1)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    fs::rename(path, rotated(path, 1)).await
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::{header, Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::auth::{Credentials, PasswordHash};
+    use crate::testing::{basic_auth, contact, request, TestServer};
+
+    fn entry(user: &str, id: Option<&str>, time: DateTime<Utc>) -> Entry {
+        Entry {
+            time,
+            user: Some(user.to_string()),
+            ip: "192.0.2.1".parse().unwrap(),
+            method: "PUT".to_string(),
+            path: format!("/contacts/{}", id.unwrap_or("")),
+            book: id.map(|_| "default".to_string()),
+            id: id.map(str::to_string),
+            operation: id.map(|_| Operation::Modify),
+            status: 200,
+        }
+    }
+
+    /// Writes `entries` to the log of `dir` as the server does, returning
+    /// once they are written.
+    async fn write_entries(dir: &Path, entries: Vec<Entry>, max_size: u64) -> AuditLog {
+        let (log, receiver) = AuditLog::new(dir, None);
+        for entry in entries {
+            log.sender.send(entry).unwrap();
+        }
+        let (stop, stopping) = watch::channel(true);
+        write(dir.to_path_buf(), receiver, max_size, stopping).await;
+        drop(stop);
+        log
+    }
+
+    /// Entries of `GET /admin/audit`, waiting for the ones queued by the
+    /// requests to be written.
+    async fn audit_entries(server: &TestServer, uri: &str, expected: usize) -> Vec<Value> {
+        for _ in 0..100 {
+            let response = server.get(uri).await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+            let entries = response.json().as_array().unwrap().clone();
+            if entries.len() >= expected {
+                return entries;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("fewer than {} entries in {}", expected, uri);
+    }
+
+    #[test]
+    fn entries_leave_out_what_is_unset() {
+        let line = serde_json::to_value(entry("alice", None, Utc::now())).unwrap();
+        let object = line.as_object().unwrap();
+        for field in ["book", "id", "operation"] {
+            assert!(!object.contains_key(field), "{}", field);
+        }
+        assert_eq!(line["user"], "alice");
+        assert_eq!(line["status"], 200);
+
+        let line = serde_json::to_string(&entry("alice", Some("bob"), Utc::now())).unwrap();
+        let parsed: Entry = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.id.as_deref(), Some("bob"));
+        assert_eq!(parsed.operation, Some(Operation::Modify));
+        assert!(!line.contains("Bob"));
+    }
+
+    #[tokio::test]
+    async fn reads_entries_across_rotations_with_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let entries: Vec<Entry> = (0..24)
+            .map(|n| {
+                let user = if n % 2 == 0 { "ann" } else { "bob" };
+                let id = format!("contact-{}", n % 3);
+                entry(user, Some(&id), start + chrono::Duration::minutes(n))
+            })
+            .collect();
+        let size = serde_json::to_vec(&entries[0]).unwrap().len() as u64 + 1;
+        let log = write_entries(dir.path(), entries.clone(), 3 * size).await;
+
+        assert!(dir.path().join("audit.jsonl.1").exists());
+        assert!(!dir.path().join("audit.jsonl.6").exists());
+
+        // Three entries a file, the oldest files beyond the rotated ones
+        // being dropped.
+        let read = log.read(None, None, None).await.unwrap();
+        assert_eq!(read.len(), 3 * (ROTATED_FILES + 1));
+        for (read, written) in read.iter().zip(&entries[6..]) {
+            assert_eq!(read.time, written.time);
+        }
+
+        let since = start + chrono::Duration::minutes(21);
+        let read = log.read(Some(since), None, None).await.unwrap();
+        assert_eq!(read.len(), 3);
+        let read = log
+            .read(None, Some("bob"), Some("contact-1"))
+            .await
+            .unwrap();
+        let times: Vec<DateTime<Utc>> = read.iter().map(|entry| entry.time).collect();
+        assert_eq!(
+            times,
+            [7, 13, 19].map(|n| start + chrono::Duration::minutes(n))
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_invalid_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = write_entries(dir.path(), vec![entry("alice", None, Utc::now())], u64::MAX).await;
+        let mut content = fs::read_to_string(dir.path().join(AUDIT_FILE))
+            .await
+            .unwrap();
+        content.push_str("not json\n");
+        fs::write(dir.path().join(AUDIT_FILE), content)
+            .await
+            .unwrap();
+
+        assert_eq!(log.read(None, None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn records_one_entry_per_changed_contact() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        let updated = server
+            .json(
+                Method::PUT,
+                "/contacts/alice",
+                &contact("alice", "Alice Liddell"),
+            )
+            .await;
+        assert_eq!(updated.status, StatusCode::OK, "{}", updated.text());
+        assert_eq!(server.get("/contacts/alice").await.status, StatusCode::OK);
+        assert_eq!(
+            server.delete("/contacts/nobody").await.status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            server.delete("/contacts/alice").await.status,
+            StatusCode::OK
+        );
+
+        let entries = audit_entries(&server, "/admin/audit", 4).await;
+        let summary: Vec<(&str, &str, Option<&str>, u64)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry["method"].as_str().unwrap(),
+                    entry["path"].as_str().unwrap(),
+                    entry["operation"].as_str(),
+                    entry["status"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("POST", "/contacts", Some("create"), 201),
+                ("PUT", "/contacts/alice", Some("modify"), 200),
+                ("DELETE", "/contacts/nobody", None, 404),
+                ("DELETE", "/contacts/alice", Some("delete"), 200),
+            ]
+        );
+        assert_eq!(entries[0]["book"], "default");
+        assert_eq!(entries[0]["id"], "alice");
+        assert_eq!(entries[0]["ip"], "127.0.0.1");
+        assert!(entries[0].get("user").is_none());
+        assert!(!entries.iter().any(|entry| entry["method"] == "GET"));
+        assert!(!serde_json::to_string(&entries).unwrap().contains("Liddell"));
+
+        let by_id = audit_entries(&server, "/admin/audit?id=nobody", 0).await;
+        assert!(by_id.is_empty());
+        let since = entries[1]["time"].as_str().unwrap().replace('+', "%2B");
+        let later = audit_entries(&server, &format!("/admin/audit?since={}", since), 3).await;
+        assert_eq!(later, entries[1..]);
+        assert_eq!(
+            server.get("/admin/audit?since=yesterday").await.status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn records_the_authenticated_user() {
+        let server = TestServer::with_config(|config| {
+            config.auth = Some(Credentials {
+                user: "alice".to_string(),
+                password: PasswordHash::new("alice password").unwrap(),
+            });
+        })
+        .await;
+        let created = server
+            .send(
+                request(Method::POST, "/contacts")
+                    .header(header::AUTHORIZATION, basic_auth("alice", "alice password"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(contact("bob", "Bob").to_string().into())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+
+        let mut entries = Vec::new();
+        for _ in 0..100 {
+            let response = server
+                .send(
+                    request(Method::GET, "/admin/audit?user=alice")
+                        .header(header::AUTHORIZATION, basic_auth("alice", "alice password"))
+                        .body(Default::default())
+                        .unwrap(),
+                )
+                .await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+            entries = response.json().as_array().unwrap().clone();
+            if !entries.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0],
+            json!({
+                "time": entries[0]["time"],
+                "user": "alice",
+                "ip": "127.0.0.1",
+                "method": "POST",
+                "path": "/contacts",
+                "book": "default",
+                "id": "bob",
+                "operation": "create",
+                "status": 201,
+            })
+        );
+    }
+}
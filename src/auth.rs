@@ -0,0 +1,519 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, PasswordVerifier, Version};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD_PAD_INDIFFERENT as BASE64, Engine};
+use blake2::{
+    digest::{consts::U32, KeyInit, Mac},
+    Blake2bMac,
+};
+use tower::ServiceExt;
+use tracing::{error, warn};
+
+use crate::tokens::{Token, Tokens};
+
+/// Route left open, so that monitoring works without credentials.
+const PUBLIC_PATH: &str = "/health";
+
+/// Challenges of the `401 Unauthorized` responses, for a password or a token.
+const CHALLENGES: [&str; 2] = ["Basic realm=\"dav\"", "Bearer realm=\"dav\""];
+
+/// Parameters of the hashes made by `dav hash-password`, the first
+/// recommendation of OWASP for Argon2id: 19 MiB, 2 iterations and 1 lane.
+const DEFAULT_MEMORY: u32 = 19 * 1024;
+const DEFAULT_ITERATIONS: u32 = 2;
+const DEFAULT_PARALLELISM: u32 = 1;
+
+/// Largest memory cost accepted, in KiB, so that a hash cannot exhaust the
+/// memory of the server.
+const MAX_MEMORY: u32 = 4 * 1024 * 1024;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Username and password hash the requests must authenticate with.
+#[derive(Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub password: PasswordHash,
+}
+
+impl fmt::Debug for Credentials {
+    /// Leaves the hash out of `dav config check`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Password hash in the PHC string format of the reference implementation,
+/// such as `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>` with the salt and
+/// hash in unpadded base64.
+#[derive(Clone)]
+pub struct PasswordHash(argon2::PasswordHash);
+
+impl FromStr for PasswordHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hash = argon2::PasswordHash::new(s).map_err(|e| {
+            format!(
+                "expected an Argon2 hash such as $argon2id$v=19$m=...,t=...,p=...$<salt>$<hash>, made with `dav hash-password`: {}",
+                e
+            )
+        })?;
+
+        if Algorithm::new(hash.algorithm).is_err() {
+            return Err(format!(
+                "unsupported algorithm '{}', expected argon2id",
+                hash.algorithm
+            ));
+        }
+        if hash.version != Some(Version::V0x13.into()) {
+            let version = hash
+                .version
+                .map_or_else(|| "none".to_string(), |version| format!("v={}", version));
+            return Err(format!(
+                "unsupported version '{}', expected v={}",
+                version,
+                u32::from(Version::V0x13)
+            ));
+        }
+
+        if ["m", "t", "p"]
+            .iter()
+            .any(|name| hash.params.get(*name).is_none())
+        {
+            return Err("missing one of the m, t and p parameters".to_string());
+        }
+        let params = Params::try_from(&hash)
+            .map_err(|e| format!("invalid parameters '{}': {}", hash.params, e))?;
+        if params.m_cost() > MAX_MEMORY {
+            return Err("memory must be at most 4 GiB".to_string());
+        }
+        if hash.salt.is_none() || hash.hash.is_none() {
+            return Err("missing the salt or the hash".to_string());
+        }
+
+        Ok(PasswordHash(hash))
+    }
+}
+
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl PasswordHash {
+    /// Hashes a password with a random salt, for `dav hash-password`.
+    pub fn new(password: &str) -> Result<Self, String> {
+        let mut salt = [0; SALT_LEN];
+        getrandom::fill(&mut salt).map_err(|e| format!("failed to generate a salt: {}", e))?;
+        let params = Params::new(
+            DEFAULT_MEMORY,
+            DEFAULT_ITERATIONS,
+            DEFAULT_PARALLELISM,
+            Some(HASH_LEN),
+        )
+        .map_err(|e| format!("invalid parameters: {}", e))?;
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_with_salt(password.as_bytes(), &salt)
+            .map(PasswordHash)
+            .map_err(|e| format!("failed to hash the password: {}", e))
+    }
+
+    /// Whether `password` is the hashed one, in a time that does not depend
+    /// on how much of the hash matches.
+    fn verify(&self, password: &[u8]) -> bool {
+        Argon2::default().verify_password(password, &self.0).is_ok()
+    }
+}
+
+tokio::task_local! {
+    /// User of the request being served, set once it is authenticated.
+    static USER: String;
+}
+
+/// Name of the authenticated user of the request being served, `None` when
+/// authentication is disabled.
+pub fn user() -> Option<String> {
+    USER.try_with(Clone::clone).ok()
+}
+
+/// A user with its own data directory, served by its own routes.
+pub struct Account {
+    pub credentials: Credentials,
+    pub tokens: Arc<Tokens>,
+    pub routes: Router,
+    /// Digest of the last accepted password, see [`Authenticator`].
+    accepted: Mutex<Option<Vec<u8>>>,
+}
+
+impl Account {
+    pub fn new(credentials: Credentials, tokens: Arc<Tokens>, routes: Router) -> Self {
+        Account {
+            credentials,
+            tokens,
+            routes,
+            accepted: Mutex::new(None),
+        }
+    }
+}
+
+/// Checks the credentials of the requests and hands them to the routes of
+/// their user. A hash takes tens of milliseconds by design, so the last
+/// accepted password of every user is remembered, as a keyed digest that is
+/// useless outside of the process, and the clients sending it with every
+/// request are not slowed down.
+pub struct Authenticator {
+    accounts: Vec<Account>,
+    key: [u8; 32],
+}
+
+impl Authenticator {
+    pub fn new(accounts: Vec<Account>) -> Result<Self, String> {
+        if accounts.is_empty() {
+            return Err("no user to authenticate".to_string());
+        }
+
+        let mut key = [0; 32];
+        getrandom::fill(&mut key).map_err(|e| format!("failed to generate a key: {}", e))?;
+
+        Ok(Authenticator { accounts, key })
+    }
+
+    /// Finds the account of a user and password. A password is hashed even
+    /// for an unknown user, so the time does not tell whether the user exists.
+    fn check(&self, user: &str, password: &str) -> Option<&Account> {
+        let mut mac = <Blake2bMac<U32> as KeyInit>::new_from_slice(&self.key)
+            .expect("a 32 bytes key is valid");
+        mac.update(user.as_bytes());
+        mac.update(b"\0");
+        mac.update(password.as_bytes());
+        let digest = mac.finalize().into_bytes().to_vec();
+
+        let Some(account) = self
+            .accounts
+            .iter()
+            .find(|account| account.credentials.user == user)
+        else {
+            self.accounts[0]
+                .credentials
+                .password
+                .verify(password.as_bytes());
+            return None;
+        };
+
+        let accepted = account
+            .accepted
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if accepted.is_some_and(|accepted| constant_time_eq(&accepted, &digest)) {
+            return Some(account);
+        }
+
+        if account.credentials.password.verify(password.as_bytes()) {
+            *account
+                .accepted
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(digest);
+            return Some(account);
+        }
+
+        None
+    }
+
+    /// Finds the account and unexpired token of a `Bearer` header.
+    async fn find_token(&self, secret: &str) -> Option<(&Account, Token)> {
+        for account in &self.accounts {
+            if let Some(token) = account.tokens.find(secret).await {
+                return Some((account, token));
+            }
+        }
+        None
+    }
+}
+
+/// Credentials of a request.
+enum Authorization {
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+/// Serves the requests authenticated with HTTP Basic (RFC 7617) or an API
+/// token (RFC 6750) with the routes of their user, answering
+/// `401 Unauthorized` with a challenge to every request without valid
+/// credentials. The health check is served without credentials, by the routes
+/// of the first user. Requests a token does not allow are answered with
+/// `403 Forbidden`.
+pub async fn dispatch(
+    State(authenticator): State<Arc<Authenticator>>,
+    request: Request,
+) -> Response {
+    if request.uri().path() == PUBLIC_PATH {
+        return serve(&authenticator.accounts[0], request).await;
+    }
+
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_authorization);
+
+    let (user, password) = match authorization {
+        None => return unauthorized(),
+        Some(Authorization::Bearer(secret)) => {
+            let Some((account, token)) = authenticator.find_token(&secret).await else {
+                warn!(
+                    "rejected unknown or expired token for {} {}",
+                    request.method(),
+                    request.uri().path()
+                );
+                return unauthorized();
+            };
+
+            return match token.allows(request.method(), request.uri().path(), request.headers()) {
+                Ok(()) => serve(account, request).await,
+                Err(reason) => {
+                    warn!(
+                        "token {} denied {} {}: {}",
+                        token.id,
+                        request.method(),
+                        request.uri().path(),
+                        reason
+                    );
+                    (StatusCode::FORBIDDEN, reason).into_response()
+                }
+            };
+        }
+        Some(Authorization::Basic { user, password }) => (user, password),
+    };
+
+    // Hashing blocks for a while, away from the tasks serving requests.
+    let checked = {
+        let authenticator = authenticator.clone();
+        let user = user.clone();
+        tokio::task::spawn_blocking(move || {
+            authenticator
+                .check(&user, &password)
+                .map(|account| account.credentials.user.clone())
+        })
+        .await
+    };
+    match checked {
+        Ok(Some(user)) => {
+            let account = authenticator
+                .accounts
+                .iter()
+                .find(|account| account.credentials.user == user)
+                .expect("checked account exists");
+            serve(account, request).await
+        }
+        Ok(None) => {
+            warn!(
+                "rejected credentials of user '{}' for {} {}",
+                user,
+                request.method(),
+                request.uri().path()
+            );
+            unauthorized()
+        }
+        Err(e) => {
+            error!("failed to check credentials: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to check credentials".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Serves a request with the routes of a user.
+async fn serve(account: &Account, request: Request) -> Response {
+    let response = USER
+        .scope(
+            account.credentials.user.clone(),
+            account.routes.clone().oneshot(request),
+        )
+        .await;
+    match response {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
+
+fn unauthorized() -> Response {
+    let mut response = (
+        StatusCode::UNAUTHORIZED,
+        "authentication required".to_string(),
+    )
+        .into_response();
+    for challenge in CHALLENGES {
+        response.headers_mut().append(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(challenge),
+        );
+    }
+    response
+}
+
+/// Reads the user and password of a `Basic` `Authorization` header, or the
+/// token of a `Bearer` one.
+fn parse_authorization(value: &str) -> Option<Authorization> {
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(Authorization::Bearer(credentials.to_string()));
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let credentials = String::from_utf8(BASE64.decode(credentials).ok()?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some(Authorization::Basic {
+        user: user.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// Compares two byte strings in a time that only depends on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Made by the earlier implementation of `dav hash-password`, for
+    /// `correct horse`.
+    const EARLIER_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHRzYWx0c2FsdA$AdweBLwcflnNX2HVW8i1Mtu7frrn4Ki7h/rYSGuU7Is";
+
+    #[test]
+    fn hashes_round_trip_through_the_phc_string() {
+        let hash = PasswordHash::new("correct horse").unwrap();
+        let phc = hash.to_string();
+        assert!(phc.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+
+        let parsed: PasswordHash = phc.parse().unwrap();
+        assert_eq!(parsed.to_string(), phc);
+        assert!(parsed.verify(b"correct horse"));
+        assert!(!parsed.verify(b"battery staple"));
+    }
+
+    #[test]
+    fn verifies_the_hashes_of_earlier_versions() {
+        let hash: PasswordHash = EARLIER_HASH.parse().unwrap();
+        assert_eq!(hash.to_string(), EARLIER_HASH);
+        assert!(hash.verify(b"correct horse"));
+        assert!(!hash.verify(b"correct horsE"));
+    }
+
+    #[test]
+    fn rejects_invalid_hashes() {
+        for (hash, error) in [
+            ("hunter2", "expected an Argon2 hash"),
+            (
+                "$bcrypt$v=19$m=19456,t=2,p=1$c2FsdHNhbHRzYWx0c2FsdA$AdweBLwcflnNX2HVW8i1Mtu7frrn4Ki7h/rYSGuU7Is",
+                "unsupported algorithm 'bcrypt'",
+            ),
+            (
+                "$argon2id$v=16$m=19456,t=2,p=1$c2FsdHNhbHRzYWx0c2FsdA$AdweBLwcflnNX2HVW8i1Mtu7frrn4Ki7h/rYSGuU7Is",
+                "unsupported version 'v=16'",
+            ),
+            (
+                "$argon2id$v=19$m=19456,t=2$c2FsdHNhbHRzYWx0c2FsdA$AdweBLwcflnNX2HVW8i1Mtu7frrn4Ki7h/rYSGuU7Is",
+                "missing one of the m, t and p parameters",
+            ),
+            (
+                "$argon2id$v=19$m=8388608,t=2,p=1$c2FsdHNhbHRzYWx0c2FsdA$AdweBLwcflnNX2HVW8i1Mtu7frrn4Ki7h/rYSGuU7Is",
+                "memory must be at most 4 GiB",
+            ),
+            (
+                "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHRzYWx0c2FsdA",
+                "missing the salt or the hash",
+            ),
+        ] {
+            let e = hash.parse::<PasswordHash>().err().unwrap();
+            assert!(e.contains(error), "{}: {}", hash, e);
+        }
+    }
+
+    #[test]
+    fn reads_basic_and_bearer_authorization() {
+        // `alice:open sesame`, with and without padding.
+        for value in [
+            "Basic YWxpY2U6b3BlbiBzZXNhbWU=",
+            "basic YWxpY2U6b3BlbiBzZXNhbWU",
+        ] {
+            let Some(Authorization::Basic { user, password }) = parse_authorization(value) else {
+                panic!("{} is not read as Basic", value);
+            };
+            assert_eq!(user, "alice");
+            assert_eq!(password, "open sesame");
+        }
+
+        assert!(matches!(
+            parse_authorization("Bearer dav_secret"),
+            Some(Authorization::Bearer(secret)) if secret == "dav_secret"
+        ));
+        assert!(parse_authorization("Basic not*base64").is_none());
+        assert!(parse_authorization("Digest username=\"alice\"").is_none());
+    }
+
+    #[tokio::test]
+    async fn remembers_a_keyed_digest_of_the_accepted_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = Credentials {
+            user: "alice".to_string(),
+            password: EARLIER_HASH.parse().unwrap(),
+        };
+        let tokens = Arc::new(Tokens::open(dir.path()).await.unwrap());
+        let authenticator =
+            Authenticator::new(vec![Account::new(credentials, tokens, Router::new())]).unwrap();
+        let account = &authenticator.accounts[0];
+
+        assert!(authenticator.check("alice", "battery staple").is_none());
+        assert!(account.accepted.lock().unwrap().is_none());
+
+        assert!(authenticator.check("alice", "correct horse").is_some());
+        let digest = account.accepted.lock().unwrap().clone().unwrap();
+        assert_eq!(digest.len(), 32);
+        assert!(!digest
+            .windows(b"correct horse".len())
+            .any(|window| window == b"correct horse"));
+
+        // Accepted again from the digest, and still refused otherwise.
+        assert!(authenticator.check("alice", "correct horse").is_some());
+        assert!(authenticator.check("alice", "correct horsE").is_none());
+        assert!(authenticator.check("bob", "correct horse").is_none());
+
+        // Another process, with another key, has another digest.
+        let tokens = Arc::new(Tokens::open(dir.path()).await.unwrap());
+        let other = Authenticator::new(vec![Account::new(
+            authenticator.accounts[0].credentials.clone(),
+            tokens,
+            Router::new(),
+        )])
+        .unwrap();
+        assert!(other.check("alice", "correct horse").is_some());
+        assert_ne!(
+            other.accounts[0].accepted.lock().unwrap().clone().unwrap(),
+            digest
+        );
+    }
+}
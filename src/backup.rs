@@ -0,0 +1,179 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use tokio::fs;
+
+use crate::books::Books;
+use crate::config::USERS_DIR;
+use crate::contact::Contact;
+use crate::encryption::{self, Cipher};
+
+/// A backup, a directory holding one `<book>.vcf` file per address book.
+pub struct Backup {
+    pub name: String,
+    pub created: SystemTime,
+    /// Total size of the files, in bytes.
+    pub size: u64,
+}
+
+/// Outcome of the last backup, reported by the health check.
+#[derive(Clone, Default)]
+pub struct Status {
+    pub last_success: Option<SystemTime>,
+    /// Why the last backup failed, cleared by the next successful one.
+    pub error: Option<String>,
+}
+
+/// Backups of the address books, kept in a directory and named after the UTC
+/// time they were taken, the oldest beyond the retention being removed.
+pub struct Backups {
+    dir: PathBuf,
+    retention: usize,
+    /// Key the backups are encrypted with, unset for plain files.
+    cipher: Option<Cipher>,
+    status: Mutex<Status>,
+    /// Held while a backup is taken so scheduled and requested backups do not
+    /// overlap.
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Backups {
+    pub fn new(dir: PathBuf, retention: usize, cipher: Option<Cipher>) -> Self {
+        Backups {
+            dir,
+            retention,
+            cipher,
+            status: Mutex::new(Status::default()),
+            running: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Takes a backup of every address book and records its outcome.
+    pub async fn run(&self, books: &Books) -> io::Result<Backup> {
+        let _running = self.running.lock().await;
+        let result = self.take(books).await;
+
+        let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+        match &result {
+            Ok(backup) => {
+                status.last_success = Some(backup.created);
+                status.error = None;
+            }
+            Err(e) => status.error = Some(e.to_string()),
+        }
+
+        result
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Lists the backups, oldest first.
+    pub async fn list(&self) -> io::Result<Vec<Backup>> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut backups = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            // Backups being written are hidden until they are complete, and
+            // the backups of the other users are theirs.
+            if name.starts_with('.') || name == USERS_DIR || !entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut size = 0;
+            let mut files = fs::read_dir(entry.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                size += file.metadata().await?.len();
+            }
+
+            backups.push(Backup {
+                created: entry.metadata().await?.modified()?,
+                name,
+                size,
+            });
+        }
+        backups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(backups)
+    }
+
+    /// Writes the contacts of every address book in a hidden directory, then
+    /// renames it so a backup is never seen half-written. The contact files
+    /// are replaced atomically, so each card is read whole.
+    async fn take(&self, books: &Books) -> io::Result<Backup> {
+        let created = SystemTime::now();
+        let stamp = DateTime::<Utc>::from(created)
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string();
+
+        let mut name = stamp.clone();
+        let mut suffix = 1;
+        while fs::try_exists(self.dir.join(&name)).await? {
+            suffix += 1;
+            name = format!("{}-{}", stamp, suffix);
+        }
+
+        let tmp = self.dir.join(format!(".{}", name));
+        let size = match write(&tmp, books, self.cipher.as_ref()).await {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&tmp).await;
+                return Err(e);
+            }
+        };
+
+        fs::rename(&tmp, self.dir.join(&name)).await?;
+        self.prune().await?;
+
+        Ok(Backup {
+            name,
+            created,
+            size,
+        })
+    }
+
+    /// Removes the oldest backups beyond the retention.
+    async fn prune(&self) -> io::Result<()> {
+        let backups = self.list().await?;
+        for backup in backups
+            .iter()
+            .take(backups.len().saturating_sub(self.retention))
+        {
+            fs::remove_dir_all(self.dir.join(&backup.name)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the contacts of every address book to `dir`, returning their size.
+async fn write(dir: &Path, books: &Books, cipher: Option<&Cipher>) -> io::Result<u64> {
+    fs::create_dir_all(dir).await?;
+
+    let mut size = 0;
+    for book in books.list().await {
+        let mut contacts = book.store.list().await.map_err(io::Error::other)?;
+        contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let vcards: String = contacts.iter().map(Contact::to_string).collect();
+        let content = encryption::encode(&vcards, cipher)?;
+        size += content.len() as u64;
+        fs::write(dir.join(format!("{}.vcf", book.name)), content).await?;
+    }
+
+    Ok(size)
+}
@@ -0,0 +1,48 @@
+use chrono::NaiveDate;
+
+use crate::contact::Contact;
+use crate::event::{Calendar, Event, EventTime};
+
+/// Year of the first occurrence of birthdays without a year, a leap year so
+/// that February 29 is valid.
+const UNKNOWN_YEAR: i32 = 2000;
+
+/// Returns an iCalendar feed with a yearly all-day event for the birthday of
+/// every contact having one.
+pub fn feed(contacts: &[Contact]) -> String {
+    let mut events: Vec<Event> = contacts.iter().filter_map(birthday_event).collect();
+    events.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Calendar(&events).to_string()
+}
+
+/// Returns the event of a contact's birthday. Its UID is derived from the
+/// contact's so that subscribed calendars recognize it on every refresh.
+fn birthday_event(contact: &Contact) -> Option<Event> {
+    let birthday = contact.birth_date()?;
+    let date = NaiveDate::from_ymd_opt(
+        birthday.year.unwrap_or(UNKNOWN_YEAR),
+        birthday.month,
+        birthday.day,
+    )?;
+
+    let summary = if contact.name.is_empty() {
+        &contact.id
+    } else {
+        &contact.name
+    };
+
+    Some(Event {
+        id: format!("birthday-{}", contact.id),
+        summary: summary.clone(),
+        start: EventTime::Date(date),
+        end: None,
+        duration: None,
+        location: String::new(),
+        description: String::new(),
+        rrule: Some("FREQ=YEARLY".to_string()),
+        exdates: Vec::new(),
+        // Birthdays do not make anyone busy.
+        extras: vec!["TRANSP:TRANSPARENT".to_string()],
+    })
+}
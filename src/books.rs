@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequestParts, RawPathParams},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    sync::{Mutex, RwLock},
+};
+use tracing::{info, warn};
+
+use crate::changes::ChangeLog;
+use crate::config::{Backend, Config};
+use crate::contact::Contact;
+use crate::dav::Locks;
+use crate::history::History;
+use crate::proxy;
+use crate::store::{ContactStore, FsStore, MemStore, StoreError};
+use crate::write_lock::{WriteGuard, WriteLocks};
+use crate::AppState;
+
+/// Name of the address book served at `/contacts`, which always exists.
+pub const DEFAULT_BOOK: &str = "default";
+
+/// Properties of an address book set by clients, stored in its
+/// `metadata.json` file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub displayname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Storage quota in bytes, overriding `DAV_QUOTA`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+    /// Properties set by clients that the server has no use for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<DeadProperty>,
+}
+
+/// A WebDAV property outside of the namespaces known to the server, stored as
+/// given by the client.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeadProperty {
+    pub ns: String,
+    pub name: String,
+    pub value: String,
+}
+
+/// An address book: its contacts, the log of their changes and their previous
+/// versions.
+pub struct Book {
+    pub name: String,
+    pub store: Arc<dyn ContactStore>,
+    pub changes: Arc<ChangeLog>,
+    pub history: History,
+    pub locks: Locks,
+    /// Locks serializing the writes to a contact, shared by every book so
+    /// transfers between books can lock both contacts.
+    writes: Arc<WriteLocks>,
+    /// Quota of the books without one in their metadata.
+    default_quota: Option<u64>,
+    max_contacts: Option<usize>,
+    /// Number of contacts along with the sequence number of the change log it
+    /// was counted at, counted again once the log moves on.
+    count: Mutex<Option<(u64, usize)>>,
+    /// Whether deleted contacts go to the trash.
+    soft_delete: bool,
+    /// Directory holding the contacts, the change log and the database.
+    dir: PathBuf,
+    metadata: RwLock<Metadata>,
+}
+
+impl Book {
+    async fn open(
+        name: &str,
+        dir: PathBuf,
+        config: &Config,
+        writes: Arc<WriteLocks>,
+    ) -> Result<Self, StoreError> {
+        let contacts_dir = dir.join(&config.collection_name);
+        fs::create_dir_all(&contacts_dir).await?;
+        info!("Data directory created at: {}", contacts_dir.display());
+
+        let change_log = dir.join("changes.jsonl");
+        let (store, changes): (Arc<dyn ContactStore>, _) = match config.backend {
+            Backend::Fs => {
+                let store = FsStore::new(contacts_dir, config.fsync_dir, config.encryption.clone());
+                store.check_encryption().await?;
+                (
+                    Arc::new(store),
+                    ChangeLog::open(change_log, config.change_horizon).await?,
+                )
+            }
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => {
+                let db_path = dir.join("contacts.sqlite3");
+                let store = crate::store::SqliteStore::open(db_path.clone()).await?;
+                info!("Using SQLite database at: {}", db_path.display());
+
+                let changes =
+                    ChangeLog::open_sqlite(store.database(), change_log, config.change_horizon)
+                        .await?;
+                (Arc::new(store), changes)
+            }
+            Backend::Memory => (
+                Arc::new(MemStore::new()),
+                ChangeLog::open(change_log, config.change_horizon).await?,
+            ),
+        };
+
+        let moved = store.migrate_layout().await?;
+        if moved > 0 {
+            info!(
+                "Moved {} contact files of {} to the sharded layout",
+                moved, name
+            );
+        }
+
+        let metadata = match fs::read_to_string(dir.join("metadata.json")).await {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| StoreError::Parse(format!("invalid metadata.json: {}", e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Metadata::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Book {
+            name: name.to_string(),
+            store,
+            changes: Arc::new(changes),
+            history: History::new(
+                dir.join("history"),
+                config.history_limit,
+                config.encryption.clone(),
+            ),
+            locks: Locks::new(config.lock_timeout),
+            writes,
+            default_quota: config.quota,
+            max_contacts: config.max_contacts,
+            count: Mutex::new(None),
+            soft_delete: config.soft_delete,
+            dir,
+            metadata: RwLock::new(metadata),
+        })
+    }
+
+    /// Waits for the other requests writing to the contacts `ids` and keeps
+    /// them from doing so until the guard is dropped. Taken around every read
+    /// of a contact that leads to writing it.
+    pub async fn lock_writes(&self, ids: &[&str]) -> WriteGuard<'_> {
+        self.writes
+            .lock(ids.iter().map(|id| self.write_key(id)).collect())
+            .await
+    }
+
+    /// Same as [`Book::lock_writes`] for a contact of the book and a contact
+    /// of `target`, possibly the same book.
+    pub async fn lock_transfer(&self, id: &str, target: &Book, target_id: &str) -> WriteGuard<'_> {
+        self.writes
+            .lock(vec![self.write_key(id), target.write_key(target_id)])
+            .await
+    }
+
+    fn write_key(&self, id: &str) -> String {
+        format!("{}/{}", self.name, id)
+    }
+
+    /// Deletes a contact, moving it to the trash when soft delete is enabled.
+    /// Returns `false` if it did not exist.
+    pub async fn remove(&self, id: &str) -> Result<bool, StoreError> {
+        if self.soft_delete {
+            self.store.trash(id).await
+        } else {
+            self.store.delete(id).await
+        }
+    }
+
+    pub async fn metadata(&self) -> Metadata {
+        self.metadata.read().await.clone()
+    }
+
+    pub async fn set_metadata(&self, metadata: Metadata) -> io::Result<()> {
+        let mut current = self.metadata.write().await;
+        let content = serde_json::to_string_pretty(&metadata).map_err(io::Error::other)?;
+
+        fs::write(self.dir.join("metadata.json"), content).await?;
+        *current = metadata;
+
+        Ok(())
+    }
+
+    /// Storage quota in bytes, `None` when unlimited.
+    pub async fn quota(&self) -> Option<u64> {
+        self.metadata().await.quota.or(self.default_quota)
+    }
+
+    /// Whether storing `contact`, possibly replacing a stored version, keeps the
+    /// book within its quota.
+    pub async fn fits(&self, contact: &Contact) -> Result<bool, StoreError> {
+        self.fits_with(contact, 0).await
+    }
+
+    /// Like [`Book::fits`], with `pending` more bytes about to be written along
+    /// with the contact.
+    pub async fn fits_with(&self, contact: &Contact, pending: u64) -> Result<bool, StoreError> {
+        let Some(quota) = self.quota().await else {
+            return Ok(true);
+        };
+
+        let used = self.store.usage().await?;
+        let replaced = match self.store.get(&contact.id).await? {
+            Some(stored) => stored.to_string().len() as u64,
+            None => 0,
+        };
+
+        Ok(used.saturating_sub(replaced) + pending + contact.to_string().len() as u64 <= quota)
+    }
+
+    /// Whether storing the contact `id` keeps the book within its maximum
+    /// number of contacts, with `pending` more contacts about to be created.
+    /// Replacing a stored contact is always allowed.
+    pub async fn admits(&self, id: &str, pending: usize) -> Result<bool, StoreError> {
+        let Some(max) = self.max_contacts else {
+            return Ok(true);
+        };
+
+        if self.store.exists(id).await? {
+            return Ok(true);
+        }
+
+        Ok(self.count().await? + pending < max)
+    }
+
+    /// Number of contacts, only counted again after a change was recorded.
+    async fn count(&self) -> Result<usize, StoreError> {
+        let seq = self.changes.current().await;
+        let mut count = self.count.lock().await;
+
+        match *count {
+            Some((counted_at, count)) if counted_at == seq => Ok(count),
+            _ => {
+                let counted = self.store.sizes().await?.len();
+                *count = Some((seq, counted));
+                Ok(counted)
+            }
+        }
+    }
+
+    /// Name shown to users, unless one was set by a client.
+    pub async fn displayname(&self) -> String {
+        match self.metadata().await.displayname {
+            Some(displayname) => displayname,
+            None if self.name == DEFAULT_BOOK => "Contacts".to_string(),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Path of the collection holding the contacts of the book, as seen by
+    /// the client.
+    pub fn href(&self) -> String {
+        if self.name == DEFAULT_BOOK {
+            proxy::path("/contacts")
+        } else {
+            proxy::path(&format!("/addressbooks/{}/contacts", self.name))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BookError {
+    /// The name is empty or uses characters other than ASCII letters, digits,
+    /// `-` and `_`.
+    InvalidName(String),
+    Exists(String),
+    NotFound(String),
+    /// The book still holds contacts and deletion was not forced.
+    NotEmpty(String),
+    /// The default book cannot be deleted.
+    Default,
+    Store(StoreError),
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookError::InvalidName(name) => write!(f, "invalid address book name '{}'", name),
+            BookError::Exists(name) => write!(f, "address book '{}' already exists", name),
+            BookError::NotFound(name) => write!(f, "address book '{}' not found", name),
+            BookError::NotEmpty(name) => write!(f, "address book '{}' is not empty", name),
+            BookError::Default => write!(f, "the default address book cannot be deleted"),
+            BookError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<StoreError> for BookError {
+    fn from(e: StoreError) -> Self {
+        BookError::Store(e)
+    }
+}
+
+/// The address books, the default one living at the root of the data
+/// directory and the others in its `books` subdirectory.
+pub struct Books {
+    dir: PathBuf,
+    config: Config,
+    writes: Arc<WriteLocks>,
+    books: RwLock<BTreeMap<String, Arc<Book>>>,
+}
+
+impl Books {
+    pub async fn open(dir: &Path, config: Config) -> Result<Self, StoreError> {
+        let writes = Arc::new(WriteLocks::default());
+        let mut books = BTreeMap::new();
+        books.insert(
+            DEFAULT_BOOK.to_string(),
+            Arc::new(Book::open(DEFAULT_BOOK, dir.to_path_buf(), &config, writes.clone()).await?),
+        );
+
+        let books_dir = dir.join("books");
+        fs::create_dir_all(&books_dir).await?;
+
+        let mut entries = fs::read_dir(&books_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type().await?.is_dir() || !is_valid_name(&name) {
+                warn!("Skipping unexpected entry in {}", books_dir.display());
+                continue;
+            }
+
+            let book = Book::open(&name, entry.path(), &config, writes.clone()).await?;
+            books.insert(name, Arc::new(book));
+        }
+
+        Ok(Books {
+            dir: books_dir,
+            config,
+            writes,
+            books: RwLock::new(books),
+        })
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Arc<Book>> {
+        self.books.read().await.get(name).cloned()
+    }
+
+    pub async fn list(&self) -> Vec<Arc<Book>> {
+        self.books.read().await.values().cloned().collect()
+    }
+
+    pub async fn create(&self, name: &str) -> Result<Arc<Book>, BookError> {
+        if !is_valid_name(name) {
+            return Err(BookError::InvalidName(name.to_string()));
+        }
+
+        let mut books = self.books.write().await;
+        if books.contains_key(name) {
+            return Err(BookError::Exists(name.to_string()));
+        }
+
+        let book = Arc::new(
+            Book::open(name, self.dir.join(name), &self.config, self.writes.clone()).await?,
+        );
+        books.insert(name.to_string(), book.clone());
+
+        Ok(book)
+    }
+
+    /// Deletes a book along with its files. Books holding contacts are only
+    /// deleted when `force` is set.
+    pub async fn delete(&self, name: &str, force: bool) -> Result<(), BookError> {
+        if name == DEFAULT_BOOK {
+            return Err(BookError::Default);
+        }
+
+        let mut books = self.books.write().await;
+        let book = books
+            .get(name)
+            .ok_or_else(|| BookError::NotFound(name.to_string()))?;
+
+        if !force && !book.store.list().await?.is_empty() {
+            return Err(BookError::NotEmpty(name.to_string()));
+        }
+
+        fs::remove_dir_all(&book.dir)
+            .await
+            .map_err(StoreError::from)?;
+        books.remove(name);
+
+        Ok(())
+    }
+
+    /// Copies the `.vcf` files of every book into its store in a single batch,
+    /// for the one-shot migration from files to SQLite. Returns the number of
+    /// copied contacts.
+    #[cfg(feature = "sqlite")]
+    pub async fn import_files(&self) -> Result<usize, StoreError> {
+        let mut imported = 0;
+
+        for book in self.list().await {
+            let files = FsStore::new(
+                book.dir.join(&self.config.collection_name),
+                false,
+                self.config.encryption.clone(),
+            );
+            let listing = files.scan().await?;
+            if listing.skipped > 0 {
+                warn!(
+                    "{} unreadable contacts of {} left out",
+                    listing.skipped, book.name
+                );
+            }
+
+            book.store.write_batch(&listing.contacts, &[]).await?;
+            info!(
+                "Imported {} contacts into {}",
+                listing.contacts.len(),
+                book.name
+            );
+            imported += listing.contacts.len();
+        }
+
+        Ok(imported)
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Extracts the address book named by the `book` path parameter, or the
+/// default one on routes without it.
+pub struct AddressBook(pub Arc<Book>);
+
+impl FromRequestParts<Arc<AppState>> for AddressBook {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let name = params
+            .iter()
+            .find(|(key, _)| *key == "book")
+            .map_or(DEFAULT_BOOK, |(_, value)| value);
+
+        match state.books.get(name).await {
+            Some(book) => Ok(AddressBook(book)),
+            None => {
+                warn!("address book not found: {}", name);
+                Err((StatusCode::NOT_FOUND, "address book not found".to_string()).into_response())
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "sqlite")]
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+use tracing::warn;
+
+#[cfg(feature = "sqlite")]
+use crate::store::Database;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// A single entry of the change log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Change {
+    pub seq: u64,
+    pub id: String,
+    pub op: Operation,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// Where the change log is persisted.
+enum Sink {
+    /// A file of JSON lines.
+    File(PathBuf),
+    /// The `changes` table of the SQLite database of the contacts.
+    #[cfg(feature = "sqlite")]
+    Sqlite(Database),
+}
+
+/// Append-only log of contact modifications, persisted as JSON lines or in
+/// SQLite next to the contacts.
+///
+/// Every entry gets a sequence number which is used as the sync token handed
+/// out to clients. Entries older than the horizon are pruned, except for the
+/// most recent one so the current sequence number survives restarts.
+pub struct ChangeLog {
+    sink: Sink,
+    horizon: Duration,
+    entries: Mutex<Vec<Change>>,
+}
+
+impl ChangeLog {
+    pub async fn open(path: PathBuf, horizon: Duration) -> io::Result<Self> {
+        let entries = read_file(&path).await?;
+        ChangeLog::with_entries(Sink::File(path), horizon, entries).await
+    }
+
+    /// Opens the log kept in the `changes` table of `db`. While the table is
+    /// empty, the entries of the file log at `legacy` are taken over so the
+    /// sync tokens handed out before switching to SQLite stay valid.
+    #[cfg(feature = "sqlite")]
+    pub async fn open_sqlite(db: Database, legacy: PathBuf, horizon: Duration) -> io::Result<Self> {
+        let stored = db
+            .run(|conn| {
+                let mut stmt =
+                    conn.prepare("SELECT seq, id, op, timestamp FROM changes ORDER BY seq")?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await
+            .map_err(io::Error::other)?;
+
+        let mut entries = Vec::new();
+        for (seq, id, op, timestamp) in stored {
+            match serde_json::from_value::<Operation>(serde_json::Value::String(op)) {
+                Ok(op) => entries.push(Change {
+                    seq: seq as u64,
+                    id,
+                    op,
+                    timestamp: timestamp as u64,
+                }),
+                Err(e) => warn!("skipping malformed change log entry: {}", e),
+            }
+        }
+
+        let sink = Sink::Sqlite(db);
+        if entries.is_empty() {
+            entries = read_file(&legacy).await?;
+            for change in &entries {
+                append(&sink, change).await?;
+            }
+        }
+
+        ChangeLog::with_entries(sink, horizon, entries).await
+    }
+
+    async fn with_entries(sink: Sink, horizon: Duration, entries: Vec<Change>) -> io::Result<Self> {
+        let log = ChangeLog {
+            sink,
+            horizon,
+            entries: Mutex::new(entries),
+        };
+
+        let mut entries = log.entries.lock().await;
+        if log.prune(&mut entries) {
+            log.rewrite(&entries).await?;
+        }
+        drop(entries);
+
+        Ok(log)
+    }
+
+    /// Appends a new entry for `id` and returns its sequence number.
+    pub async fn record(&self, id: &str, op: Operation) -> io::Result<u64> {
+        let mut entries = self.entries.lock().await;
+
+        let change = Change {
+            seq: entries.last().map_or(0, |change| change.seq) + 1,
+            id: id.to_string(),
+            op,
+            timestamp: now(),
+        };
+        let seq = change.seq;
+
+        append(&self.sink, &change).await?;
+        entries.push(change);
+
+        if self.prune(&mut entries) {
+            self.rewrite(&entries).await?;
+        }
+
+        Ok(seq)
+    }
+
+    /// Makes the recorded entries durable, for a clean stop. Entries kept in
+    /// SQLite already are once recorded.
+    pub async fn sync(&self) -> io::Result<()> {
+        let _entries = self.entries.lock().await;
+
+        match &self.sink {
+            Sink::File(path) => match fs::OpenOptions::new().append(true).open(path).await {
+                Ok(file) => file.sync_all().await,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e),
+            },
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite(_) => Ok(()),
+        }
+    }
+
+    /// Returns the current sequence number.
+    pub async fn current(&self) -> u64 {
+        self.entries
+            .lock()
+            .await
+            .last()
+            .map_or(0, |change| change.seq)
+    }
+
+    /// Returns the current sequence number and every change recorded after
+    /// `seq`, or `None` if `seq` is unknown or has been pruned.
+    pub async fn since(&self, seq: u64) -> Option<(u64, Vec<Change>)> {
+        let entries = self.entries.lock().await;
+
+        let oldest = entries.first().map_or(0, |change| change.seq - 1);
+        let current = entries.last().map_or(0, |change| change.seq);
+        if seq < oldest || seq > current {
+            return None;
+        }
+
+        let changes = entries
+            .iter()
+            .filter(|change| change.seq > seq)
+            .cloned()
+            .collect();
+
+        Some((current, changes))
+    }
+
+    fn prune(&self, entries: &mut Vec<Change>) -> bool {
+        let cutoff = now().saturating_sub(self.horizon.as_secs());
+        let expired = entries
+            .iter()
+            .take_while(|change| change.timestamp < cutoff)
+            .count()
+            .min(entries.len().saturating_sub(1));
+
+        entries.drain(..expired);
+        expired > 0
+    }
+
+    /// Persists the log after entries were pruned.
+    async fn rewrite(&self, entries: &[Change]) -> io::Result<()> {
+        match &self.sink {
+            Sink::File(path) => {
+                let mut content = String::new();
+                for change in entries {
+                    content.push_str(&serde_json::to_string(change)?);
+                    content.push('\n');
+                }
+
+                fs::write(path, content).await
+            }
+            #[cfg(feature = "sqlite")]
+            Sink::Sqlite(db) => {
+                let oldest = entries.first().map_or(0, |change| change.seq) as i64;
+
+                db.run(move |conn| {
+                    conn.execute("DELETE FROM changes WHERE seq < ?1", params![oldest])
+                })
+                .await
+                .map(|_| ())
+                .map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+/// Reads the entries of a file log, an empty log if the file does not exist.
+async fn read_file(path: &Path) -> io::Result<Vec<Change>> {
+    let mut entries = Vec::new();
+
+    match fs::read_to_string(path).await {
+        Ok(content) => {
+            for line in content.lines().filter(|line| !line.is_empty()) {
+                match serde_json::from_str::<Change>(line) {
+                    Ok(change) => entries.push(change),
+                    Err(e) => warn!("skipping malformed change log entry: {}", e),
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(entries)
+}
+
+/// Persists a new entry.
+async fn append(sink: &Sink, change: &Change) -> io::Result<()> {
+    match sink {
+        Sink::File(path) => {
+            let mut line = serde_json::to_string(change)?;
+            line.push('\n');
+
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        }
+        #[cfg(feature = "sqlite")]
+        Sink::Sqlite(db) => {
+            let change = change.clone();
+            let op = serde_json::to_value(change.op)?;
+
+            db.run(move |conn| {
+                conn.execute(
+                    "INSERT INTO changes (seq, id, op, timestamp) VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        change.seq as i64,
+                        change.id,
+                        op.as_str(),
+                        change.timestamp as i64
+                    ],
+                )
+            })
+            .await
+            .map(|_| ())
+            .map_err(io::Error::other)
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
@@ -0,0 +1,363 @@
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::auth::PasswordHash;
+use crate::books::{Books, DEFAULT_BOOK};
+use crate::changes::Operation;
+use crate::config::{Config, USERS_DIR};
+use crate::contact::{self, parse_vcard_bytes, Contact};
+use crate::dir_lock::DirLock;
+use crate::encryption;
+use crate::store;
+use crate::{csv, data_dir, import_cards, record_change, search};
+
+/// Flags followed by their value, which is then not a command argument.
+const VALUE_FLAGS: &[&str] = &[
+    "--config",
+    "--listen",
+    "--data-dir",
+    "--log-level",
+    "--book",
+    "--user",
+    "--format",
+    "--query",
+];
+
+const USAGE: &str = "usage: dav [serve | import <file> | export [--format vcf|csv] | \
+    list [--query <text>] | show <id> | delete <id> | migrate | encrypt | decrypt <file> | \
+    config check | hash-password]";
+
+/// Command given on the command line.
+pub enum Command {
+    /// Runs the server, also when no command is given.
+    Serve,
+    /// Copies the contact files into SQLite.
+    Migrate,
+    /// Encrypts the plain contact files, history and backups in place with
+    /// the key of `DAV_ENCRYPTION_KEY`.
+    Encrypt,
+    /// Prints an encrypted file, such as a backup, decrypted.
+    Decrypt { file: PathBuf },
+    /// Checks the configuration and prints the effective settings.
+    CheckConfig,
+    /// Hashes the password read from the standard input for
+    /// `DAV_AUTH_PASSWORD_HASH`.
+    HashPassword,
+    /// Works on an address book, from `--book` or the default one, without
+    /// the server. With `--user`, the address book is one of that user of
+    /// the users file.
+    Offline {
+        user: Option<String>,
+        book: String,
+        task: Task,
+    },
+}
+
+/// What an offline command does.
+pub enum Task {
+    Import { file: PathBuf, dry_run: bool },
+    Export { format: ExportFormat },
+    List { query: Option<String>, fuzzy: bool },
+    Show { id: String },
+    Delete { id: String },
+}
+
+pub enum ExportFormat {
+    Vcard,
+    Csv,
+}
+
+impl Command {
+    /// Parses the arguments of the process, without the program name.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
+            if arg.starts_with("--") {
+                if VALUE_FLAGS.contains(&arg.as_str()) {
+                    args_iter.next();
+                }
+                continue;
+            }
+            positional.push(arg.as_str());
+        }
+
+        let task = match positional.as_slice() {
+            [] | ["serve"] => return Ok(Command::Serve),
+            ["migrate"] => return Ok(Command::Migrate),
+            ["encrypt"] => return Ok(Command::Encrypt),
+            ["decrypt", file] => {
+                return Ok(Command::Decrypt {
+                    file: PathBuf::from(file),
+                })
+            }
+            ["config", "check"] => return Ok(Command::CheckConfig),
+            ["hash-password"] => return Ok(Command::HashPassword),
+            ["import", file] => Task::Import {
+                file: PathBuf::from(file),
+                dry_run: has_flag(args, "--dry-run"),
+            },
+            ["export"] => Task::Export {
+                format: match option(args, "--format")?.as_deref() {
+                    None | Some("vcf") => ExportFormat::Vcard,
+                    Some("csv") => ExportFormat::Csv,
+                    Some(other) => {
+                        return Err(format!("unknown format '{}', expected vcf or csv", other))
+                    }
+                },
+            },
+            ["list"] => Task::List {
+                query: option(args, "--query")?,
+                fuzzy: has_flag(args, "--fuzzy"),
+            },
+            ["show", id] => Task::Show { id: id.to_string() },
+            ["delete", id] => Task::Delete { id: id.to_string() },
+            _ => return Err(USAGE.to_string()),
+        };
+
+        Ok(Command::Offline {
+            user: option(args, "--user")?,
+            book: option(args, "--book")?.unwrap_or_else(|| DEFAULT_BOOK.to_string()),
+            task,
+        })
+    }
+}
+
+/// Reads the value of a `--<name> <value>` or `--<name>=<value>` flag.
+fn option(args: &[String], flag: &str) -> Result<Option<String>, String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args
+                .next()
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| format!("{} requires a value", flag));
+        }
+
+        if let Some(value) = arg
+            .strip_prefix(flag)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Ok(Some(value.to_string()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+/// Hashes the first line of the standard input, without its line break.
+pub fn hash_password() -> Result<String, String> {
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| format!("failed to read the password: {}", e))?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err("empty password".to_string());
+    }
+
+    PasswordHash::new(password).map(|hash| hash.to_string())
+}
+
+/// Encrypts in place the plain `.vcf` files of the data directory, the
+/// contacts, the trash and the history of every user, and of the backup
+/// directory. Returns the number of encrypted files.
+pub async fn encrypt(config: Config) -> Result<usize, String> {
+    let Some(cipher) = config.encryption.clone() else {
+        return Err("set DAV_ENCRYPTION_KEY or DAV_ENCRYPTION_KEY_FILE to encrypt".to_string());
+    };
+    if config.ephemeral {
+        return Err("nothing to encrypt in ephemeral mode".to_string());
+    }
+
+    let dir = data_dir(&config);
+    let _lock = DirLock::acquire(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    let mut dirs = vec![dir.clone()];
+    if let Some(backup_dir) = config
+        .backup_dir
+        .filter(|backup_dir| !backup_dir.starts_with(&dir))
+    {
+        dirs.push(backup_dir);
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let mut encrypted = 0;
+        for dir in dirs {
+            encrypted += encryption::encrypt_dir(&dir, &cipher)
+                .map_err(|e| format!("failed to encrypt {}: {}", dir.display(), e))?;
+        }
+        Ok(encrypted)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Reads a file encrypted with the key of `DAV_ENCRYPTION_KEY`, such as a
+/// backup, and returns it decrypted.
+pub async fn decrypt(config: &Config, file: &Path) -> Result<String, String> {
+    let Some(cipher) = &config.encryption else {
+        return Err("set DAV_ENCRYPTION_KEY or DAV_ENCRYPTION_KEY_FILE to decrypt".to_string());
+    };
+
+    encryption::read(file, Some(cipher))
+        .await
+        .map_err(|e| format!("failed to read {}: {}", file.display(), e))
+}
+
+/// Runs an offline command on an address book. The data directory is locked
+/// for the whole command, so it fails while a server uses the directory.
+pub async fn run(config: Config, user: Option<&str>, book: &str, task: Task) -> Result<(), String> {
+    if config.ephemeral {
+        return Err(
+            "offline commands work on the data directory, not in ephemeral mode".to_string(),
+        );
+    }
+
+    let dir = data_dir(&config);
+    let _lock = DirLock::acquire(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    let dir = match user {
+        Some(user) if config.users.iter().any(|other| other.user == user) => {
+            dir.join(USERS_DIR).join(user)
+        }
+        Some(user) => return Err(format!("user '{}' is not in DAV_USERS_FILE", user)),
+        None => dir,
+    };
+
+    contact::set_version(config.vcard_version);
+    let books = Books::open(&dir, config)
+        .await
+        .map_err(|e| format!("failed to open address books: {}", e))?;
+    let book = books
+        .get(book)
+        .await
+        .ok_or_else(|| format!("address book '{}' not found", book))?;
+
+    match task {
+        Task::Import { file, dry_run } => {
+            let data = fs::read(&file)
+                .await
+                .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+            let cards = parse_vcard_bytes(&data)?;
+
+            let summary = import_cards(&book, &cards, dry_run).await;
+            for failure in &summary.failed {
+                eprintln!("card {}: {}", failure.card, failure.reason);
+            }
+            println!(
+                "{} {} contacts, {} failed",
+                if dry_run { "Would import" } else { "Imported" },
+                summary.imported.len(),
+                summary.failed.len()
+            );
+            if !summary.failed.is_empty() {
+                return Err("some cards were not imported".to_string());
+            }
+        }
+        Task::Export { format } => {
+            let mut contacts = book
+                .store
+                .list()
+                .await
+                .map_err(|e| format!("failed to list contacts: {}", e))?;
+            contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+            match format {
+                ExportFormat::Vcard => {
+                    print!(
+                        "{}",
+                        contacts.iter().map(Contact::to_string).collect::<String>()
+                    )
+                }
+                ExportFormat::Csv => print!("{}", csv::write(&contacts)),
+            }
+        }
+        Task::List { query, fuzzy } => {
+            let mut contacts = book
+                .store
+                .list()
+                .await
+                .map_err(|e| format!("failed to list contacts: {}", e))?;
+            let contacts = match query {
+                Some(query) => search::filter(contacts, &query, fuzzy),
+                None => {
+                    contacts.sort_by(|a, b| a.id.cmp(&b.id));
+                    contacts
+                }
+            };
+            for contact in contacts {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    contact.id, contact.name, contact.email, contact.phone
+                );
+            }
+        }
+        Task::Show { id } => {
+            check_id(&id)?;
+            match book.store.get(&id).await {
+                Ok(Some(contact)) => print!("{}", contact),
+                Ok(None) => return Err(format!("contact '{}' not found", id)),
+                Err(e) => return Err(format!("failed to read contact {}: {}", id, e)),
+            }
+        }
+        Task::Delete { id } => {
+            check_id(&id)?;
+            match book.remove(&id).await {
+                Ok(true) => {
+                    record_change(&book, &id, Operation::Delete).await;
+                    println!("Deleted {}", id);
+                }
+                Ok(false) => return Err(format!("contact '{}' not found", id)),
+                Err(e) => return Err(format!("failed to delete contact {}: {}", id, e)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Rejects an ID that cannot name a contact, before it reaches the store and
+/// becomes part of a path.
+fn check_id(id: &str) -> Result<(), String> {
+    store::check_id(id).map_err(|e| format!("invalid ID '{}': {}", id, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_ids_leaving_the_contacts_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("secret.vcf");
+        std::fs::write(
+            &outside,
+            "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Secret\r\nEND:VCARD\r\n",
+        )
+        .unwrap();
+
+        for task in [
+            Task::Show {
+                id: "../../secret".to_string(),
+            },
+            Task::Delete {
+                id: "../../secret".to_string(),
+            },
+        ] {
+            let mut config = Config::for_tests();
+            config.data_dir = Some(dir.path().to_path_buf());
+            let e = run(config, None, DEFAULT_BOOK, task).await.unwrap_err();
+            assert!(e.starts_with("invalid ID '../../secret'"), "{}", e);
+        }
+        assert!(outside.exists());
+    }
+}
@@ -0,0 +1,927 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::Credentials;
+use crate::books::is_valid_name;
+use crate::contact::VCardVersion;
+use crate::encryption::Cipher;
+use crate::listener::{ListenAddr, TlsFiles};
+use crate::logging::LogFormat;
+use crate::proxy;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:3000";
+const DEFAULT_CHANGE_HORIZON_DAYS: u64 = 30;
+const DEFAULT_LOCK_TIMEOUT_SECS: u64 = 600;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+const DEFAULT_HISTORY_LIMIT: usize = 10;
+const DEFAULT_BACKUP_RETENTION: usize = 7;
+const DEFAULT_WATCH_INTERVAL_SECS: u64 = 5;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_SOCKET_MODE: u32 = 0o660;
+const DEFAULT_COLLECTION_NAME: &str = "contacts";
+const DEFAULT_AUDIT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Directory holding the data and the backups of the users of
+/// `DAV_USERS_FILE`, one per user, in those of the first user.
+pub const USERS_DIR: &str = "users";
+
+/// Entries of the directory of an address book the contact files cannot use.
+const RESERVED_COLLECTION_NAMES: &[&str] =
+    &["books", "history", "events", "todos", "backups", USERS_DIR];
+
+/// Where contacts are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// One `.vcf` file per contact.
+    Fs,
+    /// A single SQLite database.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    /// Kept in memory and lost on exit.
+    Memory,
+}
+
+/// Runtime settings, read from `DAV_*` environment variables, which take over
+/// the configuration file. The few that also have a command-line flag take it
+/// over the variable.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Configuration file that was read, from `--config` or `DAV_CONFIG`, else
+    /// `config.toml` in the configuration directory of the user if it exists.
+    pub file: Option<PathBuf>,
+    /// Problems of the configuration file that do not prevent starting, such
+    /// as unknown keys, logged once logging is set up.
+    pub warnings: Vec<String>,
+    /// Address the server listens on, from `--listen` or `DAV_LISTEN`, TCP or
+    /// `unix:<path>`. A socket passed by systemd socket activation is used
+    /// instead.
+    pub listen: ListenAddr,
+    /// Certificate and key of HTTPS, from `DAV_TLS_CERT` and `DAV_TLS_KEY`.
+    /// Unset means plain HTTP.
+    pub tls: Option<TlsFiles>,
+    /// Permissions of the Unix socket the server creates, from
+    /// `DAV_SOCKET_MODE` in octal.
+    pub socket_mode: u32,
+    /// Path the routes are served under, from `DAV_BASE_PATH`, empty at the
+    /// root.
+    pub base_path: String,
+    /// Reverse proxies whose `X-Forwarded-*` headers are believed, from the
+    /// comma-separated IP addresses of `DAV_TRUSTED_PROXIES`.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Credentials of HTTP Basic authentication, from `DAV_AUTH_USER` and the
+    /// Argon2 hash of `DAV_AUTH_PASSWORD_HASH`. Unset means no authentication.
+    pub auth: Option<Credentials>,
+    /// Further users, each with its own data under `users/<name>` in the data
+    /// directory, from the `<name>:<hash>` lines of the file of
+    /// `DAV_USERS_FILE`.
+    pub users: Vec<Credentials>,
+    /// Directory of the data, from `--data-dir` or `DAV_DATA_DIR`. Unset means
+    /// the data directory of the user.
+    pub data_dir: Option<PathBuf>,
+    /// Subdirectory of the contact files of every address book, from
+    /// `DAV_COLLECTION_NAME`.
+    pub collection_name: String,
+    /// Verbosity of the logs, from `--log-level` or `DAV_LOG_LEVEL`, accepting
+    /// the directives of `RUST_LOG`. Unset means `RUST_LOG`.
+    pub log_level: Option<String>,
+    /// Output format of the logs, from `DAV_LOG_FORMAT`.
+    pub log_format: LogFormat,
+    /// How long entries are kept in the change log before being pruned.
+    pub change_horizon: Duration,
+    /// Key encrypting the contact files, their history and the backups, from
+    /// the 64 hex digits of `DAV_ENCRYPTION_KEY` or of the file of
+    /// `DAV_ENCRYPTION_KEY_FILE`. Unset means plain files.
+    pub encryption: Option<Cipher>,
+    /// Storage backend, selected with `DAV_BACKEND`.
+    pub backend: Backend,
+    /// Whether the server runs without persistent data, from the `--ephemeral`
+    /// flag or `DAV_EPHEMERAL`. Contacts are kept in memory and the rest goes
+    /// to a temporary directory.
+    pub ephemeral: bool,
+    /// Whether every request that could change something is rejected, from
+    /// the `--read-only` flag or `DAV_READ_ONLY`.
+    pub read_only: bool,
+    /// Requests allowed per minute and client IP, from `DAV_RATE_LIMIT`.
+    /// Unset or `0` disables rate limiting.
+    pub rate_limit: Option<u32>,
+    /// Longest time a WebDAV lock is held without being refreshed, from
+    /// `DAV_LOCK_TIMEOUT` in seconds.
+    pub lock_timeout: Duration,
+    /// Time after which a request is answered with `408 Request Timeout`, from
+    /// `DAV_REQUEST_TIMEOUT` in seconds.
+    pub request_timeout: Duration,
+    /// Longest time the requests in flight are waited for when stopping, from
+    /// `DAV_SHUTDOWN_TIMEOUT` in seconds.
+    pub shutdown_timeout: Duration,
+    /// Storage quota of every address book in bytes, from `DAV_QUOTA`. Unset
+    /// means unlimited.
+    pub quota: Option<u64>,
+    /// Largest number of contacts of every address book, from
+    /// `DAV_MAX_CONTACTS`. Unset or `0` means unlimited.
+    pub max_contacts: Option<usize>,
+    /// Whether deleted contacts are moved to the trash instead of being
+    /// removed, from `DAV_SOFT_DELETE`.
+    pub soft_delete: bool,
+    /// How long deleted contacts stay in the trash before being removed for
+    /// good, from `DAV_TRASH_RETENTION_DAYS`.
+    pub trash_retention: Duration,
+    /// Number of previous versions kept for every contact, from
+    /// `DAV_HISTORY_LIMIT`. `0` disables the history.
+    pub history_limit: usize,
+    /// Directory of the backups, from `DAV_BACKUP_DIR`. Unset means `backups`
+    /// in the data directory.
+    pub backup_dir: Option<PathBuf>,
+    /// Time between two scheduled backups, from `DAV_BACKUP_INTERVAL_HOURS`.
+    /// Unset or `0` disables them.
+    pub backup_interval: Option<Duration>,
+    /// Number of backups kept, from `DAV_BACKUP_RETENTION`.
+    pub backup_retention: usize,
+    /// Time between two looks for contact files changed outside of the server,
+    /// from `DAV_WATCH_INTERVAL` in seconds. `0` disables them.
+    pub watch_interval: Option<Duration>,
+    /// Whether the directory is synced after each contact or event is written,
+    /// so the write survives a crash, from `DAV_FSYNC_DIR`.
+    pub fsync_dir: bool,
+    /// vCard version of the stored and served contacts, `3.0` or `4.0` from
+    /// `DAV_VCARD_VERSION`.
+    pub vcard_version: VCardVersion,
+    /// Size in bytes above which the audit log is rotated, from
+    /// `DAV_AUDIT_MAX_SIZE`.
+    pub audit_max_size: u64,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        let settings = Settings::load()?;
+
+        let listen = settings.arg("--listen", "DAV_LISTEN")?;
+        let listen = listen
+            .as_deref()
+            .unwrap_or(DEFAULT_LISTEN)
+            .parse()
+            .map_err(|e| {
+                format!(
+                    "invalid listen address '{}': {}",
+                    listen.unwrap_or_default(),
+                    e
+                )
+            })?;
+        let socket_mode = match settings.var("DAV_SOCKET_MODE") {
+            None => DEFAULT_SOCKET_MODE,
+            Some(mode) => u32::from_str_radix(&mode, 8)
+                .ok()
+                .filter(|&mode| mode <= 0o777)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid {} '{}', expected octal permissions such as 660",
+                        settings.origin("DAV_SOCKET_MODE"),
+                        mode
+                    )
+                })?,
+        };
+        let base_path = match settings.var("DAV_BASE_PATH") {
+            Some(path) => proxy::parse_base_path(&path).map_err(|e| {
+                format!(
+                    "invalid {} '{}': {}",
+                    settings.origin("DAV_BASE_PATH"),
+                    path,
+                    e
+                )
+            })?,
+            None => String::new(),
+        };
+        let trusted_proxies = settings
+            .var("DAV_TRUSTED_PROXIES")
+            .map(|proxies| {
+                proxies
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|proxy| !proxy.is_empty())
+                    .map(|proxy| {
+                        proxy.parse().map_err(|e| {
+                            format!(
+                                "invalid {} '{}': {}",
+                                settings.origin("DAV_TRUSTED_PROXIES"),
+                                proxy,
+                                e
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<IpAddr>, String>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let auth = match (
+            settings.var("DAV_AUTH_USER"),
+            settings.var("DAV_AUTH_PASSWORD_HASH"),
+        ) {
+            (None, None) => None,
+            (Some(user), Some(hash)) => Some(Credentials {
+                user,
+                password: hash.parse().map_err(|e| {
+                    format!(
+                        "invalid {}: {}",
+                        settings.origin("DAV_AUTH_PASSWORD_HASH"),
+                        e
+                    )
+                })?,
+            }),
+            (Some(_), None) => {
+                return Err(format!(
+                    "{} is set without DAV_AUTH_PASSWORD_HASH",
+                    settings.origin("DAV_AUTH_USER")
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(format!(
+                    "{} is set without DAV_AUTH_USER",
+                    settings.origin("DAV_AUTH_PASSWORD_HASH")
+                ))
+            }
+        };
+        let users = match settings.var("DAV_USERS_FILE") {
+            Some(file) => read_users(&PathBuf::from(&file)).map_err(|e| {
+                format!(
+                    "invalid {} '{}': {}",
+                    settings.origin("DAV_USERS_FILE"),
+                    file,
+                    e
+                )
+            })?,
+            None => Vec::new(),
+        };
+        if let Some(user) = users.iter().find(|user| {
+            auth.as_ref()
+                .is_some_and(|credentials| credentials.user == user.user)
+        }) {
+            return Err(format!(
+                "user '{}' is both DAV_AUTH_USER and in DAV_USERS_FILE",
+                user.user
+            ));
+        }
+        let data_dir = settings
+            .arg("--data-dir", "DAV_DATA_DIR")?
+            .map(PathBuf::from);
+        let collection_name = match settings.var("DAV_COLLECTION_NAME") {
+            Some(name) => {
+                check_collection_name(&name).map_err(|e| {
+                    format!(
+                        "invalid {} '{}': {}",
+                        settings.origin("DAV_COLLECTION_NAME"),
+                        name,
+                        e
+                    )
+                })?;
+                name
+            }
+            None => DEFAULT_COLLECTION_NAME.to_string(),
+        };
+        let log_level = settings.arg("--log-level", "DAV_LOG_LEVEL")?;
+        let log_format = settings.parse("DAV_LOG_FORMAT")?.unwrap_or(LogFormat::Text);
+
+        let days = settings
+            .parse("DAV_CHANGE_HORIZON_DAYS")?
+            .unwrap_or(DEFAULT_CHANGE_HORIZON_DAYS);
+
+        let encryption = match (
+            settings.var("DAV_ENCRYPTION_KEY"),
+            settings.var("DAV_ENCRYPTION_KEY_FILE"),
+        ) {
+            (None, None) => None,
+            // The key is never repeated in the message.
+            (Some(key), None) => Some(key.parse().map_err(|e| {
+                format!("invalid {}: {}", settings.origin("DAV_ENCRYPTION_KEY"), e)
+            })?),
+            (None, Some(file)) => Some(read_key(&PathBuf::from(&file)).map_err(|e| {
+                format!(
+                    "invalid {} '{}': {}",
+                    settings.origin("DAV_ENCRYPTION_KEY_FILE"),
+                    file,
+                    e
+                )
+            })?),
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "{} and {} are both set",
+                    settings.origin("DAV_ENCRYPTION_KEY"),
+                    settings.origin("DAV_ENCRYPTION_KEY_FILE")
+                ))
+            }
+        };
+
+        let tls = match (settings.var("DAV_TLS_CERT"), settings.var("DAV_TLS_KEY")) {
+            (None, None) => None,
+            (Some(cert), Some(key)) => Some(TlsFiles {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from(key),
+            }),
+            (Some(_), None) => {
+                return Err(format!(
+                    "{} is set without DAV_TLS_KEY",
+                    settings.origin("DAV_TLS_CERT")
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(format!(
+                    "{} is set without DAV_TLS_CERT",
+                    settings.origin("DAV_TLS_KEY")
+                ))
+            }
+        };
+        if tls.is_some() && matches!(listen, ListenAddr::Unix(_)) {
+            return Err("DAV_TLS_CERT is set but TLS is only served over TCP".to_string());
+        }
+
+        let ephemeral = settings.flag("DAV_EPHEMERAL")? || settings.has_flag("--ephemeral");
+
+        let read_only = settings.flag("DAV_READ_ONLY")? || settings.has_flag("--read-only");
+
+        let backend = match settings.var("DAV_BACKEND").as_deref() {
+            _ if ephemeral => Backend::Memory,
+            None | Some("fs") => Backend::Fs,
+            #[cfg(feature = "sqlite")]
+            Some("sqlite") => Backend::Sqlite,
+            #[cfg(not(feature = "sqlite"))]
+            Some("sqlite") => return Err("the sqlite backend is not compiled in".to_string()),
+            Some("memory") => Backend::Memory,
+            Some(other) => {
+                return Err(format!(
+                    "unknown {} '{}'",
+                    settings.origin("DAV_BACKEND"),
+                    other
+                ))
+            }
+        };
+        #[cfg(feature = "sqlite")]
+        if encryption.is_some() && backend == Backend::Sqlite {
+            return Err(
+                "DAV_ENCRYPTION_KEY only encrypts contact files, not the sqlite backend"
+                    .to_string(),
+            );
+        }
+
+        let rate_limit: u32 = settings.parse("DAV_RATE_LIMIT")?.unwrap_or(0);
+        let lock_timeout = settings
+            .parse("DAV_LOCK_TIMEOUT")?
+            .unwrap_or(DEFAULT_LOCK_TIMEOUT_SECS);
+        let request_timeout = settings
+            .parse("DAV_REQUEST_TIMEOUT")?
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+        let shutdown_timeout = settings
+            .parse("DAV_SHUTDOWN_TIMEOUT")?
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS);
+        let quota = settings.parse("DAV_QUOTA")?;
+        let max_contacts: usize = settings.parse("DAV_MAX_CONTACTS")?.unwrap_or(0);
+
+        let soft_delete = settings.flag("DAV_SOFT_DELETE")?;
+        let trash_retention_days = settings
+            .parse("DAV_TRASH_RETENTION_DAYS")?
+            .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS);
+        let fsync_dir = settings.flag("DAV_FSYNC_DIR")?;
+
+        let history_limit = settings
+            .parse("DAV_HISTORY_LIMIT")?
+            .unwrap_or(DEFAULT_HISTORY_LIMIT);
+
+        let backup_dir = settings.var("DAV_BACKUP_DIR").map(PathBuf::from);
+        let backup_interval_hours: u64 = settings.parse("DAV_BACKUP_INTERVAL_HOURS")?.unwrap_or(0);
+        let backup_retention = settings
+            .parse("DAV_BACKUP_RETENTION")?
+            .unwrap_or(DEFAULT_BACKUP_RETENTION);
+
+        let watch_interval = settings
+            .parse("DAV_WATCH_INTERVAL")?
+            .unwrap_or(DEFAULT_WATCH_INTERVAL_SECS);
+
+        let vcard_version = settings.parse("DAV_VCARD_VERSION")?.unwrap_or_default();
+
+        let audit_max_size = settings
+            .parse("DAV_AUDIT_MAX_SIZE")?
+            .unwrap_or(DEFAULT_AUDIT_MAX_SIZE);
+        if audit_max_size == 0 {
+            return Err(format!(
+                "invalid {} '0': must be greater than 0",
+                settings.origin("DAV_AUDIT_MAX_SIZE")
+            ));
+        }
+
+        Ok(Config {
+            file: settings.file,
+            warnings: settings.warnings,
+            listen,
+            tls,
+            socket_mode,
+            base_path,
+            trusted_proxies,
+            auth,
+            users,
+            data_dir,
+            collection_name,
+            log_level,
+            log_format,
+            change_horizon: Duration::from_secs(days * 24 * 60 * 60),
+            encryption,
+            backend,
+            ephemeral,
+            read_only,
+            rate_limit: (rate_limit > 0).then_some(rate_limit),
+            lock_timeout: Duration::from_secs(lock_timeout),
+            request_timeout: Duration::from_secs(request_timeout),
+            shutdown_timeout: Duration::from_secs(shutdown_timeout),
+            quota,
+            max_contacts: (max_contacts > 0).then_some(max_contacts),
+            soft_delete,
+            trash_retention: Duration::from_secs(trash_retention_days * 24 * 60 * 60),
+            history_limit,
+            backup_dir,
+            backup_interval: (backup_interval_hours > 0)
+                .then(|| Duration::from_secs(backup_interval_hours * 60 * 60)),
+            backup_retention,
+            watch_interval: (watch_interval > 0).then(|| Duration::from_secs(watch_interval)),
+            fsync_dir,
+            vcard_version,
+            audit_max_size,
+        })
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Default settings, without reading the environment, the command line or
+    /// a configuration file.
+    pub fn for_tests() -> Self {
+        Config {
+            file: None,
+            warnings: Vec::new(),
+            listen: DEFAULT_LISTEN
+                .parse()
+                .expect("invalid default listen address"),
+            tls: None,
+            socket_mode: DEFAULT_SOCKET_MODE,
+            base_path: String::new(),
+            trusted_proxies: Vec::new(),
+            auth: None,
+            users: Vec::new(),
+            data_dir: None,
+            collection_name: DEFAULT_COLLECTION_NAME.to_string(),
+            log_level: None,
+            log_format: LogFormat::Text,
+            change_horizon: Duration::from_secs(DEFAULT_CHANGE_HORIZON_DAYS * 24 * 60 * 60),
+            encryption: None,
+            backend: Backend::Fs,
+            ephemeral: false,
+            read_only: false,
+            rate_limit: None,
+            lock_timeout: Duration::from_secs(DEFAULT_LOCK_TIMEOUT_SECS),
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+            quota: None,
+            max_contacts: None,
+            soft_delete: false,
+            trash_retention: Duration::from_secs(DEFAULT_TRASH_RETENTION_DAYS * 24 * 60 * 60),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            backup_dir: None,
+            backup_interval: None,
+            backup_retention: DEFAULT_BACKUP_RETENTION,
+            watch_interval: None,
+            fsync_dir: false,
+            vcard_version: VCardVersion::default(),
+            audit_max_size: DEFAULT_AUDIT_MAX_SIZE,
+        }
+    }
+}
+
+/// Reads a users file: a `<name>:<password hash>` line per user, names being
+/// made of ASCII letters, digits, `-` and `_`. Empty lines and lines starting
+/// with `#` are skipped.
+fn read_users(file: &Path) -> Result<Vec<Credentials>, String> {
+    let text = fs::read_to_string(file).map_err(|e| e.to_string())?;
+
+    let mut users: Vec<Credentials> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (user, hash) = line
+            .split_once(':')
+            .ok_or_else(|| format!("line {}: expected <name>:<password hash>", number + 1))?;
+        if !is_valid_name(user) {
+            return Err(format!(
+                "line {}: invalid user name '{}', expected ASCII letters, digits, '-' and '_'",
+                number + 1,
+                user
+            ));
+        }
+        if users.iter().any(|other| other.user == user) {
+            return Err(format!("line {}: duplicate user '{}'", number + 1, user));
+        }
+
+        users.push(Credentials {
+            user: user.to_string(),
+            password: hash
+                .parse()
+                .map_err(|e| format!("line {}: {}", number + 1, e))?,
+        });
+    }
+
+    Ok(users)
+}
+
+/// Reads a key file, holding the 64 hex digits of the key.
+fn read_key(file: &Path) -> Result<Cipher, String> {
+    fs::read_to_string(file).map_err(|e| e.to_string())?.parse()
+}
+
+/// Checks that the name of the contacts subdirectory is a single directory
+/// that nothing else of an address book uses.
+fn check_collection_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("name is empty".to_string());
+    }
+    if name.contains(['/', '\\']) {
+        return Err("name contains a path separator".to_string());
+    }
+    if name.starts_with('.') {
+        return Err("name starts with a dot".to_string());
+    }
+    if RESERVED_COLLECTION_NAMES.contains(&name) {
+        return Err(format!("'{}' is used by the server", name));
+    }
+    Ok(())
+}
+
+/// Settings of the configuration file, each named after its variable in
+/// lowercase without the `DAV_` prefix. The part before the first underscore
+/// may be a table: `backup_retention = 7` at the top or `retention = 7` under
+/// `[backup]`.
+#[derive(Default, Deserialize, Serialize)]
+struct FileConfig {
+    listen: Option<String>,
+    /// Octal, written as a string or an integer.
+    socket_mode: Option<Scalar>,
+    base_path: Option<String>,
+    trusted_proxies: Option<String>,
+    auth_user: Option<String>,
+    auth_password_hash: Option<String>,
+    users_file: Option<String>,
+    data_dir: Option<String>,
+    collection_name: Option<String>,
+    log_level: Option<String>,
+    log_format: Option<String>,
+    change_horizon_days: Option<u64>,
+    encryption_key: Option<String>,
+    encryption_key_file: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    ephemeral: Option<bool>,
+    read_only: Option<bool>,
+    backend: Option<String>,
+    rate_limit: Option<u32>,
+    lock_timeout: Option<u64>,
+    request_timeout: Option<u64>,
+    shutdown_timeout: Option<u64>,
+    quota: Option<u64>,
+    max_contacts: Option<usize>,
+    soft_delete: Option<bool>,
+    trash_retention_days: Option<u64>,
+    fsync_dir: Option<bool>,
+    history_limit: Option<usize>,
+    backup_dir: Option<String>,
+    backup_interval_hours: Option<u64>,
+    backup_retention: Option<usize>,
+    watch_interval: Option<u64>,
+    /// `3.0` or `4.0`, written as a string or a float.
+    vcard_version: Option<Scalar>,
+    audit_max_size: Option<u64>,
+    /// Keys matching no setting, reported as warnings.
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// Value of a setting that can be written either quoted or not.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Scalar {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl FileConfig {
+    /// Parses a configuration file, failing on invalid TOML, on a value of
+    /// the wrong type or on a setting given twice.
+    fn parse(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+
+        let mut flat = toml::Table::new();
+        for (key, value) in table {
+            let entries = match value {
+                toml::Value::Table(table) => table
+                    .into_iter()
+                    .map(|(name, value)| (format!("{}_{}", key, name), value))
+                    .collect(),
+                value => vec![(key, value)],
+            };
+            for (key, value) in entries {
+                let key = key.replace('-', "_");
+                if flat.contains_key(&key) {
+                    return Err(format!("duplicate key '{}'", key));
+                }
+                flat.insert(key, value);
+            }
+        }
+
+        flat.try_into()
+            .map_err(|e: toml::de::Error| e.to_string().trim_end().replace('\n', " "))
+    }
+
+    /// Values of the settings set in the file, as text by variable name.
+    fn values(&self) -> Result<HashMap<String, String>, String> {
+        let table = toml::Table::try_from(self).map_err(|e| e.to_string())?;
+
+        Ok(table
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    toml::Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                (format!("DAV_{}", key.to_uppercase()), value)
+            })
+            .collect())
+    }
+}
+
+/// Where the settings are read from: the command-line flags, the environment
+/// and the configuration file, in that order.
+struct Settings {
+    file: Option<PathBuf>,
+    /// Values of the configuration file, by variable.
+    values: HashMap<String, String>,
+    warnings: Vec<String>,
+    /// Arguments of the command line, without the program name.
+    args: Vec<String>,
+}
+
+impl Settings {
+    /// Reads the configuration file given by `--config` or `DAV_CONFIG`, which
+    /// must exist, else the default one if it exists.
+    fn load() -> Result<Self, String> {
+        let mut settings = Settings {
+            file: None,
+            values: HashMap::new(),
+            warnings: Vec::new(),
+            args: env::args().skip(1).collect(),
+        };
+
+        let file = match settings.arg("--config", "DAV_CONFIG")? {
+            Some(file) => PathBuf::from(file),
+            None => {
+                let Some(dirs) = ProjectDirs::from("", "", "dav") else {
+                    return Ok(settings);
+                };
+                let file = dirs.config_dir().join("config.toml");
+                if !file.exists() {
+                    return Ok(settings);
+                }
+                file
+            }
+        };
+
+        let text = fs::read_to_string(&file)
+            .map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+        let config = FileConfig::parse(&text)
+            .map_err(|e| format!("invalid {}: {}", file.display(), e.trim_end()))?;
+        for key in config.unknown.keys() {
+            settings.warnings.push(format!(
+                "unknown key '{}' in {} ignored",
+                key,
+                file.display()
+            ));
+        }
+        settings.values = config
+            .values()
+            .map_err(|e| format!("invalid {}: {}", file.display(), e))?;
+        settings.file = Some(file);
+
+        Ok(settings)
+    }
+
+    /// Reads a variable from the environment, then from the file.
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(name)
+            .ok()
+            .or_else(|| self.values.get(name).cloned())
+    }
+
+    /// Names a variable after where it is read from, for error messages.
+    fn origin(&self, name: &str) -> String {
+        match &self.file {
+            Some(file) if env::var_os(name).is_none() && self.values.contains_key(name) => {
+                format!(
+                    "{} in {}",
+                    name.trim_start_matches("DAV_").to_lowercase(),
+                    file.display()
+                )
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// Reads and parses a variable, unset meaning `None`.
+    fn parse<T>(&self, name: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.var(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| format!("invalid {} '{}': {}", self.origin(name), value, e))
+            })
+            .transpose()
+    }
+
+    /// Reads a boolean variable, `true`/`1` or `false`/`0`, unset meaning
+    /// `false`.
+    fn flag(&self, name: &str) -> Result<bool, String> {
+        match self.var(name).as_deref() {
+            None | Some("false") | Some("0") => Ok(false),
+            Some("true") | Some("1") => Ok(true),
+            Some(other) => Err(format!("invalid {} '{}'", self.origin(name), other)),
+        }
+    }
+
+    /// Whether the `--<name>` flag is on the command line.
+    fn has_flag(&self, flag: &str) -> bool {
+        self.args.iter().any(|arg| arg == flag)
+    }
+
+    /// Reads a setting from the `--<name> <value>` or `--<name>=<value>` flag,
+    /// then from the `var` variable.
+    fn arg(&self, flag: &str, var: &str) -> Result<Option<String>, String> {
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            if arg == flag {
+                return args
+                    .next()
+                    .cloned()
+                    .map(Some)
+                    .ok_or_else(|| format!("{} requires a value", flag));
+            }
+
+            if let Some(value) = arg
+                .strip_prefix(flag)
+                .and_then(|rest| rest.strip_prefix('='))
+            {
+                return Ok(Some(value.to_string()));
+            }
+        }
+
+        Ok(self.var(var))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_keys_at_the_top_and_in_tables() {
+        let config = FileConfig::parse(
+            r#"
+listen = "0.0.0.0:8080"
+data_dir = "/srv/dav" # comment
+quota = 10_000_000
+soft_delete = true
+socket_mode = 660
+vcard_version = 4.0
+
+[backup]
+interval_hours = 24
+retention = 7
+
+[log]
+level = 'debug'
+"#,
+        )
+        .unwrap();
+        assert!(config.unknown.is_empty());
+
+        let values = config.values().unwrap();
+        let value = |name: &str| values.get(name).map(String::as_str);
+        assert_eq!(value("DAV_LISTEN"), Some("0.0.0.0:8080"));
+        assert_eq!(value("DAV_DATA_DIR"), Some("/srv/dav"));
+        assert_eq!(value("DAV_QUOTA"), Some("10000000"));
+        assert_eq!(value("DAV_SOFT_DELETE"), Some("true"));
+        assert_eq!(value("DAV_SOCKET_MODE"), Some("660"));
+        assert_eq!(value("DAV_VCARD_VERSION"), Some("4.0"));
+        assert_eq!(value("DAV_BACKUP_INTERVAL_HOURS"), Some("24"));
+        assert_eq!(value("DAV_BACKUP_RETENTION"), Some("7"));
+        assert_eq!(value("DAV_LOG_LEVEL"), Some("debug"));
+        assert_eq!(values.len(), 9);
+    }
+
+    #[test]
+    fn reserves_the_names_used_by_the_server() {
+        for name in ["books", "backups", "users"] {
+            assert_eq!(
+                check_collection_name(name),
+                Err(format!("'{}' is used by the server", name))
+            );
+        }
+        assert!(check_collection_name("work").is_ok());
+    }
+
+    #[test]
+    fn collects_unknown_keys() {
+        let config = FileConfig::parse(
+            r#"
+listen = "127.0.0.1:3000"
+colour = "blue"
+
+[backup]
+retention = 3
+weekday = "sunday"
+"#,
+        )
+        .unwrap();
+
+        let unknown: Vec<&str> = config.unknown.keys().map(String::as_str).collect();
+        assert_eq!(unknown, ["backup_weekday", "colour"]);
+        assert_eq!(config.values().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let e = FileConfig::parse("quota = \"lots\"").err().unwrap();
+        assert!(e.contains("quota"), "{}", e);
+
+        let e = FileConfig::parse("soft_delete = 1").err().unwrap();
+        assert!(e.contains("soft_delete"), "{}", e);
+
+        let e = FileConfig::parse("backup_retention = 7\n[backup]\nretention = 8")
+            .err()
+            .unwrap();
+        assert!(e.contains("duplicate key 'backup_retention'"), "{}", e);
+
+        let e = FileConfig::parse("listen = \"127.0.0.1:3000\"\nlisten = \"[::1]:3000\"")
+            .err()
+            .unwrap();
+        assert!(e.contains("listen"), "{}", e);
+
+        assert!(FileConfig::parse("listen = 127.0.0.1:3000").is_err());
+        assert!(FileConfig::parse("[backup").is_err());
+    }
+
+    #[test]
+    fn flags_take_over_the_environment_and_the_file() {
+        // Only used by this test, so the environment is not shared.
+        const VAR: &str = "DAV_TEST_PRECEDENCE";
+        let mut settings = Settings {
+            file: Some(PathBuf::from("config.toml")),
+            values: HashMap::from([(VAR.to_string(), "file".to_string())]),
+            warnings: Vec::new(),
+            args: Vec::new(),
+        };
+        assert_eq!(
+            settings.arg("--test", VAR).unwrap().as_deref(),
+            Some("file")
+        );
+        assert_eq!(settings.origin(VAR), "test_precedence in config.toml");
+
+        env::set_var(VAR, "env");
+        assert_eq!(settings.arg("--test", VAR).unwrap().as_deref(), Some("env"));
+        assert_eq!(settings.origin(VAR), VAR);
+
+        settings.args = vec!["serve".to_string(), "--test=flag".to_string()];
+        assert_eq!(
+            settings.arg("--test", VAR).unwrap().as_deref(),
+            Some("flag")
+        );
+        settings.args = vec!["--test".to_string(), "flag".to_string()];
+        assert_eq!(
+            settings.arg("--test", VAR).unwrap().as_deref(),
+            Some("flag")
+        );
+        env::remove_var(VAR);
+
+        settings.args = vec!["--test".to_string()];
+        assert!(settings.arg("--test", VAR).is_err());
+        assert!(settings.has_flag("--test"));
+        assert!(!settings.has_flag("--other"));
+    }
+}
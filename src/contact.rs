@@ -0,0 +1,1069 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use crate::store::check_id;
+
+/// vCard version written when serializing contacts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VCardVersion {
+    /// RFC 2426, for older clients. `KIND` and `MEMBER` are written as the
+    /// `X-ADDRESSBOOKSERVER-` properties understood by those clients.
+    V3,
+    /// RFC 6350.
+    #[default]
+    V4,
+}
+
+impl VCardVersion {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VCardVersion::V3 => "3.0",
+            VCardVersion::V4 => "4.0",
+        }
+    }
+}
+
+impl FromStr for VCardVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match version {
+            "3.0" => Ok(VCardVersion::V3),
+            "4.0" => Ok(VCardVersion::V4),
+            _ => Err(format!("unsupported vCard version '{}'", version)),
+        }
+    }
+}
+
+static VERSION: OnceLock<VCardVersion> = OnceLock::new();
+
+/// Sets the vCard version of every serialized contact, once on startup. 4.0
+/// is used until then.
+pub fn set_version(version: VCardVersion) {
+    let _ = VERSION.set(version);
+}
+
+fn version() -> VCardVersion {
+    VERSION.get().copied().unwrap_or_default()
+}
+
+/// A contact, serialized to JSON with its fields in the order of
+/// [`Contact::FIELDS`] so responses are stable.
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+pub struct Contact {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Homepage or profile, an absolute `http` or `https` URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// `individual`, `group`, `org` or `location`, an individual when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// URIs of the members of a group, usually `urn:uuid:` followed by a UID.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+    /// Instant messaging handles, URIs such as `xmpp:alice@example.com`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub impp: Vec<String>,
+    /// Value of the vCard 4.0 `GENDER`, a sex component of `M`, `F`, `O`, `N`
+    /// or `U`, possibly empty, and an optional free-text gender identity after
+    /// a `;`, such as `F;woman`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+    /// Time zone of the `TZ`, a UTC offset such as `-05:00` or a name such as
+    /// `America/New_York`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+    /// Value of the `NICKNAME`, several nicknames being separated by commas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// Date of the `BDAY`, such as `1990-04-12`, `19900412` or, without year,
+    /// `--0412`. A `BDAY` given as text is kept with the other properties.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birthday: Option<String>,
+    /// Revision timestamp, refreshed on every update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Group of the primary email, such as `item1` in `item1.EMAIL`, which ties
+    /// it to other properties of the group like an `X-ABLabel`.
+    #[serde(skip)]
+    pub email_group: Option<String>,
+    /// Group of the primary phone number.
+    #[serde(skip)]
+    pub phone_group: Option<String>,
+    /// Properties without a field of their own, kept verbatim so they survive
+    /// a round-trip.
+    #[serde(skip)]
+    pub extras: Vec<String>,
+}
+
+impl Contact {
+    /// Names of the fields exposed through the JSON API.
+    pub const FIELDS: &'static [&'static str] = &[
+        "id",
+        "name",
+        "email",
+        "phone",
+        "categories",
+        "url",
+        "kind",
+        "members",
+        "impp",
+        "gender",
+        "tz",
+        "nickname",
+        "birthday",
+        "rev",
+    ];
+
+    /// Sets the revision to the current UTC time.
+    pub fn touch(&mut self) {
+        self.rev = Some(Utc::now().format("%Y%m%dT%H%M%SZ").to_string());
+    }
+
+    /// Returns every email address, starting with the primary one.
+    pub fn emails(&self) -> Vec<&str> {
+        values(&self.email, &self.extras, "EMAIL")
+    }
+
+    /// Returns every phone number, starting with the primary one.
+    pub fn phones(&self) -> Vec<&str> {
+        values(&self.phone, &self.extras, "TEL")
+    }
+
+    /// Returns why the contact cannot be stored, if it cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        check_id(&self.id)?;
+
+        if let Some(url) = &self.url {
+            let valid = Url::parse(url)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+            if !valid {
+                return Err(format!(
+                    "invalid URL '{}', expected an http or https URL",
+                    url
+                ));
+            }
+        }
+
+        for impp in &self.impp {
+            if Url::parse(impp).is_err() {
+                return Err(format!(
+                    "invalid IMPP '{}', expected a URI such as xmpp:alice@example.com",
+                    impp
+                ));
+            }
+        }
+
+        if let Some(gender) = &self.gender {
+            let sex = gender.split(';').next().unwrap_or_default();
+            if !matches!(sex, "" | "M" | "F" | "O" | "N" | "U") {
+                return Err(format!(
+                    "invalid gender '{}', expected M, F, O, N or U, optionally followed by ';' and an identity",
+                    gender
+                ));
+            }
+        }
+
+        if let Some(tz) = &self.tz {
+            let valid = if tz.starts_with(['+', '-']) {
+                utc_offset(tz).is_some()
+            } else {
+                !tz.is_empty() && !tz.contains(|c: char| c.is_control())
+            };
+            if !valid {
+                return Err(format!(
+                    "invalid time zone '{}', expected a UTC offset such as -05:00 or a name such as America/New_York",
+                    tz
+                ));
+            }
+        }
+
+        if let Some(birthday) = &self.birthday {
+            if birthday.parse::<Birthday>().is_err() {
+                return Err(format!(
+                    "invalid birthday '{}', expected a date such as 1990-04-12 or 19900412",
+                    birthday
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the date of the birthday, if the contact has a valid one.
+    pub fn birth_date(&self) -> Option<Birthday> {
+        self.birthday.as_ref()?.parse().ok()
+    }
+
+    /// Whether the contact is of the given kind, contacts without a `KIND`
+    /// being individuals.
+    pub fn is_kind(&self, kind: &str) -> bool {
+        self.kind
+            .as_deref()
+            .unwrap_or("individual")
+            .eq_ignore_ascii_case(kind)
+    }
+
+    /// Merges `other` into the contact: emails, phones, categories, members,
+    /// instant messaging handles and the other properties are combined, while
+    /// the name, URL, kind, gender, time zone, nickname and birthday are only
+    /// taken from `other` when the contact has none.
+    pub fn merge(&mut self, other: Contact) {
+        if self.name.is_empty() {
+            self.name = other.name;
+        }
+
+        if self.kind.is_none() {
+            self.kind = other.kind;
+        }
+
+        if self.url.is_none() {
+            self.url = other.url;
+        }
+
+        if self.gender.is_none() {
+            self.gender = other.gender;
+        }
+
+        if self.tz.is_none() {
+            self.tz = other.tz;
+        }
+
+        if self.nickname.is_none() {
+            self.nickname = other.nickname;
+        }
+
+        if self.birthday.is_none() {
+            self.birthday = other.birthday;
+        }
+
+        for member in other.members {
+            if !self.members.contains(&member) {
+                self.members.push(member);
+            }
+        }
+
+        for impp in other.impp {
+            if !self.impp.contains(&impp) {
+                self.impp.push(impp);
+            }
+        }
+
+        add_value(&mut self.email, &mut self.extras, "EMAIL", other.email);
+        add_value(&mut self.phone, &mut self.extras, "TEL", other.phone);
+
+        for line in other.extras {
+            let known = match line.split_once(':') {
+                Some((property, value)) => match property_name(property).as_str() {
+                    "EMAIL" => self.emails().contains(&value),
+                    "TEL" => self.phones().contains(&value),
+                    _ => self.extras.contains(&line),
+                },
+                None => self.extras.contains(&line),
+            };
+
+            if !known {
+                self.extras.push(line);
+            }
+        }
+
+        for category in other.categories {
+            if !self.categories.contains(&category) {
+                self.categories.push(category);
+            }
+        }
+    }
+}
+
+/// A birthday, of which the year may be unknown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Birthday {
+    pub year: Option<i32>,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl FromStr for Birthday {
+    type Err = String;
+
+    /// Parses a `BDAY` date such as `19900412`, `1990-04-12` or, without year,
+    /// `--0412`. The time of day of a date with time is ignored.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let date = value.split('T').next().unwrap_or_default();
+        let (year, month_day) = match date.strip_prefix("--") {
+            Some(month_day) => (None, month_day.replace('-', "")),
+            None => {
+                let date = date.replace('-', "");
+                let (year, month_day) = date.split_at_checked(4).unwrap_or_default();
+                (Some(year.to_string()), month_day.to_string())
+            }
+        };
+
+        let invalid = || format!("invalid BDAY '{}'", value);
+        let digits =
+            |value: &str, len| value.len() == len && value.bytes().all(|b| b.is_ascii_digit());
+        if !digits(&month_day, 4) || year.as_ref().is_some_and(|year| !digits(year, 4)) {
+            return Err(invalid());
+        }
+
+        let (month, day) = month_day.split_at(2);
+        let birthday = Birthday {
+            year: year
+                .map(|year| year.parse().map_err(|_| invalid()))
+                .transpose()?,
+            month: month.parse().map_err(|_| invalid())?,
+            day: day.parse().map_err(|_| invalid())?,
+        };
+
+        // February 29 is only valid in leap years.
+        NaiveDate::from_ymd_opt(birthday.year.unwrap_or(2000), birthday.month, birthday.day)
+            .ok_or_else(invalid)?;
+
+        Ok(birthday)
+    }
+}
+
+/// Returns the name of a property without its group and parameters, in
+/// uppercase: `EMAIL` for `item1.email;TYPE=work`.
+pub fn property_name(property: &str) -> String {
+    let name = property.split(';').next().unwrap_or_default();
+    name.split_once('.')
+        .map_or(name, |(_, name)| name)
+        .to_ascii_uppercase()
+}
+
+/// Whether a property has a `VALUE=text` parameter, as a `BDAY` that is not a
+/// date.
+fn is_text(property: &str) -> bool {
+    property.split(';').skip(1).any(|parameter| {
+        parameter.split_once('=').is_some_and(|(key, value)| {
+            key.eq_ignore_ascii_case("VALUE") && value.eq_ignore_ascii_case("text")
+        })
+    })
+}
+
+/// Returns the group of a property, `item1` for `item1.EMAIL`, if it has one.
+pub fn property_group(property: &str) -> Option<&str> {
+    let name = property.split(';').next().unwrap_or_default();
+    name.split_once('.').map(|(group, _)| group)
+}
+
+/// Prefix written before a property of the given group.
+fn group_prefix(group: &Option<String>) -> String {
+    group
+        .as_ref()
+        .map(|group| format!("{}.", group))
+        .unwrap_or_default()
+}
+
+/// Returns the non-empty values of a property, from its field and extra lines.
+fn values<'a>(primary: &'a str, extras: &'a [String], property: &str) -> Vec<&'a str> {
+    let extra_values = extras.iter().filter_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        (property_name(name) == property).then_some(value)
+    });
+
+    std::iter::once(primary)
+        .chain(extra_values)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Adds a value to a property, filling its field first, then as an extra line.
+fn add_value(primary: &mut String, extras: &mut Vec<String>, property: &str, value: String) {
+    if value.is_empty() || values(primary, extras, property).contains(&value.as_str()) {
+        return;
+    }
+
+    if primary.is_empty() {
+        *primary = value;
+    } else {
+        extras.push(format!("{}:{}", property, value));
+    }
+}
+
+impl FromStr for Contact {
+    type Err = String;
+
+    fn from_str(vcard: &str) -> Result<Self, Self::Err> {
+        let mut id = None;
+        let mut name = None;
+        let mut email = None;
+        let mut phone = None;
+        let mut categories = Vec::new();
+        let mut url = None;
+        let mut kind = None;
+        let mut members = Vec::new();
+        let mut impp = Vec::new();
+        let mut gender = None;
+        let mut tz = None;
+        let mut nickname = None;
+        let mut birthday = None;
+        let mut rev = None;
+        let mut email_group = None;
+        let mut phone_group = None;
+        let mut extras = Vec::new();
+
+        for line in unfold(vcard) {
+            let Some((property, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            // Property names are case-insensitive, and parameters are dropped
+            // from the properties mapped to fields. The group of the email
+            // and phone is kept, Apple clients label them through it.
+            let value = value.to_string();
+            let group = property_group(property).map(str::to_string);
+
+            match property_name(property).as_str() {
+                "BEGIN" | "END" | "VERSION" => {}
+                "ID" | "UID" if id.is_none() => id = Some(value),
+                "FN" if name.is_none() => name = Some(value),
+                "EMAIL" if email.is_none() => {
+                    email = Some(value);
+                    email_group = group;
+                }
+                "TEL" if phone.is_none() => {
+                    phone = Some(value);
+                    phone_group = group;
+                }
+                "CATEGORIES" => categories.extend(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|category| !category.is_empty())
+                        .map(str::to_string),
+                ),
+                "URL" if url.is_none() => url = Some(value),
+                "KIND" | "X-ADDRESSBOOKSERVER-KIND" if kind.is_none() => {
+                    kind = Some(value.to_ascii_lowercase())
+                }
+                "MEMBER" | "X-ADDRESSBOOKSERVER-MEMBER" => members.push(value),
+                "IMPP" => impp.push(value),
+                "GENDER" if gender.is_none() => gender = Some(value),
+                "TZ" if tz.is_none() => tz = Some(value),
+                "NICKNAME" if nickname.is_none() => nickname = Some(value),
+                "BDAY" if birthday.is_none() && !is_text(property) => birthday = Some(value),
+                "REV" => rev = Some(value),
+                // Duplicated UIDs from files that have both an `ID` and a
+                // `UID` line.
+                "ID" | "UID" => {}
+                _ => extras.push(line),
+            }
+        }
+
+        match (id.as_ref(), name.as_ref(), email.as_ref(), phone.as_ref()) {
+            (None, None, None, None) => Err("contact is empty".to_string()),
+            (None, _, _, _) => Err("contact ID is empty".to_string()),
+            _ => Ok(Contact {
+                id: id.unwrap_or_default(),
+                name: name.unwrap_or_default(),
+                email: email.unwrap_or_default(),
+                phone: phone.unwrap_or_default(),
+                categories,
+                url,
+                kind,
+                members,
+                impp,
+                gender,
+                tz,
+                nickname,
+                birthday,
+                rev,
+                email_group,
+                phone_group,
+                extras,
+            }),
+        }
+    }
+}
+
+/// Serializes the contact with the CRLF line endings required by RFC 6350, in
+/// the version set with [`set_version`].
+impl fmt::Display for Contact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version = version();
+        let (kind_property, member_property) = match version {
+            VCardVersion::V3 => ("X-ADDRESSBOOKSERVER-KIND", "X-ADDRESSBOOKSERVER-MEMBER"),
+            VCardVersion::V4 => ("KIND", "MEMBER"),
+        };
+
+        write!(
+            f,
+            "BEGIN:VCARD\r\nVERSION:{}\r\nUID:{}\r\n",
+            version.as_str(),
+            self.id
+        )?;
+
+        if let Some(kind) = &self.kind {
+            write!(f, "{}:{}\r\n", kind_property, kind)?;
+        }
+
+        write!(
+            f,
+            "FN:{}\r\n{}EMAIL:{}\r\n{}TEL:{}\r\n",
+            self.name,
+            group_prefix(&self.email_group),
+            self.email,
+            group_prefix(&self.phone_group),
+            self.phone
+        )?;
+
+        if let Some(url) = &self.url {
+            write!(f, "URL:{}\r\n", url)?;
+        }
+
+        if !self.categories.is_empty() {
+            write!(f, "CATEGORIES:{}\r\n", self.categories.join(","))?;
+        }
+
+        for member in &self.members {
+            write!(f, "{}:{}\r\n", member_property, member)?;
+        }
+
+        for impp in &self.impp {
+            write!(f, "IMPP:{}\r\n", impp)?;
+        }
+
+        if let Some(gender) = &self.gender {
+            write!(f, "GENDER:{}\r\n", gender)?;
+        }
+
+        if let Some(tz) = &self.tz {
+            write!(f, "TZ:{}\r\n", tz)?;
+        }
+
+        if let Some(nickname) = &self.nickname {
+            write!(f, "NICKNAME:{}\r\n", nickname)?;
+        }
+
+        if let Some(birthday) = &self.birthday {
+            write!(f, "BDAY:{}\r\n", birthday)?;
+        }
+
+        if let Some(rev) = &self.rev {
+            write!(f, "REV:{}\r\n", rev)?;
+        }
+
+        for extra in &self.extras {
+            write!(f, "{}\r\n", extra)?;
+        }
+
+        write!(f, "END:VCARD\r\n")
+    }
+}
+
+impl Contact {
+    /// Converts the contact to jCard (RFC 7095), the JSON form of vCard 4.0:
+    /// `["vcard", [[name, parameters, type, value...], ...]]`. Text values are
+    /// unescaped, and structured ones such as `N` become arrays.
+    pub fn to_jcard(&self) -> Value {
+        let mut properties = vec![
+            json!(["version", {}, "text", "4.0"]),
+            json!(["uid", {}, "text", self.id]),
+        ];
+
+        if let Some(kind) = &self.kind {
+            properties.push(json!(["kind", {}, "text", kind]));
+        }
+
+        properties.push(json!(["fn", {}, "text", unescape(&self.name)]));
+        properties.push(json!([
+            "email",
+            group_parameters(&self.email_group),
+            "text",
+            unescape(&self.email)
+        ]));
+        properties.push(json!([
+            "tel",
+            group_parameters(&self.phone_group),
+            tel_type(&self.phone),
+            self.phone
+        ]));
+
+        if let Some(url) = &self.url {
+            properties.push(json!(["url", {}, "uri", url]));
+        }
+
+        if !self.categories.is_empty() {
+            let mut categories = vec![json!("categories"), json!({}), json!("text")];
+            categories.extend(
+                self.categories
+                    .iter()
+                    .map(|category| json!(unescape(category))),
+            );
+            properties.push(Value::Array(categories));
+        }
+
+        for member in &self.members {
+            properties.push(json!(["member", {}, "uri", member]));
+        }
+
+        for impp in &self.impp {
+            properties.push(json!(["impp", {}, "uri", impp]));
+        }
+
+        if let Some(gender) = &self.gender {
+            properties.push(json!(["gender", {}, "text", structured(gender)]));
+        }
+
+        if let Some(tz) = &self.tz {
+            properties.push(match utc_offset(tz) {
+                Some(offset) => json!(["tz", {}, "utc-offset", offset]),
+                None => json!(["tz", {}, "text", unescape(tz)]),
+            });
+        }
+
+        if let Some(nickname) = &self.nickname {
+            let mut property = vec![json!("nickname"), json!({}), json!("text")];
+            property.extend(
+                split_unescaped(nickname, ',')
+                    .iter()
+                    .map(|nickname| json!(unescape(nickname))),
+            );
+            properties.push(Value::Array(property));
+        }
+
+        if let Some(birthday) = &self.birthday {
+            properties.push(json!([
+                "bday",
+                {},
+                "date-and-or-time",
+                jcard_date(birthday)
+            ]));
+        }
+
+        if let Some(rev) = &self.rev {
+            properties.push(json!(["rev", {}, "timestamp", jcard_timestamp(rev)]));
+        }
+
+        properties.extend(self.extras.iter().filter_map(|line| jcard_property(line)));
+
+        json!(["vcard", properties])
+    }
+}
+
+/// Converts an extra vCard line to a jCard property, `None` if it is not a
+/// property.
+fn jcard_property(line: &str) -> Option<Value> {
+    let (head, value) = line.split_once(':')?;
+    let name = property_name(head).to_ascii_lowercase();
+
+    let mut parameters = Map::new();
+    if let Some(group) = property_group(head) {
+        parameters.insert("group".to_string(), json!(group.to_ascii_lowercase()));
+    }
+
+    let mut value_type = None;
+    for parameter in head.split(';').skip(1) {
+        let (key, values) = match parameter.split_once('=') {
+            Some((key, values)) => (key.to_ascii_lowercase(), values),
+            // vCard 2.1 shorthands such as `TEL;CELL`.
+            None => ("type".to_string(), parameter),
+        };
+        let values: Vec<String> = values
+            .split(',')
+            .map(|value| value.trim_matches('"').to_string())
+            .collect();
+
+        if key == "value" {
+            value_type = values.first().map(|value| value.to_ascii_lowercase());
+        } else if let [value] = values.as_slice() {
+            parameters.insert(key, json!(value));
+        } else {
+            parameters.insert(key, json!(values));
+        }
+    }
+
+    let value_type = value_type.unwrap_or_else(|| match name.as_str() {
+        "tel" => tel_type(value).to_string(),
+        "url" | "photo" | "logo" | "sound" | "source" | "key" | "impp" | "member" | "related"
+        | "caladruri" | "caluri" | "fburl" | "geo" => "uri".to_string(),
+        "bday" | "anniversary" => "date-and-or-time".to_string(),
+        "rev" => "timestamp".to_string(),
+        _ if name.starts_with("x-") => "unknown".to_string(),
+        _ => "text".to_string(),
+    });
+
+    let mut property = vec![json!(name), Value::Object(parameters), json!(value_type)];
+    match name.as_str() {
+        "n" | "adr" | "org" | "gender" | "clientpidmap" => property.push(structured(value)),
+        "categories" | "nickname" => property.extend(
+            split_unescaped(value, ',')
+                .iter()
+                .map(|value| json!(unescape(value))),
+        ),
+        _ if value_type == "text" => property.push(json!(unescape(value))),
+        _ => property.push(json!(value)),
+    }
+
+    Some(Value::Array(property))
+}
+
+/// Reads a UTC offset written `-05:00`, `-0500` or `-05`, returning it in the
+/// extended form of jCard.
+fn utc_offset(value: &str) -> Option<String> {
+    if !value.is_ascii() {
+        return None;
+    }
+    let sign = value.chars().next().filter(|c| matches!(c, '+' | '-'))?;
+    let rest = &value[1..];
+    let (hours, minutes) = match rest.len() {
+        2 => (rest, "00"),
+        4 => rest.split_at(2),
+        5 if &rest[2..3] == ":" => (&rest[..2], &rest[3..]),
+        _ => return None,
+    };
+    if !(hours.bytes().chain(minutes.bytes())).all(|b| b.is_ascii_digit())
+        || hours > "14"
+        || minutes > "59"
+    {
+        return None;
+    }
+
+    Some(format!("{}{}:{}", sign, hours, minutes))
+}
+
+/// Parameters of a property in a group.
+fn group_parameters(group: &Option<String>) -> Value {
+    match group {
+        Some(group) => json!({ "group": group.to_ascii_lowercase() }),
+        None => json!({}),
+    }
+}
+
+/// Value type of a phone number, a URI with the `tel:` scheme or free text.
+fn tel_type(phone: &str) -> &'static str {
+    if phone.to_ascii_lowercase().starts_with("tel:") {
+        "uri"
+    } else {
+        "text"
+    }
+}
+
+/// A structured value such as `Doe;John;;;`, as an array of its components,
+/// or a single string when it has one component.
+fn structured(value: &str) -> Value {
+    let components: Vec<String> = split_unescaped(value, ';')
+        .iter()
+        .map(|component| unescape(component))
+        .collect();
+
+    match components.as_slice() {
+        [component] => json!(component),
+        _ => json!(components),
+    }
+}
+
+/// A timestamp such as `20240101T120000Z` in the extended form of jCard,
+/// `2024-01-01T12:00:00Z`. Other forms are kept as is.
+fn jcard_timestamp(timestamp: &str) -> String {
+    NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%SZ")
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+/// A date such as `19900412` or `--0412` in the extended form of jCard,
+/// `1990-04-12` or `--04-12`. Other forms are kept as is.
+fn jcard_date(date: &str) -> String {
+    let digits = |value: &str| value.bytes().all(|b| b.is_ascii_digit());
+    match date.strip_prefix("--") {
+        Some(month_day) if month_day.len() == 4 && digits(month_day) => {
+            format!("--{}-{}", &month_day[..2], &month_day[2..])
+        }
+        None if date.len() == 8 && digits(date) => {
+            format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..])
+        }
+        _ => date.to_string(),
+    }
+}
+
+/// Splits a value on a separator that is not escaped with a backslash.
+fn split_unescaped(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == separator => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts
+}
+
+/// Removes the vCard escaping of a text value.
+fn unescape(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n' | 'N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+
+    text
+}
+
+/// Joins the folded lines of a vCard, which continue with a space or a tab.
+/// Lines may end with CRLF, LF or a lone CR, none of which is kept in values.
+pub fn unfold(vcard: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in vcard.split(['\r', '\n']).filter(|line| !line.is_empty()) {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(previous)) => previous.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    lines
+}
+
+/// Splits a raw vCard stream into its individual cards, rejecting input that
+/// is not UTF-8 instead of decoding it lossily.
+pub fn parse_vcard_bytes(input: &[u8]) -> Result<Vec<String>, String> {
+    let input =
+        std::str::from_utf8(input).map_err(|e| format!("vCard data is not valid UTF-8: {}", e))?;
+
+    Ok(split_vcards(input))
+}
+
+/// Splits a vCard stream into its individual cards.
+pub fn split_vcards(input: &str) -> Vec<String> {
+    let mut cards = Vec::new();
+    let mut current: Option<String> = None;
+
+    for line in input.lines() {
+        if line.trim().eq_ignore_ascii_case("BEGIN:VCARD") {
+            current = Some(String::new());
+        }
+
+        if let Some(card) = current.as_mut() {
+            card.push_str(line);
+            card.push_str("\r\n");
+
+            if line.trim().eq_ignore_ascii_case("END:VCARD") {
+                cards.extend(current.take());
+            }
+        }
+    }
+
+    cards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn property_names_are_case_insensitive() {
+        let contact: Contact = "BEGIN:VCARD\r\nversion:4.0\r\nuid:alice\r\nFn:Alice\r\n\
+                                email;type=work:alice@example.com\r\nRev:20240101T000000Z\r\n\
+                                END:VCARD\r\n"
+            .parse()
+            .unwrap();
+
+        assert_eq!(contact.id, "alice");
+        assert_eq!(contact.name, "Alice");
+        assert_eq!(contact.email, "alice@example.com");
+        assert_eq!(contact.rev.as_deref(), Some("20240101T000000Z"));
+        assert!(contact.extras.is_empty());
+    }
+
+    #[test]
+    fn touch_sets_the_revision() {
+        let mut contact = Contact {
+            id: "alice".to_string(),
+            ..Contact::default()
+        };
+        contact.touch();
+
+        let rev = contact.rev.clone().unwrap();
+        assert!(NaiveDateTime::parse_from_str(&rev, "%Y%m%dT%H%M%SZ").is_ok());
+        let parsed: Contact = contact.to_string().parse().unwrap();
+        assert_eq!(parsed.rev, Some(rev));
+    }
+
+    #[test]
+    fn nickname_and_birthday_round_trip() {
+        for bday in ["1990-04-12", "19900412", "--0412"] {
+            let vcard = format!(
+                "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:alice\r\nFN:Alice\r\n\
+                 NICKNAME:Al,Ally\r\nBDAY:{}\r\nEND:VCARD\r\n",
+                bday
+            );
+            let contact: Contact = vcard.parse().unwrap();
+            assert_eq!(contact.nickname.as_deref(), Some("Al,Ally"));
+            assert_eq!(contact.birthday.as_deref(), Some(bday));
+            assert!(contact.extras.is_empty());
+            assert!(contact.validate().is_ok());
+
+            let serialized = contact.to_string();
+            assert!(serialized.contains("\r\nNICKNAME:Al,Ally\r\n"));
+            assert!(serialized.contains(&format!("\r\nBDAY:{}\r\n", bday)));
+            let parsed: Contact = serialized.parse().unwrap();
+            assert_eq!(parsed.nickname, contact.nickname);
+            assert_eq!(parsed.birthday, contact.birthday);
+        }
+    }
+
+    #[test]
+    fn birth_date_accepts_both_date_forms() {
+        let expected = Birthday {
+            year: Some(1990),
+            month: 4,
+            day: 12,
+        };
+        for bday in ["1990-04-12", "19900412"] {
+            let contact = Contact {
+                birthday: Some(bday.to_string()),
+                ..Contact::default()
+            };
+            assert_eq!(contact.birth_date(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn text_birthday_is_kept_as_written() {
+        let contact: Contact =
+            "BEGIN:VCARD\r\nUID:alice\r\nBDAY;VALUE=text:circa 1800\r\nEND:VCARD\r\n"
+                .parse()
+                .unwrap();
+
+        assert_eq!(contact.birthday, None);
+        assert_eq!(contact.extras, ["BDAY;VALUE=text:circa 1800"]);
+        assert!(contact.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_birthdays() {
+        for bday in ["12/04/1990", "1990-13-01", "19890229", "yesterday"] {
+            let contact = Contact {
+                id: "alice".to_string(),
+                birthday: Some(bday.to_string()),
+                ..Contact::default()
+            };
+            assert!(contact.validate().is_err(), "{}", bday);
+        }
+    }
+
+    #[test]
+    fn jcard_of_nickname_and_birthday() {
+        let contact = Contact {
+            id: "alice".to_string(),
+            nickname: Some("Al,Ally".to_string()),
+            birthday: Some("19900412".to_string()),
+            ..Contact::default()
+        };
+        let jcard = contact.to_jcard();
+        let properties = jcard[1].as_array().unwrap();
+
+        assert!(properties.contains(&json!(["nickname", {}, "text", "Al", "Ally"])));
+        assert!(properties.contains(&json!(["bday", {}, "date-and-or-time", "1990-04-12"])));
+    }
+
+    #[test]
+    fn utc_offset_round_trips() {
+        let contact: Contact = "BEGIN:VCARD\r\nUID:alice\r\nTZ:-05:00\r\nEND:VCARD\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(contact.tz.as_deref(), Some("-05:00"));
+        assert!(contact.validate().is_ok());
+
+        let serialized = contact.to_string();
+        assert!(serialized.contains("\r\nTZ:-05:00\r\n"));
+        let parsed: Contact = serialized.parse().unwrap();
+        assert_eq!(parsed.tz, contact.tz);
+
+        let jcard = contact.to_jcard();
+        let properties = jcard[1].as_array().unwrap();
+        assert!(properties.contains(&json!(["tz", {}, "utc-offset", "-05:00"])));
+    }
+
+    #[test]
+    fn rejects_non_ascii_utc_offsets() {
+        for tz in ["+é0", "+0é0", "-0é:0"] {
+            assert_eq!(utc_offset(tz), None, "{}", tz);
+
+            let contact = Contact {
+                id: "alice".to_string(),
+                tz: Some(tz.to_string()),
+                ..Contact::default()
+            };
+            assert!(contact.validate().is_err(), "{}", tz);
+            let jcard = contact.to_jcard();
+            let properties = jcard[1].as_array().unwrap();
+            assert!(properties.contains(&json!(["tz", {}, "text", tz])));
+        }
+    }
+
+    #[test]
+    fn parses_crlf_cards_without_carriage_returns() {
+        let vcard = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:alice\r\nFN:Alice\r\n Liddell\r\n\
+                     EMAIL:alice@example.com\r\nTEL:+1 555 0100\r\nNOTE:first\r\nEND:VCARD\r\n";
+        let contact: Contact = vcard.parse().unwrap();
+
+        assert_eq!(contact.id, "alice");
+        assert_eq!(contact.name, "AliceLiddell");
+        assert_eq!(contact.email, "alice@example.com");
+        assert_eq!(contact.phone, "+1 555 0100");
+        assert_eq!(contact.extras, ["NOTE:first"]);
+    }
+
+    #[test]
+    fn serializes_with_crlf_line_endings() {
+        let contact: Contact = "BEGIN:VCARD\nUID:alice\nFN:Alice\nNOTE:first\nEND:VCARD\n"
+            .parse()
+            .unwrap();
+        let serialized = contact.to_string();
+
+        assert!(serialized.ends_with("END:VCARD\r\n"));
+        assert_eq!(
+            serialized.matches('\n').count(),
+            serialized.matches("\r\n").count()
+        );
+    }
+
+    #[test]
+    fn splits_crlf_streams() {
+        let cards = split_vcards(
+            "BEGIN:VCARD\r\nUID:alice\r\nEND:VCARD\r\nBEGIN:VCARD\r\nUID:bob\r\nEND:VCARD\r\n",
+        );
+
+        assert_eq!(
+            cards,
+            [
+                "BEGIN:VCARD\r\nUID:alice\r\nEND:VCARD\r\n",
+                "BEGIN:VCARD\r\nUID:bob\r\nEND:VCARD\r\n"
+            ]
+        );
+    }
+}
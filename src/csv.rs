@@ -0,0 +1,48 @@
+use serde_json::Value;
+
+use crate::contact::Contact;
+
+/// Writes contacts as CSV (RFC 4180), one column per field of
+/// [`Contact::FIELDS`] after a header row. Lists such as the categories are
+/// joined with commas.
+pub fn write(contacts: &[Contact]) -> String {
+    let mut csv = String::new();
+    write_row(
+        &mut csv,
+        Contact::FIELDS.iter().map(|field| field.to_string()),
+    );
+
+    for contact in contacts {
+        let fields = serde_json::json!(contact);
+        write_row(
+            &mut csv,
+            Contact::FIELDS.iter().map(|field| match &fields[*field] {
+                Value::String(value) => value.clone(),
+                Value::Array(values) => values
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(","),
+                _ => String::new(),
+            }),
+        );
+    }
+
+    csv
+}
+
+fn write_row(csv: &mut String, cells: impl Iterator<Item = String>) {
+    let cells: Vec<String> = cells.map(|cell| escape(&cell)).collect();
+    csv.push_str(&cells.join(","));
+    csv.push_str("\r\n");
+}
+
+/// Quotes a cell holding a separator, a quote or a line break, doubling its
+/// quotes.
+fn escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
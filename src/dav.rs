@@ -0,0 +1,2177 @@
+mod calendar;
+mod lock;
+mod query;
+mod xml;
+
+pub use self::calendar::calendar;
+pub use self::lock::Locks;
+
+use std::fmt;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path as AxumPath, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use self::lock::{Lock, Scope};
+use self::query::Filter;
+use self::xml::{Element, Multistatus, Propstat, CALENDARSERVER_NS, CARDDAV_NS, DAV_NS};
+use crate::auth;
+use crate::books::{AddressBook, Book, BookError, DeadProperty, DEFAULT_BOOK};
+use crate::changes::Operation;
+use crate::contact::Contact;
+use crate::proxy;
+use crate::store::{check_id, StoreError};
+use crate::{changes_since, record_change, record_revision, write_error, AppState, ContactPath};
+
+/// Prefix of the sync tokens handed out to DAV clients, followed by the change
+/// log sequence number.
+const SYNC_TOKEN_PREFIX: &str = "http://dav/ns/sync/";
+
+/// Characters escaped when building an href out of a contact ID.
+const HREF_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Methods allowed on the server root.
+const ROOT_METHODS: &[&str] = &["OPTIONS", "PROPFIND"];
+
+/// Methods allowed on the principal.
+const DISCOVERY_METHODS: &[&str] = &["OPTIONS", "PROPFIND"];
+
+/// Methods allowed on the address book home, which also lists and creates
+/// address books through the JSON API.
+const HOME_METHODS: &[&str] = &["GET", "HEAD", "POST", "OPTIONS", "PROPFIND"];
+
+/// Methods allowed on the path of an address book, which is created with
+/// `MKCOL` and deleted through the JSON API.
+const BOOK_METHODS: &[&str] = &["DELETE", "OPTIONS", "MKCOL"];
+
+/// Principal of the requests when authentication is disabled.
+const DEFAULT_USER: &str = "default";
+
+/// Methods allowed on the contacts collection. `DELETE` removes the address
+/// book along with its contacts.
+const COLLECTION_METHODS: &[&str] = &[
+    "GET",
+    "HEAD",
+    "POST",
+    "DELETE",
+    "OPTIONS",
+    "PROPFIND",
+    "PROPPATCH",
+    "REPORT",
+];
+
+/// Methods allowed on the vCard resources of an address book.
+const VCARD_METHODS: &[&str] = &["GET", "HEAD", "PUT", "DELETE"];
+
+/// Methods allowed on a single contact.
+const RESOURCE_METHODS: &[&str] = &[
+    "GET", "HEAD", "PUT", "DELETE", "OPTIONS", "PROPFIND", "COPY", "MOVE", "LOCK", "UNLOCK",
+];
+
+const VCARD_CONTENT_TYPE: &str = "text/vcard; charset=utf-8";
+
+/// REPORTs supported on the contacts collection, kept in sync with `report`.
+const REPORTS: &[(&str, &str)] = &[
+    (DAV_NS, "sync-collection"),
+    (CARDDAV_NS, "addressbook-multiget"),
+    (CARDDAV_NS, "addressbook-query"),
+];
+
+/// Handles the WebDAV methods on the contacts collection.
+pub async fn collection(
+    State(state): State<Arc<AppState>>,
+    book: Result<AddressBook, Response>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let book = match book {
+        Ok(AddressBook(book)) => book,
+        // The collection would be created in an address book that does not
+        // exist.
+        Err(_) if method.as_str() == "MKCOL" => {
+            warn!("MKCOL of a collection without its address book");
+            return (
+                StatusCode::CONFLICT,
+                "the address book does not exist, create it with MKCOL on its path".to_string(),
+            )
+                .into_response();
+        }
+        Err(rejection) => return rejection,
+    };
+
+    match method.as_str() {
+        "OPTIONS" => options(COLLECTION_METHODS),
+        "MKCOL" => {
+            warn!("MKCOL of the existing collection of {}", book.name);
+            method_not_allowed(COLLECTION_METHODS)
+        }
+        "DELETE" => delete_collection(&state, &book).await,
+        "PROPFIND" => propfind_collection(&book, &headers, &body).await,
+        "PROPPATCH" => proppatch_collection(&book, &body).await,
+        "REPORT" => report(&book, &body).await,
+        _ => method_not_allowed(COLLECTION_METHODS),
+    }
+}
+
+/// Handles the WebDAV methods on a single contact.
+pub async fn resource(
+    State(state): State<Arc<AppState>>,
+    AddressBook(book): AddressBook,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    match method.as_str() {
+        "PROPFIND" => propfind_resource(&book, &id, &body).await,
+        "COPY" => transfer(&state, &book, &id, &headers, false).await,
+        "MOVE" => transfer(&state, &book, &id, &headers, true).await,
+        "LOCK" => lock(&book, &id, &headers, &body),
+        "UNLOCK" => unlock(&book, &id, &headers),
+        _ => method_not_allowed(RESOURCE_METHODS),
+    }
+}
+
+/// Copies a contact to the `Destination` of the request, possibly in another
+/// address book, and deletes the original when `remove` is set.
+async fn transfer(
+    state: &AppState,
+    book: &Arc<Book>,
+    id: &str,
+    headers: &HeaderMap,
+    remove: bool,
+) -> Response {
+    let (target_name, target_id) = match destination(headers) {
+        Ok(destination) => destination,
+        Err((status, message)) => {
+            warn!("{}", message);
+            return (status, message.to_string()).into_response();
+        }
+    };
+
+    let Some(target) = state.books.get(&target_name).await else {
+        warn!("destination address book not found: {}", target_name);
+        return (
+            StatusCode::CONFLICT,
+            "destination address book not found".to_string(),
+        )
+            .into_response();
+    };
+
+    if target.name == book.name && target_id == id {
+        warn!("source and destination are the same: {}", id);
+        return (
+            StatusCode::FORBIDDEN,
+            "source and destination are the same".to_string(),
+        )
+            .into_response();
+    }
+
+    let _write = book.lock_transfer(id, &target, &target_id).await;
+    let mut contact = match book.store.get(id).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => {
+            warn!("contact not found: {}", id);
+            return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+        }
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return internal_error();
+        }
+    };
+
+    if remove && !book.locks.permits(id, headers) {
+        return locked(id);
+    }
+
+    if !target.locks.permits(&target_id, headers) {
+        return locked(&target_id);
+    }
+
+    let overwrite = headers
+        .get("Overwrite")
+        .is_none_or(|value| !value.as_bytes().eq_ignore_ascii_case(b"F"));
+
+    if !overwrite {
+        match target.store.exists(&target_id).await {
+            Ok(false) => {}
+            Ok(true) => {
+                warn!("destination exists: {}/{}", target.name, target_id);
+                return StatusCode::PRECONDITION_FAILED.into_response();
+            }
+            Err(e) => {
+                error!("failed to read contact {}: {}", target_id, e);
+                return internal_error();
+            }
+        }
+    }
+
+    contact.id = target_id.clone();
+    if let Err(response) = check_quota(&target, &contact).await {
+        return response;
+    }
+
+    // An overwritten destination is kept in its history.
+    let replaced = if target.history.is_enabled() {
+        match target.store.get(&target_id).await {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                error!("failed to read contact {}: {}", target_id, e);
+                return internal_error();
+            }
+        }
+    } else {
+        None
+    };
+
+    let created = match target.store.put(&contact).await {
+        Ok(created) => created,
+        Err(e) => {
+            error!("failed to save contact {}: {}", target_id, e);
+            return write_error(&e, "internal server error");
+        }
+    };
+
+    let (status, op) = if created {
+        (StatusCode::CREATED, Operation::Create)
+    } else {
+        (StatusCode::NO_CONTENT, Operation::Modify)
+    };
+    record_change(&target, &target_id, op).await;
+    if let Some(replaced) = replaced {
+        record_revision(&target, &replaced).await;
+    }
+
+    if remove {
+        if let Err(e) = book.store.delete(id).await {
+            error!("failed to delete contact {}: {}", id, e);
+            return internal_error();
+        }
+        record_change(book, id, Operation::Delete).await;
+    }
+
+    info!(
+        "contact {} {} to {}/{}",
+        id,
+        if remove { "moved" } else { "copied" },
+        target.name,
+        target_id
+    );
+    status.into_response()
+}
+
+/// Reads the `Destination` header of a COPY or MOVE into the name of an address
+/// book and a contact ID. Only contacts of this server are valid destinations.
+pub fn destination(headers: &HeaderMap) -> Result<(String, String), (StatusCode, &'static str)> {
+    const INVALID: (StatusCode, &str) = (StatusCode::BAD_REQUEST, "invalid Destination header");
+
+    let value = headers
+        .get("Destination")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(INVALID)?;
+
+    let path = match value.split_once("://") {
+        Some((_, rest)) => {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            if proxy::host().as_deref() != Some(authority) {
+                return Err((StatusCode::BAD_GATEWAY, "destination on another server"));
+            }
+            path
+        }
+        None => value,
+    };
+    let path = proxy::strip(path).ok_or(INVALID)?;
+
+    let (name, segment) = match path.strip_prefix("/contacts/") {
+        Some(segment) => (DEFAULT_BOOK, segment),
+        None => path
+            .strip_prefix("/addressbooks/")
+            .and_then(|rest| rest.split_once('/'))
+            .and_then(|(name, rest)| Some((name, rest.strip_prefix("contacts/")?)))
+            .ok_or(INVALID)?,
+    };
+
+    let id = percent_decode_str(segment)
+        .decode_utf8()
+        .map_err(|_| INVALID)?;
+    if !is_valid_id(&id) {
+        return Err(INVALID);
+    }
+
+    Ok((name.to_string(), id.into_owned()))
+}
+
+/// Takes a lock on a contact, or refreshes one when the request has no body.
+/// Contacts that do not exist yet can be locked before being created.
+fn lock(book: &Book, id: &str, headers: &HeaderMap, body: &str) -> Response {
+    let timeout = lock::requested_timeout(headers);
+
+    if body.trim().is_empty() {
+        let refreshed = lock::submitted_tokens(headers)
+            .iter()
+            .find_map(|token| book.locks.refresh(id, token, timeout));
+
+        return match refreshed {
+            Some(lock) => {
+                info!("lock on {} refreshed", id);
+                lock_response(book, id, &lock, false)
+            }
+            None => {
+                warn!("no lock to refresh on {}", id);
+                xml::error(
+                    StatusCode::PRECONDITION_FAILED,
+                    DAV_NS,
+                    "lock-token-matches-request-uri",
+                )
+            }
+        };
+    }
+
+    let lockinfo = match xml::parse(body) {
+        Ok(lockinfo) if lockinfo.is(DAV_NS, "lockinfo") => lockinfo,
+        Ok(_) => return bad_lockinfo("expected a lockinfo element".to_string()),
+        Err(e) => return bad_lockinfo(e),
+    };
+
+    let scope = match lockinfo.child(DAV_NS, "lockscope") {
+        Some(scope) if scope.child(DAV_NS, "exclusive").is_some() => Scope::Exclusive,
+        Some(scope) if scope.child(DAV_NS, "shared").is_some() => Scope::Shared,
+        _ => return bad_lockinfo("missing or unknown lockscope".to_string()),
+    };
+
+    if lockinfo
+        .child(DAV_NS, "locktype")
+        .and_then(|locktype| locktype.child(DAV_NS, "write"))
+        .is_none()
+    {
+        return bad_lockinfo("only write locks are supported".to_string());
+    }
+
+    let owner = lockinfo.child(DAV_NS, "owner").cloned();
+
+    match book.locks.lock(id, scope, owner, timeout) {
+        Some(lock) => {
+            info!("lock taken on {}", id);
+            lock_response(book, id, &lock, true)
+        }
+        None => {
+            warn!("conflicting lock on {}", id);
+            xml::error(StatusCode::LOCKED, DAV_NS, "no-conflicting-lock")
+        }
+    }
+}
+
+/// Releases the lock named by the `Lock-Token` header.
+fn unlock(book: &Book, id: &str, headers: &HeaderMap) -> Response {
+    let Some(token) = headers
+        .get("Lock-Token")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().trim_start_matches('<').trim_end_matches('>'))
+    else {
+        warn!("UNLOCK without a Lock-Token header on {}", id);
+        return (
+            StatusCode::BAD_REQUEST,
+            "missing Lock-Token header".to_string(),
+        )
+            .into_response();
+    };
+
+    if book.locks.unlock(id, token) {
+        info!("lock on {} released", id);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        warn!("unknown lock token for {}", id);
+        xml::error(
+            StatusCode::CONFLICT,
+            DAV_NS,
+            "lock-token-matches-request-uri",
+        )
+    }
+}
+
+fn bad_lockinfo(e: String) -> Response {
+    warn!("invalid LOCK body: {}", e);
+    (StatusCode::BAD_REQUEST, format!("invalid lockinfo: {}", e)).into_response()
+}
+
+/// Answers a LOCK with the `lockdiscovery` property of the new or refreshed
+/// lock, along with its token for new locks.
+fn lock_response(book: &Book, id: &str, lock: &Lock, new: bool) -> Response {
+    let prop = Element::new(DAV_NS, "prop")
+        .with_child(Element::new(DAV_NS, "lockdiscovery").with_child(active_lock(book, id, lock)));
+
+    let mut response = xml::response(StatusCode::OK, &prop);
+    if new {
+        if let Ok(value) = HeaderValue::from_str(&format!("<{}>", lock.token)) {
+            response.headers_mut().insert("Lock-Token", value);
+        }
+    }
+
+    response
+}
+
+fn active_lock(book: &Book, id: &str, lock: &Lock) -> Element {
+    let scope = match lock.scope {
+        Scope::Exclusive => "exclusive",
+        Scope::Shared => "shared",
+    };
+
+    let mut element = Element::new(DAV_NS, "activelock")
+        .with_child(Element::new(DAV_NS, "locktype").with_child(Element::new(DAV_NS, "write")))
+        .with_child(Element::new(DAV_NS, "lockscope").with_child(Element::new(DAV_NS, scope)))
+        .with_child(Element::new(DAV_NS, "depth").with_text("0"));
+
+    if let Some(owner) = &lock.owner {
+        element = element.with_child(owner.clone());
+    }
+
+    element
+        .with_child(
+            Element::new(DAV_NS, "timeout")
+                .with_text(format!("Second-{}", lock.remaining().as_secs())),
+        )
+        .with_child(Element::new(DAV_NS, "locktoken").with_child(xml::href_element(&lock.token)))
+        .with_child(Element::new(DAV_NS, "lockroot").with_child(xml::href_element(&href(book, id))))
+}
+
+/// The `supportedlock` and `lockdiscovery` properties of a contact.
+fn lock_props(book: &Book, id: &str) -> Vec<Element> {
+    let entry = |scope| {
+        Element::new(DAV_NS, "lockentry")
+            .with_child(Element::new(DAV_NS, "lockscope").with_child(Element::new(DAV_NS, scope)))
+            .with_child(Element::new(DAV_NS, "locktype").with_child(Element::new(DAV_NS, "write")))
+    };
+
+    let mut lockdiscovery = Element::new(DAV_NS, "lockdiscovery");
+    lockdiscovery.children = book
+        .locks
+        .active(id)
+        .iter()
+        .map(|lock| active_lock(book, id, lock))
+        .collect();
+
+    vec![
+        Element::new(DAV_NS, "supportedlock")
+            .with_child(entry("exclusive"))
+            .with_child(entry("shared")),
+        lockdiscovery,
+    ]
+}
+
+/// Fails with `507 Insufficient Storage` when storing `contact` would exceed
+/// the quota or the maximum number of contacts of the book.
+async fn check_quota(book: &Book, contact: &Contact) -> Result<(), Response> {
+    match book.admits(&contact.id, 0).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("contact limit of {} reached", book.name);
+            return Err(xml::error(
+                StatusCode::INSUFFICIENT_STORAGE,
+                DAV_NS,
+                "quota-not-exceeded",
+            ));
+        }
+        Err(e) => {
+            error!("failed to count the contacts of {}: {}", book.name, e);
+            return Err(internal_error());
+        }
+    }
+
+    match book.fits(contact).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            warn!("quota of {} exceeded", book.name);
+            Err(xml::error(
+                StatusCode::INSUFFICIENT_STORAGE,
+                DAV_NS,
+                "quota-not-exceeded",
+            ))
+        }
+        Err(e) => {
+            error!("failed to compute usage of {}: {}", book.name, e);
+            Err(internal_error())
+        }
+    }
+}
+
+/// Response to a modification of a locked contact without its lock token.
+fn locked(id: &str) -> Response {
+    warn!("contact is locked: {}", id);
+    xml::error(StatusCode::LOCKED, DAV_NS, "lock-token-submitted")
+}
+
+/// Path parameters of the vCard resources of an address book.
+#[derive(Deserialize)]
+pub struct VcardPath {
+    resource: String,
+}
+
+/// Serves the raw vCard of an address book resource.
+pub async fn get_vcard(
+    AddressBook(book): AddressBook,
+    AxumPath(VcardPath { resource }): AxumPath<VcardPath>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(id) = resource_id(&resource) else {
+        warn!("unknown address book resource: {}/{}", book.name, resource);
+        return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+    };
+
+    let (contact, etag) = match (book.store.get(id).await, book.store.etag(id).await) {
+        (Ok(Some(contact)), Ok(etag)) => (contact, etag),
+        (Ok(None), _) => {
+            warn!("contact not found: {}", id);
+            return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("failed to read contact {}: {}", id, e);
+            return internal_error();
+        }
+    };
+
+    if let Err(status) = check_preconditions(&headers, etag.as_deref(), StatusCode::NOT_MODIFIED) {
+        return with_etag(status.into_response(), etag.as_deref());
+    }
+
+    info!("vCard served: {}", id);
+    with_etag(
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, VCARD_CONTENT_TYPE)],
+            contact.to_string(),
+        )
+            .into_response(),
+        etag.as_deref(),
+    )
+}
+
+/// Creates or replaces an address book resource from a raw vCard.
+pub async fn put_vcard(
+    AddressBook(book): AddressBook,
+    AxumPath(VcardPath { resource }): AxumPath<VcardPath>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let Some(id) = resource_id(&resource) else {
+        warn!("invalid resource name: {}", resource);
+        return (StatusCode::BAD_REQUEST, "invalid resource name".to_string()).into_response();
+    };
+
+    let contact = match body.parse::<Contact>() {
+        Ok(contact) if contact.id == id => contact,
+        Ok(contact) => {
+            warn!("UID '{}' does not match resource {}", contact.id, resource);
+            return xml::error(StatusCode::FORBIDDEN, CARDDAV_NS, "valid-address-data");
+        }
+        Err(e) => {
+            warn!("invalid vCard for {}: {}", resource, e);
+            return xml::error(StatusCode::FORBIDDEN, CARDDAV_NS, "valid-address-data");
+        }
+    };
+    if let Err(e) = contact.validate() {
+        warn!("invalid contact {}: {}", id, e);
+        return xml::error(StatusCode::FORBIDDEN, CARDDAV_NS, "valid-address-data");
+    }
+
+    let _write = book.lock_writes(&[id]).await;
+    let etag = match book.store.etag(id).await {
+        Ok(etag) => etag,
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return internal_error();
+        }
+    };
+
+    if let Err(status) =
+        check_preconditions(&headers, etag.as_deref(), StatusCode::PRECONDITION_FAILED)
+    {
+        warn!("precondition failed for {}", id);
+        return status.into_response();
+    }
+
+    if !book.locks.permits(id, &headers) {
+        return locked(id);
+    }
+
+    if let Err(response) = check_quota(&book, &contact).await {
+        return response;
+    }
+
+    let previous = match etag {
+        Some(_) => match book.store.get(id).await {
+            Ok(previous) => previous,
+            Err(e) => {
+                error!("failed to read contact {}: {}", id, e);
+                return internal_error();
+            }
+        },
+        None => None,
+    };
+
+    let created = match book.store.put(&contact).await {
+        Ok(created) => created,
+        Err(e) => {
+            error!("failed to save contact {}: {}", id, e);
+            return write_error(&e, "internal server error");
+        }
+    };
+
+    if let Some(previous) = previous {
+        record_revision(&book, &previous).await;
+    }
+
+    let (status, op) = if created {
+        (StatusCode::CREATED, Operation::Create)
+    } else {
+        (StatusCode::NO_CONTENT, Operation::Modify)
+    };
+    record_change(&book, id, op).await;
+    info!("vCard saved: {}", id);
+
+    match book.store.etag(id).await {
+        Ok(etag) => with_etag(status.into_response(), etag.as_deref()),
+        Err(e) => {
+            warn!("failed to read ETag of {}: {}", id, e);
+            status.into_response()
+        }
+    }
+}
+
+/// Deletes an address book resource.
+pub async fn delete_vcard(
+    AddressBook(book): AddressBook,
+    AxumPath(VcardPath { resource }): AxumPath<VcardPath>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(id) = resource_id(&resource) else {
+        warn!("unknown address book resource: {}/{}", book.name, resource);
+        return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+    };
+
+    let _write = book.lock_writes(&[id]).await;
+    let etag = match book.store.etag(id).await {
+        Ok(Some(etag)) => etag,
+        Ok(None) => {
+            warn!("contact not found for deletion: {}", id);
+            return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+        }
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return internal_error();
+        }
+    };
+
+    if let Err(status) = check_preconditions(&headers, Some(&etag), StatusCode::PRECONDITION_FAILED)
+    {
+        warn!("precondition failed for {}", id);
+        return status.into_response();
+    }
+
+    if !book.locks.permits(id, &headers) {
+        return locked(id);
+    }
+
+    match book.remove(id).await {
+        Ok(_) => {
+            info!("vCard deleted: {}", id);
+            record_change(&book, id, Operation::Delete).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("failed to delete contact {}: {}", id, e);
+            internal_error()
+        }
+    }
+}
+
+/// Maps an address book resource name, `<id>.vcf` or the bare ID, to a contact
+/// ID.
+fn resource_id(resource: &str) -> Option<&str> {
+    let id = resource.strip_suffix(".vcf").unwrap_or(resource);
+    is_valid_id(id).then_some(id)
+}
+
+/// Evaluates `If-Match` and `If-None-Match` against the current ETag of a
+/// resource, `None` when it does not exist. A matching `If-None-Match` fails
+/// with `none_match_status`.
+fn check_preconditions(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+    none_match_status: StatusCode,
+) -> Result<(), StatusCode> {
+    if let Some(if_match) = headers.get(header::IF_MATCH) {
+        if !matches_etag(if_match, etag) {
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        if matches_etag(if_none_match, etag) {
+            return Err(none_match_status);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a list of entity tags, or `*`, matches the current ETag.
+fn matches_etag(value: &HeaderValue, etag: Option<&str>) -> bool {
+    let (Some(etag), Ok(value)) = (etag, value.to_str()) else {
+        return false;
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+fn with_etag(mut response: Response, etag: Option<&str>) -> Response {
+    if let Some(value) = etag.and_then(|etag| HeaderValue::from_str(etag).ok()) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+
+    response
+}
+
+pub async fn root_options() -> Response {
+    options(ROOT_METHODS)
+}
+
+/// Redirects `/.well-known/carddav` to the root, where clients start the
+/// discovery of the principal (RFC 6764).
+pub async fn well_known() -> Response {
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, proxy::url(&proxy::path("/")))],
+    )
+        .into_response()
+}
+
+/// Handles the WebDAV methods on the server root.
+pub async fn root(method: Method, body: String) -> Response {
+    match method.as_str() {
+        "PROPFIND" => {
+            let request = match PropfindRequest::parse(&body) {
+                Ok(request) => request,
+                Err(e) => return malformed_propfind(e),
+            };
+
+            let mut props =
+                vec![Element::new(DAV_NS, "resourcetype")
+                    .with_child(Element::new(DAV_NS, "collection"))];
+            props.extend(principal_props());
+
+            let mut multistatus = Multistatus::new();
+            multistatus.propstats(&proxy::path("/"), request.select(props));
+            multistatus.into_response()
+        }
+        _ => method_not_allowed(ROOT_METHODS),
+    }
+}
+
+/// Handles the WebDAV methods on the principal of a user.
+pub async fn principal(AxumPath(user): AxumPath<String>, method: Method, body: String) -> Response {
+    match method.as_str() {
+        "OPTIONS" => options(DISCOVERY_METHODS),
+        "PROPFIND" => {
+            if user != current_user() {
+                warn!("principal not found: {}", user);
+                return (StatusCode::NOT_FOUND, "principal not found".to_string()).into_response();
+            }
+
+            let request = match PropfindRequest::parse(&body) {
+                Ok(request) => request,
+                Err(e) => return malformed_propfind(e),
+            };
+
+            let mut props = vec![
+                Element::new(DAV_NS, "resourcetype").with_child(Element::new(DAV_NS, "principal")),
+                Element::new(DAV_NS, "displayname").with_text(&user),
+                Element::new(DAV_NS, "principal-URL")
+                    .with_child(xml::href_element(&principal_href())),
+            ];
+            props.extend(principal_props());
+
+            let mut multistatus = Multistatus::new();
+            multistatus.propstats(&principal_href(), request.select(props));
+
+            info!("PROPFIND on the principal {} answered successfully", user);
+            multistatus.into_response()
+        }
+        _ => method_not_allowed(DISCOVERY_METHODS),
+    }
+}
+
+/// Handles the WebDAV methods on the address book home, listing every address
+/// book with `Depth: 1`.
+pub async fn home(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    match method.as_str() {
+        "OPTIONS" => options(HOME_METHODS),
+        "PROPFIND" => {
+            let request = match PropfindRequest::parse(&body) {
+                Ok(request) => request,
+                Err(e) => return malformed_propfind(e),
+            };
+
+            let mut props =
+                vec![Element::new(DAV_NS, "resourcetype")
+                    .with_child(Element::new(DAV_NS, "collection"))];
+            props.extend(principal_props());
+
+            let mut multistatus = Multistatus::new();
+            multistatus.propstats(&home_href(), request.select(props));
+
+            if depth(&headers) > 0 {
+                for book in state.books.list().await {
+                    multistatus
+                        .propstats(&book.href(), request.select(collection_props(&book).await));
+                }
+            }
+
+            info!("PROPFIND on the address book home answered successfully");
+            multistatus.into_response()
+        }
+        _ => method_not_allowed(HOME_METHODS),
+    }
+}
+
+/// Deletes an address book along with its contacts, as DAV clients expect
+/// from a `DELETE` on a collection.
+async fn delete_collection(state: &AppState, book: &Book) -> Response {
+    match state.books.delete(&book.name, true).await {
+        Ok(()) => {
+            info!("Address book deleted with DELETE: {}", book.name);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(BookError::Default) => {
+            warn!("DELETE of the default address book");
+            (StatusCode::FORBIDDEN, BookError::Default.to_string()).into_response()
+        }
+        // Deleted by a concurrent request.
+        Err(BookError::NotFound(_)) => {
+            (StatusCode::NOT_FOUND, "address book not found".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to delete address book {}: {}", book.name, e);
+            internal_error()
+        }
+    }
+}
+
+/// Handles the methods on a path of an address book that is not a vCard
+/// resource: only its `contacts` collection can exist there, so `MKCOL` is
+/// forbidden, or conflicts when the address book itself does not exist.
+pub async fn book_member(
+    State(state): State<Arc<AppState>>,
+    AxumPath((name, resource)): AxumPath<(String, String)>,
+    method: Method,
+) -> Response {
+    match method.as_str() {
+        "MKCOL" if state.books.get(&name).await.is_none() => {
+            warn!("MKCOL of {} without its address book {}", resource, name);
+            (
+                StatusCode::CONFLICT,
+                "the address book does not exist, create it with MKCOL on its path".to_string(),
+            )
+                .into_response()
+        }
+        "MKCOL" => {
+            warn!("MKCOL of {} in address book {}", resource, name);
+            (
+                StatusCode::FORBIDDEN,
+                "address books only hold contacts".to_string(),
+            )
+                .into_response()
+        }
+        _ => method_not_allowed(VCARD_METHODS),
+    }
+}
+
+/// Handles the WebDAV methods on the path of an address book under the home.
+pub async fn book(
+    State(state): State<Arc<AppState>>,
+    AxumPath(name): AxumPath<String>,
+    method: Method,
+    body: String,
+) -> Response {
+    match method.as_str() {
+        "OPTIONS" => options(BOOK_METHODS),
+        "MKCOL" => mkcol(&state, &name, &body).await,
+        _ => method_not_allowed(BOOK_METHODS),
+    }
+}
+
+/// Creates an address book. The body may be an extended MKCOL (RFC 5689)
+/// setting its `DAV:displayname` and `CARDDAV:addressbook-description`.
+async fn mkcol(state: &AppState, name: &str, body: &str) -> Response {
+    let mut displayname = None;
+    let mut description = None;
+
+    if !body.trim().is_empty() {
+        let request = match xml::parse(body) {
+            Ok(request) if request.is(DAV_NS, "mkcol") => request,
+            Ok(_) | Err(_) => {
+                warn!("unsupported MKCOL body for {}", name);
+                return (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "expected an empty body or a DAV:mkcol element".to_string(),
+                )
+                    .into_response();
+            }
+        };
+
+        let props = request
+            .children
+            .iter()
+            .filter(|instruction| instruction.is(DAV_NS, "set"))
+            .filter_map(|set| set.child(DAV_NS, "prop"))
+            .flat_map(|prop| &prop.children);
+        for prop in props {
+            // An extended MKCOL can only create address books.
+            if prop.is(DAV_NS, "resourcetype") && prop.child(CARDDAV_NS, "addressbook").is_none() {
+                warn!("MKCOL of {} with an unsupported resource type", name);
+                return xml::error(StatusCode::FORBIDDEN, DAV_NS, "valid-resourcetype");
+            }
+
+            if prop.is(DAV_NS, "displayname") {
+                displayname = Some(prop.text.clone());
+            } else if prop.is(CARDDAV_NS, "addressbook-description") {
+                description = Some(prop.text.clone());
+            }
+        }
+    }
+
+    let book = match state.books.create(name).await {
+        Ok(book) => book,
+        Err(BookError::Exists(_)) => {
+            warn!("address book already exists: {}", name);
+            return method_not_allowed(&["DELETE", "OPTIONS"]);
+        }
+        Err(BookError::InvalidName(_)) => {
+            warn!("invalid address book name: {}", name);
+            return (
+                StatusCode::FORBIDDEN,
+                format!("invalid address book name '{}'", name),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!("failed to create address book {}: {}", name, e);
+            return internal_error();
+        }
+    };
+
+    if displayname.is_some() || description.is_some() {
+        let mut metadata = book.metadata().await;
+        metadata.displayname = displayname;
+        metadata.description = description;
+
+        if let Err(e) = book.set_metadata(metadata).await {
+            error!("failed to save metadata of {}: {}", name, e);
+            return write_error(&e.into(), "internal server error");
+        }
+    }
+
+    info!("Address book created with MKCOL: {}", name);
+    (
+        StatusCode::CREATED,
+        [(header::LOCATION, proxy::url(&book.href()))],
+    )
+        .into_response()
+}
+
+/// Name of the principal of the request, the authenticated user or the
+/// default one without authentication.
+fn current_user() -> String {
+    auth::user().unwrap_or_else(|| DEFAULT_USER.to_string())
+}
+
+fn principal_href() -> String {
+    proxy::path(&format!("/principals/{}/", current_user()))
+}
+
+/// Collection listing the address books of the user, which every user reaches
+/// at the same path.
+fn home_href() -> String {
+    proxy::path("/addressbooks/")
+}
+
+/// Properties pointing clients to the principal and its address books.
+fn principal_props() -> Vec<Element> {
+    vec![
+        Element::new(DAV_NS, "current-user-principal")
+            .with_child(xml::href_element(&principal_href())),
+        Element::new(CARDDAV_NS, "addressbook-home-set")
+            .with_child(xml::href_element(&home_href())),
+    ]
+}
+
+fn malformed_propfind(e: String) -> Response {
+    warn!("malformed PROPFIND body: {}", e);
+    (StatusCode::BAD_REQUEST, format!("malformed XML: {}", e)).into_response()
+}
+
+pub async fn resource_options() -> Response {
+    options(RESOURCE_METHODS)
+}
+
+/// Advertises the allowed methods and the DAV compliance classes.
+fn options(methods: &[&str]) -> Response {
+    let mut response = allow(StatusCode::OK, methods);
+
+    let classes = compliance_classes();
+    if !classes.is_empty() {
+        if let Ok(dav) = HeaderValue::from_str(&classes.join(", ")) {
+            response.headers_mut().insert("DAV", dav);
+        }
+    }
+
+    response
+}
+
+/// Rejects a method with the list of the allowed ones. Axum only knows about
+/// the standard methods, so its own `Allow` header would miss the WebDAV ones.
+fn method_not_allowed(methods: &[&str]) -> Response {
+    allow(StatusCode::METHOD_NOT_ALLOWED, methods)
+}
+
+fn allow(status: StatusCode, methods: &[&str]) -> Response {
+    let mut response = status.into_response();
+
+    if let Ok(allow) = HeaderValue::from_str(&methods.join(", ")) {
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+
+    response
+}
+
+/// Returns the DAV compliance classes backed by the handled methods and
+/// reports, for the `DAV` header.
+fn compliance_classes() -> Vec<&'static str> {
+    let supports = |method| COLLECTION_METHODS.contains(&method);
+    let mut classes = Vec::new();
+
+    // RFC 4918: class 3 is the revised class 1, both need the property methods.
+    let properties = supports("PROPFIND") && supports("PROPPATCH");
+    if properties {
+        classes.push("1");
+    }
+
+    // Locks are only taken on contacts.
+    if RESOURCE_METHODS.contains(&"LOCK") && RESOURCE_METHODS.contains(&"UNLOCK") {
+        classes.push("2");
+    }
+
+    if properties {
+        classes.push("3");
+    }
+
+    // RFC 6352: CardDAV servers must handle PROPFIND and both addressbook
+    // REPORTs.
+    if supports("PROPFIND")
+        && REPORTS.contains(&(CARDDAV_NS, "addressbook-multiget"))
+        && REPORTS.contains(&(CARDDAV_NS, "addressbook-query"))
+    {
+        classes.push("addressbook");
+    }
+
+    classes
+}
+
+/// Properties asked for by a PROPFIND request.
+enum PropfindRequest {
+    AllProp,
+    PropName,
+    Prop(Vec<Element>),
+}
+
+impl PropfindRequest {
+    fn parse(body: &str) -> Result<Self, String> {
+        // An empty body is equivalent to `allprop`.
+        if body.trim().is_empty() {
+            return Ok(PropfindRequest::AllProp);
+        }
+
+        let root = xml::parse(body)?;
+        if !root.is(DAV_NS, "propfind") {
+            return Err("expected a DAV:propfind element".to_string());
+        }
+
+        if root.child(DAV_NS, "propname").is_some() {
+            Ok(PropfindRequest::PropName)
+        } else if let Some(prop) = root.child(DAV_NS, "prop") {
+            Ok(PropfindRequest::Prop(
+                prop.children.iter().map(Element::name_only).collect(),
+            ))
+        } else {
+            Ok(PropfindRequest::AllProp)
+        }
+    }
+
+    /// Picks the requested properties out of the available ones.
+    fn select(&self, available: Vec<Element>) -> Vec<Propstat> {
+        match self {
+            PropfindRequest::AllProp => Propstat::found_and_missing(available, Vec::new()),
+            PropfindRequest::PropName => Propstat::found_and_missing(
+                available.iter().map(Element::name_only).collect(),
+                Vec::new(),
+            ),
+            PropfindRequest::Prop(requested) => select_props(requested, available),
+        }
+    }
+}
+
+/// Splits the requested properties into found and missing ones.
+fn select_props(requested: &[Element], available: Vec<Element>) -> Vec<Propstat> {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+
+    for prop in requested {
+        match available
+            .iter()
+            .find(|available| available.is(&prop.ns, &prop.name))
+        {
+            Some(available) => found.push(available.clone()),
+            None => missing.push(prop.clone()),
+        }
+    }
+
+    Propstat::found_and_missing(found, missing)
+}
+
+/// Depth of a PROPFIND request. The collection is flat, so `infinity` is
+/// served as `1`.
+fn depth(headers: &HeaderMap) -> u8 {
+    match headers.get("Depth").and_then(|depth| depth.to_str().ok()) {
+        Some("0") => 0,
+        _ => 1,
+    }
+}
+
+async fn propfind_collection(book: &Book, headers: &HeaderMap, body: &str) -> Response {
+    let request = match PropfindRequest::parse(body) {
+        Ok(request) => request,
+        Err(e) => return malformed_propfind(e),
+    };
+
+    let mut multistatus = Multistatus::new();
+    multistatus.propstats(&book.href(), request.select(collection_props(book).await));
+
+    if depth(headers) > 0 {
+        let contacts = match book.store.list().await {
+            Ok(contacts) => contacts,
+            Err(e) => {
+                error!("failed to list contacts: {}", e);
+                return internal_error();
+            }
+        };
+
+        for contact in contacts {
+            match contact_props(book, &contact).await {
+                Ok(props) => multistatus.propstats(&href(book, &contact.id), request.select(props)),
+                Err(e) => {
+                    error!("failed to read properties of {}: {}", contact.id, e);
+                    return internal_error();
+                }
+            }
+        }
+    }
+
+    info!("PROPFIND on the collection answered successfully");
+    multistatus.into_response()
+}
+
+async fn propfind_resource(book: &Book, id: &str, body: &str) -> Response {
+    let request = match PropfindRequest::parse(body) {
+        Ok(request) => request,
+        Err(e) => return malformed_propfind(e),
+    };
+
+    let contact = match book.store.get(id).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => {
+            warn!("contact not found: {}", id);
+            return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+        }
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return internal_error();
+        }
+    };
+
+    let props = match contact_props(book, &contact).await {
+        Ok(props) => props,
+        Err(e) => {
+            error!("failed to read properties of {}: {}", id, e);
+            return internal_error();
+        }
+    };
+
+    let mut multistatus = Multistatus::new();
+    multistatus.propstats(&href(book, id), request.select(props));
+
+    info!("PROPFIND on {} answered successfully", id);
+    multistatus.into_response()
+}
+
+async fn collection_props(book: &Book) -> Vec<Element> {
+    let seq = book.changes.current().await;
+
+    let mut props = vec![
+        Element::new(DAV_NS, "resourcetype")
+            .with_child(Element::new(DAV_NS, "collection"))
+            .with_child(Element::new(CARDDAV_NS, "addressbook")),
+        Element::new(DAV_NS, "displayname").with_text(book.displayname().await),
+        Element::new(DAV_NS, "sync-token").with_text(sync_token(seq)),
+        // Older clients poll the CTag to know whether to sync at all.
+        Element::new(CALENDARSERVER_NS, "getctag").with_text(seq.to_string()),
+        supported_report_set(),
+    ];
+
+    let metadata = book.metadata().await;
+    if let Some(description) = metadata.description {
+        props.push(Element::new(CARDDAV_NS, "addressbook-description").with_text(description));
+    }
+
+    props.extend(
+        metadata
+            .properties
+            .into_iter()
+            .map(|property| Element::new(&property.ns, &property.name).with_text(property.value)),
+    );
+
+    // RFC 4331, the available bytes are left out when there is no quota.
+    match book.store.usage().await {
+        Ok(used) => {
+            props.push(Element::new(DAV_NS, "quota-used-bytes").with_text(used.to_string()));
+            if let Some(quota) = book.quota().await {
+                props.push(
+                    Element::new(DAV_NS, "quota-available-bytes")
+                        .with_text(quota.saturating_sub(used).to_string()),
+                );
+            }
+        }
+        Err(e) => warn!("failed to compute usage of {}: {}", book.name, e),
+    }
+
+    props
+}
+
+/// The RFC 3253 `supported-report-set` property, listing `REPORTS`.
+fn supported_report_set() -> Element {
+    let mut set = Element::new(DAV_NS, "supported-report-set");
+    set.children = REPORTS
+        .iter()
+        .map(|(ns, name)| {
+            Element::new(DAV_NS, "supported-report")
+                .with_child(Element::new(DAV_NS, "report").with_child(Element::new(ns, name)))
+        })
+        .collect();
+
+    set
+}
+
+/// Applies the `set` and `remove` instructions of a PROPPATCH to the metadata
+/// of the collection. Instructions are all applied or none is.
+async fn proppatch_collection(book: &Book, body: &str) -> Response {
+    let request = match xml::parse(body) {
+        Ok(request) if request.is(DAV_NS, "propertyupdate") => request,
+        Ok(_) => {
+            warn!("PROPPATCH body is not a propertyupdate");
+            return (
+                StatusCode::BAD_REQUEST,
+                "expected a DAV:propertyupdate element".to_string(),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            warn!("malformed PROPPATCH body: {}", e);
+            return (StatusCode::BAD_REQUEST, format!("malformed XML: {}", e)).into_response();
+        }
+    };
+
+    let mut metadata = book.metadata().await;
+    let mut updated = Vec::new();
+    let mut protected = Vec::new();
+
+    for instruction in &request.children {
+        let set = if instruction.is(DAV_NS, "set") {
+            true
+        } else if instruction.is(DAV_NS, "remove") {
+            false
+        } else {
+            continue;
+        };
+
+        let props = instruction
+            .child(DAV_NS, "prop")
+            .map_or(&[][..], |prop| &prop.children);
+
+        for prop in props {
+            let value = set.then(|| prop.text.clone());
+
+            match (prop.ns.as_str(), prop.name.as_str()) {
+                (DAV_NS, "displayname") => metadata.displayname = value,
+                (CARDDAV_NS, "addressbook-description") => metadata.description = value,
+                (DAV_NS | CARDDAV_NS, _) | (CALENDARSERVER_NS, "getctag") => {
+                    protected.push(prop.name_only());
+                    continue;
+                }
+                // Properties of other namespaces, such as the colors set by
+                // some clients, are kept as dead properties.
+                (ns, name) => {
+                    metadata
+                        .properties
+                        .retain(|property| property.ns != ns || property.name != name);
+                    if let Some(value) = value {
+                        metadata.properties.push(DeadProperty {
+                            ns: ns.to_string(),
+                            name: name.to_string(),
+                            value,
+                        });
+                    }
+                }
+            }
+
+            updated.push(prop.name_only());
+        }
+    }
+
+    let propstats = if protected.is_empty() {
+        if let Err(e) = book.set_metadata(metadata).await {
+            error!("failed to save metadata of {}: {}", book.name, e);
+            return write_error(&e.into(), "internal server error");
+        }
+
+        info!("PROPPATCH on {} applied successfully", book.name);
+        vec![Propstat {
+            status: StatusCode::OK,
+            props: updated,
+            error: None,
+        }]
+    } else {
+        warn!("PROPPATCH on {} touches protected properties", book.name);
+        let mut propstats = vec![Propstat {
+            status: StatusCode::FORBIDDEN,
+            props: protected,
+            error: Some(Element::new(DAV_NS, "cannot-modify-protected-property")),
+        }];
+
+        if !updated.is_empty() {
+            propstats.push(Propstat {
+                status: StatusCode::FAILED_DEPENDENCY,
+                props: updated,
+                error: None,
+            });
+        }
+
+        propstats
+    };
+
+    let mut multistatus = Multistatus::new();
+    multistatus.propstats(&book.href(), propstats);
+    multistatus.into_response()
+}
+
+/// Returns the WebDAV properties of a stored contact.
+async fn contact_props(book: &Book, contact: &Contact) -> Result<Vec<Element>, StoreError> {
+    let mut props = vec![
+        Element::new(DAV_NS, "resourcetype"),
+        Element::new(DAV_NS, "getcontenttype").with_text(VCARD_CONTENT_TYPE),
+        Element::new(DAV_NS, "getcontentlength").with_text(contact.to_string().len().to_string()),
+    ];
+    props.extend(lock_props(book, &contact.id));
+
+    if let Some(etag) = book.store.etag(&contact.id).await? {
+        props.push(Element::new(DAV_NS, "getetag").with_text(etag));
+    }
+
+    if let Some(modified) = book.store.modified(&contact.id).await? {
+        props.push(
+            Element::new(DAV_NS, "getlastmodified").with_text(httpdate::fmt_http_date(modified)),
+        );
+    }
+
+    Ok(props)
+}
+
+async fn report(book: &Book, body: &str) -> Response {
+    let request = match xml::parse(body) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("malformed REPORT body: {}", e);
+            return (StatusCode::BAD_REQUEST, format!("malformed XML: {}", e)).into_response();
+        }
+    };
+
+    match (request.ns.as_str(), request.name.as_str()) {
+        (DAV_NS, "sync-collection") => sync_collection(book, &request).await,
+        (CARDDAV_NS, "addressbook-multiget") => addressbook_multiget(book, &request).await,
+        (CARDDAV_NS, "addressbook-query") => addressbook_query(book, &request).await,
+        _ => {
+            warn!("unsupported REPORT: {{{}}}{}", request.ns, request.name);
+            xml::error(StatusCode::FORBIDDEN, DAV_NS, "supported-report")
+        }
+    }
+}
+
+/// Answers an RFC 6578 `sync-collection` REPORT from the change log.
+async fn sync_collection(book: &Book, request: &Element) -> Response {
+    let token = request
+        .child(DAV_NS, "sync-token")
+        .map_or("", |token| token.text.as_str());
+
+    let since = if token.is_empty() {
+        None
+    } else {
+        match token
+            .strip_prefix(SYNC_TOKEN_PREFIX)
+            .and_then(|seq| seq.parse::<u64>().ok())
+        {
+            Some(seq) => Some(seq),
+            None => {
+                warn!("invalid sync token: {}", token);
+                return xml::error(StatusCode::CONFLICT, DAV_NS, "valid-sync-token");
+            }
+        }
+    };
+
+    let requested = requested_props(request, vec![Element::new(DAV_NS, "getetag")]);
+
+    let feed = match changes_since(book, since).await {
+        Ok(Some(feed)) => feed,
+        Ok(None) => {
+            warn!("expired sync token: {}", token);
+            return xml::error(StatusCode::CONFLICT, DAV_NS, "valid-sync-token");
+        }
+        Err(e) => {
+            error!("failed to list changes: {}", e);
+            return internal_error();
+        }
+    };
+
+    let mut multistatus = Multistatus::new();
+    for changed in &feed.changed {
+        let contact = match book.store.get(&changed.id).await {
+            Ok(Some(contact)) => contact,
+            Ok(None) => {
+                multistatus.status(&href(book, &changed.id), StatusCode::NOT_FOUND);
+                continue;
+            }
+            Err(e) => {
+                error!("failed to read contact {}: {}", changed.id, e);
+                return internal_error();
+            }
+        };
+
+        let props = match address_props(book, &contact).await {
+            Ok(props) => props,
+            Err(e) => {
+                error!("failed to read properties of {}: {}", changed.id, e);
+                return internal_error();
+            }
+        };
+
+        multistatus.propstats(&href(book, &changed.id), select_props(&requested, props));
+    }
+
+    for id in &feed.deleted {
+        multistatus.status(&href(book, id), StatusCode::NOT_FOUND);
+    }
+
+    multistatus.sync_token(&sync_token(feed.token));
+
+    info!("sync-collection report created successfully");
+    multistatus.into_response()
+}
+
+/// Answers an RFC 6352 `addressbook-multiget` REPORT with the requested contacts.
+async fn addressbook_multiget(book: &Book, request: &Element) -> Response {
+    let requested = requested_props(
+        request,
+        vec![
+            Element::new(DAV_NS, "getetag"),
+            Element::new(CARDDAV_NS, "address-data"),
+        ],
+    );
+
+    let mut multistatus = Multistatus::new();
+    for requested_href in request
+        .children
+        .iter()
+        .filter(|child| child.is(DAV_NS, "href"))
+    {
+        let requested_href = requested_href.text.as_str();
+
+        let Some(id) = id_from_href(book, requested_href) else {
+            warn!("href outside of the collection: {}", requested_href);
+            multistatus.status(requested_href, StatusCode::FORBIDDEN);
+            continue;
+        };
+
+        let contact = match book.store.get(&id).await {
+            Ok(Some(contact)) => contact,
+            Ok(None) => {
+                multistatus.status(requested_href, StatusCode::NOT_FOUND);
+                continue;
+            }
+            Err(e) => {
+                error!("failed to read contact {}: {}", id, e);
+                return internal_error();
+            }
+        };
+
+        match address_props(book, &contact).await {
+            Ok(props) => multistatus.propstats(requested_href, select_props(&requested, props)),
+            Err(e) => {
+                error!("failed to read properties of {}: {}", id, e);
+                return internal_error();
+            }
+        }
+    }
+
+    info!("addressbook-multiget report created successfully");
+    multistatus.into_response()
+}
+
+/// Answers an RFC 6352 `addressbook-query` REPORT with the contacts matching
+/// its filter.
+async fn addressbook_query(book: &Book, request: &Element) -> Response {
+    let filter = match request.child(CARDDAV_NS, "filter").map(Filter::parse) {
+        Some(Ok(filter)) => filter,
+        Some(Err(e)) => {
+            warn!("rejected addressbook-query filter: {}", e);
+            return xml::error(StatusCode::FORBIDDEN, CARDDAV_NS, e.precondition());
+        }
+        None => {
+            warn!("addressbook-query without a filter");
+            return (StatusCode::BAD_REQUEST, "missing filter".to_string()).into_response();
+        }
+    };
+
+    let limit = match request
+        .child(CARDDAV_NS, "limit")
+        .and_then(|limit| limit.child(CARDDAV_NS, "nresults"))
+        .map(|nresults| nresults.text.parse::<usize>())
+    {
+        Some(Ok(limit)) => Some(limit),
+        Some(Err(e)) => {
+            warn!("invalid addressbook-query limit: {}", e);
+            return (StatusCode::BAD_REQUEST, format!("invalid limit: {}", e)).into_response();
+        }
+        None => None,
+    };
+
+    let requested = requested_props(
+        request,
+        vec![
+            Element::new(DAV_NS, "getetag"),
+            Element::new(CARDDAV_NS, "address-data"),
+        ],
+    );
+
+    let mut contacts = match book.store.list().await {
+        Ok(contacts) => contacts,
+        Err(e) => {
+            error!("failed to list contacts: {}", e);
+            return internal_error();
+        }
+    };
+    contacts.retain(|contact| filter.matches(contact));
+    contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let truncated = limit.is_some_and(|limit| contacts.len() > limit);
+    if let Some(limit) = limit {
+        contacts.truncate(limit);
+    }
+
+    let mut multistatus = Multistatus::new();
+    for contact in &contacts {
+        match address_props(book, contact).await {
+            Ok(props) => {
+                multistatus.propstats(&href(book, &contact.id), select_props(&requested, props))
+            }
+            Err(e) => {
+                error!("failed to read properties of {}: {}", contact.id, e);
+                return internal_error();
+            }
+        }
+    }
+
+    if truncated {
+        multistatus.error(
+            &book.href(),
+            StatusCode::INSUFFICIENT_STORAGE,
+            DAV_NS,
+            "number-of-matches-within-limits",
+        );
+    }
+
+    info!("addressbook-query report created successfully");
+    multistatus.into_response()
+}
+
+/// Returns the properties listed in the `prop` element of a REPORT, or
+/// `default` when there is none.
+fn requested_props(request: &Element, default: Vec<Element>) -> Vec<Element> {
+    match request.child(DAV_NS, "prop") {
+        Some(prop) => prop.children.iter().map(Element::name_only).collect(),
+        None => default,
+    }
+}
+
+/// Returns the properties of a contact along with its vCard, as served by the
+/// REPORTs.
+async fn address_props(book: &Book, contact: &Contact) -> Result<Vec<Element>, StoreError> {
+    let mut props = contact_props(book, contact).await?;
+    props.push(Element::new(CARDDAV_NS, "address-data").with_text(contact.to_string()));
+
+    Ok(props)
+}
+
+fn sync_token(seq: impl fmt::Display) -> String {
+    format!("{}{}", SYNC_TOKEN_PREFIX, seq)
+}
+
+fn href(book: &Book, id: &str) -> String {
+    format!("{}/{}", book.href(), utf8_percent_encode(id, HREF_SEGMENT))
+}
+
+/// Extracts the contact ID out of an href, which may be an absolute URL. Returns
+/// `None` for anything that is not a direct member of the collection.
+fn id_from_href(book: &Book, href: &str) -> Option<String> {
+    let path = match href.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => href,
+    };
+
+    let segment = path.strip_prefix(&book.href())?.strip_prefix('/')?;
+    let id = percent_decode_str(segment).decode_utf8().ok()?;
+
+    is_valid_id(&id).then(|| id.into_owned())
+}
+
+/// Whether an ID from a request can be used as a contact ID, see
+/// [`check_id`].
+pub fn is_valid_id(id: &str) -> bool {
+    check_id(id).is_ok()
+}
+
+fn internal_error() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "internal server error".to_string(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::testing::{contact, TestResponse, TestServer};
+
+    fn report_method() -> Method {
+        Method::from_bytes(b"REPORT").unwrap()
+    }
+
+    /// Parses a multistatus response, failing the test if it is not one.
+    fn multistatus(response: &TestResponse) -> Element {
+        assert_eq!(
+            response.status,
+            StatusCode::MULTI_STATUS,
+            "{}",
+            response.text()
+        );
+        xml::parse(&response.text()).expect("malformed multistatus")
+    }
+
+    /// Hrefs of the responses of a multistatus, with their status when the
+    /// response has no properties.
+    fn hrefs(multistatus: &Element) -> Vec<(String, Option<String>)> {
+        multistatus
+            .children
+            .iter()
+            .filter(|child| child.is(DAV_NS, "response"))
+            .map(|response| {
+                let href = response
+                    .child(DAV_NS, "href")
+                    .expect("no href")
+                    .text
+                    .clone();
+                let status = response
+                    .child(DAV_NS, "status")
+                    .map(|status| status.text.clone());
+                (href, status)
+            })
+            .collect()
+    }
+
+    async fn sync(server: &TestServer, token: &str) -> Element {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<d:sync-collection xmlns:d="DAV:">
+  <d:sync-token>{}</d:sync-token>
+  <d:sync-level>1</d:sync-level>
+  <d:prop><d:getetag/></d:prop>
+</d:sync-collection>"#,
+            token
+        );
+        multistatus(&server.call(report_method(), "/contacts", body).await)
+    }
+
+    #[tokio::test]
+    async fn sync_collection_lists_the_changes_since_the_token() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+        server.create(&contact("carol", "Carol")).await;
+
+        let initial = sync(&server, "").await;
+        let mut initial_hrefs: Vec<_> = hrefs(&initial).into_iter().map(|(href, _)| href).collect();
+        initial_hrefs.sort();
+        assert_eq!(
+            initial_hrefs,
+            ["/contacts/alice", "/contacts/bob", "/contacts/carol"]
+        );
+        let token = initial
+            .child(DAV_NS, "sync-token")
+            .expect("no sync token")
+            .text
+            .clone();
+
+        let response = server
+            .json(Method::PUT, "/contacts/bob", &contact("bob", "Robert"))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+        let delta = sync(&server, &token).await;
+        assert_eq!(hrefs(&delta), [("/contacts/bob".to_string(), None)]);
+        let newer = delta.child(DAV_NS, "sync-token").expect("no sync token");
+        assert_ne!(newer.text, token);
+
+        server.delete("/contacts/carol").await;
+        let delta = sync(&server, &newer.text).await;
+        assert_eq!(
+            hrefs(&delta),
+            [(
+                "/contacts/carol".to_string(),
+                Some("HTTP/1.1 404 Not Found".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_collection_rejects_an_unknown_token() {
+        let server = TestServer::new().await;
+
+        let body = r#"<d:sync-collection xmlns:d="DAV:"><d:sync-token>bogus</d:sync-token></d:sync-collection>"#;
+        let response = server.call(report_method(), "/contacts", body).await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        assert!(response.text().contains("valid-sync-token"));
+    }
+
+    async fn query(server: &TestServer, filter: &str, limit: &str) -> TestResponse {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:addressbook-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <D:prop>
+    <D:getetag/>
+    <C:address-data>
+      <C:prop name="VERSION"/>
+      <C:prop name="UID"/>
+      <C:prop name="FN"/>
+    </C:address-data>
+  </D:prop>
+  {}
+  {}
+</C:addressbook-query>"#,
+            filter, limit
+        );
+        server.call(report_method(), "/contacts", body).await
+    }
+
+    #[tokio::test]
+    async fn addressbook_query_filters_the_contacts() {
+        let server = TestServer::new().await;
+        server.create(&contact("cyrus", "Cyrus Daboo")).await;
+        server
+            .create(&contact("bernard", "Bernard Desruisseaux"))
+            .await;
+        let mut daboo = contact("mike", "Mike Douglass");
+        daboo["email"] = "mike.daboo@example.com".into();
+        server.create(&daboo).await;
+
+        // Example of RFC 6352 section 8.6.5.
+        let filter = r#"<C:filter test="anyof">
+    <C:prop-filter name="FN">
+      <C:text-match collation="i;unicode-casemap" match-type="contains">daboo</C:text-match>
+    </C:prop-filter>
+    <C:prop-filter name="EMAIL">
+      <C:text-match collation="i;unicode-casemap" match-type="contains">daboo</C:text-match>
+    </C:prop-filter>
+  </C:filter>"#;
+        let response = multistatus(&query(&server, filter, "").await);
+        let found: Vec<_> = hrefs(&response).into_iter().map(|(href, _)| href).collect();
+        assert_eq!(found, ["/contacts/cyrus", "/contacts/mike"]);
+        let address_data = response.children[0]
+            .child(DAV_NS, "propstat")
+            .and_then(|propstat| propstat.child(DAV_NS, "prop"))
+            .and_then(|prop| prop.child(CARDDAV_NS, "address-data"))
+            .expect("no address data");
+        assert!(address_data.text.contains("FN:Cyrus Daboo"));
+
+        // Truncated results are flagged on the collection, as in section 8.6.1.
+        let limit = "<C:limit><C:nresults>1</C:nresults></C:limit>";
+        let response = multistatus(&query(&server, filter, limit).await);
+        let found = hrefs(&response);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, "/contacts/cyrus");
+        assert_eq!(
+            found[1],
+            (
+                "/contacts".to_string(),
+                Some("HTTP/1.1 507 Insufficient Storage".to_string())
+            )
+        );
+
+        let unsupported = r#"<C:filter><C:prop-filter name="FN"><C:text-match collation="i;klingon">a</C:text-match></C:prop-filter></C:filter>"#;
+        let response = query(&server, unsupported, "").await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert!(response.text().contains("supported-collation"));
+
+        let response = query(&server, "", "").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn unsupported_methods_list_the_allowed_ones() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+
+        let response = server
+            .call(Method::from_bytes(b"MKCALENDAR").unwrap(), "/contacts", "")
+            .await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.header("Allow"),
+            Some("GET, HEAD, POST, DELETE, OPTIONS, PROPFIND, PROPPATCH, REPORT")
+        );
+
+        let response = server.call(Method::PATCH, "/contacts/alice", "").await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.header("Allow"),
+            Some("GET, HEAD, PUT, DELETE, OPTIONS, PROPFIND, COPY, MOVE, LOCK, UNLOCK")
+        );
+    }
+
+    fn propstat_statuses(multistatus: &Element) -> Vec<(String, Vec<String>)> {
+        multistatus.children[0]
+            .children
+            .iter()
+            .filter(|child| child.is(DAV_NS, "propstat"))
+            .map(|propstat| {
+                let status = propstat.child(DAV_NS, "status").unwrap().text.clone();
+                let props = propstat.child(DAV_NS, "prop").unwrap();
+                (
+                    status,
+                    props.children.iter().map(|p| p.name.clone()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn proppatch_sets_the_display_name() {
+        let server = TestServer::new().await;
+        let proppatch = Method::from_bytes(b"PROPPATCH").unwrap();
+
+        let response = server
+            .call(
+                proppatch.clone(),
+                "/contacts",
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<A:propertyupdate xmlns:A="DAV:">
+  <A:set><A:prop><A:displayname>Friends</A:displayname></A:prop></A:set>
+</A:propertyupdate>"#,
+            )
+            .await;
+        assert_eq!(
+            propstat_statuses(&multistatus(&response)),
+            [(
+                "HTTP/1.1 200 OK".to_string(),
+                vec!["displayname".to_string()]
+            )]
+        );
+
+        let response = server
+            .call(
+                Method::from_bytes(b"PROPFIND").unwrap(),
+                "/contacts",
+                r#"<d:propfind xmlns:d="DAV:"><d:prop><d:displayname/></d:prop></d:propfind>"#,
+            )
+            .await;
+        let prop = multistatus(&response).children[0]
+            .child(DAV_NS, "propstat")
+            .and_then(|propstat| propstat.child(DAV_NS, "prop"))
+            .and_then(|prop| prop.child(DAV_NS, "displayname"))
+            .cloned()
+            .unwrap();
+        assert_eq!(prop.text, "Friends");
+
+        // Protected properties fail the whole update.
+        let response = server
+            .call(
+                proppatch,
+                "/contacts",
+                r#"<d:propertyupdate xmlns:d="DAV:"><d:set><d:prop>
+  <d:displayname>Family</d:displayname><d:getetag>"x"</d:getetag>
+</d:prop></d:set></d:propertyupdate>"#,
+            )
+            .await;
+        assert_eq!(
+            propstat_statuses(&multistatus(&response)),
+            [
+                (
+                    "HTTP/1.1 403 Forbidden".to_string(),
+                    vec!["getetag".to_string()]
+                ),
+                (
+                    "HTTP/1.1 424 Failed Dependency".to_string(),
+                    vec!["displayname".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn options_advertises_the_dav_capabilities() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+
+        for (uri, allow) in [
+            ("/", "OPTIONS, PROPFIND"),
+            (
+                "/contacts",
+                "GET, HEAD, POST, DELETE, OPTIONS, PROPFIND, PROPPATCH, REPORT",
+            ),
+            (
+                "/contacts/alice",
+                "GET, HEAD, PUT, DELETE, OPTIONS, PROPFIND, COPY, MOVE, LOCK, UNLOCK",
+            ),
+        ] {
+            let response = server.call(Method::OPTIONS, uri, "").await;
+            assert_eq!(response.status, StatusCode::OK, "{}", uri);
+            assert_eq!(
+                response.header("DAV"),
+                Some("1, 2, 3, addressbook"),
+                "{}",
+                uri
+            );
+            assert_eq!(response.header("Allow"), Some(allow), "{}", uri);
+        }
+    }
+
+    fn mkcol_method() -> Method {
+        Method::from_bytes(b"MKCOL").unwrap()
+    }
+
+    #[tokio::test]
+    async fn mkcol_creates_an_address_book() {
+        let server = TestServer::new().await;
+
+        let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:mkcol xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <D:set>
+    <D:prop>
+      <D:resourcetype><D:collection/><C:addressbook/></D:resourcetype>
+      <D:displayname>Work</D:displayname>
+      <C:addressbook-description>Colleagues</C:addressbook-description>
+    </D:prop>
+  </D:set>
+</D:mkcol>"#;
+        let response = server
+            .call(mkcol_method(), "/addressbooks/work/", body)
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        assert_eq!(
+            response.header("Location"),
+            Some("http://localhost/addressbooks/work/contacts")
+        );
+
+        let books = server.get("/addressbooks").await.json();
+        let work = books
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|book| book["name"] == "work")
+            .expect("address book not listed");
+        assert_eq!(work["displayname"], "Work");
+        assert_eq!(work["description"], "Colleagues");
+
+        let response = server
+            .json(
+                Method::POST,
+                "/addressbooks/work/contacts",
+                &contact("alice", "Alice"),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn mkcol_status_codes() {
+        let server = TestServer::new().await;
+        let created = server.call(mkcol_method(), "/addressbooks/work", "").await;
+        assert_eq!(created.status, StatusCode::CREATED);
+
+        // Existing collections.
+        for path in [
+            "/addressbooks/work",
+            "/addressbooks/work/contacts",
+            "/contacts",
+        ] {
+            let response = server.call(mkcol_method(), path, "").await;
+            assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+            assert!(response
+                .header("Allow")
+                .is_some_and(|allow| allow.contains("DELETE")));
+        }
+
+        // Missing parents.
+        for path in [
+            "/addressbooks/missing/contacts",
+            "/addressbooks/missing/other",
+        ] {
+            let response = server.call(mkcol_method(), path, "").await;
+            assert_eq!(response.status, StatusCode::CONFLICT, "{}", path);
+        }
+        assert!(server.state().books.get("missing").await.is_none());
+
+        // Only the contacts collection exists in an address book.
+        let response = server
+            .call(mkcol_method(), "/addressbooks/work/other", "")
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn mkcol_validates_the_name_like_the_json_api() {
+        let server = TestServer::new().await;
+
+        let response = server
+            .call(mkcol_method(), "/addressbooks/bad.name", "")
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = server
+            .json(
+                Method::POST,
+                "/addressbooks",
+                &serde_json::json!({ "name": "bad.name" }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert!(server.state().books.get("bad.name").await.is_none());
+
+        let calendar = r#"<D:mkcol xmlns:D="DAV:" xmlns:CAL="urn:ietf:params:xml:ns:caldav">
+  <D:set><D:prop>
+    <D:resourcetype><D:collection/><CAL:calendar/></D:resourcetype>
+  </D:prop></D:set>
+</D:mkcol>"#;
+        let response = server
+            .call(mkcol_method(), "/addressbooks/cal", calendar)
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert!(response.text().contains("valid-resourcetype"));
+        assert!(server.state().books.get("cal").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_collection_and_its_members() {
+        let server = TestServer::new().await;
+        server.call(mkcol_method(), "/addressbooks/work", "").await;
+        let response = server
+            .json(
+                Method::POST,
+                "/addressbooks/work/contacts",
+                &contact("alice", "Alice"),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+        let dir = server.dir.path().join("books").join("work");
+        assert!(dir.exists());
+
+        let response = server.delete("/addressbooks/work/contacts/").await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert!(server.state().books.get("work").await.is_none());
+        assert!(!dir.exists());
+        assert_eq!(
+            server.get("/addressbooks/work/contacts/alice").await.status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            server.delete("/addressbooks/work/contacts").await.status,
+            StatusCode::NOT_FOUND
+        );
+
+        let response = server.delete("/contacts").await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    /// Puts a vCard of `alice` with the `extra` properties into the default
+    /// address book.
+    async fn put_alice(server: &TestServer, extra: &str) -> TestResponse {
+        let body = format!(
+            "BEGIN:VCARD\r\nVERSION:3.0\r\nUID:alice\r\nFN:Alice\r\n{}END:VCARD\r\n",
+            extra
+        );
+        server
+            .call(Method::PUT, "/addressbooks/default/alice.vcf", body)
+            .await
+    }
+
+    #[tokio::test]
+    async fn put_rejects_an_invalid_birthday() {
+        let server = TestServer::new().await;
+
+        let response = put_alice(&server, "BDAY:the 12th of April\r\n").await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert!(response.text().contains("valid-address-data"));
+        let response = server.get("/addressbooks/default/alice.vcf").await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = put_alice(&server, "BDAY:1990-04-12\r\n").await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    }
+
+    #[tokio::test]
+    async fn put_rejects_an_invalid_url() {
+        let server = TestServer::new().await;
+
+        let response = put_alice(&server, "URL:notaurl\r\n").await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert!(response.text().contains("valid-address-data"));
+
+        let response = put_alice(&server, "URL:https://alice.example.com\r\n").await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    }
+}
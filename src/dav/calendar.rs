@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use percent_encoding::utf8_percent_encode;
+use tracing::{error, info, warn};
+
+use super::xml::{self, Element, Multistatus, CALDAV_NS, DAV_NS};
+use super::{
+    depth, internal_error, malformed_propfind, method_not_allowed, options, requested_props,
+    select_props, PropfindRequest, HREF_SEGMENT,
+};
+use crate::event::{Calendar, Event};
+use crate::freebusy;
+use crate::proxy;
+use crate::store::EventStore;
+use crate::AppState;
+
+/// Path of the calendar collection.
+fn calendar_href() -> String {
+    proxy::path("/events")
+}
+
+/// Methods allowed on the calendar collection.
+const CALENDAR_METHODS: &[&str] = &["GET", "HEAD", "POST", "OPTIONS", "PROPFIND", "REPORT"];
+
+const CALENDAR_CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+/// Handles the CalDAV methods on the calendar collection.
+pub async fn calendar(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    match method.as_str() {
+        "OPTIONS" => {
+            let mut response = options(CALENDAR_METHODS);
+            let classes = match response.headers().get("DAV").and_then(|v| v.to_str().ok()) {
+                Some(classes) => format!("{}, calendar-access", classes),
+                None => "calendar-access".to_string(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&classes) {
+                response.headers_mut().insert("DAV", value);
+            }
+
+            response
+        }
+        "PROPFIND" => propfind_calendar(&state.events, &headers, &body).await,
+        "REPORT" => report(&state.events, &body).await,
+        _ => method_not_allowed(CALENDAR_METHODS),
+    }
+}
+
+async fn propfind_calendar(events: &EventStore, headers: &HeaderMap, body: &str) -> Response {
+    let request = match PropfindRequest::parse(body) {
+        Ok(request) => request,
+        Err(e) => return malformed_propfind(e),
+    };
+
+    let mut multistatus = Multistatus::new();
+    multistatus.propstats(&calendar_href(), request.select(calendar_props()));
+
+    if depth(headers) > 0 {
+        let events_list = match events.list().await {
+            Ok(events_list) => events_list,
+            Err(e) => {
+                error!("failed to list events: {}", e);
+                return internal_error();
+            }
+        };
+
+        for event in events_list {
+            match event_props(events, &event).await {
+                Ok(props) => multistatus.propstats(&href(&event.id), request.select(props)),
+                Err(response) => return response,
+            }
+        }
+    }
+
+    info!("PROPFIND on the calendar answered successfully");
+    multistatus.into_response()
+}
+
+fn calendar_props() -> Vec<Element> {
+    vec![
+        Element::new(DAV_NS, "resourcetype")
+            .with_child(Element::new(DAV_NS, "collection"))
+            .with_child(Element::new(CALDAV_NS, "calendar")),
+        Element::new(DAV_NS, "displayname").with_text("Calendar"),
+        Element::new(CALDAV_NS, "supported-calendar-component-set").with_child(Element {
+            attributes: vec![("name".to_string(), "VEVENT".to_string())],
+            ..Element::new(CALDAV_NS, "comp")
+        }),
+    ]
+}
+
+async fn event_props(events: &EventStore, event: &Event) -> Result<Vec<Element>, Response> {
+    let mut props = vec![
+        Element::new(DAV_NS, "resourcetype"),
+        Element::new(DAV_NS, "getcontenttype").with_text(CALENDAR_CONTENT_TYPE),
+        Element::new(DAV_NS, "getcontentlength").with_text(event.to_string().len().to_string()),
+    ];
+
+    match events.etag(&event.id).await {
+        Ok(Some(etag)) => props.push(Element::new(DAV_NS, "getetag").with_text(etag)),
+        Ok(None) => {}
+        Err(e) => {
+            error!("failed to read properties of {}: {}", event.id, e);
+            return Err(internal_error());
+        }
+    }
+
+    Ok(props)
+}
+
+async fn report(events: &EventStore, body: &str) -> Response {
+    let request = match xml::parse(body) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("malformed REPORT body: {}", e);
+            return (StatusCode::BAD_REQUEST, format!("malformed XML: {}", e)).into_response();
+        }
+    };
+
+    if request.is(CALDAV_NS, "free-busy-query") {
+        return free_busy_query(events, &request).await;
+    }
+
+    if !request.is(CALDAV_NS, "calendar-query") {
+        warn!("unsupported REPORT: {{{}}}{}", request.ns, request.name);
+        return xml::error(StatusCode::FORBIDDEN, DAV_NS, "supported-report");
+    }
+
+    let filter = match request.child(CALDAV_NS, "filter").map(EventFilter::parse) {
+        Some(Ok(filter)) => filter,
+        Some(Err(e)) => {
+            warn!("rejected calendar-query filter: {}", e);
+            return xml::error(StatusCode::FORBIDDEN, CALDAV_NS, "supported-filter");
+        }
+        None => {
+            warn!("calendar-query without a filter");
+            return (StatusCode::BAD_REQUEST, "missing filter".to_string()).into_response();
+        }
+    };
+
+    let expand = match expand_range(&request) {
+        Ok(expand) => expand,
+        Err(e) => {
+            warn!("invalid expand in calendar-query: {}", e);
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    };
+
+    let requested = requested_props(
+        &request,
+        vec![
+            Element::new(DAV_NS, "getetag"),
+            Element::new(CALDAV_NS, "calendar-data"),
+        ],
+    );
+
+    let mut matching = match events.list().await {
+        Ok(events_list) => events_list,
+        Err(e) => {
+            error!("failed to list events: {}", e);
+            return internal_error();
+        }
+    };
+    matching.retain(|event| filter.matches(event));
+    matching.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut multistatus = Multistatus::new();
+    for event in &matching {
+        let mut props = match event_props(events, event).await {
+            Ok(props) => props,
+            Err(response) => return response,
+        };
+        let data = match expand {
+            Some((start, end)) => match event.instances(Some(start), Some(end)) {
+                Ok(instances) => Calendar(&instances).to_string(),
+                Err(e) => {
+                    warn!("failed to expand event {}: {}", event.id, e);
+                    event.to_string()
+                }
+            },
+            None => event.to_string(),
+        };
+        props.push(Element::new(CALDAV_NS, "calendar-data").with_text(data));
+
+        multistatus.propstats(&href(&event.id), select_props(&requested, props));
+    }
+
+    info!("calendar-query report created successfully");
+    multistatus.into_response()
+}
+
+/// Answers a `free-busy-query` REPORT with a `VFREEBUSY` component, which
+/// unlike other REPORTs is not wrapped in a multistatus.
+async fn free_busy_query(events: &EventStore, request: &Element) -> Response {
+    let range = request
+        .child(CALDAV_NS, "time-range")
+        .ok_or_else(|| "missing time-range".to_string())
+        .and_then(
+            |range| match (range.attribute("start"), range.attribute("end")) {
+                (Some(start), Some(end)) => Ok((parse_utc(start)?, parse_utc(end)?)),
+                _ => Err("time-range requires start and end".to_string()),
+            },
+        );
+    let (start, end) = match range {
+        Ok(range) => range,
+        Err(e) => {
+            warn!("invalid free-busy-query: {}", e);
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    };
+
+    let events_list = match events.list().await {
+        Ok(events_list) => events_list,
+        Err(e) => {
+            error!("failed to list events: {}", e);
+            return internal_error();
+        }
+    };
+
+    info!("free-busy-query report created successfully");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, CALENDAR_CONTENT_TYPE)],
+        freebusy::vfreebusy(&freebusy::busy(&events_list, start, end), start, end),
+    )
+        .into_response()
+}
+
+/// The `filter` of a `calendar-query` REPORT: a `VCALENDAR` component filter,
+/// optionally holding a `VEVENT` one restricted to a time range.
+enum EventFilter {
+    /// Matches every event overlapping the range, of which both bounds are
+    /// optional.
+    Events {
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    },
+    /// Filters on other components, such as `VTODO`, which are never stored.
+    Nothing,
+}
+
+impl EventFilter {
+    fn parse(filter: &Element) -> Result<Self, String> {
+        let calendar = match filter.children.as_slice() {
+            [calendar] if is_comp_filter(calendar, "VCALENDAR") => calendar,
+            _ => return Err("expected a single VCALENDAR comp-filter".to_string()),
+        };
+
+        let component = match calendar.children.as_slice() {
+            [] => {
+                return Ok(EventFilter::Events {
+                    start: None,
+                    end: None,
+                })
+            }
+            [component] if component.is(CALDAV_NS, "comp-filter") => component,
+            _ => return Err("expected a single comp-filter in VCALENDAR".to_string()),
+        };
+
+        if !is_comp_filter(component, "VEVENT") {
+            return Ok(EventFilter::Nothing);
+        }
+
+        let mut start = None;
+        let mut end = None;
+        for child in &component.children {
+            if !child.is(CALDAV_NS, "time-range") {
+                return Err(format!(
+                    "unsupported element '{}' in comp-filter",
+                    child.name
+                ));
+            }
+
+            start = child.attribute("start").map(parse_utc).transpose()?;
+            end = child.attribute("end").map(parse_utc).transpose()?;
+        }
+
+        Ok(EventFilter::Events { start, end })
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        match self {
+            // A series matches when one of its occurrences does.
+            EventFilter::Events { start, end } => match event.instances(*start, *end) {
+                Ok(instances) => !instances.is_empty(),
+                Err(e) => {
+                    warn!("failed to expand event {}: {}", event.id, e);
+                    event.overlaps(*start, *end)
+                }
+            },
+            EventFilter::Nothing => false,
+        }
+    }
+}
+
+/// Start and end of a time range.
+type TimeRange = (DateTime<Utc>, DateTime<Utc>);
+
+/// Reads the range of the `expand` element of the requested `calendar-data`,
+/// in which case the occurrences of a series are returned instead of the
+/// series itself.
+fn expand_range(request: &Element) -> Result<Option<TimeRange>, String> {
+    let Some(expand) = request
+        .child(DAV_NS, "prop")
+        .and_then(|prop| prop.child(CALDAV_NS, "calendar-data"))
+        .and_then(|data| data.child(CALDAV_NS, "expand"))
+    else {
+        return Ok(None);
+    };
+
+    match (expand.attribute("start"), expand.attribute("end")) {
+        (Some(start), Some(end)) => Ok(Some((parse_utc(start)?, parse_utc(end)?))),
+        _ => Err("expand requires start and end".to_string()),
+    }
+}
+
+fn is_comp_filter(element: &Element, name: &str) -> bool {
+    element.is(CALDAV_NS, "comp-filter")
+        && element
+            .attribute("name")
+            .is_some_and(|comp| comp.eq_ignore_ascii_case(name))
+}
+
+/// Parses a time range bound, a UTC date with time such as `20240101T000000Z`.
+fn parse_utc(value: &str) -> Result<DateTime<Utc>, String> {
+    value
+        .strip_suffix('Z')
+        .and_then(|value| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok())
+        .map(|time| time.and_utc())
+        .ok_or_else(|| format!("invalid time-range bound '{}'", value))
+}
+
+fn href(id: &str) -> String {
+    format!(
+        "{}/{}",
+        calendar_href(),
+        utf8_percent_encode(id, HREF_SEGMENT)
+    )
+}
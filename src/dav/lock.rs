@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderMap;
+use uuid::Uuid;
+
+use super::xml::Element;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Exclusive,
+    Shared,
+}
+
+/// A write lock on a contact, identified by its token.
+#[derive(Clone, Debug)]
+pub struct Lock {
+    pub token: String,
+    pub scope: Scope,
+    /// The `owner` element of the LOCK request, returned as-is.
+    pub owner: Option<Element>,
+    expires: Instant,
+}
+
+impl Lock {
+    /// Time left before the lock expires.
+    pub fn remaining(&self) -> Duration {
+        self.expires.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Write locks of the contacts of an address book, kept in memory. Expired locks
+/// are dropped whenever the locks of a contact are looked at.
+pub struct Locks {
+    max_timeout: Duration,
+    locks: Mutex<HashMap<String, Vec<Lock>>>,
+}
+
+impl Locks {
+    /// Locks last for `max_timeout` unless clients ask for less.
+    pub fn new(max_timeout: Duration) -> Self {
+        Locks {
+            max_timeout,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn timeout(&self, requested: Option<Duration>) -> Duration {
+        requested.map_or(self.max_timeout, |timeout| timeout.min(self.max_timeout))
+    }
+
+    /// Runs `f` on the live locks of a contact.
+    fn with_locks<T>(&self, id: &str, f: impl FnOnce(&mut Vec<Lock>) -> T) -> T {
+        let mut locks = self.locks.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        locks.retain(|_, locks| {
+            locks.retain(|lock| lock.expires > now);
+            !locks.is_empty()
+        });
+
+        let entry = locks.entry(id.to_string()).or_default();
+        let result = f(entry);
+        if entry.is_empty() {
+            locks.remove(id);
+        }
+
+        result
+    }
+
+    /// Returns the live locks of a contact.
+    pub(super) fn active(&self, id: &str) -> Vec<Lock> {
+        self.with_locks(id, |locks| locks.clone())
+    }
+
+    /// Takes a new lock, or returns `None` when it conflicts with an existing
+    /// one: an exclusive lock conflicts with any other lock.
+    pub(super) fn lock(
+        &self,
+        id: &str,
+        scope: Scope,
+        owner: Option<Element>,
+        timeout: Option<Duration>,
+    ) -> Option<Lock> {
+        let expires = Instant::now() + self.timeout(timeout);
+
+        self.with_locks(id, |locks| {
+            let conflict = locks
+                .iter()
+                .any(|lock| scope == Scope::Exclusive || lock.scope == Scope::Exclusive);
+            if conflict {
+                return None;
+            }
+
+            let lock = Lock {
+                token: format!("urn:uuid:{}", Uuid::new_v4()),
+                scope,
+                owner,
+                expires,
+            };
+            locks.push(lock.clone());
+
+            Some(lock)
+        })
+    }
+
+    /// Extends a lock of the contact, returning `None` when it does not exist.
+    pub(super) fn refresh(&self, id: &str, token: &str, timeout: Option<Duration>) -> Option<Lock> {
+        let expires = Instant::now() + self.timeout(timeout);
+
+        self.with_locks(id, |locks| {
+            let lock = locks.iter_mut().find(|lock| lock.token == token)?;
+            lock.expires = expires;
+
+            Some(lock.clone())
+        })
+    }
+
+    /// Releases a lock of the contact, returning whether it existed.
+    pub(super) fn unlock(&self, id: &str, token: &str) -> bool {
+        self.with_locks(id, |locks| {
+            let count = locks.len();
+            locks.retain(|lock| lock.token != token);
+
+            locks.len() != count
+        })
+    }
+
+    /// Whether a request may modify a contact: it is not locked, or the `If`
+    /// header of the request submits the token of one of its locks.
+    pub fn permits(&self, id: &str, headers: &HeaderMap) -> bool {
+        let tokens = submitted_tokens(headers);
+
+        self.with_locks(id, |locks| {
+            locks.is_empty() || locks.iter().any(|lock| tokens.contains(&lock.token))
+        })
+    }
+}
+
+/// Returns the lock tokens listed in the `If` header of a request. Conditions
+/// are not evaluated: submitting the token is enough to use a lock.
+pub(super) fn submitted_tokens(headers: &HeaderMap) -> Vec<String> {
+    let Some(value) = headers.get("If").and_then(|value| value.to_str().ok()) else {
+        return Vec::new();
+    };
+
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('>'))
+        .map(|(token, _)| token.to_string())
+        .collect()
+}
+
+/// Reads the `Timeout` header of a LOCK request, `None` for `Infinite` or a
+/// missing header.
+pub(super) fn requested_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get("Timeout")?.to_str().ok()?;
+
+    value
+        .split(',')
+        .find_map(|timeout| timeout.trim().strip_prefix("Second-")?.parse().ok())
+        .map(Duration::from_secs)
+}
@@ -0,0 +1,371 @@
+use std::fmt;
+
+use super::xml::{Element, CARDDAV_NS};
+use crate::contact::Contact;
+
+/// Reason for rejecting a filter, mapped to a CardDAV precondition.
+#[derive(Debug)]
+pub enum FilterError {
+    /// Maps to `supported-collation`.
+    Collation(String),
+    /// Maps to `supported-filter`.
+    Unsupported(String),
+}
+
+impl FilterError {
+    pub fn precondition(&self) -> &'static str {
+        match self {
+            FilterError::Collation(_) => "supported-collation",
+            FilterError::Unsupported(_) => "supported-filter",
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Collation(collation) => write!(f, "unsupported collation '{}'", collation),
+            FilterError::Unsupported(reason) => write!(f, "unsupported filter: {}", reason),
+        }
+    }
+}
+
+/// Whether any or all of the tests of a filter must match.
+#[derive(Clone, Copy, Debug)]
+enum Test {
+    AnyOf,
+    AllOf,
+}
+
+impl Test {
+    fn parse(element: &Element) -> Result<Self, FilterError> {
+        match element.attribute("test") {
+            None | Some("anyof") => Ok(Test::AnyOf),
+            Some("allof") => Ok(Test::AllOf),
+            Some(test) => Err(FilterError::Unsupported(format!("unknown test '{}'", test))),
+        }
+    }
+
+    fn eval(self, mut results: impl Iterator<Item = bool>) -> bool {
+        match self {
+            Test::AnyOf => results.any(|matched| matched),
+            Test::AllOf => results.all(|matched| matched),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum MatchType {
+    Equals,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug)]
+struct TextMatch {
+    text: String,
+    match_type: MatchType,
+    case_sensitive: bool,
+    negate: bool,
+}
+
+impl TextMatch {
+    fn parse(element: &Element) -> Result<Self, FilterError> {
+        let match_type = match element.attribute("match-type") {
+            None | Some("contains") => MatchType::Contains,
+            Some("equals") => MatchType::Equals,
+            Some("starts-with") => MatchType::StartsWith,
+            Some("ends-with") => MatchType::EndsWith,
+            Some(match_type) => {
+                return Err(FilterError::Unsupported(format!(
+                    "unknown match type '{}'",
+                    match_type
+                )))
+            }
+        };
+
+        let case_sensitive = match element.attribute("collation") {
+            None | Some("i;unicode-casemap") | Some("i;ascii-casemap") => false,
+            Some("i;octet") => true,
+            Some(collation) => return Err(FilterError::Collation(collation.to_string())),
+        };
+
+        let text = if case_sensitive {
+            element.text.clone()
+        } else {
+            element.text.to_lowercase()
+        };
+
+        Ok(TextMatch {
+            text,
+            match_type,
+            case_sensitive,
+            negate: element.attribute("negate-condition") == Some("yes"),
+        })
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        let value = if self.case_sensitive {
+            value.to_string()
+        } else {
+            value.to_lowercase()
+        };
+
+        let matched = match self.match_type {
+            MatchType::Equals => value == self.text,
+            MatchType::Contains => value.contains(&self.text),
+            MatchType::StartsWith => value.starts_with(&self.text),
+            MatchType::EndsWith => value.ends_with(&self.text),
+        };
+
+        matched != self.negate
+    }
+}
+
+#[derive(Debug)]
+struct PropFilter {
+    name: String,
+    test: Test,
+    is_not_defined: bool,
+    text_matches: Vec<TextMatch>,
+}
+
+impl PropFilter {
+    fn parse(element: &Element) -> Result<Self, FilterError> {
+        let name = element
+            .attribute("name")
+            .ok_or_else(|| FilterError::Unsupported("prop-filter without a name".to_string()))?
+            .to_ascii_uppercase();
+
+        let mut is_not_defined = false;
+        let mut text_matches = Vec::new();
+
+        for child in &element.children {
+            if child.is(CARDDAV_NS, "is-not-defined") {
+                is_not_defined = true;
+            } else if child.is(CARDDAV_NS, "text-match") {
+                text_matches.push(TextMatch::parse(child)?);
+            } else {
+                return Err(FilterError::Unsupported(format!(
+                    "unknown element '{}' in prop-filter",
+                    child.name
+                )));
+            }
+        }
+
+        Ok(PropFilter {
+            name,
+            test: Test::parse(element)?,
+            is_not_defined,
+            text_matches,
+        })
+    }
+
+    fn matches(&self, contact: &Contact) -> bool {
+        let values = property_values(contact, &self.name);
+
+        if self.is_not_defined {
+            return values.is_empty();
+        }
+
+        if self.text_matches.is_empty() {
+            return !values.is_empty();
+        }
+
+        // A text match is satisfied by any instance of the property.
+        self.test.eval(
+            self.text_matches
+                .iter()
+                .map(|text_match| values.iter().any(|value| text_match.matches(value))),
+        )
+    }
+}
+
+/// An RFC 6352 `filter` element of an `addressbook-query` REPORT.
+#[derive(Debug)]
+pub struct Filter {
+    test: Test,
+    prop_filters: Vec<PropFilter>,
+}
+
+impl Filter {
+    pub fn parse(element: &Element) -> Result<Self, FilterError> {
+        let prop_filters = element
+            .children
+            .iter()
+            .map(|child| {
+                if child.is(CARDDAV_NS, "prop-filter") {
+                    PropFilter::parse(child)
+                } else {
+                    Err(FilterError::Unsupported(format!(
+                        "unknown element '{}' in filter",
+                        child.name
+                    )))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Filter {
+            test: Test::parse(element)?,
+            prop_filters,
+        })
+    }
+
+    /// An empty filter matches every contact.
+    pub fn matches(&self, contact: &Contact) -> bool {
+        self.prop_filters.is_empty()
+            || self.test.eval(
+                self.prop_filters
+                    .iter()
+                    .map(|prop_filter| prop_filter.matches(contact)),
+            )
+    }
+}
+
+/// Returns the values of a vCard property, empty when it is not defined.
+fn property_values<'a>(contact: &'a Contact, name: &str) -> Vec<&'a str> {
+    let values: Vec<&str> = match name {
+        "UID" | "ID" => vec![&contact.id],
+        "FN" => vec![&contact.name],
+        "EMAIL" => vec![&contact.email],
+        "TEL" => vec![&contact.phone],
+        "CATEGORIES" => contact.categories.iter().map(String::as_str).collect(),
+        "URL" => contact.url.iter().map(String::as_str).collect(),
+        "KIND" => contact.kind.iter().map(String::as_str).collect(),
+        "MEMBER" => contact.members.iter().map(String::as_str).collect(),
+        "IMPP" => contact.impp.iter().map(String::as_str).collect(),
+        "GENDER" => contact.gender.iter().map(String::as_str).collect(),
+        "NICKNAME" => contact.nickname.iter().map(String::as_str).collect(),
+        "BDAY" => contact.birthday.iter().map(String::as_str).collect(),
+        "REV" => contact.rev.iter().map(String::as_str).collect(),
+        _ => Vec::new(),
+    };
+
+    values
+        .into_iter()
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dav::xml;
+
+    fn filter(body: &str) -> Result<Filter, FilterError> {
+        let element = xml::parse(&format!(
+            r#"<C:filter xmlns:C="urn:ietf:params:xml:ns:carddav" {}</C:filter>"#,
+            body
+        ))
+        .unwrap();
+        Filter::parse(&element)
+    }
+
+    fn contact(name: &str, email: &str) -> Contact {
+        Contact {
+            id: name.to_lowercase(),
+            name: name.to_string(),
+            email: email.to_string(),
+            ..Contact::default()
+        }
+    }
+
+    /// Example of RFC 6352 section 8.6.5: a full name or an email address
+    /// containing `daboo`.
+    const FN_OR_EMAIL: &str = r#"test="anyof">
+        <C:prop-filter name="FN">
+          <C:text-match collation="i;unicode-casemap" match-type="contains">daboo</C:text-match>
+        </C:prop-filter>
+        <C:prop-filter name="EMAIL">
+          <C:text-match collation="i;unicode-casemap" match-type="contains">daboo</C:text-match>
+        </C:prop-filter>"#;
+
+    #[test]
+    fn matches_any_prop_filter() {
+        let filter = filter(FN_OR_EMAIL).unwrap();
+
+        assert!(filter.matches(&contact("Cyrus Daboo", "cyrus@example.com")));
+        assert!(filter.matches(&contact("Cyrus", "daboo@example.com")));
+        assert!(!filter.matches(&contact("Bernard Desruisseaux", "bernard@example.com")));
+    }
+
+    /// Example of RFC 6352 section 8.6.4: a nickname equal to `me`.
+    #[test]
+    fn matches_the_nickname() {
+        let filter = filter(
+            r#"><C:prop-filter name="NICKNAME">
+                 <C:text-match collation="i;unicode-casemap" match-type="equals">me</C:text-match>
+               </C:prop-filter>"#,
+        )
+        .unwrap();
+
+        let mut cyrus = contact("Cyrus Daboo", "cyrus@example.com");
+        assert!(!filter.matches(&cyrus));
+        cyrus.nickname = Some("Me".to_string());
+        assert!(filter.matches(&cyrus));
+    }
+
+    #[test]
+    fn matches_every_prop_filter() {
+        let filter = filter(&FN_OR_EMAIL.replace("anyof", "allof")).unwrap();
+
+        assert!(filter.matches(&contact("Cyrus Daboo", "daboo@example.com")));
+        assert!(!filter.matches(&contact("Cyrus Daboo", "cyrus@example.com")));
+    }
+
+    #[test]
+    fn match_types_and_negation() {
+        let alice = contact("Alice Liddell", "alice@example.com");
+        let matches = |text_match: &str| {
+            filter(&format!(
+                r#"><C:prop-filter name="FN">{}</C:prop-filter>"#,
+                text_match
+            ))
+            .unwrap()
+            .matches(&alice)
+        };
+
+        assert!(matches(
+            r#"<C:text-match match-type="equals">alice liddell</C:text-match>"#
+        ));
+        assert!(matches(
+            r#"<C:text-match match-type="starts-with">ALICE</C:text-match>"#
+        ));
+        assert!(matches(
+            r#"<C:text-match match-type="ends-with">liddell</C:text-match>"#
+        ));
+        assert!(!matches(
+            r#"<C:text-match match-type="equals">alice</C:text-match>"#
+        ));
+        assert!(!matches(
+            r#"<C:text-match collation="i;octet">alice</C:text-match>"#
+        ));
+        assert!(matches(
+            r#"<C:text-match negate-condition="yes">bob</C:text-match>"#
+        ));
+    }
+
+    #[test]
+    fn is_not_defined() {
+        let filter =
+            filter(r#"><C:prop-filter name="URL"><C:is-not-defined/></C:prop-filter>"#).unwrap();
+
+        let mut alice = contact("Alice", "alice@example.com");
+        assert!(filter.matches(&alice));
+        alice.url = Some("https://example.com".to_string());
+        assert!(!filter.matches(&alice));
+    }
+
+    #[test]
+    fn rejects_unsupported_filters() {
+        let collation = filter(
+            r#"><C:prop-filter name="FN"><C:text-match collation="i;klingon">a</C:text-match></C:prop-filter>"#,
+        );
+        assert_eq!(collation.unwrap_err().precondition(), "supported-collation");
+
+        let param =
+            filter(r#"><C:prop-filter name="TEL"><C:param-filter name="TYPE"/></C:prop-filter>"#);
+        assert_eq!(param.unwrap_err().precondition(), "supported-filter");
+    }
+}
@@ -0,0 +1,558 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use quick_xml::{
+    escape::escape,
+    events::{BytesStart, Event},
+    name::ResolveResult,
+    NsReader,
+};
+
+pub const DAV_NS: &str = "DAV:";
+pub const CARDDAV_NS: &str = "urn:ietf:params:xml:ns:carddav";
+pub const CALDAV_NS: &str = "urn:ietf:params:xml:ns:caldav";
+pub const CALENDARSERVER_NS: &str = "http://calendarserver.org/ns/";
+
+/// Prefixes of the namespaces declared on the root element of every response.
+const PREFIXES: &[(&str, &str)] = &[
+    (DAV_NS, "d"),
+    (CARDDAV_NS, "card"),
+    (CALDAV_NS, "cal"),
+    (CALENDARSERVER_NS, "cs"),
+];
+
+const CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
+/// An XML element, used both for parsed request bodies and for the properties
+/// of a response.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Element {
+    pub ns: String,
+    pub name: String,
+    /// Attributes by local name.
+    pub attributes: Vec<(String, String)>,
+    pub text: String,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    pub fn new(ns: &str, name: &str) -> Self {
+        Element {
+            ns: ns.to_string(),
+            name: name.to_string(),
+            ..Element::default()
+        }
+    }
+
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.text = text.into();
+        self
+    }
+
+    pub fn with_child(mut self, child: Element) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn is(&self, ns: &str, name: &str) -> bool {
+        self.ns == ns && self.name == name
+    }
+
+    pub fn child(&self, ns: &str, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.is(ns, name))
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the same element without its content, as listed in a `prop`
+    /// element of a request or a 404 propstat.
+    pub fn name_only(&self) -> Self {
+        Element::new(&self.ns, &self.name)
+    }
+
+    /// Serializes the element, adding `declarations` to its opening tag.
+    fn write(&self, out: &mut String, declarations: &str) {
+        // Known namespaces use the prefixes declared on the root element,
+        // anything else gets its own declaration.
+        let prefix = PREFIXES
+            .iter()
+            .find(|(ns, _)| *ns == self.ns)
+            .map(|(_, prefix)| prefix);
+        let (name, mut declaration) = match (prefix, self.ns.as_str()) {
+            (Some(prefix), _) => (format!("{}:{}", prefix, self.name), String::new()),
+            (None, "") => (self.name.clone(), String::new()),
+            (None, ns) => (
+                format!("x:{}", self.name),
+                format!(" xmlns:x=\"{}\"", escape(ns)),
+            ),
+        };
+        declaration.push_str(declarations);
+        for (attribute, value) in &self.attributes {
+            declaration.push_str(&format!(" {}=\"{}\"", attribute, escape(value.as_str())));
+        }
+
+        if self.text.is_empty() && self.children.is_empty() {
+            out.push_str(&format!("<{}{}/>", name, declaration));
+            return;
+        }
+
+        out.push_str(&format!("<{}{}>", name, declaration));
+        out.push_str(&escape(self.text.as_str()));
+        for child in &self.children {
+            child.write(out, "");
+        }
+        out.push_str(&format!("</{}>", name));
+    }
+}
+
+/// Properties of a resource sharing the same status.
+pub struct Propstat {
+    pub status: StatusCode,
+    pub props: Vec<Element>,
+    /// Precondition explaining the status.
+    pub error: Option<Element>,
+}
+
+impl Propstat {
+    /// Groups found properties under `200 OK` and missing ones under
+    /// `404 Not Found`, skipping empty groups.
+    pub fn found_and_missing(found: Vec<Element>, missing: Vec<Element>) -> Vec<Propstat> {
+        [(StatusCode::OK, found), (StatusCode::NOT_FOUND, missing)]
+            .into_iter()
+            .filter(|(_, props)| !props.is_empty())
+            .map(|(status, props)| Propstat {
+                status,
+                props,
+                error: None,
+            })
+            .collect()
+    }
+}
+
+/// Builder for a `207 Multi-Status` response body.
+#[derive(Default)]
+pub struct Multistatus {
+    children: Vec<Element>,
+}
+
+impl Multistatus {
+    pub fn new() -> Self {
+        Multistatus::default()
+    }
+
+    /// Adds the properties of a resource.
+    pub fn propstats(&mut self, href: &str, propstats: Vec<Propstat>) {
+        let mut response = Element::new(DAV_NS, "response").with_child(href_element(href));
+
+        for propstat in propstats {
+            let mut prop = Element::new(DAV_NS, "prop");
+            prop.children = propstat.props;
+
+            let mut element = Element::new(DAV_NS, "propstat")
+                .with_child(prop)
+                .with_child(status_element(propstat.status));
+            if let Some(error) = propstat.error {
+                element = element.with_child(Element::new(DAV_NS, "error").with_child(error));
+            }
+
+            response.children.push(element);
+        }
+
+        self.children.push(response);
+    }
+
+    /// Adds a status for a whole resource, without properties.
+    pub fn status(&mut self, href: &str, status: StatusCode) {
+        self.children.push(
+            Element::new(DAV_NS, "response")
+                .with_child(href_element(href))
+                .with_child(status_element(status)),
+        );
+    }
+
+    /// Adds a status for a whole resource along with a precondition element.
+    pub fn error(&mut self, href: &str, status: StatusCode, ns: &str, precondition: &str) {
+        self.children.push(
+            Element::new(DAV_NS, "response")
+                .with_child(href_element(href))
+                .with_child(status_element(status))
+                .with_child(
+                    Element::new(DAV_NS, "error").with_child(Element::new(ns, precondition)),
+                ),
+        );
+    }
+
+    pub fn sync_token(&mut self, token: &str) {
+        self.children
+            .push(Element::new(DAV_NS, "sync-token").with_text(token));
+    }
+}
+
+impl IntoResponse for Multistatus {
+    fn into_response(self) -> Response {
+        let mut root = Element::new(DAV_NS, "multistatus");
+        root.children = self.children;
+
+        (
+            StatusCode::MULTI_STATUS,
+            [(header::CONTENT_TYPE, CONTENT_TYPE)],
+            document(&root),
+        )
+            .into_response()
+    }
+}
+
+/// Builds an error response carrying a precondition element.
+pub fn error(status: StatusCode, ns: &str, precondition: &str) -> Response {
+    response(
+        status,
+        &Element::new(DAV_NS, "error").with_child(Element::new(ns, precondition)),
+    )
+}
+
+/// Builds a response with `root` as the XML body.
+pub fn response(status: StatusCode, root: &Element) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        document(root),
+    )
+        .into_response()
+}
+
+pub fn href_element(href: &str) -> Element {
+    Element::new(DAV_NS, "href").with_text(href)
+}
+
+fn status_element(status: StatusCode) -> Element {
+    Element::new(DAV_NS, "status").with_text(format!(
+        "HTTP/1.1 {} {}",
+        status.as_str(),
+        status.canonical_reason().unwrap_or_default()
+    ))
+}
+
+/// Serializes a root element along with the namespace declarations.
+fn document(root: &Element) -> String {
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    let declarations: String = PREFIXES
+        .iter()
+        .map(|(ns, prefix)| format!(" xmlns:{}=\"{}\"", prefix, ns))
+        .collect();
+    root.write(&mut body, &declarations);
+
+    body
+}
+
+/// Parses a request body into a tree of elements.
+pub fn parse(body: &str) -> Result<Element, String> {
+    let mut reader = NsReader::from_str(body);
+    reader.config_mut().trim_text(true);
+
+    let mut stack: Vec<Element> = Vec::new();
+
+    loop {
+        let (ns, event) = reader.read_resolved_event().map_err(|e| e.to_string())?;
+
+        match event {
+            Event::Start(start) => stack.push(element(ns, &start)?),
+            Event::Empty(start) => {
+                let element = element(ns, &start)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop().ok_or("unexpected closing tag")?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element
+                        .text
+                        .push_str(&text.unescape().map_err(|e| e.to_string())?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(element) = stack.last_mut() {
+                    element
+                        .text
+                        .push_str(&String::from_utf8_lossy(&data.into_inner()));
+                }
+            }
+            Event::Eof => return Err("unexpected end of document".to_string()),
+            _ => {}
+        }
+    }
+}
+
+fn element(ns: ResolveResult, start: &BytesStart) -> Result<Element, String> {
+    let ns = match ns {
+        ResolveResult::Bound(ns) => String::from_utf8_lossy(ns.as_ref()).into_owned(),
+        ResolveResult::Unbound => String::new(),
+        ResolveResult::Unknown(prefix) => {
+            return Err(format!(
+                "unknown namespace prefix '{}'",
+                String::from_utf8_lossy(&prefix)
+            ))
+        }
+    };
+
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        // Namespace declarations are already resolved by the reader.
+        if attribute.key.as_namespace_binding().is_some() {
+            continue;
+        }
+
+        attributes.push((
+            String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned(),
+            attribute
+                .unescape_value()
+                .map_err(|e| e.to_string())?
+                .into_owned(),
+        ));
+    }
+
+    Ok(Element {
+        ns,
+        name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+        attributes,
+        ..Element::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PROPFIND sent by DAVx⁵ when refreshing an address book, with a default
+    /// namespace and prefixes of its own.
+    const DAVX5_PROPFIND: &str = r#"<?xml version='1.0' encoding='UTF-8' ?>
+<propfind xmlns="DAV:" xmlns:CARD="urn:ietf:params:xml:ns:carddav" xmlns:CS="http://calendarserver.org/ns/">
+  <prop>
+    <resourcetype />
+    <displayname />
+    <CARD:addressbook-description />
+    <CARD:supported-address-data />
+    <CS:getctag />
+    <sync-token />
+    <current-user-privilege-set />
+  </prop>
+</propfind>"#;
+
+    /// Multiget sent by Evolution, using the `D` and `C` prefixes.
+    const EVOLUTION_MULTIGET: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<C:addressbook-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <D:prop>
+    <D:getetag/>
+    <C:address-data/>
+  </D:prop>
+  <D:href>/addressbooks/default/alice.vcf</D:href>
+  <D:href>/addressbooks/default/bob%20smith.vcf</D:href>
+</C:addressbook-multiget>"#;
+
+    /// Sync report sent by the macOS Contacts app, with single letter prefixes
+    /// and a property in a namespace of its own.
+    const MACOS_SYNC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<A:sync-collection xmlns:A="DAV:">
+  <A:sync-token>http://localhost/sync/42</A:sync-token>
+  <A:sync-level>1</A:sync-level>
+  <A:prop>
+    <A:getetag/>
+    <B:me-card xmlns:B="http://calendarserver.org/ns/"/>
+    <E:getctag xmlns:E="http://apple.com/ns/ical/"/>
+  </A:prop>
+</A:sync-collection>"#;
+
+    /// PROPPATCH sent by the macOS Contacts app when renaming an address book.
+    const MACOS_PROPPATCH: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<A:propertyupdate xmlns:A="DAV:">
+  <A:set>
+    <A:prop>
+      <A:displayname>Friends &amp; Family</A:displayname>
+    </A:prop>
+  </A:set>
+  <A:remove>
+    <A:prop>
+      <C:addressbook-description xmlns:C="urn:ietf:params:xml:ns:carddav"/>
+    </A:prop>
+  </A:remove>
+</A:propertyupdate>"#;
+
+    fn names(element: &Element) -> Vec<(&str, &str)> {
+        element
+            .children
+            .iter()
+            .map(|child| (child.ns.as_str(), child.name.as_str()))
+            .collect()
+    }
+
+    /// Serializes the element as a response body and parses it back.
+    fn round_trip(element: &Element) -> Element {
+        parse(&document(element)).unwrap()
+    }
+
+    #[test]
+    fn parses_a_davx5_propfind() {
+        let propfind = parse(DAVX5_PROPFIND).unwrap();
+
+        assert!(propfind.is(DAV_NS, "propfind"));
+        assert_eq!(
+            names(propfind.child(DAV_NS, "prop").unwrap()),
+            [
+                (DAV_NS, "resourcetype"),
+                (DAV_NS, "displayname"),
+                (CARDDAV_NS, "addressbook-description"),
+                (CARDDAV_NS, "supported-address-data"),
+                (CALENDARSERVER_NS, "getctag"),
+                (DAV_NS, "sync-token"),
+                (DAV_NS, "current-user-privilege-set"),
+            ]
+        );
+        assert_eq!(round_trip(&propfind), propfind);
+    }
+
+    #[test]
+    fn parses_an_evolution_multiget() {
+        let multiget = parse(EVOLUTION_MULTIGET).unwrap();
+
+        assert!(multiget.is(CARDDAV_NS, "addressbook-multiget"));
+        assert_eq!(
+            names(multiget.child(DAV_NS, "prop").unwrap()),
+            [(DAV_NS, "getetag"), (CARDDAV_NS, "address-data")]
+        );
+        let hrefs: Vec<_> = multiget
+            .children
+            .iter()
+            .filter(|child| child.is(DAV_NS, "href"))
+            .map(|href| href.text.as_str())
+            .collect();
+        assert_eq!(
+            hrefs,
+            [
+                "/addressbooks/default/alice.vcf",
+                "/addressbooks/default/bob%20smith.vcf"
+            ]
+        );
+        assert_eq!(round_trip(&multiget), multiget);
+    }
+
+    #[test]
+    fn parses_a_macos_sync_collection() {
+        let sync = parse(MACOS_SYNC).unwrap();
+
+        assert!(sync.is(DAV_NS, "sync-collection"));
+        assert_eq!(
+            sync.child(DAV_NS, "sync-token").unwrap().text,
+            "http://localhost/sync/42"
+        );
+        assert_eq!(sync.child(DAV_NS, "sync-level").unwrap().text, "1");
+        assert_eq!(
+            names(sync.child(DAV_NS, "prop").unwrap()),
+            [
+                (DAV_NS, "getetag"),
+                (CALENDARSERVER_NS, "me-card"),
+                ("http://apple.com/ns/ical/", "getctag"),
+            ]
+        );
+        // The unknown namespace gets a declaration of its own.
+        assert!(document(&sync).contains(r#"<x:getctag xmlns:x="http://apple.com/ns/ical/"/>"#));
+        assert_eq!(round_trip(&sync), sync);
+    }
+
+    #[test]
+    fn parses_a_macos_proppatch() {
+        let update = parse(MACOS_PROPPATCH).unwrap();
+
+        assert!(update.is(DAV_NS, "propertyupdate"));
+        let set = update.child(DAV_NS, "set").unwrap();
+        let name = set
+            .child(DAV_NS, "prop")
+            .and_then(|prop| prop.child(DAV_NS, "displayname"))
+            .unwrap();
+        assert_eq!(name.text, "Friends & Family");
+        let remove = update.child(DAV_NS, "remove").unwrap();
+        assert_eq!(
+            names(remove.child(DAV_NS, "prop").unwrap()),
+            [(CARDDAV_NS, "addressbook-description")]
+        );
+
+        // The text is escaped again when serialized.
+        assert!(document(&update).contains("<d:displayname>Friends &amp; Family</d:displayname>"));
+        assert_eq!(round_trip(&update), update);
+    }
+
+    #[test]
+    fn keeps_attributes_and_unbound_elements() {
+        let element = parse(
+            r#"<C:text-match xmlns:C="urn:ietf:params:xml:ns:carddav" match-type="starts-with" negate-condition="yes">Dab&lt;</C:text-match>"#,
+        )
+        .unwrap();
+        assert_eq!(element.attribute("match-type"), Some("starts-with"));
+        assert_eq!(element.attribute("negate-condition"), Some("yes"));
+        assert_eq!(element.text, "Dab<");
+        assert_eq!(round_trip(&element), element);
+
+        let unbound = parse("<note><line>one</line></note>").unwrap();
+        assert_eq!(names(&unbound), [("", "line")]);
+        assert_eq!(round_trip(&unbound), unbound);
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(parse("<A:propfind xmlns:A=\"DAV:\"><A:prop>").is_err());
+        assert!(parse("<B:propfind xmlns:A=\"DAV:\"/>").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn serializes_a_multistatus() {
+        let mut multistatus = Multistatus::new();
+        multistatus.propstats(
+            "/addressbooks/default/",
+            Propstat::found_and_missing(
+                vec![Element::new(DAV_NS, "displayname").with_text("Contacts")],
+                vec![Element::new(CALENDARSERVER_NS, "getctag")],
+            ),
+        );
+        multistatus.status("/addressbooks/default/gone.vcf", StatusCode::NOT_FOUND);
+        multistatus.sync_token("http://localhost/sync/43");
+
+        let mut root = Element::new(DAV_NS, "multistatus");
+        root.children = multistatus.children;
+        let body = document(&root);
+        assert!(body.starts_with(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"DAV:\" xmlns:card=\"urn:ietf:params:xml:ns:carddav\""
+        ));
+
+        let parsed = parse(&body).unwrap();
+        assert_eq!(parsed, root);
+        let statuses: Vec<_> = parsed.children[0]
+            .children
+            .iter()
+            .filter_map(|propstat| propstat.child(DAV_NS, "status"))
+            .map(|status| status.text.as_str())
+            .collect();
+        assert_eq!(statuses, ["HTTP/1.1 200 OK", "HTTP/1.1 404 Not Found"]);
+        assert_eq!(
+            parsed.children[1].child(DAV_NS, "status").unwrap().text,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(
+            parsed.child(DAV_NS, "sync-token").unwrap().text,
+            "http://localhost/sync/43"
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs::{self, File, TryLockError};
+use std::io;
+use std::path::Path;
+
+/// Name of the lock file at the root of the data directory.
+const LOCK_FILE: &str = "dav.lock";
+
+/// Exclusive lock on the data directory, held by the server while it runs and
+/// by the offline commands, so two processes never write to the same stores.
+/// The operating system releases it when the process exits, even on a crash.
+pub struct DirLock {
+    _file: File,
+}
+
+impl DirLock {
+    /// Takes the lock, failing right away if another process holds it.
+    pub fn acquire(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(LOCK_FILE))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(DirLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "the data directory is in use by another dav process",
+            )),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::contact::Contact;
+use crate::search::{normalize_email, normalize_name};
+
+/// Contacts that are probably the same person, linked by a shared email
+/// address or name, possibly through other contacts of the cluster.
+#[derive(Serialize)]
+pub struct Cluster {
+    pub contacts: Vec<Contact>,
+    /// Normalized email addresses shared by several contacts of the cluster.
+    pub emails: Vec<String>,
+    /// Normalized names shared by several contacts of the cluster.
+    pub names: Vec<String>,
+}
+
+/// Groups the contacts sharing a normalized email address or name, ignoring
+/// empty ones. Clusters and their contacts are sorted by ID, and contacts
+/// without a duplicate are left out.
+pub fn find(mut contacts: Vec<Contact>) -> Vec<Cluster> {
+    contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+    // Union-find over the positions of the contacts, each set being a
+    // cluster led by its first contact.
+    let mut leaders: Vec<usize> = (0..contacts.len()).collect();
+    let mut emails: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut names: HashMap<String, Vec<usize>> = HashMap::new();
+
+    for (index, contact) in contacts.iter().enumerate() {
+        let contact_emails: BTreeSet<String> = contact
+            .emails()
+            .into_iter()
+            .map(normalize_email)
+            .filter(|email| !email.is_empty())
+            .collect();
+        for email in contact_emails {
+            emails.entry(email).or_default().push(index);
+        }
+
+        let name = normalize_name(&contact.name);
+        if !name.is_empty() {
+            names.entry(name).or_default().push(index);
+        }
+    }
+
+    for indexes in emails.values().chain(names.values()) {
+        for &index in &indexes[1..] {
+            let (a, b) = (
+                leader(&mut leaders, indexes[0]),
+                leader(&mut leaders, index),
+            );
+            leaders[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); contacts.len()];
+    for index in 0..contacts.len() {
+        let leader = leader(&mut leaders, index);
+        members[leader].push(index);
+    }
+
+    let shared = |keys: &HashMap<String, Vec<usize>>, cluster: &[usize]| {
+        let mut shared: Vec<String> = keys
+            .iter()
+            .filter(|(_, indexes)| indexes.len() > 1 && cluster.contains(&indexes[0]))
+            .map(|(key, _)| key.clone())
+            .collect();
+        shared.sort();
+        shared
+    };
+
+    let clusters: Vec<(Vec<usize>, Vec<String>, Vec<String>)> = members
+        .into_iter()
+        .filter(|cluster| cluster.len() > 1)
+        .map(|cluster| {
+            let emails = shared(&emails, &cluster);
+            let names = shared(&names, &cluster);
+            (cluster, emails, names)
+        })
+        .collect();
+
+    let mut contacts: Vec<Option<Contact>> = contacts.into_iter().map(Some).collect();
+    clusters
+        .into_iter()
+        .map(|(cluster, emails, names)| Cluster {
+            contacts: cluster
+                .into_iter()
+                .filter_map(|index| contacts[index].take())
+                .collect(),
+            emails,
+            names,
+        })
+        .collect()
+}
+
+/// Leader of the set of a contact, compressing the path to it on the way.
+fn leader(leaders: &mut [usize], mut index: usize) -> usize {
+    while leaders[index] != index {
+        leaders[index] = leaders[leaders[index]];
+        index = leaders[index];
+    }
+    index
+}
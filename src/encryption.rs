@@ -0,0 +1,349 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use uuid::Uuid;
+
+/// Start of every encrypted file, followed by the version of the format.
+const MAGIC: &[u8] = b"DAVENC";
+
+/// Version of the format: [`MAGIC`], this byte, a 24-byte nonce, then the
+/// XChaCha20-Poly1305 ciphertext and its tag.
+const VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 24;
+
+/// Length of the header of an encrypted file, before the ciphertext.
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_LEN;
+
+/// Key encrypting the contact files, their history and the backups, parsed
+/// from 64 hex digits. Its debug output leaves the key out.
+#[derive(Clone)]
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Cipher {
+            aead: XChaCha20Poly1305::new(&(*key).into()),
+        }
+    }
+
+    /// Encrypts `plaintext` under a random nonce, the header being
+    /// authenticated along with it.
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::fill(&mut nonce).map_err(io::Error::other)?;
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        sealed.extend(MAGIC);
+        sealed.push(VERSION);
+        let ciphertext = self
+            .aead
+            .encrypt(
+                &XNonce::from(nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &sealed,
+                },
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        sealed.extend(nonce);
+        sealed.extend(ciphertext);
+
+        Ok(sealed)
+    }
+
+    /// Decrypts what [`Cipher::seal`] returned, failing if the data was
+    /// encrypted with another key or changed since.
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        if !is_encrypted(sealed) {
+            return Err(invalid("not an encrypted file"));
+        }
+        if sealed[MAGIC.len()] != VERSION {
+            return Err(invalid(format!(
+                "unsupported encryption format version {}",
+                sealed[MAGIC.len()]
+            )));
+        }
+        if sealed.len() < HEADER_LEN {
+            return Err(invalid("truncated encrypted file"));
+        }
+
+        let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+        let (aad, nonce) = header.split_at(MAGIC.len() + 1);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().map_err(invalid)?;
+        self.aead
+            .decrypt(
+                &XNonce::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| invalid("cannot decrypt, wrong key or damaged file"))
+    }
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cipher(..)")
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("expected a key of 64 hex digits".to_string());
+        }
+
+        let mut key = [0; 32];
+        for (byte, pair) in key.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|e| e.to_string())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|e| e.to_string())?;
+        }
+
+        Ok(Cipher::new(&key))
+    }
+}
+
+/// Whether data starts like an encrypted file.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.len() > MAGIC.len() && data.starts_with(MAGIC)
+}
+
+/// Turns the content of a file into text, decrypting it if it is encrypted.
+/// Plain files are read as they are, also with a key, so a directory can be
+/// encrypted little by little.
+pub fn decode(data: Vec<u8>, cipher: Option<&Cipher>) -> io::Result<String> {
+    let data = match cipher {
+        _ if !is_encrypted(&data) => data,
+        Some(cipher) => cipher.open(&data)?,
+        None => {
+            return Err(invalid(
+                "file is encrypted, set DAV_ENCRYPTION_KEY or DAV_ENCRYPTION_KEY_FILE",
+            ))
+        }
+    };
+
+    String::from_utf8(data).map_err(invalid)
+}
+
+/// Turns text into the content of a file, encrypted with a key.
+pub fn encode(text: &str, cipher: Option<&Cipher>) -> io::Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.seal(text.as_bytes()),
+        None => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+/// Reads a file written with [`encode`].
+pub async fn read(path: &Path, cipher: Option<&Cipher>) -> io::Result<String> {
+    decode(tokio::fs::read(path).await?, cipher)
+}
+
+/// Encrypts in place every plain `.vcf` file under `dir`, for `dav encrypt`.
+/// Each file is replaced atomically and keeps its modification time, which
+/// the trash takes as the deletion time. Returns the number of encrypted
+/// files.
+pub fn encrypt_dir(dir: &Path, cipher: &Cipher) -> io::Result<usize> {
+    let mut encrypted = 0;
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        // Files being written are left to their writer.
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            encrypted += encrypt_dir(&path, cipher)?;
+        } else if file_type.is_file()
+            && path.extension().is_some_and(|extension| extension == "vcf")
+            && encrypt_file(&path, cipher)?
+        {
+            encrypted += 1;
+        }
+    }
+
+    Ok(encrypted)
+}
+
+/// Encrypts a file unless it already is, returning whether it was.
+fn encrypt_file(path: &Path, cipher: &Cipher) -> io::Result<bool> {
+    let data = fs::read(path)?;
+    if is_encrypted(&data) {
+        return Ok(false);
+    }
+    let modified = fs::metadata(path)?.modified()?;
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dir.join(format!(".{}.{}.tmp", name, Uuid::new_v4().simple()));
+    let written = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&cipher.seal(&data)?)?;
+        file.set_modified(modified)?;
+        file.sync_all()?;
+
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(e) = written {
+        // The plain file is left as it was.
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    Ok(true)
+}
+
+fn invalid<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> Cipher {
+        Cipher::new(&[byte; 32])
+    }
+
+    #[test]
+    fn seals_and_opens() {
+        let cipher = cipher(1);
+        let sealed = cipher
+            .seal(b"BEGIN:VCARD\r\nFN:Alice\r\nEND:VCARD\r\n")
+            .unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert_eq!(sealed[MAGIC.len()], VERSION);
+        assert!(!sealed
+            .windows(b"Alice".len())
+            .any(|window| window == b"Alice"));
+        assert_eq!(
+            cipher.open(&sealed).unwrap(),
+            b"BEGIN:VCARD\r\nFN:Alice\r\nEND:VCARD\r\n"
+        );
+
+        // Every seal draws a new nonce.
+        assert_ne!(cipher.seal(b"same").unwrap(), cipher.seal(b"same").unwrap());
+    }
+
+    #[test]
+    fn rejects_a_wrong_key_and_changed_data() {
+        let sealed = cipher(1).seal(b"FN:Alice").unwrap();
+        assert!(cipher(2).open(&sealed).is_err());
+
+        let mut changed = sealed.clone();
+        let last = changed.len() - 1;
+        changed[last] ^= 1;
+        assert!(cipher(1).open(&changed).is_err());
+
+        // The version is authenticated as well.
+        let mut version = sealed.clone();
+        version[MAGIC.len()] = 2;
+        assert!(cipher(1).open(&version).is_err());
+
+        assert!(cipher(1).open(&sealed[..HEADER_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn parses_hex_keys() {
+        let key = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+        let sealed = key.parse::<Cipher>().unwrap().seal(b"FN:Alice").unwrap();
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = ((i % 16) as u8) * 0x11;
+        }
+        assert_eq!(Cipher::new(&bytes).open(&sealed).unwrap(), b"FN:Alice");
+
+        // Surrounding whitespace, as in a key file, is ignored.
+        assert!(format!("{}\n", key).parse::<Cipher>().is_ok());
+
+        assert!("00112233".parse::<Cipher>().is_err());
+        assert!(key.replace('0', "g").parse::<Cipher>().is_err());
+        assert!(format!("{}é", &key[..62]).parse::<Cipher>().is_err());
+        assert_eq!(format!("{:?}", cipher(1)), "Cipher(..)");
+    }
+
+    #[test]
+    fn decodes_plain_and_encrypted_files() {
+        let cipher = cipher(1);
+        let sealed = encode("FN:Alice", Some(&cipher)).unwrap();
+
+        assert_eq!(decode(sealed.clone(), Some(&cipher)).unwrap(), "FN:Alice");
+        assert_eq!(decode(b"FN:Bob".to_vec(), Some(&cipher)).unwrap(), "FN:Bob");
+        assert_eq!(decode(b"FN:Bob".to_vec(), None).unwrap(), "FN:Bob");
+
+        let e = decode(sealed, None).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("DAV_ENCRYPTION_KEY"));
+    }
+
+    #[test]
+    fn encrypts_a_directory_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = cipher(1);
+        let shard = dir.path().join("ab");
+        fs::create_dir_all(&shard).unwrap();
+        fs::write(shard.join("alice.vcf"), "FN:Alice").unwrap();
+        fs::write(dir.path().join("bob.vcf"), "FN:Bob").unwrap();
+        fs::write(dir.path().join("notes.txt"), "plain").unwrap();
+        fs::write(dir.path().join(".carol.vcf.1.tmp"), "FN:Carol").unwrap();
+
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(dir.path().join("bob.vcf"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        assert_eq!(encrypt_dir(dir.path(), &cipher).unwrap(), 2);
+        let alice = fs::read(shard.join("alice.vcf")).unwrap();
+        assert!(is_encrypted(&alice));
+        assert_eq!(decode(alice, Some(&cipher)).unwrap(), "FN:Alice");
+        assert_eq!(
+            fs::metadata(dir.path().join("bob.vcf"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            old
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+            "plain"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".carol.vcf.1.tmp")).unwrap(),
+            "FN:Carol"
+        );
+
+        // Encrypted files are left as they are.
+        assert_eq!(encrypt_dir(dir.path(), &cipher).unwrap(), 0);
+        assert_eq!(
+            decode(fs::read(shard.join("alice.vcf")).unwrap(), Some(&cipher)).unwrap(),
+            "FN:Alice"
+        );
+    }
+}
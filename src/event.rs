@@ -0,0 +1,469 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::contact::{property_name, unfold};
+use crate::rrule::Rule;
+
+/// Start or end of an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EventTime {
+    /// A point in time, written with a `Z` suffix.
+    Utc(DateTime<Utc>),
+    /// A local time. Times with a `TZID` parameter are read as floating since
+    /// time zones are not resolved.
+    Floating(NaiveDateTime),
+    /// A whole day.
+    Date(NaiveDate),
+}
+
+impl EventTime {
+    /// Parses the value of a date property such as `DTSTART` along with its
+    /// parameters.
+    pub fn parse(property: &str, value: &str) -> Result<Self, String> {
+        let is_date = property
+            .split(';')
+            .skip(1)
+            .any(|param| param.eq_ignore_ascii_case("VALUE=DATE"));
+
+        let parsed = if is_date {
+            NaiveDate::parse_from_str(value, "%Y%m%d").map(EventTime::Date)
+        } else if let Some(value) = value.strip_suffix('Z') {
+            NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+                .map(|time| EventTime::Utc(time.and_utc()))
+        } else {
+            NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map(EventTime::Floating)
+        };
+
+        parsed.map_err(|e| format!("invalid date '{}': {}", value, e))
+    }
+
+    /// The time read as UTC, floating times and dates included, to compare
+    /// events with each other.
+    pub fn as_utc(&self) -> DateTime<Utc> {
+        match self {
+            EventTime::Utc(time) => *time,
+            EventTime::Floating(time) => time.and_utc(),
+            EventTime::Date(date) => date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+        }
+    }
+
+    /// The same kind of time, moved to `time`.
+    fn moved_to(&self, time: NaiveDateTime) -> Self {
+        match self {
+            EventTime::Utc(_) => EventTime::Utc(time.and_utc()),
+            EventTime::Floating(_) => EventTime::Floating(time),
+            EventTime::Date(_) => EventTime::Date(time.date()),
+        }
+    }
+
+    /// Writes the time as the value of `property`.
+    pub fn write(&self, f: &mut fmt::Formatter<'_>, property: &str) -> fmt::Result {
+        write!(f, "{}\r\n", self.property(property))
+    }
+
+    /// Returns the line of `property` holding the time.
+    fn property(&self, property: &str) -> String {
+        match self {
+            EventTime::Utc(time) => format!("{}:{}", property, time.format("%Y%m%dT%H%M%SZ")),
+            EventTime::Floating(time) => format!("{}:{}", property, time.format("%Y%m%dT%H%M%S")),
+            EventTime::Date(date) => format!("{};VALUE=DATE:{}", property, date.format("%Y%m%d")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Event {
+    pub id: String,
+    #[serde(default)]
+    pub summary: String,
+    pub start: EventTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<EventTime>,
+    /// ISO 8601 duration such as `PT1H`, used instead of `end`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    #[serde(default)]
+    pub location: String,
+    #[serde(default)]
+    pub description: String,
+    /// Recurrence rule such as `FREQ=WEEKLY;BYDAY=MO,WE`, see [`Rule`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rrule: Option<String>,
+    /// Starts of the occurrences removed from the series.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exdates: Vec<EventTime>,
+    /// Properties of the event without a field of their own, kept verbatim.
+    #[serde(skip)]
+    pub extras: Vec<String>,
+}
+
+impl Event {
+    /// End of the event: its `end`, or its start plus its duration. Events
+    /// without either last a day when they start on a date, and no time
+    /// otherwise.
+    pub fn end(&self) -> DateTime<Utc> {
+        let start = self.start.as_utc();
+
+        match (&self.end, self.duration.as_deref().and_then(parse_duration)) {
+            (Some(end), _) => end.as_utc(),
+            (None, Some(duration)) => start + duration,
+            (None, None) if matches!(self.start, EventTime::Date(_)) => start + TimeDelta::days(1),
+            (None, None) => start,
+        }
+    }
+
+    /// Whether the event is marked `TRANSP:TRANSPARENT`, leaving its time free.
+    pub fn is_transparent(&self) -> bool {
+        self.extras.iter().any(|line| {
+            line.split_once(':').is_some_and(|(property, value)| {
+                property_name(property) == "TRANSP" && value.eq_ignore_ascii_case("TRANSPARENT")
+            })
+        })
+    }
+
+    /// Whether the event overlaps the `[start, end)` range, as defined for
+    /// CalDAV time ranges. Missing bounds are unlimited.
+    pub fn overlaps(&self, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> bool {
+        let event_start = self.start.as_utc();
+        let event_end = self.end();
+
+        let starts_before_end = end.is_none_or(|end| event_start < end);
+        let ends_after_start = match start {
+            None => true,
+            // An event without duration is matched by its start.
+            Some(start) if event_end == event_start => event_start >= start,
+            Some(start) => event_end > start,
+        };
+
+        starts_before_end && ends_after_start
+    }
+
+    /// Returns the occurrences of the event overlapping the `[start, end)`
+    /// range, each with its own start and end and a `RECURRENCE-ID`. An event
+    /// without `rrule` is its only occurrence.
+    pub fn instances(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Event>, String> {
+        let Some(rule) = &self.rrule else {
+            return Ok(self
+                .overlaps(start, end)
+                .then(|| self.clone())
+                .into_iter()
+                .collect());
+        };
+        let rule: Rule = rule.parse()?;
+
+        let first = self.start.as_utc().naive_utc();
+        let length = self.end() - self.start.as_utc();
+        let occurrences = rule.occurrences(first, end.map(|end| end.naive_utc()));
+
+        Ok(occurrences
+            .into_iter()
+            .filter(|time| !self.is_excluded(*time))
+            .map(|time| self.occurrence(time, length))
+            .filter(|instance| instance.overlaps(start, end))
+            .collect())
+    }
+
+    /// Whether an occurrence starting at `time` is listed in `exdates`. A date
+    /// excludes the occurrences of the whole day.
+    fn is_excluded(&self, time: NaiveDateTime) -> bool {
+        self.exdates.iter().any(|exdate| match exdate {
+            EventTime::Date(date) => *date == time.date(),
+            exdate => exdate.as_utc().naive_utc() == time,
+        })
+    }
+
+    /// Returns the occurrence of the series starting at `time`.
+    fn occurrence(&self, time: NaiveDateTime, length: TimeDelta) -> Event {
+        let start = self.start.moved_to(time);
+        let mut extras = self.extras.clone();
+        extras.push(start.property("RECURRENCE-ID"));
+
+        Event {
+            start,
+            end: self.end.map(|end| end.moved_to(time + length)),
+            rrule: None,
+            exdates: Vec::new(),
+            extras,
+            ..self.clone()
+        }
+    }
+}
+
+/// Parses an ISO 8601 duration as used in iCalendar, such as `P1D` or
+/// `PT1H30M`. Only weeks, days, hours, minutes and seconds are allowed.
+fn parse_duration(value: &str) -> Option<TimeDelta> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in value.strip_prefix('P')?.chars() {
+        let unit = match (c, in_time) {
+            ('0'..='9', _) => {
+                number.push(c);
+                continue;
+            }
+            ('T', false) => {
+                in_time = true;
+                continue;
+            }
+            ('W', false) => TimeDelta::weeks,
+            ('D', false) => TimeDelta::days,
+            ('H', true) => TimeDelta::hours,
+            ('M', true) => TimeDelta::minutes,
+            ('S', true) => TimeDelta::seconds,
+            _ => return None,
+        };
+
+        total += unit(number.parse().ok()?);
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(if negative { -total } else { total })
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    /// Parses the first `VEVENT` of an iCalendar object.
+    fn from_str(ics: &str) -> Result<Self, Self::Err> {
+        let mut id = None;
+        let mut summary = None;
+        let mut start = None;
+        let mut end = None;
+        let mut duration = None;
+        let mut location = None;
+        let mut description = None;
+        let mut rrule = None;
+        let mut exdates = Vec::new();
+        let mut extras = Vec::new();
+        let mut in_event = false;
+
+        for line in unfold(ics) {
+            let Some((property, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let name = property_name(property);
+            match (name.as_str(), in_event) {
+                ("BEGIN", false) if value.eq_ignore_ascii_case("VEVENT") => in_event = true,
+                ("END", true) if value.eq_ignore_ascii_case("VEVENT") => break,
+                (_, false) => {}
+                ("UID", true) if id.is_none() => id = Some(value.to_string()),
+                ("SUMMARY", true) if summary.is_none() => summary = Some(value.to_string()),
+                ("DTSTART", true) if start.is_none() => {
+                    start = Some(EventTime::parse(property, value)?)
+                }
+                ("DTEND", true) if end.is_none() => end = Some(EventTime::parse(property, value)?),
+                ("DURATION", true) if duration.is_none() => duration = Some(value.to_string()),
+                ("LOCATION", true) if location.is_none() => location = Some(value.to_string()),
+                ("DESCRIPTION", true) if description.is_none() => {
+                    description = Some(value.to_string())
+                }
+                ("RRULE", true) if rrule.is_none() => rrule = Some(value.to_string()),
+                ("EXDATE", true) => {
+                    for value in value.split(',') {
+                        exdates.push(EventTime::parse(property, value)?);
+                    }
+                }
+                _ => extras.push(line),
+            }
+        }
+
+        Ok(Event {
+            id: id.ok_or("event UID is empty")?,
+            summary: summary.unwrap_or_default(),
+            start: start.ok_or("event DTSTART is missing")?,
+            end,
+            duration,
+            location: location.unwrap_or_default(),
+            description: description.unwrap_or_default(),
+            rrule,
+            exdates,
+            extras,
+        })
+    }
+}
+
+/// Serializes the event as an iCalendar object with CRLF line endings.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Calendar(std::slice::from_ref(self)).fmt(f)
+    }
+}
+
+/// Several events, such as the occurrences of a series, serialized as a single
+/// iCalendar object.
+pub struct Calendar<'a>(pub &'a [Event]);
+
+impl fmt::Display for Calendar<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//dav//EN\r\n")?;
+        for event in self.0 {
+            event.write_vevent(f)?;
+        }
+
+        write!(f, "END:VCALENDAR\r\n")
+    }
+}
+
+impl Event {
+    fn write_vevent(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BEGIN:VEVENT\r\nUID:{}\r\nSUMMARY:{}\r\n",
+            self.id, self.summary
+        )?;
+
+        self.start.write(f, "DTSTART")?;
+        if let Some(end) = &self.end {
+            end.write(f, "DTEND")?;
+        } else if let Some(duration) = &self.duration {
+            write!(f, "DURATION:{}\r\n", duration)?;
+        }
+
+        if !self.location.is_empty() {
+            write!(f, "LOCATION:{}\r\n", self.location)?;
+        }
+
+        if !self.description.is_empty() {
+            write!(f, "DESCRIPTION:{}\r\n", self.description)?;
+        }
+
+        if let Some(rrule) = &self.rrule {
+            write!(f, "RRULE:{}\r\n", rrule)?;
+        }
+
+        for exdate in &self.exdates {
+            exdate.write(f, "EXDATE")?;
+        }
+
+        for extra in &self.extras {
+            write!(f, "{}\r\n", extra)?;
+        }
+
+        write!(f, "END:VEVENT\r\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(properties: &str) -> Event {
+        format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:standup\r\nSUMMARY:Standup\r\n{}END:VEVENT\r\nEND:VCALENDAR\r\n",
+            properties
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn starts(instances: &[Event]) -> Vec<String> {
+        instances
+            .iter()
+            .map(|instance| instance.start.as_utc().format("%Y%m%dT%H%M").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn exdates_remove_occurrences() {
+        let event = event(
+            "DTSTART:20240101T090000Z\r\nDTEND:20240101T091500Z\r\nRRULE:FREQ=DAILY;COUNT=5\r\n\
+             EXDATE:20240102T090000Z,20240104T090000Z\r\n",
+        );
+        assert_eq!(event.exdates.len(), 2);
+
+        // Excluded occurrences still count for COUNT.
+        let instances = event.instances(None, None).unwrap();
+        assert_eq!(
+            starts(&instances),
+            ["20240101T0900", "20240103T0900", "20240105T0900"]
+        );
+        for instance in &instances {
+            assert_eq!(
+                instance.end() - instance.start.as_utc(),
+                TimeDelta::minutes(15)
+            );
+            assert!(instance.rrule.is_none() && instance.exdates.is_empty());
+        }
+        assert!(instances[1]
+            .extras
+            .contains(&"RECURRENCE-ID:20240103T090000Z".to_string()));
+    }
+
+    #[test]
+    fn date_exdates_remove_the_whole_day() {
+        let event = event(
+            "DTSTART:20240101T090000\r\nRRULE:FREQ=DAILY;COUNT=3\r\nEXDATE;VALUE=DATE:20240102\r\n",
+        );
+        assert_eq!(
+            starts(&event.instances(None, None).unwrap()),
+            ["20240101T0900", "20240103T0900"]
+        );
+    }
+
+    #[test]
+    fn exdates_not_on_an_occurrence_change_nothing() {
+        let event = event(
+            "DTSTART:20240101T090000Z\r\nRRULE:FREQ=DAILY;COUNT=2\r\nEXDATE:20240102T100000Z\r\n",
+        );
+        assert_eq!(
+            starts(&event.instances(None, None).unwrap()),
+            ["20240101T0900", "20240102T0900"]
+        );
+    }
+
+    #[test]
+    fn instances_are_limited_to_the_range() {
+        let event = event(
+            "DTSTART:20240101T090000Z\r\nDURATION:PT1H\r\nRRULE:FREQ=WEEKLY;BYDAY=MO,TH\r\n\
+             EXDATE:20240111T090000Z\r\n",
+        );
+
+        let instances = event
+            .instances(Some(utc("20240105T000000")), Some(utc("20240119T000000")))
+            .unwrap();
+        assert_eq!(
+            starts(&instances),
+            ["20240108T0900", "20240115T0900", "20240118T0900"]
+        );
+    }
+
+    #[test]
+    fn writes_the_exdates() {
+        let event = event(
+            "DTSTART:20240101T090000Z\r\nRRULE:FREQ=DAILY;COUNT=5\r\n\
+             EXDATE:20240102T090000Z\r\nEXDATE;VALUE=DATE:20240104\r\n",
+        );
+
+        let ics = event.to_string();
+        assert!(ics.contains(
+            "RRULE:FREQ=DAILY;COUNT=5\r\nEXDATE:20240102T090000Z\r\nEXDATE;VALUE=DATE:20240104\r\n"
+        ));
+        let parsed: Event = ics.parse().unwrap();
+        assert_eq!(parsed.exdates, event.exdates);
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::event::Event;
+
+/// A time during which at least one event takes place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Busy {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Returns the busy times within `[start, end)`, sorted and merged so that
+/// overlapping or adjacent events make a single interval. Transparent events
+/// and events without duration leave the time free.
+pub fn busy(events: &[Event], start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<Busy> {
+    let mut intervals: Vec<Busy> = events
+        .iter()
+        .filter(|event| !event.is_transparent())
+        .flat_map(|event| {
+            event.instances(Some(start), Some(end)).unwrap_or_else(|_| {
+                // An unsupported rule leaves only the first occurrence.
+                event
+                    .overlaps(Some(start), Some(end))
+                    .then(|| event.clone())
+                    .into_iter()
+                    .collect()
+            })
+        })
+        .map(|instance| Busy {
+            start: instance.start.as_utc().max(start),
+            end: instance.end().min(end),
+        })
+        .filter(|busy| busy.start < busy.end)
+        .collect();
+    intervals.sort_by_key(|busy| busy.start);
+
+    let mut merged: Vec<Busy> = Vec::new();
+    for interval in intervals {
+        match merged.last_mut() {
+            Some(last) if interval.start <= last.end => last.end = last.end.max(interval.end),
+            _ => merged.push(interval),
+        }
+    }
+
+    merged
+}
+
+/// Serializes busy times as an iCalendar object holding a `VFREEBUSY`
+/// component for the `[start, end)` range.
+pub fn vfreebusy(busy: &[Busy], start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    const FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+    let mut ics = format!(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//dav//EN\r\nBEGIN:VFREEBUSY\r\nDTSTAMP:{}\r\nDTSTART:{}\r\nDTEND:{}\r\n",
+        Utc::now().format(FORMAT),
+        start.format(FORMAT),
+        end.format(FORMAT)
+    );
+
+    for busy in busy {
+        ics.push_str(&format!(
+            "FREEBUSY:{}/{}\r\n",
+            busy.start.format(FORMAT),
+            busy.end.format(FORMAT)
+        ));
+    }
+
+    ics.push_str("END:VFREEBUSY\r\nEND:VCALENDAR\r\n");
+    ics
+}
@@ -0,0 +1,126 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::fs;
+
+use crate::contact::Contact;
+use crate::encryption::{self, Cipher};
+use crate::store::content_etag;
+
+/// A previous version of a contact.
+pub struct Revision {
+    /// Name of the revision, the time it was replaced in milliseconds since the
+    /// Unix epoch.
+    pub rev: String,
+    pub replaced: SystemTime,
+    pub etag: String,
+}
+
+/// Previous versions of the contacts of an address book, each kept as
+/// `<id>/<rev>.vcf` in a directory, whatever the storage backend.
+pub struct History {
+    dir: PathBuf,
+    /// Number of revisions kept per contact, `0` disabling the history.
+    limit: usize,
+    /// Key the revisions are encrypted with, unset for plain files.
+    cipher: Option<Cipher>,
+}
+
+impl History {
+    pub fn new(dir: PathBuf, limit: usize, cipher: Option<Cipher>) -> Self {
+        History { dir, limit, cipher }
+    }
+
+    /// Whether revisions are kept at all.
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    /// Keeps `previous` as a revision of the contact before it is replaced,
+    /// dropping the oldest revisions beyond the limit.
+    pub async fn record(&self, previous: &Contact) -> io::Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+
+        let dir = self.dir.join(&previous.id);
+        fs::create_dir_all(&dir).await?;
+
+        let mut millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        // Two replacements within the same millisecond get distinct names.
+        while fs::try_exists(dir.join(format!("{}.vcf", millis))).await? {
+            millis += 1;
+        }
+        let content = encryption::encode(&previous.to_string(), self.cipher.as_ref())?;
+        fs::write(dir.join(format!("{}.vcf", millis)), content).await?;
+
+        let revs = self.revs(&previous.id).await?;
+        for rev in revs.iter().take(revs.len().saturating_sub(self.limit)) {
+            fs::remove_file(dir.join(format!("{}.vcf", rev))).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists the revisions of a contact, oldest first.
+    pub async fn list(&self, id: &str) -> io::Result<Vec<Revision>> {
+        let mut revisions = Vec::new();
+
+        for rev in self.revs(id).await? {
+            let Some(content) = self.get(id, &rev.to_string()).await? else {
+                continue;
+            };
+
+            revisions.push(Revision {
+                rev: rev.to_string(),
+                replaced: UNIX_EPOCH + Duration::from_millis(rev as u64),
+                etag: content_etag(&content),
+            });
+        }
+
+        Ok(revisions)
+    }
+
+    /// Returns the vCard of a revision, if it exists.
+    pub async fn get(&self, id: &str, rev: &str) -> io::Result<Option<String>> {
+        if rev.is_empty() || !rev.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(None);
+        }
+
+        let path = self.dir.join(id).join(format!("{}.vcf", rev));
+        match encryption::read(&path, self.cipher.as_ref()).await {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Names of the revisions of a contact, sorted oldest first.
+    async fn revs(&self, id: &str) -> io::Result<Vec<u128>> {
+        let mut entries = match fs::read_dir(self.dir.join(id)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut revs = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "vcf") {
+                if let Some(rev) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+                {
+                    revs.push(rev);
+                }
+            }
+        }
+        revs.sort_unstable();
+
+        Ok(revs)
+    }
+}
@@ -0,0 +1,597 @@
+use std::fmt::{self, Display};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+/// Longest time a client gets to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections whose TLS handshake is complete, waiting to be served.
+const TLS_BACKLOG: usize = 64;
+
+/// Where the server listens, from `--listen` or `DAV_LISTEN`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Path of a Unix domain socket, written `unix:<path>`.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("missing socket path after 'unix:'".to_string()),
+            Some(path) if cfg!(unix) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            Some(_) => Err("Unix domain sockets are not supported on this platform".to_string()),
+            None => s.parse().map(ListenAddr::Tcp).map_err(|e| format!("{}", e)),
+        }
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Address of either end of a connection. Only TCP addresses tell clients
+/// apart.
+#[derive(Clone, Debug)]
+pub enum Addr {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+impl Addr {
+    /// IP address of the client. Clients connecting through the Unix socket,
+    /// in practice a reverse proxy on the same host, count as the local host.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Addr::Tcp(addr) => addr.ip(),
+            Addr::Unix => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, Listener>> for Addr {
+    fn connect_info(stream: IncomingStream<'_, Listener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
+
+/// Files of the certificate chain and private key served over TLS, in PEM,
+/// from `DAV_TLS_CERT` and `DAV_TLS_KEY`.
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Certificate served over TLS, read from its files at startup and again
+/// with [`Certificates::reload`], so a renewed certificate is picked up by the
+/// next handshakes without restarting.
+#[derive(Debug)]
+pub struct Certificates {
+    files: TlsFiles,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl Certificates {
+    /// Reads the certificate chain and the key, failing if either cannot be
+    /// read or if they do not match.
+    pub fn load(files: &TlsFiles) -> Result<Self, String> {
+        Ok(Certificates {
+            current: RwLock::new(Arc::new(read_certified_key(files)?)),
+            files: files.clone(),
+        })
+    }
+
+    /// Reads the files again. The previous certificate is kept on failure.
+    pub fn reload(&self) -> Result<(), String> {
+        let certified_key = read_certified_key(&self.files)?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(certified_key);
+        Ok(())
+    }
+
+    /// Acceptor of TLS connections, presenting the current certificate.
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor, String> {
+        let mut config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        )
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn read_certified_key(files: &TlsFiles) -> Result<CertifiedKey, String> {
+    let read_error = |path: &Path, e| format!("cannot read {}: {}", path.display(), e);
+
+    let chain = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| read_error(&files.cert, e))?;
+    if chain.is_empty() {
+        return Err(format!("no certificate in {}", files.cert.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(&files.key).map_err(|e| read_error(&files.key, e))?;
+
+    CertifiedKey::from_der(chain, key, &provider()).map_err(|e| {
+        format!(
+            "certificate {} does not go with key {}: {}",
+            files.cert.display(),
+            files.key.display(),
+            e
+        )
+    })
+}
+
+/// Socket the server accepts connections on, TCP or Unix, so both go through
+/// the same `axum::serve`.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    /// TCP socket whose connections are served over TLS, handed over once
+    /// their handshake completes.
+    Tls {
+        local_addr: SocketAddr,
+        connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    },
+}
+
+impl Listener {
+    /// Binds `addr`. A Unix socket left behind by a server that is no longer
+    /// running is removed first, and the new one gets the `mode` permissions.
+    pub async fn bind(addr: &ListenAddr, mode: u32) -> io::Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => TcpListener::bind(addr).await.map(Listener::Tcp),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                let stale = match tokio::fs::symlink_metadata(path).await {
+                    Ok(metadata) => metadata.file_type().is_socket(),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+                    Err(e) => return Err(e),
+                };
+                if stale {
+                    if UnixStream::connect(path).await.is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            "another server is listening on the socket",
+                        ));
+                    }
+                    tokio::fs::remove_file(path).await?;
+                }
+
+                let listener = UnixListener::bind(path)?;
+                tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+                Ok(Listener::Unix(listener))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => {
+                let _ = mode;
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Unix domain sockets are not supported on this platform",
+                ))
+            }
+        }
+    }
+
+    /// Takes the socket passed by systemd socket activation, if `LISTEN_FDS`
+    /// and `LISTEN_PID` name one for this process.
+    #[cfg(unix)]
+    pub fn from_systemd() -> io::Result<Option<Self>> {
+        use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+
+        /// First descriptor passed by systemd.
+        const SD_LISTEN_FDS_START: RawFd = 3;
+
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pid == std::process::id());
+        if !for_us {
+            return Ok(None);
+        }
+
+        match std::env::var("LISTEN_FDS").as_deref() {
+            Ok("0") | Err(_) => return Ok(None),
+            Ok("1") => {}
+            Ok(count) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("LISTEN_FDS is '{}', a single socket is expected", count),
+                ))
+            }
+        }
+
+        // SAFETY: systemd hands the descriptor over to this process, which
+        // takes it once and nothing else uses it.
+        let socket = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        if socket.local_addr().is_ok() {
+            socket.set_nonblocking(true)?;
+            return UnixListener::from_std(socket).map(|listener| Some(Listener::Unix(listener)));
+        }
+
+        // SAFETY: the descriptor was released by the Unix listener above.
+        let socket = unsafe { std::net::TcpListener::from_raw_fd(socket.into_raw_fd()) };
+        socket.local_addr()?;
+        socket.set_nonblocking(true)?;
+        TcpListener::from_std(socket).map(|listener| Some(Listener::Tcp(listener)))
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_systemd() -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// Serves the connections of a TCP socket over TLS. Handshakes run apart
+    /// from each other, so a slow client does not hold back the others.
+    pub fn with_tls(self, acceptor: TlsAcceptor) -> io::Result<Self> {
+        let mut listener = match self {
+            Listener::Tcp(listener) => listener,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "TLS is only served over TCP",
+                ))
+            }
+        };
+        let local_addr = listener.local_addr()?;
+
+        let (sender, connections) = mpsc::channel(TLS_BACKLOG);
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = tokio::select! {
+                    accepted = axum::serve::Listener::accept(&mut listener) => accepted,
+                    // The server stopped.
+                    () = sender.closed() => break,
+                };
+
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Listener::Tls {
+            local_addr,
+            connections,
+        })
+    }
+
+    /// Describes the transport and address, for the logs.
+    pub fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("at http://{} over TCP", addr),
+                Err(_) => "over TCP".to_string(),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => match listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+            {
+                Some(path) => format!("on Unix socket {}", path),
+                None => "on an unnamed Unix socket".to_string(),
+            },
+            Listener::Tls { local_addr, .. } => format!("at https://{} over TCP", local_addr),
+        }
+    }
+}
+
+impl axum::serve::Listener for Listener {
+    type Io = Stream;
+    type Addr = Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = axum::serve::Listener::accept(listener).await;
+                (Stream::Tcp(stream), Addr::Tcp(addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = axum::serve::Listener::accept(listener).await;
+                (Stream::Unix(stream), Addr::Unix)
+            }
+            Listener::Tls { connections, .. } => match connections.recv().await {
+                Some((stream, addr)) => (Stream::Tls(Box::new(stream)), Addr::Tcp(addr)),
+                // The accepting task only stops with the listener.
+                None => std::future::pending().await,
+            },
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Addr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(_) => Ok(Addr::Unix),
+            Listener::Tls { local_addr, .. } => Ok(Addr::Tcp(*local_addr)),
+        }
+    }
+}
+
+/// Connection accepted by a [`Listener`].
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Stream::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Stream::Tcp(stream) => stream.is_write_vectored(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.is_write_vectored(),
+            Stream::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::future::IntoFuture;
+
+    use axum::{routing::get, Router};
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(name)
+    }
+
+    fn files(cert: &str, key: &str) -> TlsFiles {
+        TlsFiles {
+            cert: testdata(cert),
+            key: testdata(key),
+        }
+    }
+
+    /// Connects to `addr` trusting the test CA, returning the certificate the
+    /// server presented and its answer to `GET /`.
+    async fn fetch(addr: SocketAddr) -> (CertificateDer<'static>, String) {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(testdata("ca.pem")).unwrap())
+            .unwrap();
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        let cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        (cert, response)
+    }
+
+    #[test]
+    fn loads_a_matching_certificate_and_key() {
+        assert!(Certificates::load(&files("server.pem", "server-key.pem")).is_ok());
+    }
+
+    #[test]
+    fn rejects_unusable_certificates() {
+        let e = Certificates::load(&files("missing.pem", "server-key.pem")).unwrap_err();
+        assert!(
+            e.contains("cannot read") && e.contains("missing.pem"),
+            "{}",
+            e
+        );
+
+        let e = Certificates::load(&files("server.pem", "missing-key.pem")).unwrap_err();
+        assert!(e.contains("missing-key.pem"), "{}", e);
+
+        // A key is not a certificate.
+        let e = Certificates::load(&files("server-key.pem", "server-key.pem")).unwrap_err();
+        assert!(e.contains("no certificate"), "{}", e);
+
+        let e = Certificates::load(&files("server.pem", "renewed-key.pem")).unwrap_err();
+        assert!(e.contains("does not go with key"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn serves_https_and_reloads_the_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = TlsFiles {
+            cert: dir.path().join("cert.pem"),
+            key: dir.path().join("key.pem"),
+        };
+        fs::copy(testdata("server.pem"), &tls.cert).unwrap();
+        fs::copy(testdata("server-key.pem"), &tls.key).unwrap();
+
+        let certificates = Arc::new(Certificates::load(&tls).unwrap());
+        let listener = Listener::bind(&"127.0.0.1:0".parse().unwrap(), 0o660)
+            .await
+            .unwrap()
+            .with_tls(certificates.acceptor().unwrap())
+            .unwrap();
+        let Ok(Addr::Tcp(addr)) = axum::serve::Listener::local_addr(&listener) else {
+            panic!("not a TCP listener");
+        };
+        assert_eq!(listener.describe(), format!("at https://{} over TCP", addr));
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        let (cert, response) = fetch(addr).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("hello"), "{}", response);
+        let server = CertificateDer::from_pem_file(testdata("server.pem")).unwrap();
+        assert_eq!(cert, server);
+
+        // A plain HTTP request does not get through.
+        let mut plain = TcpStream::connect(addr).await.unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut answer = Vec::new();
+        let _ = plain.read_to_end(&mut answer).await;
+        assert!(!String::from_utf8_lossy(&answer).contains("hello"));
+
+        // A mismatched pair is refused and the served certificate kept.
+        fs::copy(testdata("renewed.pem"), &tls.cert).unwrap();
+        assert!(certificates.reload().is_err());
+        assert_eq!(fetch(addr).await.0, server);
+
+        fs::copy(testdata("renewed-key.pem"), &tls.key).unwrap();
+        certificates.reload().unwrap();
+        let (cert, response) = fetch(addr).await;
+        assert_eq!(
+            cert,
+            CertificateDer::from_pem_file(testdata("renewed.pem")).unwrap()
+        );
+        assert!(response.ends_with("hello"), "{}", response);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_tls_over_unix_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dav.sock");
+        let listener = Listener::bind(&ListenAddr::Unix(path), 0o660)
+            .await
+            .unwrap();
+        let certificates =
+            Arc::new(Certificates::load(&files("server.pem", "server-key.pem")).unwrap());
+        assert!(listener.with_tls(certificates.acceptor().unwrap()).is_err());
+    }
+}
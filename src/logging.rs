@@ -0,0 +1,142 @@
+use std::env;
+use std::io;
+use std::str::FromStr;
+
+use tracing::Subscriber;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Level used when none of `--log-level`, `DAV_LOG_LEVEL` and `RUST_LOG` is
+/// set.
+const DEFAULT_LEVEL: &str = "info";
+
+/// Output format of the logs, selected with `DAV_LOG_FORMAT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
+
+/// Installs the global subscriber. The verbosity comes from `log_level`, set
+/// by `--log-level` or `DAV_LOG_LEVEL`, then from `RUST_LOG`, and accepts the
+/// same directives. Logs go to the standard output, or to the standard error
+/// with `stderr` so they stay out of the output of the offline commands.
+pub fn init(log_level: Option<&str>, format: LogFormat, stderr: bool) -> Result<(), String> {
+    let directives = match log_level {
+        Some(level) => level.to_string(),
+        None => env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LEVEL.to_string()),
+    };
+    let filter = EnvFilter::try_new(&directives)
+        .map_err(|e| format!("invalid log level '{}': {}", directives, e))?;
+
+    let writer = if stderr {
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+    subscriber(filter, format, writer).init();
+
+    Ok(())
+}
+
+fn subscriber(
+    filter: EnvFilter,
+    format: LogFormat,
+    writer: BoxMakeWriter,
+) -> Box<dyn Subscriber + Send + Sync> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs a line with the given format and returns the output.
+    fn log(format: LogFormat) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = subscriber(
+            EnvFilter::new("info"),
+            format,
+            BoxMakeWriter::new(move || writer.clone()),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(id = "alice", "Contact created successfully");
+            tracing::debug!("filtered out");
+        });
+
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn parses_the_format() {
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn json_format_writes_one_object_per_line() {
+        let output = log(LogFormat::Json);
+
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 1, "{}", output);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["fields"]["message"], "Contact created successfully");
+        assert_eq!(line["fields"]["id"], "alice");
+    }
+
+    #[test]
+    fn text_format_writes_plain_lines() {
+        let output = log(LogFormat::Text);
+
+        assert_eq!(output.lines().count(), 1, "{}", output);
+        assert!(serde_json::from_str::<serde_json::Value>(&output).is_err());
+        assert!(output.contains("INFO"), "{}", output);
+        assert!(
+            output.contains("Contact created successfully"),
+            "{}",
+            output
+        );
+    }
+}
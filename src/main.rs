@@ -1,274 +1,4345 @@
-use std::fmt;
-use std::path::PathBuf;
-use std::str::FromStr;
+mod audit;
+mod auth;
+mod backup;
+mod birthdays;
+mod books;
+mod changes;
+mod cli;
+mod config;
+mod contact;
+mod csv;
+mod dav;
+mod dir_lock;
+mod duplicates;
+mod encryption;
+mod event;
+mod freebusy;
+mod history;
+mod listener;
+mod logging;
+mod openapi;
+mod pretty;
+mod proxy;
+mod rate_limit;
+mod read_only;
+mod rrule;
+mod search;
+mod store;
+mod todo;
+mod tokens;
+mod write_lock;
+
+#[cfg(test)]
+mod testing;
+
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::future::IntoFuture;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use axum::{
-    extract::{Path as AxumPath, State},
-    http::StatusCode,
-    routing::get,
-    Json, Router,
+    body::Bytes,
+    extract::{Path as AxumPath, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{any, delete, get, options, post},
+    Json, Router, ServiceExt,
 };
+use chrono::{DateTime, Utc};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use tokio::{fs, io::AsyncWriteExt};
-use tokio_stream::{wrappers::ReadDirStream, StreamExt};
+use tokio::fs;
+use tokio::sync::watch;
+use tower_http::{
+    compression::CompressionLayer, normalize_path::NormalizePath, timeout::TimeoutLayer,
+};
 use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::audit::AuditLog;
+use crate::auth::{Account, Authenticator, Credentials};
+use crate::backup::{Backup, Backups};
+use crate::books::{AddressBook, Book, BookError, Books};
+use crate::changes::Operation;
+use crate::cli::Command;
+#[cfg(feature = "sqlite")]
+use crate::config::Backend;
+use crate::config::{Config, USERS_DIR};
+use crate::contact::{parse_vcard_bytes, Contact};
+use crate::dir_lock::DirLock;
+use crate::event::Event;
+use crate::freebusy::Busy;
+use crate::listener::{Certificates, ListenAddr, Listener};
+use crate::rate_limit::RateLimiter;
+use crate::rrule::Rule;
+use crate::store::{EventStore, ExternalChange, StoreError, TodoStore};
+use crate::todo::{Status, Todo};
+use crate::tokens::Tokens;
+
+/// Header of the contact list carrying the collection tag, which changes with
+/// every change to the address book.
+const CTAG_HEADER: &str = "X-CTag";
+
+/// Time between two purges of the expired contacts of the trash.
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Header of the contact list counting the stored contacts left out because
+/// they could not be read.
+const SKIPPED_HEADER: &str = "X-Skipped-Count";
+
+/// Media type of jCard, the JSON form of vCard (RFC 7095).
+const JCARD_CONTENT_TYPE: &str = "application/vcard+json";
 
-const ADDR: &str = "127.0.0.1:3000";
+struct AppState {
+    books: Books,
+    backups: Backups,
+    events: EventStore,
+    todos: TodoStore,
+    audit: Arc<AuditLog>,
+}
 
-#[derive(Default, Deserialize, Serialize, Debug)]
-struct Contact {
+/// Path parameters of the routes of a single contact, event or task.
+#[derive(Deserialize)]
+struct ContactPath {
     id: String,
-    name: String,
-    email: String,
-    phone: String,
-}
-
-impl FromStr for Contact {
-    type Err = String;
-
-    fn from_str(vcard: &str) -> Result<Self, Self::Err> {
-        let mut id = None;
-        let mut name = None;
-        let mut email = None;
-        let mut phone = None;
-
-        for line in vcard.lines() {
-            if line.starts_with("ID:") {
-                id = Some(line.trim_start_matches("ID:").to_string());
-            } else if line.starts_with("FN:") {
-                name = Some(line.trim_start_matches("FN:").to_string());
-            } else if line.starts_with("EMAIL:") {
-                email = Some(line.trim_start_matches("EMAIL:").to_string());
-            } else if line.starts_with("TEL:") {
-                phone = Some(line.trim_start_matches("TEL:").to_string());
-            }
-        }
-
-        match (id.as_ref(), name.as_ref(), email.as_ref(), phone.as_ref()) {
-            (None, None, None, None) => Err("contact is empty".to_string()),
-            (None, _, _, _) => Err("contact ID is empty".to_string()),
-            _ => Ok(Contact {
-                id: id.unwrap_or_default(),
-                name: name.unwrap_or_default(),
-                email: email.unwrap_or_default(),
-                phone: phone.unwrap_or_default(),
-            }),
-        }
-    }
 }
 
-impl fmt::Display for Contact {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "BEGIN:VCARD\nVERSION:4.0\nID:{}\nFN:{}\nEMAIL:{}\nTEL:{}\nEND:VCARD\n",
-            self.id, self.name, self.email, self.phone
-        )
-    }
+/// Path parameters of the routes of a previous version of a contact.
+#[derive(Deserialize)]
+struct RevisionPath {
+    id: String,
+    rev: String,
 }
 
-#[derive(Clone)]
-struct AppState {
-    data_dir: Arc<PathBuf>,
+#[derive(Deserialize)]
+struct EmailPath {
+    email: String,
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Command::CheckConfig = command {
+        check_config();
+        return;
+    }
+    if let Command::HashPassword = command {
+        match cli::hash_password() {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let base_path = ProjectDirs::from("", "", "dav").expect("failed to determine base directories");
-    let data_dir = base_path.data_dir().join("contacts");
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration: {}", e);
+            return;
+        }
+    };
 
-    if let Err(e) = fs::create_dir_all(&data_dir).await {
-        error!("failed to create contact directory: {}", e);
+    let offline = matches!(
+        command,
+        Command::Offline { .. } | Command::Encrypt | Command::Decrypt { .. }
+    );
+    if let Err(e) = logging::init(config.log_level.as_deref(), config.log_format, offline) {
+        eprintln!("invalid logging configuration: {}", e);
         return;
     }
-    info!("Data directory created at: {}", data_dir.display());
 
-    let state = AppState {
-        data_dir: Arc::new(data_dir),
+    for warning in &config.warnings {
+        warn!("{}", warning);
+    }
+
+    match command {
+        Command::Serve => run(config).await,
+        Command::Migrate => migrate(config).await,
+        Command::Encrypt => match cli::encrypt(config).await {
+            Ok(count) => println!("Encrypted {} files", count),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Command::Decrypt { file } => match cli::decrypt(&config, &file).await {
+            Ok(content) => print!("{}", content),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Command::CheckConfig | Command::HashPassword => {
+            unreachable!("run before reading the configuration")
+        }
+        Command::Offline { user, book, task } => {
+            if let Err(e) = cli::run(config, user.as_deref(), &book, task).await {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Checks the configuration for `dav config check`, printing the effective
+/// settings without starting the server. Exits with an error when it is
+/// invalid.
+fn check_config() {
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration: {}", e);
+            std::process::exit(1);
+        }
     };
 
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/contacts", get(list_contacts).post(create_contact))
-        .route(
-            "/contacts/:id",
-            get(contact_by_id)
-                .put(modify_contact)
-                .delete(delete_contact),
-        )
-        .with_state(Arc::new(state));
+    for warning in &config.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if let Some(files) = &config.tls {
+        if let Err(e) = Certificates::load(files) {
+            eprintln!("invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+    match &config.file {
+        Some(file) => println!("configuration file: {}", file.display()),
+        None => println!("no configuration file"),
+    }
+    println!("{:#?}", config);
+}
+
+/// Opens the data directory and serves the routes until the server fails.
+async fn run(config: Config) {
+    contact::set_version(config.vcard_version);
+    proxy::configure(config.base_path.clone(), config.trusted_proxies.clone());
+
+    let read_only = config.read_only;
+    let auth = config.auth.clone();
+    let listen = config.listen.clone();
+    let base_path = config.base_path.clone();
+    let socket_mode = config.socket_mode;
+    let shutdown_timeout = config.shutdown_timeout;
+
+    // Read first, so a certificate that cannot be used stops the server
+    // before it opens the data directory.
+    let certificates = match &config.tls {
+        Some(files) => match Certificates::load(files) {
+            Ok(certificates) => Some(Arc::new(certificates)),
+            Err(e) => {
+                error!("failed to load the TLS certificate: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let data_dir = if config.ephemeral {
+        let dir = env::temp_dir().join(format!("dav-{}", Uuid::new_v4().simple()));
+        warn!(
+            "Running in ephemeral mode, nothing is kept after exit (data in {})",
+            dir.display()
+        );
+        dir
+    } else {
+        data_dir(&config)
+    };
+    if let Err(e) = check_writable(&data_dir).await {
+        error!("{}", e);
+        return;
+    }
 
-    let listener = match tokio::net::TcpListener::bind(ADDR).await {
-        Ok(listener) => listener,
+    // Held until the server exits, so offline commands cannot change the
+    // stores under it.
+    let _lock = match DirLock::acquire(&data_dir) {
+        Ok(lock) => lock,
         Err(e) => {
-            error!("failed to bind to address {}: {}", ADDR, e);
+            error!("failed to lock {}: {}", data_dir.display(), e);
             return;
         }
     };
 
-    info!("Server running at http://{}", ADDR);
-    if let Err(e) = axum::serve(listener, app).await {
-        error!("failed to run server: {}", e);
+    let backup_dir = config
+        .backup_dir
+        .clone()
+        .unwrap_or_else(|| data_dir.join("backups"));
+
+    // Set when stopping, for the background tasks to finish their current run.
+    let (stop, stopping) = watch::channel(false);
+
+    // Shared by every user, and only stopped once the requests in flight are
+    // answered, so that their entries are written.
+    let (audit, entries) = AuditLog::new(&data_dir, auth.as_ref().map(|auth| auth.user.clone()));
+    let audit = Arc::new(audit);
+    let (stop_audit, audit_stopping) = watch::channel(false);
+    let audit_task = tokio::spawn(audit::write(
+        data_dir.clone(),
+        entries,
+        config.audit_max_size,
+        audit_stopping,
+    ));
+
+    if read_only {
+        warn!("Running in read-only mode, every change is rejected");
     }
-}
 
-async fn health_check() -> StatusCode {
-    StatusCode::OK
-}
+    let Served {
+        mut app,
+        states,
+        tasks,
+    } = match serve(&config, &data_dir, &backup_dir, &audit, &stopping).await {
+        Ok(served) => served,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let authenticated = auth.is_some() || !config.users.is_empty();
 
-async fn create_contact(
-    State(state): State<Arc<AppState>>,
-    Json(contact): Json<Contact>,
-) -> (StatusCode, String) {
-    let mut file_path = state.data_dir.join(contact.id);
-    file_path.set_extension("vcf");
+    // A socket passed by systemd is used as is, and left for systemd to
+    // remove.
+    let (listener, created_socket) = match Listener::from_systemd() {
+        Ok(Some(listener)) => {
+            info!("Using the socket passed by systemd socket activation");
+            (listener, None)
+        }
+        Ok(None) => match Listener::bind(&listen, socket_mode).await {
+            Ok(listener) => match listen {
+                ListenAddr::Unix(path) => (listener, Some(path)),
+                ListenAddr::Tcp(_) => (listener, None),
+            },
+            Err(e) => {
+                error!("failed to bind to address {}: {}", listen, e);
+                return;
+            }
+        },
+        Err(e) => {
+            error!("failed to use the socket passed by systemd: {}", e);
+            return;
+        }
+    };
 
-    let vcard = format!(
-        "BEGIN:VCARD\nVERSION:4.0\nFN:{}\nEMAIL:{}\nTEL:{}\nEND:VCARD\n",
-        contact.name, contact.email, contact.phone
-    );
+    let listener = match &certificates {
+        Some(certificates) => {
+            match certificates
+                .acceptor()
+                .and_then(|acceptor| listener.with_tls(acceptor).map_err(|e| e.to_string()))
+            {
+                Ok(listener) => {
+                    tokio::spawn(reload_on_hangup(certificates.clone()));
+                    listener
+                }
+                Err(e) => {
+                    error!("failed to serve TLS: {}", e);
+                    return;
+                }
+            }
+        }
+        None => listener,
+    };
 
-    match fs::File::create(&file_path).await {
-        Ok(mut file) => {
-            if let Err(e) = file.write_all(vcard.as_bytes()).await {
-                error!("Error writing to file: {}", e);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "failed to save contact".to_string(),
+    // The password crosses the network in clear, unless a TLS terminating
+    // proxy on the same host is in front.
+    if authenticated && certificates.is_none() {
+        if let Ok(listener::Addr::Tcp(addr)) = axum::serve::Listener::local_addr(&listener) {
+            if !addr.ip().is_loopback() {
+                warn!(
+                    "Basic authentication over plain HTTP on {} sends passwords in clear, set DAV_TLS_CERT and DAV_TLS_KEY or serve HTTPS through a reverse proxy",
+                    addr
                 );
             }
-
-            (StatusCode::CREATED, "Contact created".to_string())
         }
-        Err(e) => {
-            error!("failed to create file at {}: {}", file_path.display(), e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "failed to create file".to_string(),
-            )
+    }
+
+    if !base_path.is_empty() {
+        info!("Serving the routes under {}", base_path);
+        // Discovery starts at the root of the host whatever the base path.
+        app = Router::new()
+            .nest(&base_path, app)
+            .route("/.well-known/carddav", any(dav::well_known));
+    }
+    let app = app.layer(middleware::from_fn(proxy::forwarded));
+
+    // Trailing slashes are trimmed before routing, so `/contacts/` and
+    // `/contacts` reach the same handler.
+    let app = NormalizePath::trim_trailing_slash(app);
+
+    info!("Server running {}", listener.describe());
+    let mut draining = stopping.clone();
+    let mut server = tokio::spawn(
+        axum::serve(
+            listener,
+            ServiceExt::<Request>::into_make_service_with_connect_info::<listener::Addr>(app),
+        )
+        .with_graceful_shutdown(async move {
+            let _ = draining.wait_for(|&stop| stop).await;
+        })
+        .into_future(),
+    );
+
+    tokio::select! {
+        result = &mut server => {
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("failed to run server: {}", e),
+                Err(e) => error!("server task failed: {}", e),
+            }
+            return;
         }
+        () = shutdown_signal() => {}
     }
-}
 
-async fn modify_contact(
-    AxumPath(id): AxumPath<String>,
-    State(state): State<Arc<AppState>>,
-    Json(updated_contact): Json<Contact>,
-) -> (StatusCode, String) {
-    let mut file_path = state.data_dir.join(&id);
-    file_path.set_extension("vcf");
+    info!(
+        "Shutting down, waiting up to {} seconds for the requests in flight",
+        shutdown_timeout.as_secs()
+    );
+    let _ = stop.send(true);
+    match tokio::time::timeout(shutdown_timeout, server).await {
+        Ok(Ok(Ok(()))) => info!("Requests in flight answered"),
+        Ok(Ok(Err(e))) => error!("failed to run server: {}", e),
+        Ok(Err(e)) => error!("server task failed: {}", e),
+        Err(_) => warn!("Requests still in flight after the shutdown timeout are dropped"),
+    }
 
-    if !file_path.exists() {
-        warn!("contact not found for update: {}", file_path.display());
-        return (StatusCode::NOT_FOUND, "contact not found".to_string());
+    for task in tasks {
+        if let Err(e) = task.await {
+            error!("background task failed: {}", e);
+        }
     }
+    info!("Background tasks stopped");
 
-    if id != updated_contact.id {
-        warn!("ID '{}' does not match body ID: {}", id, updated_contact.id);
-        return (
-            StatusCode::BAD_REQUEST,
-            "ID in URL and body must match".to_string(),
-        );
+    let _ = stop_audit.send(true);
+    if let Err(e) = audit_task.await {
+        error!("audit log task failed: {}", e);
     }
 
-    match fs::write(&file_path, updated_contact.to_string()).await {
-        Ok(_) => {
-            info!("contact updated: {}", file_path.display());
-            (StatusCode::OK, "Contact updated".to_string())
+    if let Some(path) = created_socket {
+        if let Err(e) = fs::remove_file(&path).await {
+            error!("failed to remove socket {}: {}", path.display(), e);
         }
-        Err(e) => {
-            error!("failed to update contact {}: {}", file_path.display(), e);
+    }
+
+    for state in states {
+        for book in state.books.list().await {
+            if let Err(e) = book.changes.sync().await {
+                error!("failed to sync the change log of {}: {}", book.name, e);
+            }
+        }
+    }
+    info!("Shutdown complete");
+}
+
+/// Routes and background tasks of a data directory.
+struct Served {
+    app: Router,
+    states: Vec<Arc<AppState>>,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+/// Opens the stores of every user of the data directory, starts their
+/// background tasks and builds the routes serving them.
+async fn serve(
+    config: &Config,
+    data_dir: &Path,
+    backup_dir: &Path,
+    audit: &Arc<AuditLog>,
+    stopping: &watch::Receiver<bool>,
+) -> Result<Served, String> {
+    let timeout = timeout_layer(config);
+    let mut tasks = Vec::new();
+    let mut states = Vec::new();
+
+    // Every user of the users file has its own data, the one of
+    // `DAV_AUTH_USER` being at the root of the data directory.
+    let accounts: Vec<(Credentials, PathBuf, PathBuf)> = config
+        .auth
+        .clone()
+        .into_iter()
+        .map(|credentials| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "failed to update contact".to_string(),
+                credentials,
+                data_dir.to_path_buf(),
+                backup_dir.to_path_buf(),
+            )
+        })
+        .chain(config.users.iter().map(|credentials| {
+            (
+                credentials.clone(),
+                data_dir.join(USERS_DIR).join(&credentials.user),
+                backup_dir.join(USERS_DIR).join(&credentials.user),
             )
+        }))
+        .collect();
+    let mut app = if accounts.is_empty() {
+        let state = open_state(data_dir, backup_dir.to_path_buf(), audit, config).await?;
+        tasks.extend(spawn_tasks(&state, config, stopping));
+        states.push(state.clone());
+        routes(state, timeout, config.read_only)
+    } else {
+        let mut served = Vec::new();
+        for (credentials, dir, backup_dir) in accounts {
+            info!(
+                "Requiring HTTP Basic authentication of user {}, with data in {}",
+                credentials.user,
+                dir.display()
+            );
+            let state = open_state(&dir, backup_dir, audit, config)
+                .await
+                .map_err(|e| format!("user {}: {}", credentials.user, e))?;
+            tasks.extend(spawn_tasks(&state, config, stopping));
+            states.push(state.clone());
+
+            let tokens = Tokens::open(&dir).await.map_err(|e| {
+                format!("failed to open tokens of user {}: {}", credentials.user, e)
+            })?;
+            let tokens = Arc::new(tokens);
+            let routes =
+                routes(state, timeout, config.read_only).merge(tokens::routes(tokens.clone()));
+            served.push(Account::new(credentials, tokens, routes));
         }
+
+        // Requests are authenticated before anything else, so that anonymous
+        // clients learn nothing but that they must authenticate.
+        let authenticator = Authenticator::new(served)
+            .map_err(|e| format!("failed to set up authentication: {}", e))?;
+        Router::new()
+            .fallback(auth::dispatch)
+            .with_state(Arc::new(authenticator))
+    };
+
+    if let Some(limit) = config.rate_limit {
+        info!("Rate limiting clients to {} requests per minute", limit);
+        app = app.layer(middleware::from_fn_with_state(
+            Arc::new(RateLimiter::new(limit)),
+            rate_limit::rate_limit,
+        ));
     }
+
+    Ok(Served { app, states, tasks })
 }
 
-async fn delete_contact(
-    AxumPath(id): AxumPath<String>,
-    State(state): State<Arc<AppState>>,
-) -> (StatusCode, String) {
-    let mut file_path = state.data_dir.join(id);
-    file_path.set_extension("vcf");
+/// Layer answering `408 Request Timeout` to requests still running after the
+/// configured timeout.
+fn timeout_layer(config: &Config) -> TimeoutLayer {
+    TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, config.request_timeout)
+}
+
+/// Reads the TLS certificate again on every SIGHUP, keeping the previous one
+/// if the new one cannot be used.
+async fn reload_on_hangup(certificates: Arc<Certificates>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match certificates.reload() {
+                Ok(()) => info!("Reloaded the TLS certificate"),
+                Err(e) => error!(
+                    "failed to reload the TLS certificate, keeping the previous one: {}",
+                    e
+                ),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = certificates;
+}
+
+/// Resolves on `SIGINT` (Ctrl+C) or, on Unix, `SIGTERM`.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    if file_path.exists() {
-        match fs::remove_file(&file_path).await {
-            Ok(_) => {
-                info!("Contact deleted: {}", file_path.display());
-                (StatusCode::OK, "Contact deleted".to_string())
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
             }
             Err(e) => {
-                error!("failed to delete contact {}: {}", file_path.display(), e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "failed to delete contact".to_string(),
-                )
+                error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
             }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => info!("Received SIGINT"),
+        () = terminate => info!("Received SIGTERM"),
+    }
+}
+
+/// Opens the stores of a data directory.
+async fn open_state(
+    dir: &Path,
+    backup_dir: PathBuf,
+    audit: &Arc<AuditLog>,
+    config: &Config,
+) -> Result<Arc<AppState>, String> {
+    let books = Books::open(dir, config.clone())
+        .await
+        .map_err(|e| format!("failed to open address books: {}", e))?;
+
+    let events = EventStore::open(dir.join("events"), config.fsync_dir)
+        .await
+        .map_err(|e| format!("failed to open events: {}", e))?;
+
+    let todos = TodoStore::open(dir.join("todos"), config.fsync_dir)
+        .await
+        .map_err(|e| format!("failed to open tasks: {}", e))?;
+
+    Ok(Arc::new(AppState {
+        books,
+        backups: Backups::new(
+            backup_dir,
+            config.backup_retention,
+            config.encryption.clone(),
+        ),
+        events,
+        todos,
+        audit: audit.clone(),
+    }))
+}
+
+/// Starts the background tasks of the stores, which finish their current run
+/// once `stopping` is set.
+fn spawn_tasks(
+    state: &Arc<AppState>,
+    config: &Config,
+    stopping: &watch::Receiver<bool>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut tasks = Vec::new();
+
+    // Nothing is purged from the trash of a read-only server.
+    if config.soft_delete && !config.read_only {
+        tasks.push(tokio::spawn(purge_trash(
+            state.clone(),
+            config.trash_retention,
+            stopping.clone(),
+        )));
+    }
+
+    if let Some(interval) = config.backup_interval {
+        tasks.push(tokio::spawn(scheduled_backups(
+            state.clone(),
+            interval,
+            stopping.clone(),
+        )));
+    }
+
+    if let Some(interval) = config.watch_interval {
+        tasks.push(tokio::spawn(watch_contacts(
+            state.clone(),
+            interval,
+            stopping.clone(),
+        )));
+    }
+
+    tasks
+}
+
+/// Routes of the stores of a data directory.
+fn routes(state: Arc<AppState>, timeout: TimeoutLayer, read_only: bool) -> Router {
+    // The export streams every contact and is left out of the timeout, which
+    // only applies to the routes added before it.
+    let contacts = Router::new()
+        .route(
+            "/",
+            get(list_contacts)
+                .post(create_contact)
+                .fallback(dav::collection),
+        )
+        .route("/import", post(import_contacts))
+        .route("/validate", post(validate_contact))
+        .route("/batch-get", post(batch_get_contacts))
+        .route("/batch-delete", post(batch_delete_contacts))
+        .route("/duplicates", get(duplicate_contacts))
+        .route("/search", get(search_contacts))
+        .route("/by-email/{email}", get(contact_by_email))
+        .route("/trash", get(list_trash))
+        .route("/trash/{id}", delete(purge_contact))
+        .route("/stats", get(collection_stats))
+        .route("/random", get(random_contact))
+        .route("/ids", get(contact_ids))
+        .route("/{id}/merge", post(merge_contacts))
+        .route("/{id}/restore", post(restore_contact))
+        .route("/{id}/history", get(contact_history))
+        .route("/{id}/history/{rev}", get(contact_revision))
+        .route("/{id}/revert/{rev}", post(revert_contact))
+        .route(
+            "/{id}",
+            get(contact_by_id)
+                .put(modify_contact)
+                .delete(delete_contact)
+                .options(dav::resource_options)
+                .fallback(dav::resource),
+        )
+        .layer(timeout)
+        .route("/export", get(export_contacts));
+
+    let app = Router::new()
+        .route("/", options(dav::root_options).fallback(dav::root))
+        .route("/.well-known/carddav", any(dav::well_known))
+        .route("/principals/{user}", any(dav::principal))
+        .route("/health", get(health_check))
+        .route("/openapi.json", get(openapi::openapi))
+        .route(
+            "/addressbooks",
+            get(list_books).post(create_book).fallback(dav::home),
+        )
+        .route(
+            "/addressbooks/{book}",
+            delete(delete_book).fallback(dav::book),
+        )
+        .route(
+            "/addressbooks/{book}/{resource}",
+            get(dav::get_vcard)
+                .put(dav::put_vcard)
+                .delete(dav::delete_vcard)
+                .fallback(dav::book_member),
+        )
+        .route(
+            "/events",
+            get(list_events).post(create_event).fallback(dav::calendar),
+        )
+        .route(
+            "/events/{id}",
+            get(event_by_id).put(modify_event).delete(delete_event),
+        )
+        .route("/events/{id}/instances", get(event_instances))
+        .route("/calendars/birthdays.ics", get(birthday_feed))
+        .route("/freebusy", get(free_busy))
+        .route("/todos", get(list_todos).post(create_todo))
+        .route(
+            "/todos/{id}",
+            get(todo_by_id).put(modify_todo).delete(delete_todo),
+        )
+        .route("/todos/{id}/complete", post(complete_todo))
+        .route("/changes", get(list_changes))
+        .route("/stats", get(stats))
+        .route("/admin/reindex", post(reindex))
+        .route("/admin/fsck", post(fsck))
+        .route("/admin/migrate-layout", post(migrate_layout))
+        .route("/admin/backup", post(create_backup))
+        .route("/admin/backups", get(list_backups))
+        .route("/admin/audit", get(list_audit))
+        .layer(timeout)
+        .nest("/contacts", contacts.clone())
+        .nest("/addressbooks/{book}/contacts", contacts)
+        .layer(middleware::from_fn_with_state(
+            state.audit.clone(),
+            audit::record,
+        ))
+        .with_state(state)
+        .layer(middleware::from_fn(pretty::pretty))
+        .layer(CompressionLayer::new());
+
+    if read_only {
+        app.layer(middleware::from_fn(read_only::read_only))
     } else {
-        warn!("contact not found for deletion: {}", file_path.display());
-        (StatusCode::NOT_FOUND, "contact not found".to_string())
+        app
     }
 }
 
-async fn contact_by_id(
-    AxumPath(id): AxumPath<String>,
-    State(state): State<Arc<AppState>>,
-) -> (StatusCode, String) {
-    let mut file_path = state.data_dir.join(id);
-    file_path.set_extension("vcf");
+/// Directory of the data, from the configuration or else the data directory
+/// of the user.
+fn data_dir(config: &Config) -> PathBuf {
+    match &config.data_dir {
+        Some(dir) => dir.clone(),
+        None => ProjectDirs::from("", "", "dav")
+            .expect("failed to determine base directories")
+            .data_dir()
+            .to_path_buf(),
+    }
+}
+
+/// One-shot migration run by `dav migrate`: copies the `.vcf` files of every
+/// address book into its SQLite database, then exits. The files are left in
+/// place and the change log is carried over.
+#[cfg(feature = "sqlite")]
+async fn migrate(mut config: Config) {
+    if config.encryption.is_some() {
+        error!("DAV_ENCRYPTION_KEY only encrypts contact files, not the sqlite backend");
+        return;
+    }
+    config.backend = Backend::Sqlite;
 
-    match fs::read_to_string(&file_path).await {
-        Ok(content) => {
-            info!("Contact found at {}", file_path.display());
-            (StatusCode::OK, content)
+    let data_dir = data_dir(&config);
+    let _lock = match DirLock::acquire(&data_dir) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("failed to lock {}: {}", data_dir.display(), e);
+            return;
         }
+    };
+
+    let books = match Books::open(&data_dir, config).await {
+        Ok(books) => books,
         Err(e) => {
-            error!("contact not found at {}: {}", file_path.display(), e);
-            (StatusCode::NOT_FOUND, "Contact not found".to_string())
+            error!("failed to open address books: {}", e);
+            return;
         }
+    };
+
+    match books.import_files().await {
+        Ok(count) => info!("Migrated {} contacts to SQLite", count),
+        Err(e) => error!("failed to migrate contacts: {}", e),
     }
 }
 
-async fn list_contacts(
-    State(state): State<Arc<AppState>>,
-) -> Result<(StatusCode, Json<Vec<Contact>>), (StatusCode, String)> {
-    match tokio::fs::read_dir(&*state.data_dir).await {
-        Ok(read_dir) => {
-            let mut contacts = Vec::new();
-            let mut dir_stream = ReadDirStream::new(read_dir);
-
-            while let Some(entry) = dir_stream.next().await {
-                match entry {
-                    Ok(entry) => {
-                        let path = entry.path();
-                        if let Ok(content) = tokio::fs::read_to_string(&path).await {
-                            if let Ok(contact) = content.parse::<Contact>() {
-                                contacts.push(contact);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Failed to read directory entry: {}", e);
-                    }
-                }
-            }
+#[cfg(not(feature = "sqlite"))]
+async fn migrate(_config: Config) {
+    error!("the sqlite backend is not compiled in");
+}
 
-            info!("Contact list created successfully");
-            Ok((StatusCode::OK, Json(contacts)))
-        }
+/// Checks that the data directory can be written, so an unwritable one is
+/// reported at startup rather than on the first write. Returns the message to
+/// report otherwise.
+async fn check_writable(dir: &Path) -> Result<(), String> {
+    probe_write(dir)
+        .await
+        .map_err(|e| format!("data directory is not writable: {}: {}", dir.display(), e))
+}
+
+/// Creates and removes a file in `dir`, creating it if needed.
+async fn probe_write(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir).await?;
+
+    let probe = dir.join(".write-probe");
+    fs::write(&probe, b"").await?;
+    fs::remove_file(&probe).await
+}
+
+#[derive(Serialize)]
+struct Health {
+    /// `ok`, or `degraded` when the last backup failed.
+    status: &'static str,
+    /// RFC 3339 time of the last successful backup since the server started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_backup: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backup_error: Option<String>,
+}
+
+async fn health_check(State(state): State<Arc<AppState>>) -> Json<Health> {
+    let backup = state.backups.status();
+
+    Json(Health {
+        status: if backup.error.is_some() {
+            "degraded"
+        } else {
+            "ok"
+        },
+        last_backup: backup
+            .last_success
+            .map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+        backup_error: backup.error,
+    })
+}
+
+async fn create_contact(
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+    Json(contact): Json<Contact>,
+) -> Response {
+    if let Err(response) = check_contact(&contact) {
+        return response.into_response();
+    }
+
+    // The ID may be locked before its contact exists, or the contact replaced.
+    if !book.locks.permits(&contact.id, &headers) {
+        warn!("contact is locked: {}", contact.id);
+        return (StatusCode::LOCKED, "contact is locked".to_string()).into_response();
+    }
+
+    let _write = book.lock_writes(&[&contact.id]).await;
+    // A replaced contact is kept in the history, like an update.
+    let previous = match book.store.get(&contact.id).await {
+        Ok(previous) => previous,
         Err(e) => {
-            error!("failed to list contacts: {}", e);
-            Err((
+            error!("failed to read contact {}: {}", contact.id, e);
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "failed to list contacts".to_string(),
-            ))
+                "failed to save contact".to_string(),
+            )
+                .into_response();
         }
+    };
+
+    match book.admits(&contact.id, 0).await {
+        Ok(true) => {}
+        Ok(false) => return contact_limit_reached(&book),
+        Err(e) => {
+            error!("failed to count the contacts of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to save contact".to_string(),
+            )
+                .into_response();
+        }
+    }
+
+    match book.fits(&contact).await {
+        Ok(true) => {}
+        Ok(false) => return quota_exceeded(&book),
+        Err(e) => {
+            error!("failed to compute usage of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to save contact".to_string(),
+            )
+                .into_response();
+        }
+    }
+
+    match book.store.put(&contact).await {
+        Ok(true) => {
+            info!("Contact saved: {}", contact.id);
+            record_change(&book, &contact.id, Operation::Create).await;
+            (StatusCode::CREATED, "Contact created".to_string()).into_response()
+        }
+        Ok(false) => {
+            info!("Contact replaced: {}", contact.id);
+            record_change(&book, &contact.id, Operation::Modify).await;
+            if let Some(previous) = &previous {
+                record_revision(&book, previous).await;
+            }
+            (StatusCode::OK, "Contact updated".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to save contact {}: {}", contact.id, e);
+            write_error(&e, "failed to save contact")
+        }
+    }
+}
+
+/// Rejects an ID from the path of a request that cannot name a file, with the
+/// reason.
+fn check_id(id: &str) -> Result<(), (StatusCode, String)> {
+    store::check_id(id).map_err(|e| {
+        warn!("invalid ID '{}': {}", id, e);
+        (StatusCode::BAD_REQUEST, e)
+    })
+}
+
+/// Rejects a contact that cannot be stored.
+fn check_contact(contact: &Contact) -> Result<(), (StatusCode, String)> {
+    contact.validate().map_err(|e| {
+        warn!("invalid contact {}: {}", contact.id, e);
+        (StatusCode::BAD_REQUEST, e)
+    })
+}
+
+/// JSON error answering writes that would exceed the quota of the book.
+fn quota_exceeded(book: &Book) -> Response {
+    warn!("quota of {} exceeded", book.name);
+    (
+        StatusCode::INSUFFICIENT_STORAGE,
+        Json(serde_json::json!({ "error": "quota exceeded" })),
+    )
+        .into_response()
+}
+
+/// JSON error answering the creation of a contact in a book holding its
+/// maximum number of contacts.
+fn contact_limit_reached(book: &Book) -> Response {
+    warn!("contact limit of {} reached", book.name);
+    (
+        StatusCode::INSUFFICIENT_STORAGE,
+        Json(serde_json::json!({ "error": "contact limit reached" })),
+    )
+        .into_response()
+}
+
+/// Seconds clients are asked to wait before retrying a write that failed on a
+/// full disk.
+const STORAGE_FULL_RETRY_SECS: u64 = 60;
+
+/// Status of a failed write: `507 Insufficient Storage` when the disk is full,
+/// so clients can tell a capacity problem from a bug, `500` otherwise.
+fn write_status(e: &StoreError) -> StatusCode {
+    if e.is_storage_full() {
+        StatusCode::INSUFFICIENT_STORAGE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// Response to a failed write, asking clients to retry later when the disk is
+/// full.
+fn write_error(e: &StoreError, message: &str) -> Response {
+    match write_status(e) {
+        StatusCode::INSUFFICIENT_STORAGE => {
+            warn!("storage is full");
+            (
+                StatusCode::INSUFFICIENT_STORAGE,
+                [(header::RETRY_AFTER, STORAGE_FULL_RETRY_SECS.to_string())],
+                "insufficient storage, retry later".to_string(),
+            )
+                .into_response()
+        }
+        status => (status, message.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct ImportFailure {
+    /// Position of the card in the imported file, starting at 1.
+    card: usize,
+    reason: String,
+}
+
+#[derive(Default, Serialize)]
+struct ImportSummary {
+    dry_run: bool,
+    imported: Vec<String>,
+    failed: Vec<ImportFailure>,
+}
+
+async fn import_contacts(
+    AddressBook(book): AddressBook,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Result<Json<ImportSummary>, (StatusCode, String)> {
+    let cards = parse_vcard_bytes(&body).map_err(|e| {
+        warn!("rejected import into {}: {}", book.name, e);
+        (StatusCode::BAD_REQUEST, e)
+    })?;
+
+    Ok(Json(import_cards(&book, &cards, query.dry_run).await))
+}
+
+/// Parses and validates a single vCard without storing it, returning the
+/// contact it would make.
+async fn validate_contact(body: Bytes) -> Result<Json<Contact>, (StatusCode, String)> {
+    let contact = std::str::from_utf8(&body)
+        .map_err(|e| format!("vCard data is not valid UTF-8: {}", e))
+        .and_then(str::parse::<Contact>)
+        .and_then(|contact| contact.validate().map(|()| contact))
+        .map_err(|e| {
+            warn!("invalid vCard: {}", e);
+            (StatusCode::BAD_REQUEST, e)
+        })?;
+
+    info!("vCard validated: {}", contact.id);
+    Ok(Json(contact))
+}
+
+/// Imports vCard cards into an address book, for the import route and the
+/// `dav import` command. Each card is validated and checked against the
+/// quota, then the accepted ones are saved together.
+async fn import_cards(book: &Book, cards: &[String], dry_run: bool) -> ImportSummary {
+    let mut summary = ImportSummary {
+        dry_run,
+        ..ImportSummary::default()
+    };
+    let mut parsed = Vec::new();
+
+    for (index, card) in cards.iter().enumerate() {
+        match card
+            .parse::<Contact>()
+            .and_then(|contact| contact.validate().map(|()| contact))
+        {
+            Ok(contact) => parsed.push((index, contact)),
+            Err(reason) => summary.failed.push(ImportFailure {
+                card: index + 1,
+                reason,
+            }),
+        }
+    }
+
+    if dry_run {
+        summary.imported = parsed.into_iter().map(|(_, contact)| contact.id).collect();
+    } else {
+        save_cards(book, parsed, &mut summary).await;
+    }
+
+    info!(
+        "Import finished: {} imported, {} failed (dry run: {})",
+        summary.imported.len(),
+        summary.failed.len(),
+        dry_run
+    );
+    summary
+}
+
+/// Checks the valid cards of an import, with their index, against the
+/// contact limit and the quota, then saves the accepted ones together.
+async fn save_cards(book: &Book, cards: Vec<(usize, Contact)>, summary: &mut ImportSummary) {
+    // Held from the checks to the save, so a concurrent write cannot take the
+    // room the checks counted on.
+    let _write = {
+        let ids: Vec<&str> = cards
+            .iter()
+            .map(|(_, contact)| contact.id.as_str())
+            .collect();
+        book.lock_writes(&ids).await
+    };
+
+    let mut accepted = Vec::new();
+    let mut pending = 0;
+    // A card repeating the ID of a new contact of the import replaces it, so
+    // it is only counted once.
+    let mut pending_new = HashSet::new();
+
+    for (index, contact) in cards {
+        let repeated = pending_new.contains(&contact.id);
+
+        match book.admits(&contact.id, pending_new.len()).await {
+            Ok(true) => {}
+            Ok(false) if repeated => {}
+            Ok(false) => {
+                warn!("contact limit of {} reached by {}", book.name, contact.id);
+                summary.failed.push(ImportFailure {
+                    card: index + 1,
+                    reason: "contact limit reached".to_string(),
+                });
+                continue;
+            }
+            Err(e) => {
+                error!("failed to count the contacts of {}: {}", book.name, e);
+                summary.failed.push(ImportFailure {
+                    card: index + 1,
+                    reason: "failed to save contact".to_string(),
+                });
+                continue;
+            }
+        }
+
+        match book.fits_with(&contact, pending).await {
+            Ok(true) => pending += contact.to_string().len() as u64,
+            Ok(false) => {
+                warn!("quota of {} exceeded by {}", book.name, contact.id);
+                summary.failed.push(ImportFailure {
+                    card: index + 1,
+                    reason: "quota exceeded".to_string(),
+                });
+                continue;
+            }
+            Err(e) => {
+                error!("failed to compute usage of {}: {}", book.name, e);
+                summary.failed.push(ImportFailure {
+                    card: index + 1,
+                    reason: "failed to save contact".to_string(),
+                });
+                continue;
+            }
+        }
+
+        if !repeated && matches!(book.store.exists(&contact.id).await, Ok(false)) {
+            pending_new.insert(contact.id.clone());
+        }
+        accepted.push((index, contact));
+    }
+
+    // The cards are saved together so a failure leaves none of them behind on
+    // backends with transactions.
+    let (indexes, contacts): (Vec<_>, Vec<_>) = accepted.into_iter().unzip();
+
+    // Contacts replaced by the import are kept in their history.
+    let mut replaced = Vec::new();
+    if book.history.is_enabled() {
+        for contact in &contacts {
+            match book.store.get(&contact.id).await {
+                Ok(Some(previous)) => replaced.push(previous),
+                Ok(None) => {}
+                Err(e) => error!("failed to read contact {}: {}", contact.id, e),
+            }
+        }
+    }
+
+    match book.store.write_batch(&contacts, &[]).await {
+        Ok(created) => {
+            for previous in &replaced {
+                record_revision(book, previous).await;
+            }
+
+            for (contact, created) in contacts.into_iter().zip(created) {
+                let op = if created {
+                    Operation::Create
+                } else {
+                    Operation::Modify
+                };
+                record_change(book, &contact.id, op).await;
+                summary.imported.push(contact.id);
+            }
+        }
+        Err(e) => {
+            error!("failed to save imported contacts: {}", e);
+            for index in indexes {
+                summary.failed.push(ImportFailure {
+                    card: index + 1,
+                    reason: "failed to save contact".to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Returns every contact of the address book as a single vCard stream.
+async fn export_contacts(AddressBook(book): AddressBook) -> Response {
+    let mut contacts = match book.store.list().await {
+        Ok(contacts) => contacts,
+        Err(e) => {
+            error!("failed to list contacts: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to export contacts".to_string(),
+            )
+                .into_response();
+        }
+    };
+    contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let body: String = contacts.iter().map(Contact::to_string).collect();
+
+    info!("Exported {} contacts from {}", contacts.len(), book.name);
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/vcard; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+/// Largest number of contacts fetched by a single batch request.
+const MAX_BATCH_IDS: usize = 500;
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct BatchGetQuery {
+    fields: Option<String>,
+}
+
+/// Returns the requested contacts by ID, with `null` for the missing ones.
+async fn batch_get_contacts(
+    AddressBook(book): AddressBook,
+    Query(query): Query<BatchGetQuery>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<serde_json::Map<String, serde_json::Value>>, (StatusCode, String)> {
+    let fields = query.fields.as_deref().map(parse_fields).transpose()?;
+
+    if request.ids.len() > MAX_BATCH_IDS {
+        warn!("batch of {} IDs rejected", request.ids.len());
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("at most {} IDs can be requested at once", MAX_BATCH_IDS),
+        ));
+    }
+
+    let mut contacts = serde_json::Map::new();
+    for id in request.ids {
+        // IDs that cannot name a stored contact are reported as missing.
+        if !dav::is_valid_id(&id) {
+            contacts.insert(id, serde_json::Value::Null);
+            continue;
+        }
+
+        let contact = match book.store.get(&id).await {
+            Ok(contact) => contact,
+            Err(e) => {
+                error!("failed to read contact {}: {}", id, e);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "failed to read contacts".to_string(),
+                ));
+            }
+        };
+
+        let value = match (&contact, &fields) {
+            (Some(contact), Some(fields)) => project(contact, fields),
+            _ => serde_json::json!(contact),
+        };
+        contacts.insert(id, value);
+    }
+
+    info!("Batch of {} contacts served", contacts.len());
+    Ok(Json(contacts))
+}
+
+/// Outcome of the deletion of a contact of a batch.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BatchDeleteResult {
+    Deleted,
+    NotFound,
+    Locked,
+    Error,
+}
+
+/// Deletes the requested contacts, returning the outcome for each ID. A
+/// failure only affects its own contact.
+async fn batch_delete_contacts(
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<BTreeMap<String, BatchDeleteResult>>, (StatusCode, String)> {
+    if request.ids.len() > MAX_BATCH_IDS {
+        warn!("batch of {} IDs rejected", request.ids.len());
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("at most {} IDs can be deleted at once", MAX_BATCH_IDS),
+        ));
+    }
+
+    let mut results = BTreeMap::new();
+    for id in request.ids {
+        // IDs that cannot name a stored contact are reported as missing.
+        if !dav::is_valid_id(&id) {
+            results.insert(id, BatchDeleteResult::NotFound);
+            continue;
+        }
+
+        if !book.locks.permits(&id, &headers) {
+            warn!("contact is locked: {}", id);
+            results.insert(id, BatchDeleteResult::Locked);
+            continue;
+        }
+
+        let _write = book.lock_writes(&[&id]).await;
+        let result = match book.remove(&id).await {
+            Ok(true) => {
+                record_change(&book, &id, Operation::Delete).await;
+                BatchDeleteResult::Deleted
+            }
+            Ok(false) => BatchDeleteResult::NotFound,
+            Err(e) => {
+                error!("failed to delete contact {}: {}", id, e);
+                BatchDeleteResult::Error
+            }
+        };
+        results.insert(id, result);
+    }
+
+    let deleted = results
+        .values()
+        .filter(|result| matches!(result, BatchDeleteResult::Deleted))
+        .count();
+    info!(
+        "Batch delete of {} contacts, {} deleted",
+        results.len(),
+        deleted
+    );
+    Ok(Json(results))
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    /// Ranks the contacts by similarity of their name instead of requiring it
+    /// to contain the query.
+    #[serde(default)]
+    fuzzy: bool,
+    fields: Option<String>,
+}
+
+/// Searches the contacts by name, sorted by ID, or by decreasing similarity
+/// for a fuzzy search.
+async fn search_contacts(
+    AddressBook(book): AddressBook,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, String)> {
+    let fields = query.fields.as_deref().map(parse_fields).transpose()?;
+
+    let contacts = book.store.list().await.map_err(|e| {
+        error!("failed to list contacts: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to search contacts".to_string(),
+        )
+    })?;
+    let found = search::filter(contacts, &query.q, query.fuzzy);
+
+    info!("Search for '{}' found {} contacts", query.q, found.len());
+    Ok(Json(
+        found
+            .iter()
+            .map(|contact| match &fields {
+                Some(fields) => project(contact, fields),
+                None => serde_json::json!(contact),
+            })
+            .collect(),
+    ))
+}
+
+/// Groups the contacts that look like duplicates, sharing an email address or
+/// a name, for a client to offer merging them.
+async fn duplicate_contacts(
+    AddressBook(book): AddressBook,
+) -> Result<Json<Vec<duplicates::Cluster>>, (StatusCode, String)> {
+    let contacts = book.store.list().await.map_err(|e| {
+        error!("failed to list contacts: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to read contacts".to_string(),
+        )
+    })?;
+
+    let clusters = duplicates::find(contacts);
+    info!(
+        "Found {} clusters of duplicates in {}",
+        clusters.len(),
+        book.name
+    );
+    Ok(Json(clusters))
+}
+
+/// Returns the first contact, by ID, with the given email address, compared
+/// case-insensitively.
+async fn contact_by_email(
+    AxumPath(EmailPath { email }): AxumPath<EmailPath>,
+    AddressBook(book): AddressBook,
+) -> Response {
+    let mut contacts = match book.store.list().await {
+        Ok(contacts) => contacts,
+        Err(e) => {
+            error!("failed to list contacts: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read contacts".to_string(),
+            )
+                .into_response();
+        }
+    };
+    contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let normalized = search::normalize_email(&email);
+    let found = contacts.into_iter().find(|contact| {
+        contact
+            .emails()
+            .iter()
+            .any(|candidate| search::normalize_email(candidate) == normalized)
+    });
+
+    match found {
+        Some(contact) => {
+            info!("Contact found by email: {}", contact.id);
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/vcard; charset=utf-8")],
+                contact.to_string(),
+            )
+                .into_response()
+        }
+        None => {
+            warn!("no contact with email: {}", email);
+            (StatusCode::NOT_FOUND, "Contact not found".to_string()).into_response()
+        }
+    }
+}
+
+async fn modify_contact(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+    Json(mut updated_contact): Json<Contact>,
+) -> Response {
+    if let Err(e) = check_id(&id) {
+        return e.into_response();
+    }
+
+    if !book.locks.permits(&id, &headers) {
+        warn!("contact is locked: {}", id);
+        return (StatusCode::LOCKED, "contact is locked".to_string()).into_response();
+    }
+
+    if let Err(response) = check_contact(&updated_contact) {
+        return response.into_response();
+    }
+
+    let _write = book.lock_writes(&[&id]).await;
+    let existing = match book.store.get(&id).await {
+        Ok(Some(existing)) => existing,
+        Ok(None) => {
+            warn!("contact not found for update: {}", id);
+            return (StatusCode::NOT_FOUND, "contact not found".to_string()).into_response();
+        }
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to update contact".to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    if id != updated_contact.id {
+        warn!("ID '{}' does not match body ID: {}", id, updated_contact.id);
+        return (
+            StatusCode::BAD_REQUEST,
+            "ID in URL and body must match".to_string(),
+        )
+            .into_response();
+    }
+
+    // A stored card with another UID was probably renamed or copied by hand,
+    // overwriting it would silently change its identity.
+    if existing.id != id {
+        warn!("stored contact {} has the ID '{}'", id, existing.id);
+        return (
+            StatusCode::CONFLICT,
+            format!("stored contact has a different ID: {}", existing.id),
+        )
+            .into_response();
+    }
+
+    // The JSON body only carries the fields, keep the other vCard properties
+    // and the groups tying them to the email and phone.
+    updated_contact.extras = existing.extras.clone();
+    updated_contact.email_group = existing.email_group.clone();
+    updated_contact.phone_group = existing.phone_group.clone();
+    updated_contact.touch();
+
+    match book.fits(&updated_contact).await {
+        Ok(true) => {}
+        Ok(false) => return quota_exceeded(&book),
+        Err(e) => {
+            error!("failed to compute usage of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to update contact".to_string(),
+            )
+                .into_response();
+        }
+    }
+
+    match book.store.put(&updated_contact).await {
+        Ok(_) => {
+            info!("contact updated: {}", id);
+            record_change(&book, &id, Operation::Modify).await;
+            record_revision(&book, &existing).await;
+            (StatusCode::OK, "Contact updated".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to update contact {}: {}", id, e);
+            write_error(&e, "failed to update contact")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    other_id: String,
+}
+
+/// Merges another contact into the one at `id`, then deletes the other one.
+async fn merge_contacts(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+    Json(request): Json<MergeRequest>,
+) -> Result<Json<Contact>, Response> {
+    check_id(&id).map_err(IntoResponse::into_response)?;
+    check_id(&request.other_id).map_err(IntoResponse::into_response)?;
+
+    // The merge modifies one contact and deletes the other.
+    for locked in [&id, &request.other_id] {
+        if !book.locks.permits(locked, &headers) {
+            warn!("contact is locked: {}", locked);
+            return Err((StatusCode::LOCKED, "contact is locked".to_string()).into_response());
+        }
+    }
+
+    if request.other_id == id {
+        warn!("cannot merge contact {} into itself", id);
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "cannot merge a contact into itself".to_string(),
+        )
+            .into_response());
+    }
+
+    let merge_error = |e| {
+        error!("failed to merge {} into {}: {}", request.other_id, id, e);
+        write_error(&e, "failed to merge contacts")
+    };
+
+    let _write = book.lock_writes(&[&id, &request.other_id]).await;
+    let Some(mut contact) = book.store.get(&id).await.map_err(merge_error)? else {
+        warn!("contact not found for merge: {}", id);
+        return Err((StatusCode::NOT_FOUND, "contact not found".to_string()).into_response());
+    };
+    let Some(other) = book
+        .store
+        .get(&request.other_id)
+        .await
+        .map_err(merge_error)?
+    else {
+        warn!("contact to merge not found: {}", request.other_id);
+        return Err((
+            StatusCode::NOT_FOUND,
+            "contact to merge not found".to_string(),
+        )
+            .into_response());
+    };
+
+    // Both contacts are kept in their history, so the merge can be undone.
+    let previous = contact.clone();
+    contact.merge(other.clone());
+    contact.touch();
+
+    book.store
+        .write_batch(std::slice::from_ref(&contact), &[&request.other_id])
+        .await
+        .map_err(merge_error)?;
+    record_change(&book, &id, Operation::Modify).await;
+    record_change(&book, &request.other_id, Operation::Delete).await;
+    record_revision(&book, &previous).await;
+    record_revision(&book, &other).await;
+
+    info!("Contact {} merged into {}", request.other_id, id);
+    Ok(Json(contact))
+}
+
+async fn delete_contact(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+) -> (StatusCode, String) {
+    if let Err(e) = check_id(&id) {
+        return e;
+    }
+
+    if !book.locks.permits(&id, &headers) {
+        warn!("contact is locked: {}", id);
+        return (StatusCode::LOCKED, "contact is locked".to_string());
+    }
+
+    let _write = book.lock_writes(&[&id]).await;
+    match book.remove(&id).await {
+        Ok(true) => {
+            info!("Contact deleted: {}", id);
+            record_change(&book, &id, Operation::Delete).await;
+            (StatusCode::OK, "Contact deleted".to_string())
+        }
+        Ok(false) => {
+            warn!("contact not found for deletion: {}", id);
+            (StatusCode::NOT_FOUND, "contact not found".to_string())
+        }
+        Err(e) => {
+            error!("failed to delete contact {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to delete contact".to_string(),
+            )
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TrashedContact {
+    #[serde(flatten)]
+    contact: Contact,
+    /// RFC 3339 time of the deletion.
+    deleted: String,
+}
+
+/// Lists the contacts in the trash, sorted by ID.
+async fn list_trash(
+    AddressBook(book): AddressBook,
+) -> Result<Json<Vec<TrashedContact>>, (StatusCode, String)> {
+    let mut trashed = book.store.trashed().await.map_err(|e| {
+        error!("failed to list the trash of {}: {}", book.name, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list trash".to_string(),
+        )
+    })?;
+    trashed.sort_by(|a, b| a.contact.id.cmp(&b.contact.id));
+
+    info!("Trash of {} listed successfully", book.name);
+    Ok(Json(
+        trashed
+            .into_iter()
+            .map(|trashed| TrashedContact {
+                contact: trashed.contact,
+                deleted: DateTime::<Utc>::from(trashed.deleted).to_rfc3339(),
+            })
+            .collect(),
+    ))
+}
+
+/// Removes a contact from the trash for good.
+async fn purge_contact(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+) -> (StatusCode, String) {
+    if let Err(e) = check_id(&id) {
+        return e;
+    }
+
+    match book.store.purge(&id).await {
+        Ok(true) => {
+            info!("Contact purged from the trash: {}", id);
+            (StatusCode::OK, "Contact purged".to_string())
+        }
+        Ok(false) => {
+            warn!("contact not found in trash: {}", id);
+            (
+                StatusCode::NOT_FOUND,
+                "contact not found in trash".to_string(),
+            )
+        }
+        Err(e) => {
+            error!("failed to purge contact {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to purge contact".to_string(),
+            )
+        }
+    }
+}
+
+/// Every hour, removes for good the contacts of every address book that have
+/// been in the trash for longer than `retention`.
+async fn purge_trash(
+    state: Arc<AppState>,
+    retention: Duration,
+    mut stopping: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stopping.wait_for(|&stop| stop) => break,
+        }
+
+        let cutoff = SystemTime::now() - retention;
+        for book in state.books.list().await {
+            match book.store.purge_before(cutoff).await {
+                Ok(0) => {}
+                Ok(purged) => info!("Purged {} contacts from the trash of {}", purged, book.name),
+                Err(e) => error!("failed to purge the trash of {}: {}", book.name, e),
+            }
+        }
+    }
+}
+
+/// Every `interval`, records the contacts of every address book changed outside
+/// of the server, such as files edited by hand, so clients sync them. Changes
+/// made within an interval are picked up together.
+async fn watch_contacts(
+    state: Arc<AppState>,
+    interval: Duration,
+    mut stopping: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stopping.wait_for(|&stop| stop) => break,
+        }
+
+        for book in state.books.list().await {
+            let changes = match book.store.external_changes().await {
+                Ok(changes) => changes,
+                Err(e) => {
+                    error!("failed to look for changes in {}: {}", book.name, e);
+                    continue;
+                }
+            };
+
+            for change in changes {
+                let (id, op, what) = match change {
+                    ExternalChange::Created(id) => (id, Operation::Create, "created"),
+                    ExternalChange::Modified(id) => (id, Operation::Modify, "modified"),
+                    ExternalChange::Deleted(id) => (id, Operation::Delete, "deleted"),
+                };
+
+                info!(
+                    "Contact {} of {} {} outside of the server",
+                    id, book.name, what
+                );
+                record_change(&book, &id, op).await;
+            }
+        }
+    }
+}
+
+/// Moves a contact back from the trash, unless a contact with the same ID was
+/// created since.
+async fn restore_contact(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+) -> (StatusCode, String) {
+    if let Err(e) = check_id(&id) {
+        return e;
+    }
+
+    let _write = book.lock_writes(&[&id]).await;
+    match book.store.exists(&id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            warn!("cannot restore {} over an existing contact", id);
+            return (StatusCode::CONFLICT, "contact already exists".to_string());
+        }
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to restore contact".to_string(),
+            );
+        }
+    }
+
+    match book.admits(&id, 0).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("contact limit of {} reached", book.name);
+            return (
+                StatusCode::INSUFFICIENT_STORAGE,
+                "contact limit reached".to_string(),
+            );
+        }
+        Err(e) => {
+            error!("failed to count the contacts of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to restore contact".to_string(),
+            );
+        }
+    }
+
+    match book.store.restore(&id).await {
+        Ok(true) => {
+            info!("Contact restored: {}", id);
+            record_change(&book, &id, Operation::Create).await;
+            (StatusCode::OK, "Contact restored".to_string())
+        }
+        Ok(false) => {
+            warn!("contact not found in trash: {}", id);
+            (
+                StatusCode::NOT_FOUND,
+                "contact not found in trash".to_string(),
+            )
+        }
+        Err(e) => {
+            error!("failed to restore contact {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to restore contact".to_string(),
+            )
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RevisionSummary {
+    rev: String,
+    /// RFC 3339 time at which the revision was replaced.
+    replaced: String,
+    etag: String,
+}
+
+/// Lists the previous versions of a contact, oldest first.
+async fn contact_history(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+) -> Result<Json<Vec<RevisionSummary>>, (StatusCode, String)> {
+    check_id(&id)?;
+
+    let revisions = book.history.list(&id).await.map_err(|e| {
+        error!("failed to list the history of {}: {}", id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list history".to_string(),
+        )
+    })?;
+
+    info!("History of {} listed successfully", id);
+    Ok(Json(
+        revisions
+            .into_iter()
+            .map(|revision| RevisionSummary {
+                rev: revision.rev,
+                replaced: DateTime::<Utc>::from(revision.replaced).to_rfc3339(),
+                etag: revision.etag,
+            })
+            .collect(),
+    ))
+}
+
+/// Reads a previous version of a contact as a vCard.
+async fn find_revision(book: &Book, id: &str, rev: &str) -> Result<String, (StatusCode, String)> {
+    check_id(id)?;
+
+    match book.history.get(id, rev).await {
+        Ok(Some(vcard)) => Ok(vcard),
+        Ok(None) => {
+            warn!("revision {} of {} not found", rev, id);
+            Err((StatusCode::NOT_FOUND, "revision not found".to_string()))
+        }
+        Err(e) => {
+            error!("failed to read revision {} of {}: {}", rev, id, e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read revision".to_string(),
+            ))
+        }
+    }
+}
+
+/// Returns a previous version of a contact.
+async fn contact_revision(
+    AxumPath(RevisionPath { id, rev }): AxumPath<RevisionPath>,
+    AddressBook(book): AddressBook,
+) -> Response {
+    match find_revision(&book, &id, &rev).await {
+        Ok(vcard) => {
+            info!("Revision {} of {} found", rev, id);
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/vcard; charset=utf-8")],
+                vcard,
+            )
+                .into_response()
+        }
+        Err(response) => response.into_response(),
+    }
+}
+
+/// Writes a previous version of a contact back as a new version, the current
+/// one being kept in the history.
+async fn revert_contact(
+    AxumPath(RevisionPath { id, rev }): AxumPath<RevisionPath>,
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+) -> Response {
+    if !book.locks.permits(&id, &headers) {
+        warn!("contact is locked: {}", id);
+        return (StatusCode::LOCKED, "contact is locked".to_string()).into_response();
+    }
+
+    let _write = book.lock_writes(&[&id]).await;
+    let mut contact = match find_revision(&book, &id, &rev).await {
+        Ok(vcard) => match vcard.parse::<Contact>() {
+            Ok(contact) => contact,
+            Err(e) => {
+                error!("invalid revision {} of {}: {}", rev, id, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "failed to read revision".to_string(),
+                )
+                    .into_response();
+            }
+        },
+        Err(response) => return response.into_response(),
+    };
+    contact.touch();
+
+    let current = match book.store.get(&id).await {
+        Ok(current) => current,
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to revert contact".to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    match book.admits(&id, 0).await {
+        Ok(true) => {}
+        Ok(false) => return contact_limit_reached(&book),
+        Err(e) => {
+            error!("failed to count the contacts of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to revert contact".to_string(),
+            )
+                .into_response();
+        }
+    }
+
+    match book.fits(&contact).await {
+        Ok(true) => {}
+        Ok(false) => return quota_exceeded(&book),
+        Err(e) => {
+            error!("failed to compute usage of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to revert contact".to_string(),
+            )
+                .into_response();
+        }
+    }
+
+    match book.store.put(&contact).await {
+        Ok(created) => {
+            info!("Contact {} reverted to {}", id, rev);
+            let op = if created {
+                Operation::Create
+            } else {
+                Operation::Modify
+            };
+            record_change(&book, &id, op).await;
+            if let Some(current) = current {
+                record_revision(&book, &current).await;
+            }
+
+            (StatusCode::OK, Json(contact)).into_response()
+        }
+        Err(e) => {
+            error!("failed to revert contact {}: {}", id, e);
+            write_error(&e, "failed to revert contact")
+        }
+    }
+}
+
+/// Returns a contact picked at random, for a "contact of the day".
+async fn random_contact(AddressBook(book): AddressBook) -> Response {
+    match book.store.random().await {
+        Ok(Some(contact)) => {
+            info!("Random contact picked: {}", contact.id);
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/vcard; charset=utf-8")],
+                contact.to_string(),
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            warn!("no contact to pick in {}", book.name);
+            (StatusCode::NOT_FOUND, "no contacts".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to pick a random contact: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to pick a contact".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn contact_by_id(
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    AddressBook(book): AddressBook,
+    headers: HeaderMap,
+    Query(query): Query<ContactQuery>,
+) -> Response {
+    if let Err(e) = check_id(&id) {
+        return e.into_response();
+    }
+
+    let fields = match query.fields.as_deref().map(parse_fields).transpose() {
+        Ok(fields) => fields,
+        Err(e) => return e.into_response(),
+    };
+
+    let include_raw = match query.include.as_deref() {
+        None => false,
+        Some("raw") => true,
+        Some(include) => {
+            warn!("unknown include requested: {}", include);
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown include '{}', valid values are: raw", include),
+            )
+                .into_response();
+        }
+    };
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default();
+    let wants_jcard = accept.contains(JCARD_CONTENT_TYPE);
+    let wants_json = accept.contains("application/json");
+
+    let modified = match book.store.modified(&id).await {
+        Ok(modified) => modified,
+        Err(e) => {
+            warn!("failed to read modification time of {}: {}", id, e);
+            None
+        }
+    };
+    let last_modified = modified
+        .and_then(|modified| HeaderValue::from_str(&httpdate::fmt_http_date(modified)).ok());
+
+    if modified.is_some_and(|modified| unmodified_since(&headers, modified)) {
+        info!("Contact not modified: {}", id);
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        if let Some(value) = last_modified {
+            response.headers_mut().insert(header::LAST_MODIFIED, value);
+        }
+        return response;
+    }
+
+    let contact = match book.store.get(&id).await {
+        Ok(Some(contact)) => {
+            info!("Contact found: {}", id);
+            contact
+        }
+        Ok(None) => {
+            warn!("contact not found: {}", id);
+            return (StatusCode::NOT_FOUND, "Contact not found".to_string()).into_response();
+        }
+        Err(e) => {
+            error!("failed to read contact {}: {}", id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read contact".to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    let mut response = if wants_jcard {
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, JCARD_CONTENT_TYPE)],
+            contact.to_jcard().to_string(),
+        )
+            .into_response()
+    } else if include_raw && wants_json {
+        let structured = match &fields {
+            Some(fields) => project(&contact, fields),
+            None => serde_json::json!(contact),
+        };
+
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "contact": structured,
+                "raw": contact.to_string(),
+            })),
+        )
+            .into_response()
+    } else {
+        match fields {
+            Some(fields) => (StatusCode::OK, Json(project(&contact, &fields))).into_response(),
+            None => (StatusCode::OK, contact.to_string()).into_response(),
+        }
+    };
+
+    if let Some(value) = last_modified {
+        response.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+
+    response
+}
+
+/// Whether the `If-Modified-Since` header of a request dates from `modified`
+/// or later, compared at second resolution as HTTP dates have no finer one.
+/// The header is ignored next to `If-None-Match`, and when it is later than
+/// the clock of the server since the client clock is then ahead.
+fn unmodified_since(headers: &HeaderMap, modified: SystemTime) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+
+    let Some(since) = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| httpdate::parse_http_date(since).ok())
+    else {
+        return false;
+    };
+    if since > SystemTime::now() {
+        return false;
+    }
+
+    let secs = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    };
+    secs(modified) <= secs(since)
+}
+
+#[derive(Serialize)]
+struct ContactEtag {
+    id: String,
+    etag: String,
+}
+
+/// Lists the ID and the ETag of every contact, sorted by ID, for clients that
+/// only need to know what changed. The contacts are not read.
+async fn contact_ids(
+    AddressBook(book): AddressBook,
+) -> Result<Json<Vec<ContactEtag>>, (StatusCode, String)> {
+    let mut etags = book.store.etags().await.map_err(|e| {
+        error!("failed to list the ETags of {}: {}", book.name, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list contacts".to_string(),
+        )
+    })?;
+    etags.sort_unstable();
+
+    Ok(Json(
+        etags
+            .into_iter()
+            .map(|(id, etag)| ContactEtag { id, etag })
+            .collect(),
+    ))
+}
+
+async fn list_contacts(
+    AddressBook(book): AddressBook,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let fields = query.fields.as_deref().map(parse_fields).transpose()?;
+    let modified_since = query
+        .modified_since
+        .as_deref()
+        .map(|since| {
+            DateTime::parse_from_rfc3339(since).map_err(|e| {
+                warn!("invalid modified_since '{}': {}", since, e);
+                (
+                    StatusCode::BAD_REQUEST,
+                    "modified_since must be an RFC 3339 timestamp".to_string(),
+                )
+            })
+        })
+        .transpose()?;
+
+    let list_error = |e| {
+        error!("failed to list contacts: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list contacts".to_string(),
+        )
+    };
+
+    let listing = book.store.scan().await.map_err(list_error)?;
+    let mut contacts = Vec::new();
+    for contact in listing.contacts {
+        if query
+            .kind
+            .as_deref()
+            .is_some_and(|kind| !contact.is_kind(kind))
+        {
+            continue;
+        }
+
+        if let Some(since) = modified_since {
+            let modified = book.store.modified(&contact.id).await.map_err(list_error)?;
+            if modified.is_none_or(|modified| DateTime::<Utc>::from(modified) <= since) {
+                continue;
+            }
+        }
+
+        contacts.push(match &fields {
+            Some(fields) => project(&contact, fields),
+            None => serde_json::json!(contact),
+        });
+    }
+
+    info!("Contact list created successfully");
+    Ok((
+        StatusCode::OK,
+        [
+            (CTAG_HEADER, book.changes.current().await.to_string()),
+            (SKIPPED_HEADER, listing.skipped.to_string()),
+        ],
+        Json(contacts),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    fields: Option<String>,
+    modified_since: Option<String>,
+    /// Only lists the contacts of this `KIND`.
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ContactQuery {
+    fields: Option<String>,
+    /// `raw` adds the vCard text to JSON responses.
+    include: Option<String>,
+}
+
+/// Parses a comma-separated list of contact field names.
+fn parse_fields(fields: &str) -> Result<Vec<String>, (StatusCode, String)> {
+    let mut parsed = Vec::new();
+
+    for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        if !Contact::FIELDS.contains(&field) {
+            warn!("unknown contact field requested: {}", field);
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "unknown field '{}', valid fields are: {}",
+                    field,
+                    Contact::FIELDS.join(", ")
+                ),
+            ));
+        }
+
+        parsed.push(field.to_string());
+    }
+
+    Ok(parsed)
+}
+
+/// Serializes only the given fields of a contact.
+fn project(contact: &Contact, fields: &[String]) -> serde_json::Value {
+    let mut value = serde_json::json!(contact);
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, _| fields.contains(key));
+    }
+
+    value
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    since: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChangedContact {
+    id: String,
+    etag: String,
+}
+
+#[derive(Serialize)]
+struct ChangesFeed {
+    token: String,
+    changed: Vec<ChangedContact>,
+    deleted: Vec<String>,
+}
+
+async fn list_changes(
+    AddressBook(book): AddressBook,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesFeed>, (StatusCode, String)> {
+    let since = query
+        .since
+        .map(|since| {
+            since.parse::<u64>().map_err(|_| {
+                warn!("invalid sync token: {}", since);
+                (StatusCode::BAD_REQUEST, "invalid sync token".to_string())
+            })
+        })
+        .transpose()?;
+
+    match changes_since(&book, since).await {
+        Ok(Some(feed)) => {
+            info!("Changes listed successfully");
+            Ok(Json(feed))
+        }
+        Ok(None) => {
+            warn!("expired sync token: {:?}", since);
+            Err((
+                StatusCode::GONE,
+                "sync token expired, full resync required".to_string(),
+            ))
+        }
+        Err(e) => {
+            error!("failed to list changes: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to list changes".to_string(),
+            ))
+        }
+    }
+}
+
+/// Computes what changed since the given sequence number, or `None` if it has
+/// expired. Without a sequence number every stored contact counts as changed.
+async fn changes_since(book: &Book, since: Option<u64>) -> Result<Option<ChangesFeed>, StoreError> {
+    let Some(since) = since else {
+        let token = book.changes.current().await;
+
+        let mut changed = Vec::new();
+        for contact in book.store.list().await? {
+            if let Some(etag) = book.store.etag(&contact.id).await? {
+                changed.push(ChangedContact {
+                    id: contact.id,
+                    etag,
+                });
+            }
+        }
+
+        return Ok(Some(ChangesFeed {
+            token: token.to_string(),
+            changed,
+            deleted: Vec::new(),
+        }));
+    };
+
+    let Some((token, entries)) = book.changes.since(since).await else {
+        return Ok(None);
+    };
+
+    // Only the latest operation on each contact matters to the client.
+    let mut latest = BTreeMap::new();
+    for entry in entries {
+        latest.insert(entry.id, entry.op);
+    }
+
+    let mut changed = Vec::new();
+    let mut deleted = Vec::new();
+    for (id, op) in latest {
+        let etag = match op {
+            Operation::Create | Operation::Modify => book.store.etag(&id).await?,
+            Operation::Delete => None,
+        };
+
+        match etag {
+            Some(etag) => changed.push(ChangedContact { id, etag }),
+            None => deleted.push(id),
+        }
+    }
+
+    Ok(Some(ChangesFeed {
+        token: token.to_string(),
+        changed,
+        deleted,
+    }))
+}
+
+#[derive(Default, Serialize)]
+struct Stats {
+    total: usize,
+    with_email: usize,
+    without_email: usize,
+    with_phone: usize,
+    without_phone: usize,
+    categories: BTreeMap<String, usize>,
+    total_bytes: u64,
+    last_modified: Option<String>,
+}
+
+async fn stats(AddressBook(book): AddressBook) -> Result<Json<Stats>, (StatusCode, String)> {
+    let stats_error = |e| {
+        error!("failed to compute statistics: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to compute statistics".to_string(),
+        )
+    };
+
+    let mut stats = Stats {
+        total_bytes: book.store.usage().await.map_err(stats_error)?,
+        ..Stats::default()
+    };
+    let mut last_modified = None;
+
+    for contact in book.store.list().await.map_err(stats_error)? {
+        stats.total += 1;
+
+        if contact.email.is_empty() {
+            stats.without_email += 1;
+        } else {
+            stats.with_email += 1;
+        }
+
+        if contact.phone.is_empty() {
+            stats.without_phone += 1;
+        } else {
+            stats.with_phone += 1;
+        }
+
+        for category in contact.categories {
+            *stats.categories.entry(category).or_default() += 1;
+        }
+
+        let modified = book
+            .store
+            .modified(&contact.id)
+            .await
+            .map_err(stats_error)?;
+        last_modified = last_modified.max(modified);
+    }
+
+    stats.last_modified =
+        last_modified.map(|modified| DateTime::<Utc>::from(modified).to_rfc3339());
+
+    info!("Statistics computed successfully");
+    Ok(Json(stats))
+}
+
+#[derive(Serialize)]
+struct CollectionStats {
+    count: usize,
+    total_bytes: u64,
+    largest_bytes: u64,
+}
+
+/// Reports the number and size of the stored contacts from the file sizes,
+/// without reading them.
+async fn collection_stats(
+    AddressBook(book): AddressBook,
+) -> Result<Json<CollectionStats>, (StatusCode, String)> {
+    let sizes = book.store.sizes().await.map_err(|e| {
+        error!("failed to compute the size of {}: {}", book.name, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to compute statistics".to_string(),
+        )
+    })?;
+
+    info!("Size of {} computed successfully", book.name);
+    Ok(Json(CollectionStats {
+        count: sizes.len(),
+        total_bytes: sizes.iter().sum(),
+        largest_bytes: sizes.iter().copied().max().unwrap_or_default(),
+    }))
+}
+
+#[derive(Serialize)]
+struct Reindexed {
+    books: usize,
+    contacts: usize,
+}
+
+/// Rebuilds the index of every address book, for contacts edited on disk.
+async fn reindex(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Reindexed>, (StatusCode, String)> {
+    let mut reindexed = Reindexed {
+        books: 0,
+        contacts: 0,
+    };
+
+    for book in state.books.list().await {
+        reindexed.contacts += book.store.reindex().await.map_err(|e| {
+            error!("failed to reindex {}: {}", book.name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to reindex contacts".to_string(),
+            )
+        })?;
+        reindexed.books += 1;
+    }
+
+    info!(
+        "Reindexed {} contacts of {} address books",
+        reindexed.contacts, reindexed.books
+    );
+    Ok(Json(reindexed))
+}
+
+#[derive(Serialize)]
+struct Migrated {
+    books: usize,
+    moved: usize,
+}
+
+/// Moves the contact files still in an older layout of the store, for files
+/// copied into the data directory while the server runs.
+async fn migrate_layout(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Migrated>, (StatusCode, String)> {
+    let mut migrated = Migrated { books: 0, moved: 0 };
+
+    for book in state.books.list().await {
+        migrated.moved += book.store.migrate_layout().await.map_err(|e| {
+            error!("failed to migrate the layout of {}: {}", book.name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to migrate the layout".to_string(),
+            )
+        })?;
+        migrated.books += 1;
+    }
+
+    info!(
+        "Moved {} contact files of {} address books to the sharded layout",
+        migrated.moved, migrated.books
+    );
+    Ok(Json(migrated))
+}
+
+#[derive(Deserialize)]
+struct FsckQuery {
+    #[serde(default)]
+    repair: bool,
+}
+
+#[derive(Serialize)]
+struct FsckProblem {
+    book: String,
+    file: String,
+    problem: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    /// What was done about the problem, when repairing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repair: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct FsckReport {
+    repair: bool,
+    problems: Vec<FsckProblem>,
+}
+
+/// Checks the stored contacts of every address book, and repairs what can be
+/// repaired safely with `?repair=true`.
+async fn fsck(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FsckQuery>,
+) -> Result<Json<FsckReport>, (StatusCode, String)> {
+    let mut report = FsckReport {
+        repair: query.repair,
+        problems: Vec::new(),
+    };
+
+    for book in state.books.list().await {
+        let problems = book.store.check(query.repair).await.map_err(|e| {
+            error!("failed to check {}: {}", book.name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to check contacts".to_string(),
+            )
+        })?;
+
+        for problem in problems {
+            warn!("{} of {}: {}", problem.file, book.name, problem.kind.name());
+            report.problems.push(FsckProblem {
+                book: book.name.clone(),
+                file: problem.file,
+                problem: problem.kind.name(),
+                detail: problem.kind.detail().map(str::to_string),
+                repair: problem.repair,
+            });
+        }
+    }
+
+    info!(
+        "Checked contacts: {} problems (repair: {})",
+        report.problems.len(),
+        query.repair
+    );
+    Ok(Json(report))
+}
+
+#[derive(Serialize)]
+struct BackupSummary {
+    name: String,
+    /// RFC 3339 time at which the backup was taken.
+    created: String,
+    size: u64,
+}
+
+impl From<Backup> for BackupSummary {
+    fn from(backup: Backup) -> Self {
+        BackupSummary {
+            name: backup.name,
+            created: DateTime::<Utc>::from(backup.created).to_rfc3339(),
+            size: backup.size,
+        }
+    }
+}
+
+/// Takes a backup of every address book right away.
+async fn create_backup(
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<BackupSummary>), (StatusCode, String)> {
+    match state.backups.run(&state.books).await {
+        Ok(backup) => {
+            info!("Backup {} taken", backup.name);
+            Ok((StatusCode::CREATED, Json(backup.into())))
+        }
+        Err(e) => {
+            error!("failed to back up the address books: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to back up the address books".to_string(),
+            ))
+        }
+    }
+}
+
+/// Lists the backups, oldest first.
+async fn list_backups(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<BackupSummary>>, (StatusCode, String)> {
+    let backups = state.backups.list().await.map_err(|e| {
+        error!("failed to list backups: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list backups".to_string(),
+        )
+    })?;
+
+    info!("{} backups listed", backups.len());
+    Ok(Json(backups.into_iter().map(BackupSummary::from).collect()))
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    since: Option<String>,
+    user: Option<String>,
+    id: Option<String>,
+}
+
+/// Lists the entries of the audit log, oldest first, which only the
+/// administrator can read once authentication is on.
+async fn list_audit(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<audit::Entry>>, (StatusCode, String)> {
+    if !state.audit.readable() {
+        warn!("audit log read refused to {:?}", auth::user());
+        return Err((
+            StatusCode::FORBIDDEN,
+            "the audit log is only readable by the administrator".to_string(),
+        ));
+    }
+
+    let since = query
+        .since
+        .as_deref()
+        .map(|since| {
+            DateTime::parse_from_rfc3339(since).map_err(|e| {
+                warn!("invalid since '{}': {}", since, e);
+                (
+                    StatusCode::BAD_REQUEST,
+                    "since must be an RFC 3339 timestamp".to_string(),
+                )
+            })
+        })
+        .transpose()?;
+
+    let entries = state
+        .audit
+        .read(
+            since.map(|since| since.with_timezone(&Utc)),
+            query.user.as_deref(),
+            query.id.as_deref(),
+        )
+        .await
+        .map_err(|e| {
+            error!("failed to read the audit log: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read the audit log".to_string(),
+            )
+        })?;
+
+    info!("{} audit entries listed", entries.len());
+    Ok(Json(entries))
+}
+
+/// Takes a backup of every address book every `interval`, the first one on
+/// startup.
+async fn scheduled_backups(
+    state: Arc<AppState>,
+    interval: Duration,
+    mut stopping: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stopping.wait_for(|&stop| stop) => break,
+        }
+
+        match state.backups.run(&state.books).await {
+            Ok(backup) => info!("Backup {} taken", backup.name),
+            Err(e) => error!("scheduled backup failed: {}", e),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BookSummary {
+    name: String,
+    displayname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EventRange {
+    start: Option<String>,
+    end: Option<String>,
+}
+
+/// Parses a bound of an [`EventRange`] as an RFC 3339 timestamp.
+fn range_bound(
+    name: &str,
+    value: Option<String>,
+) -> Result<Option<DateTime<Utc>>, (StatusCode, String)> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|time| time.to_utc())
+                .map_err(|e| {
+                    warn!("invalid {} '{}': {}", name, value, e);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        format!("{} must be an RFC 3339 timestamp", name),
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// Lists the events, sorted by start, whose start is within the optional
+/// `[start, end)` range of RFC 3339 timestamps.
+async fn list_events(
+    State(state): State<Arc<AppState>>,
+    Query(range): Query<EventRange>,
+) -> Result<Json<Vec<Event>>, (StatusCode, String)> {
+    let start = range_bound("start", range.start)?;
+    let end = range_bound("end", range.end)?;
+
+    let mut events = state.events.list().await.map_err(|e| {
+        error!("failed to list events: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list events".to_string(),
+        )
+    })?;
+
+    events.retain(|event| {
+        let time = event.start.as_utc();
+        start.is_none_or(|start| time >= start) && end.is_none_or(|end| time < end)
+    });
+    events.sort_by_key(|event| event.start.as_utc());
+
+    info!("Event list created successfully");
+    Ok(Json(events))
+}
+
+/// Lists the occurrences of an event overlapping the `[start, end)` range,
+/// both bounds being required since a series may never end.
+async fn event_instances(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    Query(range): Query<EventRange>,
+) -> Result<Json<Vec<Event>>, (StatusCode, String)> {
+    check_id(&id)?;
+
+    let start = range_bound("start", range.start)?;
+    let end = range_bound("end", range.end)?;
+    let (Some(start), Some(end)) = (start, end) else {
+        warn!("instances of {} requested without a range", id);
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "start and end are required".to_string(),
+        ));
+    };
+
+    let event = match state.events.get(&id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => {
+            warn!("event not found: {}", id);
+            return Err((StatusCode::NOT_FOUND, "event not found".to_string()));
+        }
+        Err(e) => {
+            error!("failed to read event {}: {}", id, e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read event".to_string(),
+            ));
+        }
+    };
+
+    let instances = event.instances(Some(start), Some(end)).map_err(|e| {
+        error!("failed to expand event {}: {}", id, e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("invalid recurrence rule: {}", e),
+        )
+    })?;
+
+    info!("Instances of event {} listed successfully", id);
+    Ok(Json(instances))
+}
+
+/// Returns the busy times within the required `[start, end)` range, without
+/// any detail of the events.
+async fn free_busy(
+    State(state): State<Arc<AppState>>,
+    Query(range): Query<EventRange>,
+) -> Result<Json<Vec<Busy>>, (StatusCode, String)> {
+    let start = range_bound("start", range.start)?;
+    let end = range_bound("end", range.end)?;
+    let (Some(start), Some(end)) = (start, end) else {
+        warn!("free busy times requested without a range");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "start and end are required".to_string(),
+        ));
+    };
+
+    let events = state.events.list().await.map_err(|e| {
+        error!("failed to list events: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list events".to_string(),
+        )
+    })?;
+
+    info!("Free busy times computed successfully");
+    Ok(Json(freebusy::busy(&events, start, end)))
+}
+
+/// Rejects an event of which the recurrence rule is not supported.
+fn check_rrule(event: &Event) -> Result<(), (StatusCode, String)> {
+    match event.rrule.as_deref().map(str::parse::<Rule>) {
+        Some(Err(e)) => {
+            warn!("invalid recurrence rule for event {}: {}", event.id, e);
+            Err((
+                StatusCode::BAD_REQUEST,
+                format!("invalid recurrence rule: {}", e),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn create_event(State(state): State<Arc<AppState>>, Json(event): Json<Event>) -> Response {
+    if let Err(e) = check_id(&event.id) {
+        return e.into_response();
+    }
+
+    if let Err(response) = check_rrule(&event) {
+        return response.into_response();
+    }
+
+    match state.events.put(&event).await {
+        Ok(_) => {
+            info!("Event saved: {}", event.id);
+            (StatusCode::CREATED, "Event created".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to save event {}: {}", event.id, e);
+            write_error(&e, "failed to save event")
+        }
+    }
+}
+
+/// Returns an event as an iCalendar object.
+async fn event_by_id(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+) -> Response {
+    if let Err(e) = check_id(&id) {
+        return e.into_response();
+    }
+
+    match state.events.get(&id).await {
+        Ok(Some(event)) => {
+            info!("Event found: {}", id);
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+                event.to_string(),
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            warn!("event not found: {}", id);
+            (StatusCode::NOT_FOUND, "event not found".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to read event {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read event".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn modify_event(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    Json(mut event): Json<Event>,
+) -> Response {
+    if let Err(e) = check_id(&id) {
+        return e.into_response();
+    }
+
+    if id != event.id {
+        warn!("ID '{}' does not match body ID: {}", id, event.id);
+        return (
+            StatusCode::BAD_REQUEST,
+            "ID in URL and body must match".to_string(),
+        )
+            .into_response();
+    }
+
+    if let Err(response) = check_rrule(&event) {
+        return response.into_response();
+    }
+
+    let existing = match state.events.get(&id).await {
+        Ok(Some(existing)) => existing,
+        Ok(None) => {
+            warn!("event not found for update: {}", id);
+            return (StatusCode::NOT_FOUND, "event not found".to_string()).into_response();
+        }
+        Err(e) => {
+            error!("failed to read event {}: {}", id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to update event".to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    // The JSON body only carries the fields, keep the other properties.
+    event.extras = existing.extras;
+
+    match state.events.put(&event).await {
+        Ok(_) => {
+            info!("event updated: {}", id);
+            (StatusCode::OK, "Event updated".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to update event {}: {}", id, e);
+            write_error(&e, "failed to update event")
+        }
+    }
+}
+
+async fn delete_event(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+) -> (StatusCode, String) {
+    if let Err(e) = check_id(&id) {
+        return e;
+    }
+
+    match state.events.delete(&id).await {
+        Ok(true) => {
+            info!("Event deleted: {}", id);
+            (StatusCode::OK, "Event deleted".to_string())
+        }
+        Ok(false) => {
+            warn!("event not found for deletion: {}", id);
+            (StatusCode::NOT_FOUND, "event not found".to_string())
+        }
+        Err(e) => {
+            error!("failed to delete event {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to delete event".to_string(),
+            )
+        }
+    }
+}
+
+/// Returns the birthdays of the contacts of the default address book as an
+/// iCalendar feed, generated on every request.
+async fn birthday_feed(AddressBook(book): AddressBook) -> Response {
+    let listing = match book.store.scan().await {
+        Ok(listing) => listing,
+        Err(e) => {
+            error!("failed to list contacts of {}: {}", book.name, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to list contacts".to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    info!("Birthday feed created successfully");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        birthdays::feed(&listing.contacts),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct TodoQuery {
+    status: Option<Status>,
+}
+
+/// Lists the tasks, optionally with the given status, sorted by due date with
+/// the tasks without one last.
+async fn list_todos(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TodoQuery>,
+) -> Result<Json<Vec<Todo>>, (StatusCode, String)> {
+    let mut todos = state.todos.list().await.map_err(|e| {
+        error!("failed to list tasks: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to list tasks".to_string(),
+        )
+    })?;
+
+    if let Some(status) = query.status {
+        todos.retain(|todo| todo.status == status);
+    }
+    todos.sort_by_key(|todo| (todo.due.is_none(), todo.due.map(|due| due.as_utc())));
+
+    info!("Task list created successfully");
+    Ok(Json(todos))
+}
+
+/// Rejects a task that cannot be stored.
+fn check_todo(todo: &Todo) -> Result<(), (StatusCode, String)> {
+    todo.validate().map_err(|e| {
+        warn!("invalid task {}: {}", todo.id, e);
+        (StatusCode::BAD_REQUEST, e)
+    })
+}
+
+async fn create_todo(State(state): State<Arc<AppState>>, Json(todo): Json<Todo>) -> Response {
+    if let Err(e) = check_id(&todo.id) {
+        return e.into_response();
+    }
+
+    if let Err(response) = check_todo(&todo) {
+        return response.into_response();
+    }
+
+    match state.todos.put(&todo).await {
+        Ok(_) => {
+            info!("Task saved: {}", todo.id);
+            (StatusCode::CREATED, "Task created".to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to save task {}: {}", todo.id, e);
+            write_error(&e, "failed to save task")
+        }
+    }
+}
+
+/// Reads a task for the handlers of `/todos/<id>`.
+async fn find_todo(state: &AppState, id: &str) -> Result<Todo, (StatusCode, String)> {
+    check_id(id)?;
+
+    match state.todos.get(id).await {
+        Ok(Some(todo)) => Ok(todo),
+        Ok(None) => {
+            warn!("task not found: {}", id);
+            Err((StatusCode::NOT_FOUND, "task not found".to_string()))
+        }
+        Err(e) => {
+            error!("failed to read task {}: {}", id, e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read task".to_string(),
+            ))
+        }
+    }
+}
+
+/// Returns a task as an iCalendar object.
+async fn todo_by_id(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+) -> Response {
+    match find_todo(&state, &id).await {
+        Ok(todo) => {
+            info!("Task found: {}", id);
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+                todo.to_string(),
+            )
+                .into_response()
+        }
+        Err(response) => response.into_response(),
+    }
+}
+
+async fn modify_todo(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+    Json(mut todo): Json<Todo>,
+) -> Response {
+    if id != todo.id {
+        warn!("ID '{}' does not match body ID: {}", id, todo.id);
+        return (
+            StatusCode::BAD_REQUEST,
+            "ID in URL and body must match".to_string(),
+        )
+            .into_response();
+    }
+
+    if let Err(response) = check_todo(&todo) {
+        return response.into_response();
+    }
+
+    let existing = match find_todo(&state, &id).await {
+        Ok(existing) => existing,
+        Err(response) => return response.into_response(),
+    };
+
+    // The JSON body only carries the fields, keep the other properties.
+    todo.extras = existing.extras;
+
+    save_todo(&state, &todo, "Task updated").await
+}
+
+/// Marks a task as completed now.
+async fn complete_todo(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+) -> Response {
+    let mut todo = match find_todo(&state, &id).await {
+        Ok(todo) => todo,
+        Err(response) => return response.into_response(),
+    };
+
+    todo.complete(Utc::now());
+    save_todo(&state, &todo, "Task completed").await
+}
+
+async fn save_todo(state: &AppState, todo: &Todo, message: &str) -> Response {
+    match state.todos.put(todo).await {
+        Ok(_) => {
+            info!("{}: {}", message, todo.id);
+            (StatusCode::OK, message.to_string()).into_response()
+        }
+        Err(e) => {
+            error!("failed to update task {}: {}", todo.id, e);
+            write_error(&e, "failed to update task")
+        }
+    }
+}
+
+async fn delete_todo(
+    State(state): State<Arc<AppState>>,
+    AxumPath(ContactPath { id }): AxumPath<ContactPath>,
+) -> (StatusCode, String) {
+    if let Err(e) = check_id(&id) {
+        return e;
+    }
+
+    match state.todos.delete(&id).await {
+        Ok(true) => {
+            info!("Task deleted: {}", id);
+            (StatusCode::OK, "Task deleted".to_string())
+        }
+        Ok(false) => {
+            warn!("task not found for deletion: {}", id);
+            (StatusCode::NOT_FOUND, "task not found".to_string())
+        }
+        Err(e) => {
+            error!("failed to delete task {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to delete task".to_string(),
+            )
+        }
+    }
+}
+
+async fn list_books(State(state): State<Arc<AppState>>) -> Json<Vec<BookSummary>> {
+    let mut books = Vec::new();
+    for book in state.books.list().await {
+        books.push(BookSummary {
+            name: book.name.clone(),
+            displayname: book.displayname().await,
+            description: book.metadata().await.description,
+        });
+    }
+
+    Json(books)
+}
+
+#[derive(Deserialize)]
+struct NewBook {
+    name: String,
+}
+
+async fn create_book(
+    State(state): State<Arc<AppState>>,
+    Json(new_book): Json<NewBook>,
+) -> (StatusCode, String) {
+    match state.books.create(&new_book.name).await {
+        Ok(_) => {
+            info!("Address book created: {}", new_book.name);
+            (StatusCode::CREATED, "Address book created".to_string())
+        }
+        Err(e) => book_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteBookQuery {
+    #[serde(default)]
+    force: bool,
+}
+
+async fn delete_book(
+    State(state): State<Arc<AppState>>,
+    AxumPath(name): AxumPath<String>,
+    Query(query): Query<DeleteBookQuery>,
+) -> (StatusCode, String) {
+    match state.books.delete(&name, query.force).await {
+        Ok(()) => {
+            info!("Address book deleted: {}", name);
+            (StatusCode::OK, "Address book deleted".to_string())
+        }
+        Err(e) => book_error(e),
+    }
+}
+
+fn book_error(e: BookError) -> (StatusCode, String) {
+    let status = match &e {
+        BookError::InvalidName(_) => StatusCode::BAD_REQUEST,
+        BookError::Exists(_) | BookError::NotEmpty(_) => StatusCode::CONFLICT,
+        BookError::NotFound(_) => StatusCode::NOT_FOUND,
+        BookError::Default => StatusCode::FORBIDDEN,
+        BookError::Store(e) => {
+            error!("failed to update address books: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to update address books".to_string(),
+            );
+        }
+    };
+
+    warn!("{}", e);
+    (status, e.to_string())
+}
+
+/// Keeps the version of a contact that was just replaced.
+async fn record_revision(book: &Book, previous: &Contact) {
+    if let Err(e) = book.history.record(previous).await {
+        error!("failed to keep a revision of {}: {}", previous.id, e);
+    }
+}
+
+async fn record_change(book: &Book, id: &str, op: Operation) {
+    audit::note(&book.name, id, op);
+    if let Err(e) = book.changes.record(id, op).await {
+        error!("failed to record change for {}: {}", id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, Method, StatusCode},
+        routing::get,
+        Router,
+    };
+    use chrono::{SecondsFormat, Utc};
+    use tower::ServiceExt;
+
+    use super::{check_writable, timeout_layer};
+    use crate::auth::{Credentials, PasswordHash};
+    use crate::books::Books;
+    use crate::cli;
+    use crate::config::Config;
+    use crate::contact::Contact;
+    use crate::encryption;
+    use crate::testing::{basic_auth, contact, request, TestResponse, TestServer};
+
+    fn ids(contacts: &serde_json::Value) -> Vec<&str> {
+        let mut ids: Vec<_> = contacts
+            .as_array()
+            .expect("not a list")
+            .iter()
+            .map(|contact| contact["id"].as_str().expect("no ID"))
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Contact files under a directory.
+    fn vcf_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .map(|entry| entry.unwrap().path())
+                    .map(|path| {
+                        if path.is_dir() {
+                            vcf_files(&path)
+                        } else {
+                            usize::from(path.extension().is_some_and(|ext| ext == "vcf"))
+                        }
+                    })
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    const IMPORTED: &str = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:alice\r\nFN:Alice\r\nEND:VCARD\r\n\
+        BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Nobody\r\nEND:VCARD\r\n\
+        BEGIN:VCARD\r\nVERSION:4.0\r\nUID:bob\r\nFN:Bob\r\nEND:VCARD\r\n";
+
+    #[tokio::test]
+    async fn dry_run_import_stores_nothing() {
+        let server = TestServer::new().await;
+
+        let response = server
+            .vcard(Method::POST, "/contacts/import?dry_run=true", IMPORTED)
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let summary = response.json();
+        assert_eq!(summary["dry_run"], true);
+        assert_eq!(summary["imported"], serde_json::json!(["alice", "bob"]));
+        assert_eq!(summary["failed"][0]["card"], 2);
+
+        assert_eq!(vcf_files(server.dir.path()), 0);
+        assert_eq!(
+            ids(&server.get("/contacts").await.json()),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn import_stores_the_valid_cards() {
+        let server = TestServer::new().await;
+
+        let response = server
+            .vcard(Method::POST, "/contacts/import", IMPORTED)
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let summary = response.json();
+        assert_eq!(summary["dry_run"], false);
+        assert_eq!(summary["failed"][0]["reason"], "contact ID is empty");
+
+        assert_eq!(vcf_files(server.dir.path()), 2);
+        assert_eq!(ids(&server.get("/contacts").await.json()), ["alice", "bob"]);
+    }
+
+    #[tokio::test]
+    async fn import_counts_a_repeated_id_once_against_the_contact_limit() {
+        let server = TestServer::with_config(|config| config.max_contacts = Some(2)).await;
+
+        let cards = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:alice\r\nFN:Alice\r\nEND:VCARD\r\n\
+            BEGIN:VCARD\r\nVERSION:4.0\r\nUID:bob\r\nFN:Bob\r\nEND:VCARD\r\n\
+            BEGIN:VCARD\r\nVERSION:4.0\r\nUID:alice\r\nFN:Alice Liddell\r\nEND:VCARD\r\n\
+            BEGIN:VCARD\r\nVERSION:4.0\r\nUID:carol\r\nFN:Carol\r\nEND:VCARD\r\n";
+        let response = server.vcard(Method::POST, "/contacts/import", cards).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let summary = response.json();
+        assert_eq!(
+            summary["imported"],
+            serde_json::json!(["alice", "bob", "alice"])
+        );
+        assert_eq!(summary["failed"][0]["card"], 4);
+        assert_eq!(summary["failed"][0]["reason"], "contact limit reached");
+
+        assert_eq!(ids(&server.get("/contacts").await.json()), ["alice", "bob"]);
+        let alice = server.get("/contacts/alice").await.text();
+        assert!(alice.contains("FN:Alice Liddell"), "{}", alice);
+    }
+
+    #[tokio::test]
+    async fn updates_bump_the_revision() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+
+        let mut revs = Vec::new();
+        for name in ["Alice Liddell", "Alice Pleasance Liddell"] {
+            let response = server
+                .json(Method::PUT, "/contacts/alice", &contact("alice", name))
+                .await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+            let stored = server.get("/contacts/alice?fields=rev").await.json();
+            revs.push(stored["rev"].as_str().expect("no REV").to_string());
+
+            // REV has a resolution of a second.
+            tokio::time::sleep(Duration::from_millis(1100)).await;
+        }
+
+        assert_ne!(revs[0], revs[1]);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_malformed_birthday() {
+        let server = TestServer::new().await;
+
+        let mut alice = contact("alice", "Alice");
+        alice["birthday"] = "12/04/1990".into();
+        let response = server.json(Method::POST, "/contacts", &alice).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert!(response.text().contains("invalid birthday"));
+
+        alice["birthday"] = "1990-04-12".into();
+        alice["nickname"] = "Al".into();
+        server.create(&alice).await;
+        let stored = server
+            .get("/contacts/alice?fields=nickname,birthday")
+            .await
+            .json();
+        assert_eq!(
+            stored,
+            serde_json::json!({ "nickname": "Al", "birthday": "1990-04-12" })
+        );
+
+        alice["birthday"] = "19901304".into();
+        let response = server.json(Method::PUT, "/contacts/alice", &alice).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn include_raw_returns_the_fields_and_the_vcard() {
+        let server = TestServer::new().await;
+        let mut alice = contact("alice", "Alice");
+        alice["categories"] = serde_json::json!(["friends"]);
+        server.create(&alice).await;
+
+        let response = server.get_json("/contacts/alice?include=raw").await;
+        assert_eq!(response.status, StatusCode::OK);
+        let body = response.json();
+        let object = body.as_object().unwrap();
+        assert_eq!(object.keys().collect::<Vec<_>>(), ["contact", "raw"]);
+        assert_eq!(body["contact"]["name"], "Alice");
+
+        let raw: Contact = body["raw"].as_str().unwrap().parse().unwrap();
+        assert_eq!(serde_json::json!(raw), body["contact"]);
+    }
+
+    #[tokio::test]
+    async fn compresses_large_responses() {
+        let server = TestServer::new().await;
+        for i in 0..50 {
+            server
+                .create(&contact(&format!("contact-{}", i), "Someone"))
+                .await;
+        }
+
+        let plain = server.get("/contacts").await;
+        assert_eq!(plain.header("Content-Encoding"), None);
+
+        let compressed = server
+            .send(
+                request(Method::GET, "/contacts")
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(compressed.status, StatusCode::OK);
+        assert_eq!(compressed.header("Content-Encoding"), Some("gzip"));
+        assert!(compressed.body.starts_with(&[0x1f, 0x8b]));
+        assert!(compressed.body.len() < plain.body.len());
+    }
+
+    #[tokio::test]
+    async fn rejects_updates_of_a_contact_stored_with_another_id() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+
+        // A file copied by hand, keeping the UID of the original.
+        let file = server
+            .dir
+            .path()
+            .join("contacts")
+            .join(crate::store::shard("alice"))
+            .join("alice.vcf");
+        let copied = std::fs::read_to_string(&file)
+            .unwrap()
+            .replace("UID:alice", "UID:bob");
+        std::fs::write(&file, copied).unwrap();
+
+        let response = server
+            .json(Method::PUT, "/contacts/alice", &contact("alice", "Alice"))
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        assert_eq!(response.text(), "stored contact has a different ID: bob");
+    }
+
+    #[tokio::test]
+    async fn reports_an_unwritable_data_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_writable(&dir.path().join("data")).await.is_ok());
+
+        // Even root cannot create a directory under a regular file.
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let e = check_writable(&file.join("data")).await.unwrap_err();
+        assert!(
+            e.starts_with(&format!(
+                "data directory is not writable: {}",
+                file.join("data").display()
+            )),
+            "{}",
+            e
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_a_contact_by_email_ignoring_case() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+
+        for email in [
+            "bob@example.com",
+            "Bob@Example.COM",
+            "%20bob@example.com%20",
+        ] {
+            let response = server.get(&format!("/contacts/by-email/{}", email)).await;
+            assert_eq!(response.status, StatusCode::OK, "{}", email);
+            assert_eq!(
+                response.header("Content-Type"),
+                Some("text/vcard; charset=utf-8")
+            );
+            let found: Contact = response.text().parse().unwrap();
+            assert_eq!(found.id, "bob");
+        }
+
+        let response = server.get("/contacts/by-email/carol@example.com").await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+
+    /// Locks a contact, returning the `If` header submitting the lock token.
+    async fn lock(server: &TestServer, id: &str) -> String {
+        let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:lockinfo xmlns:D="DAV:">
+  <D:lockscope><D:exclusive/></D:lockscope>
+  <D:locktype><D:write/></D:locktype>
+</D:lockinfo>"#;
+        let response = server
+            .call(
+                Method::from_bytes(b"LOCK").unwrap(),
+                &format!("/contacts/{}", id),
+                body,
+            )
+            .await;
+        assert!(response.status.is_success(), "{}", response.text());
+
+        format!(
+            "({})",
+            response.header("Lock-Token").expect("no lock token")
+        )
+    }
+
+    #[tokio::test]
+    async fn create_honors_locks() {
+        let server = TestServer::new().await;
+
+        // Locked before the contact exists.
+        let token = lock(&server, "alice").await;
+        let response = server
+            .json(Method::POST, "/contacts", &contact("alice", "Alice"))
+            .await;
+        assert_eq!(response.status, StatusCode::LOCKED);
+        assert_eq!(
+            server.get("/contacts/alice").await.status,
+            StatusCode::NOT_FOUND
+        );
+
+        let response = server
+            .send(
+                request(Method::POST, "/contacts")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("If", &token)
+                    .body(Body::from(contact("alice", "Alice").to_string()))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn merge_honors_the_locks_of_both_contacts() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+        let merge = serde_json::json!({ "other_id": "bob" }).to_string();
+
+        let alice = lock(&server, "alice").await;
+        let bob = lock(&server, "bob").await;
+        for submitted in [None, Some(&alice), Some(&bob)] {
+            let mut request = request(Method::POST, "/contacts/alice/merge")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(token) = submitted {
+                request = request.header("If", token);
+            }
+            let response = server
+                .send(request.body(Body::from(merge.clone())).unwrap())
+                .await;
+            assert_eq!(response.status, StatusCode::LOCKED, "{:?}", submitted);
+        }
+        assert_eq!(server.get("/contacts/bob").await.status, StatusCode::OK);
+
+        let response = server
+            .send(
+                request(Method::POST, "/contacts/alice/merge")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("If", format!("{} {}", alice, bob))
+                    .body(Body::from(merge))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        assert_eq!(
+            server.get("/contacts/bob").await.status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_requests_time_out() {
+        let mut config = Config::for_tests();
+        config.request_timeout = Duration::from_millis(50);
+
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "done" }))
+            .layer(timeout_layer(&config));
+
+        let call = |uri: &'static str| {
+            app.clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        };
+        assert_eq!(call("/fast").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            call("/slow").await.unwrap().status(),
+            StatusCode::REQUEST_TIMEOUT
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_the_skipped_files() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+
+        let shard = crate::store::shard("broken");
+        let dir = server.dir.path().join("contacts").join(shard);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.vcf"), "BEGIN:VCARD\r\nFN:Broken\r\n").unwrap();
+
+        let response = server.get("/contacts").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.header("X-Skipped-Count"), Some("1"));
+        assert_eq!(ids(&response.json()), ["alice"]);
+    }
+
+    #[tokio::test]
+    async fn filters_the_contacts_by_kind() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+        let mut group = contact("team", "Team");
+        group["kind"] = "group".into();
+        group["members"] = serde_json::json!(["urn:uuid:alice", "urn:uuid:bob"]);
+        server.create(&group).await;
+
+        let groups = server.get("/contacts?kind=group").await.json();
+        assert_eq!(ids(&groups), ["team"]);
+        assert_eq!(
+            groups[0]["members"],
+            serde_json::json!(["urn:uuid:alice", "urn:uuid:bob"])
+        );
+        assert_eq!(
+            ids(&server.get("/contacts?kind=individual").await.json()),
+            ["alice", "bob"]
+        );
+
+        let card = server.get("/contacts/team").await.text();
+        assert!(card.contains("KIND:group\r\n"), "{}", card);
+        assert!(card.contains("MEMBER:urn:uuid:alice\r\nMEMBER:urn:uuid:bob\r\n"));
+        let parsed: Contact = card.parse().unwrap();
+        assert_eq!(parsed.kind.as_deref(), Some("group"));
+        assert_eq!(parsed.members, ["urn:uuid:alice", "urn:uuid:bob"]);
+    }
+
+    #[tokio::test]
+    async fn batch_get_returns_null_for_missing_contacts() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+
+        let response = server
+            .json(
+                Method::POST,
+                "/contacts/batch-get",
+                &serde_json::json!({ "ids": ["bob", "carol", "alice"] }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let contacts = response.json();
+        let contacts = contacts.as_object().unwrap();
+        // Contacts come back in the requested order.
+        assert_eq!(
+            contacts.keys().collect::<Vec<_>>(),
+            ["bob", "carol", "alice"]
+        );
+        assert_eq!(contacts["bob"]["name"], "Bob");
+        assert_eq!(contacts["alice"]["email"], "alice@example.com");
+        assert!(contacts["carol"].is_null());
+
+        let response = server
+            .json(
+                Method::POST,
+                "/contacts/batch-get?fields=name",
+                &serde_json::json!({ "ids": ["alice"] }),
+            )
+            .await;
+        assert_eq!(
+            response.json(),
+            serde_json::json!({ "alice": { "name": "Alice" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn search_and_batch_get_return_only_the_requested_fields() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+
+        let found = server.get("/contacts/search?q=ali&fields=name").await;
+        assert_eq!(found.status, StatusCode::OK, "{}", found.text());
+        assert_eq!(found.json(), serde_json::json!([{ "name": "Alice" }]));
+
+        let fetched = server
+            .json(
+                Method::POST,
+                "/contacts/batch-get?fields=name",
+                &serde_json::json!({ "ids": ["alice", "bob"] }),
+            )
+            .await;
+        assert_eq!(fetched.status, StatusCode::OK, "{}", fetched.text());
+        for (id, contact) in fetched.json().as_object().unwrap() {
+            let contact = contact.as_object().unwrap();
+            assert!(contact.contains_key("name"), "{}", id);
+            assert!(!contact.contains_key("phone"), "{}", id);
+            assert!(!contact.contains_key("email"), "{}", id);
+        }
+
+        // Without the parameter every field is returned.
+        let found = server.get("/contacts/search?q=ali").await.json();
+        assert_eq!(found[0]["phone"], "+1 555 0100");
+        assert_eq!(found[0]["email"], "alice@example.com");
+
+        let rejected = server.get("/contacts/search?q=ali&fields=name,age").await;
+        assert_eq!(rejected.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn import_rejects_invalid_utf8() {
+        let server = TestServer::new().await;
+
+        // "Zoë" in Latin-1.
+        let body = b"BEGIN:VCARD\r\nVERSION:4.0\r\nUID:zoe\r\nFN:Zo\xeb\r\nEND:VCARD\r\n".to_vec();
+        let response = server.call(Method::POST, "/contacts/import", body).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert!(
+            response.text().starts_with("vCard data is not valid UTF-8"),
+            "{}",
+            response.text()
+        );
+        assert_eq!(vcf_files(server.dir.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn deleted_contacts_can_be_restored_from_the_trash() {
+        let server = TestServer::with_config(|config| config.soft_delete = true).await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+
+        assert_eq!(
+            server.delete("/contacts/alice").await.status,
+            StatusCode::OK
+        );
+        assert_eq!(
+            server.get("/contacts/alice").await.status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(ids(&server.get("/contacts").await.json()), ["bob"]);
+
+        let trash = server.get("/contacts/trash").await.json();
+        assert_eq!(ids(&trash), ["alice"]);
+        assert_eq!(trash[0]["name"], "Alice");
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(trash[0]["deleted"].as_str().unwrap()).is_ok()
+        );
+
+        let response = server
+            .call(Method::POST, "/contacts/alice/restore", Body::empty())
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        assert_eq!(
+            server.get("/contacts/alice?fields=email").await.json()["email"],
+            "alice@example.com"
+        );
+        assert_eq!(
+            ids(&server.get("/contacts/trash").await.json()),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            server
+                .call(Method::POST, "/contacts/carol/restore", Body::empty())
+                .await
+                .status,
+            StatusCode::NOT_FOUND
+        );
+
+        // A contact created since the deletion is not overwritten.
+        server.delete("/contacts/bob").await;
+        server.create(&contact("bob", "Robert")).await;
+        let response = server
+            .call(Method::POST, "/contacts/bob/restore", Body::empty())
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        assert_eq!(
+            server.get("/contacts/bob?fields=name").await.json()["name"],
+            "Robert"
+        );
+
+        assert_eq!(
+            server.delete("/contacts/trash/bob").await.status,
+            StatusCode::OK
+        );
+        assert_eq!(
+            ids(&server.get("/contacts/trash").await.json()),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn round_trips_and_validates_urls() {
+        let server = TestServer::new().await;
+        let mut alice = contact("alice", "Alice");
+        alice["url"] = "https://example.com/~alice?tab=about".into();
+        server.create(&alice).await;
+
+        let card = server.get("/contacts/alice").await.text();
+        assert!(
+            card.contains("URL:https://example.com/~alice?tab=about\r\n"),
+            "{}",
+            card
+        );
+        assert_eq!(
+            server.get("/contacts/alice?fields=url").await.json()["url"],
+            "https://example.com/~alice?tab=about"
+        );
+
+        for url in ["notaurl", "ftp://example.com/alice", "https://"] {
+            let mut bob = contact("bob", "Bob");
+            bob["url"] = url.into();
+            let response = server.json(Method::POST, "/contacts", &bob).await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", url);
+            assert!(
+                response.text().contains("invalid URL"),
+                "{}",
+                response.text()
+            );
+        }
+
+        alice["url"] = "notaurl".into();
+        let response = server.json(Method::PUT, "/contacts/alice", &alice).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            server.get("/contacts/alice?fields=url").await.json()["url"],
+            "https://example.com/~alice?tab=about"
+        );
+        assert_eq!(ids(&server.get("/contacts").await.json()), ["alice"]);
+    }
+
+    #[tokio::test]
+    async fn reverts_to_a_previous_revision() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        assert_eq!(
+            server.get_json("/contacts/alice/history").await.json(),
+            serde_json::json!([])
+        );
+
+        let mut renamed = contact("alice", "Alice Smith");
+        renamed["phone"] = "+1 555 0199".into();
+        let response = server.json(Method::PUT, "/contacts/alice", &renamed).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+        let history = server.get_json("/contacts/alice/history").await.json();
+        assert_eq!(history.as_array().unwrap().len(), 1);
+        let rev = history[0]["rev"].as_str().unwrap().to_string();
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(history[0]["replaced"].as_str().unwrap()).is_ok()
+        );
+
+        let response = server
+            .get(&format!("/contacts/alice/history/{}", rev))
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/vcard; charset=utf-8")
+        );
+        let previous: Contact = response.text().parse().unwrap();
+        assert_eq!(previous.name, "Alice");
+        assert_eq!(previous.phone, "+1 555 0100");
+        assert_eq!(
+            server.get("/contacts/alice/history/12345").await.status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            server.get("/contacts/alice/history/latest").await.status,
+            StatusCode::NOT_FOUND
+        );
+
+        let response = server
+            .call(
+                Method::POST,
+                &format!("/contacts/alice/revert/{}", rev),
+                Body::empty(),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        assert_eq!(response.json()["name"], "Alice");
+
+        let current = server.get("/contacts/alice?fields=name,phone").await.json();
+        assert_eq!(current["name"], "Alice");
+        assert_eq!(current["phone"], "+1 555 0100");
+
+        // The replaced version is kept, so the revert can be undone.
+        let history = server.get_json("/contacts/alice/history").await.json();
+        assert_eq!(history.as_array().unwrap().len(), 2);
+        let undo = history[1]["rev"].as_str().unwrap();
+        let undone: Contact = server
+            .get(&format!("/contacts/alice/history/{}", undo))
+            .await
+            .text()
+            .parse()
+            .unwrap();
+        assert_eq!(undone.name, "Alice Smith");
+
+        assert_eq!(
+            server
+                .call(Method::POST, "/contacts/bob/revert/12345", Body::empty())
+                .await
+                .status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn create_over_an_existing_contact_keeps_its_history() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+
+        for name in ["Alice Smith", "Alice Jones"] {
+            let response = server
+                .json(Method::POST, "/contacts", &contact("alice", name))
+                .await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+            assert_eq!(response.text(), "Contact updated");
+        }
+
+        let history = server.get_json("/contacts/alice/history").await.json();
+        let mut names = Vec::new();
+        for revision in history.as_array().unwrap() {
+            let uri = format!(
+                "/contacts/alice/history/{}",
+                revision["rev"].as_str().unwrap()
+            );
+            let previous: Contact = server.get(&uri).await.text().parse().unwrap();
+            names.push(previous.name);
+        }
+        assert_eq!(names, ["Alice", "Alice Smith"]);
+        assert_eq!(
+            server.get("/contacts/alice?fields=name").await.json()["name"],
+            "Alice Jones"
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypts_contacts_history_and_backups_at_rest() {
+        let key: encryption::Cipher = "42".repeat(32).parse().unwrap();
+        let server = TestServer::with_config(|c| c.encryption = Some(key.clone())).await;
+        server.create(&contact("alice", "Alice")).await;
+        let response = server
+            .json(
+                Method::PUT,
+                "/contacts/alice",
+                &contact("alice", "Alice Smith"),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let response = server
+            .call(Method::POST, "/admin/backup", Body::empty())
+            .await;
+        assert!(response.status.is_success(), "{}", response.text());
+
+        fn vcf_files(dir: &Path, files: &mut Vec<PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    vcf_files(&path, files);
+                } else if path.extension().is_some_and(|extension| extension == "vcf") {
+                    files.push(path);
+                }
+            }
+        }
+        let mut files = Vec::new();
+        vcf_files(server.dir.path(), &mut files);
+        // The contact, its revision and the backup.
+        assert_eq!(files.len(), 3, "{:?}", files);
+        for file in &files {
+            let content = std::fs::read(file).unwrap();
+            assert!(encryption::is_encrypted(&content), "{}", file.display());
+            assert!(!content.windows(5).any(|window| window == b"Alice"));
+        }
+
+        let history = server.get_json("/contacts/alice/history").await.json();
+        let rev = history[0]["rev"].as_str().unwrap();
+        let previous: Contact = server
+            .get(&format!("/contacts/alice/history/{}", rev))
+            .await
+            .text()
+            .parse()
+            .unwrap();
+        assert_eq!(previous.name, "Alice");
+
+        let (dir, mut config) = server.stop();
+        let backup = files
+            .iter()
+            .find(|file| file.starts_with(dir.path().join("backups")))
+            .unwrap();
+        let vcards = cli::decrypt(&config, backup).await.unwrap();
+        assert!(vcards.contains("FN:Alice Smith"), "{}", vcards);
+
+        // Without the key the server refuses to start rather than failing on
+        // every read.
+        config.encryption = None;
+        let e = Books::open(dir.path(), config.clone())
+            .await
+            .err()
+            .expect("opened encrypted files without a key")
+            .to_string();
+        assert!(e.contains("DAV_ENCRYPTION_KEY"), "{}", e);
+        assert!(cli::decrypt(&config, backup).await.is_err());
+
+        config.encryption = Some(key);
+        let server = TestServer::open(dir, config).await;
+        assert_eq!(
+            server.get("/contacts/alice?fields=name").await.json()["name"],
+            "Alice Smith"
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypts_a_plain_data_directory_in_place() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+        let (dir, mut config) = server.stop();
+
+        config.encryption = Some("42".repeat(32).parse().unwrap());
+        assert_eq!(cli::encrypt(config.clone()).await.unwrap(), 2);
+        assert_eq!(cli::encrypt(config.clone()).await.unwrap(), 0);
+
+        let server = TestServer::open(dir, config).await;
+        let names: Vec<String> = server
+            .get("/contacts?fields=name")
+            .await
+            .json()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|contact| contact["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"Alice".to_string()));
+        assert!(names.contains(&"Bob".to_string()));
+    }
+
+    /// Server authenticating alice, the user of `DAV_AUTH_USER`, and bob, of
+    /// the users file.
+    async fn server_of_alice_and_bob(configure: impl FnOnce(&mut Config)) -> TestServer {
+        TestServer::with_config(|config| {
+            config.auth = Some(Credentials {
+                user: "alice".to_string(),
+                password: PasswordHash::new("alice password").unwrap(),
+            });
+            config.users = vec![Credentials {
+                user: "bob".to_string(),
+                password: PasswordHash::new("bob password").unwrap(),
+            }];
+            configure(config);
+        })
+        .await
+    }
+
+    /// Sends a request as `user`, whose password is `<user> password`.
+    async fn send_as(
+        server: &TestServer,
+        user: &str,
+        method: Method,
+        uri: &str,
+        body: Option<&serde_json::Value>,
+    ) -> TestResponse {
+        let request = request(method, uri).header(
+            header::AUTHORIZATION,
+            basic_auth(user, &format!("{} password", user)),
+        );
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        server.send(request.unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn users_cannot_reach_the_contacts_of_other_users() {
+        let server = server_of_alice_and_bob(|_| {}).await;
+        let created = send_as(
+            &server,
+            "bob",
+            Method::POST,
+            "/contacts",
+            Some(&contact("bob-friend", "Friend of Bob")),
+        )
+        .await;
+        assert_eq!(created.status, StatusCode::CREATED, "{}", created.text());
+
+        for (method, uri) in [
+            (Method::GET, "/contacts/bob-friend"),
+            (Method::DELETE, "/contacts/bob-friend"),
+            (Method::GET, "/contacts/by-email/bob-friend@example.com"),
+            (Method::GET, "/addressbooks/contacts/contacts/bob-friend"),
+            (Method::GET, "/contacts/bob-friend/history/1"),
+        ] {
+            let response = send_as(&server, "alice", method.clone(), uri, None).await;
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+        }
+
+        let listed = send_as(&server, "alice", Method::GET, "/contacts", None).await;
+        assert_eq!(listed.status, StatusCode::OK);
+        assert!(!listed.text().contains("bob-friend"));
+        let history = send_as(
+            &server,
+            "alice",
+            Method::GET,
+            "/contacts/bob-friend/history",
+            None,
+        )
+        .await;
+        assert_eq!(history.json(), serde_json::json!([]));
+        let fetched = send_as(
+            &server,
+            "alice",
+            Method::POST,
+            "/contacts/batch-get",
+            Some(&serde_json::json!({ "ids": ["bob-friend"] })),
+        )
+        .await;
+        assert!(
+            !fetched.text().contains("Friend of Bob"),
+            "{}",
+            fetched.text()
+        );
+
+        let replaced = send_as(
+            &server,
+            "alice",
+            Method::PUT,
+            "/contacts/bob-friend",
+            Some(&contact("bob-friend", "Friend of Alice")),
+        )
+        .await;
+        assert_eq!(replaced.status, StatusCode::NOT_FOUND);
+        let kept = send_as(&server, "bob", Method::GET, "/contacts/bob-friend", None).await;
+        assert_eq!(kept.status, StatusCode::OK);
+        assert!(kept.text().contains("Friend of Bob"), "{}", kept.text());
+
+        // Only alice, the administrator, reads the audit log.
+        let audit = send_as(&server, "bob", Method::GET, "/admin/audit", None).await;
+        assert_eq!(audit.status, StatusCode::FORBIDDEN);
+        let audit = send_as(&server, "alice", Method::GET, "/admin/audit", None).await;
+        assert_eq!(audit.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn backups_of_other_users_are_kept_apart() {
+        let server = server_of_alice_and_bob(|config| config.backup_retention = 1).await;
+        for user in ["bob", "alice", "alice"] {
+            let response = send_as(&server, user, Method::POST, "/admin/backup", None).await;
+            assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        }
+
+        // The backups of bob are neither listed nor pruned with those of
+        // alice, in whose backup directory they are.
+        for user in ["alice", "bob"] {
+            let listed = send_as(&server, user, Method::GET, "/admin/backups", None).await;
+            let backups = listed.json();
+            let backups = backups.as_array().unwrap();
+            assert_eq!(backups.len(), 1, "{}: {:?}", user, backups);
+            assert_ne!(backups[0]["name"], "users");
+        }
+        assert!(server.dir.path().join("backups/users/bob").is_dir());
+    }
+
+    #[tokio::test]
+    async fn lists_contacts_modified_since() {
+        let server = TestServer::new().await;
+        server.create(&contact("alice", "Alice")).await;
+        server.create(&contact("bob", "Bob")).await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let since = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let response = server
+            .json(Method::PUT, "/contacts/bob", &contact("bob", "Robert"))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+        let response = server
+            .get(&format!("/contacts?modified_since={}", since))
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(ids(&response.json()), ["bob"]);
+
+        let response = server.get("/contacts?modified_since=yesterday").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
     }
 }
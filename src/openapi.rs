@@ -0,0 +1,359 @@
+use axum::Json;
+use serde_json::{json, Value};
+
+use crate::contact::Contact;
+
+/// Serves an OpenAPI 3 description of the contact routes.
+pub async fn openapi() -> Json<Value> {
+    Json(document())
+}
+
+/// The OpenAPI document. The routes under `/contacts` are also served for
+/// each address book under `/addressbooks/{book}/contacts`.
+fn document() -> Value {
+    let id = parameter("id", "path", "ID of the contact.");
+    let rev = parameter("rev", "path", "Name of a revision of the contact.");
+    let fields = parameter(
+        "fields",
+        "query",
+        "Comma-separated list of the fields to return.",
+    );
+    let contact = json!({ "$ref": "#/components/schemas/Contact" });
+    let contacts = json!({ "type": "array", "items": contact });
+    let vcard = json!({ "text/vcard": { "schema": { "type": "string" } } });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "dav",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Contacts, served as JSON and vCard. The routes under \
+                `/contacts` are also available for each address book under \
+                `/addressbooks/{book}/contacts`.",
+        },
+        "paths": {
+            "/contacts": {
+                "get": {
+                    "summary": "List the contacts",
+                    "parameters": [
+                        fields,
+                        parameter(
+                            "modified_since",
+                            "query",
+                            "Only lists the contacts modified since this RFC 3339 time.",
+                        ),
+                        parameter("kind", "query", "Only lists the contacts of this kind."),
+                    ],
+                    "responses": { "200": response("The contacts.", contacts.clone()) },
+                },
+                "post": {
+                    "summary": "Create a contact",
+                    "requestBody": body(contact.clone()),
+                    "responses": {
+                        "200": { "description": "A contact with this ID was replaced." },
+                        "201": { "description": "The contact was created." },
+                        "507": { "description": "The quota or the disk is full." },
+                    },
+                },
+            },
+            "/contacts/{id}": {
+                "get": {
+                    "summary": "Get a contact",
+                    "parameters": [
+                        id,
+                        fields,
+                        parameter("include", "query", "`raw` adds the vCard text to JSON."),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The contact, as a vCard unless JSON or jCard is accepted.",
+                            "content": {
+                                "text/vcard": { "schema": { "type": "string" } },
+                                "application/json": { "schema": contact },
+                                "application/vcard+json": { "schema": { "type": "array" } },
+                            },
+                        },
+                        "304": { "description": "The contact is unchanged since `If-Modified-Since`." },
+                        "404": { "description": "The contact does not exist." },
+                    },
+                },
+                "put": {
+                    "summary": "Update a contact",
+                    "parameters": [id],
+                    "requestBody": body(contact.clone()),
+                    "responses": {
+                        "200": { "description": "The contact was updated." },
+                        "404": { "description": "The contact does not exist." },
+                        "423": { "description": "The contact is locked." },
+                    },
+                },
+                "delete": {
+                    "summary": "Delete a contact",
+                    "parameters": [id],
+                    "responses": {
+                        "200": { "description": "The contact was deleted." },
+                        "404": { "description": "The contact does not exist." },
+                    },
+                },
+            },
+            "/contacts/import": {
+                "post": {
+                    "summary": "Import contacts from a vCard file",
+                    "parameters": [
+                        parameter("dry_run", "query", "Validates the cards without storing them."),
+                    ],
+                    "requestBody": { "required": true, "content": vcard },
+                    "responses": { "200": { "description": "The imported and failed cards." } },
+                },
+            },
+            "/contacts/validate": {
+                "post": {
+                    "summary": "Check that a vCard parses, without storing it",
+                    "requestBody": { "required": true, "content": vcard },
+                    "responses": {
+                        "200": response("The contact the card makes.", contact.clone()),
+                        "400": { "description": "Why the card is invalid." },
+                    },
+                },
+            },
+            "/contacts/export": {
+                "get": {
+                    "summary": "Export every contact as a single vCard file",
+                    "responses": { "200": { "description": "The contacts.", "content": vcard } },
+                },
+            },
+            "/contacts/batch-get": {
+                "post": {
+                    "summary": "Get several contacts by ID",
+                    "parameters": [fields],
+                    "requestBody": body(json!({
+                        "type": "object",
+                        "required": ["ids"],
+                        "properties": {
+                            "ids": { "type": "array", "items": { "type": "string" } },
+                        },
+                    })),
+                    "responses": {
+                        "200": { "description": "The contacts by ID, `null` for the missing ones." },
+                    },
+                },
+            },
+            "/contacts/batch-delete": {
+                "post": {
+                    "summary": "Delete several contacts by ID",
+                    "requestBody": body(json!({
+                        "type": "object",
+                        "required": ["ids"],
+                        "properties": {
+                            "ids": { "type": "array", "items": { "type": "string" } },
+                        },
+                    })),
+                    "responses": {
+                        "200": { "description": "The outcome for each ID, `deleted`, `not_found`, `locked` or `error`." },
+                    },
+                },
+            },
+            "/contacts/search": {
+                "get": {
+                    "summary": "Search the contacts by name",
+                    "parameters": [
+                        required(parameter("q", "query", "Text searched in the names.")),
+                        parameter("fuzzy", "query", "Tolerates typos, ranking by similarity."),
+                        fields,
+                    ],
+                    "responses": { "200": response("The matching contacts.", contacts.clone()) },
+                },
+            },
+            "/contacts/duplicates": {
+                "get": {
+                    "summary": "Group the contacts sharing an email address or a name",
+                    "responses": {
+                        "200": response("The clusters of duplicates.", json!({
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "contacts": contacts.clone(),
+                                    "emails": { "type": "array", "items": { "type": "string" } },
+                                    "names": { "type": "array", "items": { "type": "string" } },
+                                },
+                            },
+                        })),
+                    },
+                },
+            },
+            "/contacts/by-email/{email}": {
+                "get": {
+                    "summary": "Find the contacts with an email address",
+                    "parameters": [parameter("email", "path", "The email address.")],
+                    "responses": { "200": response("The matching contacts.", contacts.clone()) },
+                },
+            },
+            "/contacts/ids": {
+                "get": {
+                    "summary": "List the ID and ETag of every contact",
+                    "responses": {
+                        "200": response("The IDs and ETags, sorted by ID.", json!({
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "id": { "type": "string" },
+                                    "etag": { "type": "string" },
+                                },
+                            },
+                        })),
+                    },
+                },
+            },
+            "/contacts/stats": {
+                "get": {
+                    "summary": "Count the contacts and their size",
+                    "responses": { "200": { "description": "The statistics." } },
+                },
+            },
+            "/contacts/trash": {
+                "get": {
+                    "summary": "List the deleted contacts",
+                    "responses": { "200": { "description": "The trashed contacts." } },
+                },
+            },
+            "/contacts/trash/{id}": {
+                "delete": {
+                    "summary": "Remove a contact from the trash for good",
+                    "parameters": [id],
+                    "responses": {
+                        "200": { "description": "The contact was purged." },
+                        "404": { "description": "The contact is not in the trash." },
+                    },
+                },
+            },
+            "/contacts/{id}/restore": {
+                "post": {
+                    "summary": "Restore a deleted contact",
+                    "parameters": [id],
+                    "responses": {
+                        "200": { "description": "The contact was restored." },
+                        "409": { "description": "A contact with this ID exists." },
+                    },
+                },
+            },
+            "/contacts/{id}/merge": {
+                "post": {
+                    "summary": "Merge another contact into this one",
+                    "parameters": [id],
+                    "requestBody": body(json!({
+                        "type": "object",
+                        "required": ["other_id"],
+                        "properties": { "other_id": { "type": "string" } },
+                    })),
+                    "responses": { "200": response("The merged contact.", contact.clone()) },
+                },
+            },
+            "/contacts/{id}/history": {
+                "get": {
+                    "summary": "List the previous versions of a contact",
+                    "parameters": [id],
+                    "responses": { "200": { "description": "The revisions, oldest first." } },
+                },
+            },
+            "/contacts/{id}/history/{rev}": {
+                "get": {
+                    "summary": "Get a previous version of a contact",
+                    "parameters": [id, rev],
+                    "responses": {
+                        "200": { "description": "The revision.", "content": vcard },
+                        "404": { "description": "The revision does not exist." },
+                    },
+                },
+            },
+            "/contacts/{id}/revert/{rev}": {
+                "post": {
+                    "summary": "Revert a contact to a previous version",
+                    "parameters": [id, rev],
+                    "responses": {
+                        "200": response("The reverted contact.", contact),
+                        "404": { "description": "The revision does not exist." },
+                    },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "Contact": contact_schema(),
+            },
+            "securitySchemes": {
+                "basic": {
+                    "type": "http",
+                    "scheme": "basic",
+                    "description": "Required by every route but `/health` when \
+                        `DAV_AUTH_USER` is set.",
+                },
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "API token created with `POST /auth/tokens`, \
+                        accepted instead of the password within its scope.",
+                },
+            },
+        },
+    })
+}
+
+/// Schema of [`Contact`], listing its properties in the order of
+/// [`Contact::FIELDS`].
+fn contact_schema() -> Value {
+    let string = json!({ "type": "string" });
+    let strings = json!({ "type": "array", "items": { "type": "string" } });
+
+    let properties: serde_json::Map<String, Value> = Contact::FIELDS
+        .iter()
+        .map(|field| {
+            let schema = match *field {
+                "categories" | "members" | "impp" => strings.clone(),
+                "url" => json!({ "type": "string", "format": "uri" }),
+                "kind" => json!({
+                    "type": "string",
+                    "enum": ["individual", "group", "org", "location"],
+                }),
+                _ => string.clone(),
+            };
+            (field.to_string(), schema)
+        })
+        .collect();
+
+    json!({
+        "type": "object",
+        "required": ["id", "name", "email", "phone"],
+        "properties": properties,
+    })
+}
+
+fn parameter(name: &str, location: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": location,
+        "required": location == "path",
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn required(mut parameter: Value) -> Value {
+    parameter["required"] = json!(true);
+    parameter
+}
+
+fn body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
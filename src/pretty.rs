@@ -0,0 +1,51 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+/// Whether the query string asks for `pretty=true`.
+fn wants_pretty(query: Option<&str>) -> bool {
+    query.is_some_and(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .any(|(key, value)| key == "pretty" && (value == "true" || value == "1"))
+    })
+}
+
+/// Middleware re-indenting JSON responses when the request has `?pretty=true`,
+/// for humans reading them. Keys keep the order they were serialized in.
+pub async fn pretty(request: Request, next: Next) -> Response {
+    let pretty = wants_pretty(request.uri().query());
+    let response = next.run(request).await;
+
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    if !pretty || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("failed to read JSON response: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let indented = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_string_pretty(&value));
+    match indented {
+        Ok(indented) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(indented))
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
@@ -0,0 +1,147 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+use crate::listener::Addr;
+
+/// How a request reached the server, as told by a trusted reverse proxy or
+/// by the request itself.
+#[derive(Clone, Debug)]
+struct Forwarded {
+    /// Path the routes are served under, empty at the root.
+    prefix: String,
+    /// Scheme the client used, `http` or `https`.
+    proto: String,
+    /// Host the client connected to, if known.
+    host: Option<String>,
+}
+
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+static TRUSTED_PROXIES: OnceLock<Vec<IpAddr>> = OnceLock::new();
+
+tokio::task_local! {
+    static FORWARDED: Forwarded;
+}
+
+/// Sets the base path the routes are nested under and the proxies whose
+/// `X-Forwarded-*` headers are believed, once on startup.
+pub fn configure(base_path: String, trusted_proxies: Vec<IpAddr>) {
+    let _ = BASE_PATH.set(base_path);
+    let _ = TRUSTED_PROXIES.set(trusted_proxies);
+}
+
+fn base_path() -> &'static str {
+    BASE_PATH.get().map_or("", String::as_str)
+}
+
+/// Checks and normalizes a base path, `/dav/` becoming `/dav` and `/` the
+/// empty root.
+pub fn parse_base_path(path: &str) -> Result<String, String> {
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return Ok(String::new());
+    }
+
+    if !path.starts_with('/') {
+        return Err("must start with '/'".to_string());
+    }
+    if path.contains(['?', '#', '{', '}', '*']) || path.contains("//") {
+        return Err("must be a plain path".to_string());
+    }
+
+    Ok(path.to_string())
+}
+
+/// Middleware recording how the request reached the server, so the paths and
+/// URLs built while handling it point back through the proxy. The
+/// `X-Forwarded-Prefix`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers
+/// are only used from trusted proxies, and the prefix comes before the base
+/// path.
+pub async fn forwarded(
+    ConnectInfo(addr): ConnectInfo<Addr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let trusted = TRUSTED_PROXIES
+        .get()
+        .is_some_and(|proxies| proxies.contains(&addr.ip()));
+    let forwarded_header = |name: &str| {
+        trusted
+            .then(|| headers.get(name))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let forwarded_prefix = match forwarded_header("X-Forwarded-Prefix") {
+        Some(prefix) => match parse_base_path(&prefix) {
+            Ok(prefix) => prefix,
+            Err(e) => {
+                warn!("ignored X-Forwarded-Prefix '{}': {}", prefix, e);
+                String::new()
+            }
+        },
+        None => String::new(),
+    };
+
+    let forwarded = Forwarded {
+        prefix: format!("{}{}", forwarded_prefix, base_path()),
+        proto: forwarded_header("X-Forwarded-Proto")
+            .filter(|proto| proto == "http" || proto == "https")
+            .unwrap_or_else(|| "http".to_string()),
+        host: forwarded_header("X-Forwarded-Host").or_else(|| host_header(headers)),
+    };
+
+    FORWARDED.scope(forwarded, next.run(request)).await
+}
+
+fn host_header(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Path under which the client reaches the server path `path`.
+pub fn path(path: &str) -> String {
+    let prefix = FORWARDED
+        .try_with(|forwarded| forwarded.prefix.clone())
+        .unwrap_or_else(|_| base_path().to_string());
+    format!("{}{}", prefix, path)
+}
+
+/// Removes the prefix of [`path`] from a path sent by the client, `None` if
+/// it is outside of the server.
+pub fn strip(path: &str) -> Option<String> {
+    let prefix = self::path("");
+    let rest = path.strip_prefix(&prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then(|| rest.to_string())
+}
+
+/// Host the client connected to, from a trusted `X-Forwarded-Host` or the
+/// `Host` header.
+pub fn host() -> Option<String> {
+    FORWARDED
+        .try_with(|forwarded| forwarded.host.clone())
+        .ok()
+        .flatten()
+}
+
+/// Absolute URL of a path built by [`path`] for the client, or the path
+/// alone when the host is unknown.
+pub fn url(path: &str) -> String {
+    match FORWARDED.try_with(|forwarded| (forwarded.proto.clone(), forwarded.host.clone())) {
+        Ok((proto, Some(host))) => format!("{}://{}{}", proto, host, path),
+        _ => path.to_string(),
+    }
+}
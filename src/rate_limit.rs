@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::listener::Addr;
+
+/// Number of tracked clients above which full buckets are dropped.
+const MAX_IDLE_CLIENTS: usize = 1024;
+
+/// Tokens left for a client, refilled continuously.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter keyed on the client IP address. Every client can
+/// send a burst of `limit` requests, then gets `limit` more per minute.
+pub struct RateLimiter {
+    limit: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn refill_rate(&self) -> f64 {
+        f64::from(self.limit) / 60.0
+    }
+
+    /// Takes a token for `ip`, or returns how long to wait for the next one.
+    fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let capacity = f64::from(self.limit);
+        let rate = self.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if buckets.len() >= MAX_IDLE_CLIENTS && !buckets.contains_key(&ip) {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` once a client exceeds the limit.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<Addr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(addr.ip()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            warn!("rate limit exceeded by {}", addr.ip());
+            let retry_after = wait.as_secs_f64().ceil() as u64;
+
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                "too many requests".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestServer;
+
+    #[test]
+    fn buckets_are_per_client() {
+        let limiter = RateLimiter::new(2);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert!(limiter.check(client).is_ok());
+        assert!(limiter.check(client).is_ok());
+        let wait = limiter.check(client).unwrap_err();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+
+        assert!(limiter.check(other).is_ok());
+    }
+
+    #[tokio::test]
+    async fn exceeding_the_limit_is_answered_with_429() {
+        let server = TestServer::with_config(|config| config.rate_limit = Some(2)).await;
+
+        for _ in 0..2 {
+            assert_eq!(server.get("/contacts").await.status, StatusCode::OK);
+        }
+
+        let response = server.get("/contacts").await;
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.header("Retry-After"), Some("30"));
+    }
+}
@@ -0,0 +1,163 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+/// Methods that never change anything.
+const READ_METHODS: &[&str] = &["GET", "HEAD", "OPTIONS", "PROPFIND", "REPORT"];
+
+/// Routes of the contacts answering a `POST` without changing anything.
+const READ_POSTS: &[&str] = &["batch-get", "validate"];
+
+/// Whether a request only reads, including the `POST` of a batch get or of a
+/// vCard validation.
+pub fn is_read(method: &Method, path: &str) -> bool {
+    READ_METHODS.contains(&method.as_str()) || (method == Method::POST && is_read_post(path))
+}
+
+/// Whether a path is one of [`READ_POSTS`] under `/contacts` or under
+/// `/addressbooks/{book}/contacts`.
+fn is_read_post(path: &str) -> bool {
+    let Some((contacts, route)) = path.rsplit_once('/') else {
+        return false;
+    };
+    if !READ_POSTS.contains(&route) {
+        return false;
+    }
+
+    contacts == "/contacts"
+        || contacts
+            .strip_prefix("/addressbooks/")
+            .and_then(|book| book.strip_suffix("/contacts"))
+            .is_some_and(|book| !book.is_empty() && !book.contains('/'))
+}
+
+/// Middleware of the read-only mode, rejecting every request that could change
+/// something with `403 Forbidden`. The `Allow` headers of the responses only
+/// list the methods that still work.
+pub async fn read_only(request: Request, next: Next) -> Response {
+    if !is_read(request.method(), request.uri().path()) {
+        warn!(
+            "rejected {} {} on a read-only server",
+            request.method(),
+            request.uri().path()
+        );
+        return (StatusCode::FORBIDDEN, "server is read-only".to_string()).into_response();
+    }
+
+    let mut response = next.run(request).await;
+
+    let allowed = response
+        .headers()
+        .get(header::ALLOW)
+        .and_then(|allow| allow.to_str().ok())
+        .map(|allow| {
+            allow
+                .split(',')
+                .map(str::trim)
+                .filter(|method| READ_METHODS.contains(method))
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+    if let Some(allowed) = allowed.and_then(|allowed| HeaderValue::from_str(&allowed).ok()) {
+        response.headers_mut().insert(header::ALLOW, allowed);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{contact, TestServer};
+
+    const CARD: &str = "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:alice\r\nFN:Alice\r\nEND:VCARD\r\n";
+
+    #[test]
+    fn only_the_batch_get_and_validate_routes_read_with_post() {
+        for path in [
+            "/contacts/validate",
+            "/contacts/batch-get",
+            "/addressbooks/work/contacts/validate",
+            "/addressbooks/work/contacts/batch-get",
+        ] {
+            assert!(is_read(&Method::POST, path), "{}", path);
+        }
+        for path in [
+            "/contacts",
+            "/contacts/batch-delete",
+            "/contacts/import",
+            "/contacts/alice/validate",
+            "/contacts/alice/batch-get",
+            "/events/validate",
+            "/admin/validate",
+            "/addressbooks/contacts/validate",
+            "/addressbooks//contacts/validate",
+            "/addressbooks/work/other/contacts/batch-get",
+            "/validate",
+        ] {
+            assert!(!is_read(&Method::POST, path), "{}", path);
+        }
+        assert!(is_read(&Method::GET, "/contacts/alice"));
+        assert!(!is_read(&Method::PUT, "/contacts/validate"));
+    }
+
+    #[tokio::test]
+    async fn validates_cards_without_storing_them() {
+        for read_only in [false, true] {
+            let server = TestServer::with_config(|config| config.read_only = read_only).await;
+
+            let valid = server.vcard(Method::POST, "/contacts/validate", CARD).await;
+            assert_eq!(valid.status, StatusCode::OK, "{}", valid.text());
+            assert_eq!(valid.json()["id"], "alice");
+            assert_eq!(valid.json()["name"], "Alice");
+
+            let malformed = server
+                .vcard(
+                    Method::POST,
+                    "/contacts/validate",
+                    "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Alice\r\n",
+                )
+                .await;
+            assert_eq!(malformed.status, StatusCode::BAD_REQUEST);
+
+            assert_eq!(server.get("/contacts").await.json(), json!([]));
+            assert_eq!(
+                server.get("/contacts/alice").await.status,
+                StatusCode::NOT_FOUND
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only_server_rejects_the_other_posts() {
+        let server = TestServer::with_config(|config| config.read_only = true).await;
+
+        for (uri, body) in [
+            ("/contacts", contact("alice", "Alice")),
+            ("/contacts/batch-delete", json!({ "ids": ["alice"] })),
+            ("/contacts/alice/merge", json!({ "ids": ["bob"] })),
+            ("/addressbooks/default/contacts/import", json!({})),
+        ] {
+            let response = server.json(Method::POST, uri, &body).await;
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", uri);
+            assert_eq!(response.text(), "server is read-only");
+        }
+
+        let fetched = server
+            .json(
+                Method::POST,
+                "/contacts/batch-get",
+                &json!({ "ids": ["alice"] }),
+            )
+            .await;
+        assert_eq!(fetched.status, StatusCode::OK, "{}", fetched.text());
+        assert_eq!(fetched.json(), json!({ "alice": null }));
+    }
+}
@@ -0,0 +1,474 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime, Weekday};
+
+/// Number of periods looked at before giving up on a rule whose `BYDAY` never
+/// matches, or that has neither `COUNT` nor `UNTIL` and no window end.
+const MAX_PERIODS: u32 = 50_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A weekday of `BYDAY`, with its position in the month for monthly rules:
+/// `1MO` is the first Monday, `-1FR` the last Friday.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ByDay {
+    ordinal: Option<i32>,
+    weekday: Weekday,
+}
+
+/// An RFC 5545 recurrence rule, limited to `FREQ`, `INTERVAL`, `COUNT`,
+/// `UNTIL`, `BYDAY` and `WKST`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<NaiveDateTime>,
+    by_day: Vec<ByDay>,
+    week_start: Weekday,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        let mut by_day = Vec::new();
+        let mut week_start = Weekday::Mon;
+
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid RRULE part '{}'", part))?;
+
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("unsupported FREQ '{}'", value)),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| format!("invalid INTERVAL '{}'", value))?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid COUNT '{}'", value))?,
+                    )
+                }
+                "UNTIL" => until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    by_day = value
+                        .split(',')
+                        .map(parse_by_day)
+                        .collect::<Result<_, _>>()?
+                }
+                "WKST" => week_start = parse_weekday(value)?,
+                _ => return Err(format!("unsupported RRULE part '{}'", name)),
+            }
+        }
+
+        let frequency = frequency.ok_or("RRULE without FREQ")?;
+        if count.is_some() && until.is_some() {
+            return Err("RRULE with both COUNT and UNTIL".to_string());
+        }
+
+        let ordinals = by_day.iter().any(|day| day.ordinal.is_some());
+        if frequency == Frequency::Yearly && !by_day.is_empty()
+            || frequency != Frequency::Monthly && ordinals
+        {
+            return Err(format!("unsupported BYDAY for {:?} rules", frequency));
+        }
+
+        Ok(Rule {
+            frequency,
+            interval,
+            count,
+            until,
+            by_day,
+            week_start,
+        })
+    }
+}
+
+impl Rule {
+    /// Returns the starts of the occurrences of a series starting at `start`,
+    /// which is always the first one, up to the first occurrence at or after
+    /// `before` when given.
+    pub fn occurrences(
+        &self,
+        start: NaiveDateTime,
+        before: Option<NaiveDateTime>,
+    ) -> Vec<NaiveDateTime> {
+        let mut occurrences = vec![start];
+
+        for period in 0..MAX_PERIODS {
+            let Some(candidates) = self.period(start, period * self.interval) else {
+                break;
+            };
+
+            for candidate in candidates {
+                // The start is always the first occurrence, even when it does
+                // not match the rule.
+                if candidate <= start {
+                    continue;
+                }
+
+                let done = self
+                    .count
+                    .is_some_and(|count| occurrences.len() >= count as usize)
+                    || self.until.is_some_and(|until| candidate > until)
+                    || before.is_some_and(|before| candidate >= before);
+                if done {
+                    return occurrences;
+                }
+
+                occurrences.push(candidate);
+            }
+        }
+
+        occurrences
+    }
+
+    /// Returns the sorted candidates of the period `offset` frequency units
+    /// after the one of `start`, `None` once dates overflow.
+    fn period(&self, start: NaiveDateTime, offset: u32) -> Option<Vec<NaiveDateTime>> {
+        let time = start.time();
+        let date = start.date();
+
+        let mut dates = match self.frequency {
+            Frequency::Daily => {
+                let day = date.checked_add_days(Days::new(offset.into()))?;
+                if self.by_day.is_empty()
+                    || self.by_day.iter().any(|by| by.weekday == day.weekday())
+                {
+                    vec![day]
+                } else {
+                    Vec::new()
+                }
+            }
+            Frequency::Weekly => {
+                let week = date
+                    .checked_sub_days(Days::new(date.weekday().days_since(self.week_start).into()))?
+                    .checked_add_days(Days::new(u64::from(offset) * 7))?;
+
+                let weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+                    vec![date.weekday()]
+                } else {
+                    self.by_day.iter().map(|by| by.weekday).collect()
+                };
+
+                weekdays
+                    .into_iter()
+                    .filter_map(|weekday| {
+                        week.checked_add_days(Days::new(weekday.days_since(self.week_start).into()))
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let month = date.with_day(1)?.checked_add_months(Months::new(offset))?;
+
+                if self.by_day.is_empty() {
+                    month.with_day(date.day()).into_iter().collect()
+                } else {
+                    self.by_day
+                        .iter()
+                        .flat_map(|by| month_days(month, *by))
+                        .collect()
+                }
+            }
+            Frequency::Yearly => date
+                .checked_add_months(Months::new(offset.checked_mul(12)?))
+                // Skips the years without the day, such as February 29.
+                .filter(|day| day.day() == date.day())
+                .into_iter()
+                .collect(),
+        };
+
+        dates.sort();
+        dates.dedup();
+
+        Some(dates.into_iter().map(|day| day.and_time(time)).collect())
+    }
+}
+
+/// Returns the days of `month`, its first day, matching a `BYDAY` entry.
+fn month_days(month: NaiveDate, by: ByDay) -> Vec<NaiveDate> {
+    let days: Vec<NaiveDate> = month
+        .iter_days()
+        .take_while(|day| day.month() == month.month())
+        .filter(|day| day.weekday() == by.weekday)
+        .collect();
+
+    match by.ordinal {
+        None => days,
+        Some(ordinal) if ordinal > 0 => days
+            .get(ordinal as usize - 1)
+            .copied()
+            .into_iter()
+            .collect(),
+        Some(ordinal) => days
+            .len()
+            .checked_sub(ordinal.unsigned_abs() as usize)
+            .and_then(|index| days.get(index).copied())
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn parse_by_day(value: &str) -> Result<ByDay, String> {
+    let split = value.len().saturating_sub(2);
+    let (ordinal, weekday) = value.split_at(split);
+
+    let ordinal = match ordinal {
+        "" => None,
+        ordinal => Some(
+            ordinal
+                .parse::<i32>()
+                .ok()
+                .filter(|ordinal| *ordinal != 0 && ordinal.abs() <= 5)
+                .ok_or_else(|| format!("invalid BYDAY '{}'", value))?,
+        ),
+    };
+
+    Ok(ByDay {
+        ordinal,
+        weekday: parse_weekday(weekday)?,
+    })
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday, String> {
+    match value.to_ascii_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("invalid weekday '{}'", value)),
+    }
+}
+
+/// Parses `UNTIL`, a date, which includes the whole day, or a date with time.
+fn parse_until(value: &str) -> Result<NaiveDateTime, String> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .map(|date| date.and_hms_opt(23, 59, 59).unwrap_or_default())
+        })
+        .map_err(|_| format!("invalid UNTIL '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").unwrap()
+    }
+
+    /// Days of the occurrences of `rule` for a series starting at `start`.
+    fn days(rule: &str, start: &str, before: Option<&str>) -> Vec<String> {
+        let rule: Rule = rule.parse().unwrap();
+        rule.occurrences(time(start), before.map(time))
+            .into_iter()
+            .map(|occurrence| {
+                assert_eq!(occurrence.time(), time(start).time());
+                occurrence.format("%Y%m%d").to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn count_includes_the_start() {
+        assert_eq!(
+            days("FREQ=DAILY;COUNT=3", "20240101T090000", None),
+            ["20240101", "20240102", "20240103"]
+        );
+        assert_eq!(
+            days("FREQ=WEEKLY;COUNT=1", "20240101T090000", None),
+            ["20240101"]
+        );
+    }
+
+    #[test]
+    fn interval_skips_periods() {
+        assert_eq!(
+            days("FREQ=DAILY;INTERVAL=2;COUNT=4", "20240101T090000", None),
+            ["20240101", "20240103", "20240105", "20240107"]
+        );
+        assert_eq!(
+            days("FREQ=MONTHLY;INTERVAL=3;COUNT=3", "20240115T090000", None),
+            ["20240115", "20240415", "20240715"]
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        // A date includes the whole day.
+        assert_eq!(
+            days("FREQ=DAILY;UNTIL=20240103", "20240101T090000", None),
+            ["20240101", "20240102", "20240103"]
+        );
+        assert_eq!(
+            days("FREQ=DAILY;UNTIL=20240103T080000Z", "20240101T090000", None),
+            ["20240101", "20240102"]
+        );
+        assert_eq!(
+            days("FREQ=DAILY;UNTIL=20240103T090000", "20240101T090000", None),
+            ["20240101", "20240102", "20240103"]
+        );
+    }
+
+    #[test]
+    fn stops_at_the_window_end() {
+        assert_eq!(
+            days("FREQ=DAILY", "20240101T090000", Some("20240104T000000")),
+            ["20240101", "20240102", "20240103"]
+        );
+        assert_eq!(
+            days(
+                "FREQ=DAILY;COUNT=2",
+                "20240101T090000",
+                Some("20240110T000000")
+            ),
+            ["20240101", "20240102"]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day() {
+        assert_eq!(
+            days(
+                "FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=5",
+                "20240101T090000",
+                None
+            ),
+            ["20240101", "20240103", "20240105", "20240108", "20240110"]
+        );
+        assert_eq!(
+            days(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH;COUNT=4",
+                "20240102T090000",
+                None
+            ),
+            ["20240102", "20240104", "20240116", "20240118"]
+        );
+        // The start is the first occurrence even when it does not match.
+        assert_eq!(
+            days("FREQ=WEEKLY;BYDAY=MO;COUNT=3", "20240103T090000", None),
+            ["20240103", "20240108", "20240115"]
+        );
+    }
+
+    /// Examples of RFC 5545 section 3.8.5.3 showing the effect of `WKST`.
+    #[test]
+    fn week_start_changes_the_weeks() {
+        assert_eq!(
+            days(
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=MO",
+                "19970805T090000",
+                None
+            ),
+            ["19970805", "19970810", "19970819", "19970824"]
+        );
+        assert_eq!(
+            days(
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=SU",
+                "19970805T090000",
+                None
+            ),
+            ["19970805", "19970817", "19970819", "19970831"]
+        );
+    }
+
+    #[test]
+    fn daily_by_day() {
+        assert_eq!(
+            days("FREQ=DAILY;BYDAY=MO;COUNT=3", "20240101T090000", None),
+            ["20240101", "20240108", "20240115"]
+        );
+    }
+
+    #[test]
+    fn monthly_by_day_with_ordinals() {
+        assert_eq!(
+            days("FREQ=MONTHLY;BYDAY=2TU;COUNT=3", "20240109T090000", None),
+            ["20240109", "20240213", "20240312"]
+        );
+        assert_eq!(
+            days("FREQ=MONTHLY;BYDAY=-1FR;COUNT=3", "20240126T090000", None),
+            ["20240126", "20240223", "20240329"]
+        );
+        // Months without a fifth Monday are skipped.
+        assert_eq!(
+            days("FREQ=MONTHLY;BYDAY=5MO;COUNT=3", "20240129T090000", None),
+            ["20240129", "20240429", "20240729"]
+        );
+        // Without ordinal, every matching day of the month.
+        assert_eq!(
+            days("FREQ=MONTHLY;BYDAY=MO;COUNT=5", "20240101T090000", None),
+            ["20240101", "20240108", "20240115", "20240122", "20240129"]
+        );
+    }
+
+    #[test]
+    fn skips_missing_days() {
+        assert_eq!(
+            days("FREQ=MONTHLY;COUNT=3", "20240131T090000", None),
+            ["20240131", "20240331", "20240531"]
+        );
+        assert_eq!(
+            days("FREQ=YEARLY;COUNT=2", "20240229T090000", None),
+            ["20240229", "20280229"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rule in [
+            "COUNT=3",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;COUNT=3;UNTIL=20240101",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=many",
+            "FREQ=DAILY;UNTIL=tomorrow",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=YEARLY;BYDAY=MO",
+            "FREQ=MONTHLY;BYDAY=6MO",
+            "FREQ=MONTHLY;BYDAY=0MO",
+            "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=WEEKLY;WKST=XX",
+            "FREQ=YEARLY;BYMONTH=1",
+            "FREQ",
+        ] {
+            assert!(rule.parse::<Rule>().is_err(), "{}", rule);
+        }
+
+        assert!("freq=weekly;byday=mo,-1fr"
+            .parse::<Rule>()
+            .is_err_and(|e| e.contains("BYDAY")));
+        assert!("freq=monthly;byday=mo,-1fr;".parse::<Rule>().is_ok());
+    }
+}
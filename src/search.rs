@@ -0,0 +1,97 @@
+use crate::contact::Contact;
+
+/// Lowest similarity for a contact to match a fuzzy search.
+pub const FUZZY_THRESHOLD: f64 = 0.7;
+
+/// Keeps the contacts whose name matches the query, sorted by ID, or by
+/// decreasing similarity for a fuzzy search.
+pub fn filter(mut contacts: Vec<Contact>, query: &str, fuzzy: bool) -> Vec<Contact> {
+    contacts.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if fuzzy {
+        let mut scored: Vec<_> = contacts
+            .into_iter()
+            .map(|contact| (fuzzy_score(&contact.name, query), contact))
+            .filter(|(score, _)| *score >= FUZZY_THRESHOLD)
+            .collect();
+        // The sort is stable, contacts with the same score stay sorted by ID.
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        scored.into_iter().map(|(_, contact)| contact).collect()
+    } else {
+        contacts.retain(|contact| contains(&contact.name, query));
+        contacts
+    }
+}
+
+/// Email address as compared between contacts: trimmed and lowercased.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Name as compared between contacts: lowercased with its words separated by
+/// single spaces.
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Whether the name contains the query, ignoring case.
+pub fn contains(name: &str, query: &str) -> bool {
+    name.to_lowercase().contains(&query.to_lowercase())
+}
+
+/// Similarity between a name and a query, from 0 to 1, ignoring case. The
+/// query is compared to the whole name and to each of its words, so `jhon`
+/// is close to `John Doe`.
+pub fn fuzzy_score(name: &str, query: &str) -> f64 {
+    let name = name.to_lowercase();
+    let query = query.to_lowercase();
+
+    name.split_whitespace()
+        .map(|word| similarity(word, &query))
+        .fold(similarity(&name, &query), f64::max)
+}
+
+/// One minus the edit distance relative to the longest string.
+fn similarity(a: &str, b: &str) -> f64 {
+    let len = a.chars().count().max(b.chars().count());
+    if len == 0 {
+        return 1.0;
+    }
+
+    1.0 - distance(a, b) as f64 / len as f64
+}
+
+/// Levenshtein distance counting the transposition of two adjacent characters
+/// as a single edit (optimal string alignment).
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // Rows of the matrix for the two previous characters of `a` and the current one.
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
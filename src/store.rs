@@ -0,0 +1,594 @@
+mod calendar;
+mod fs;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tokio::fs::{self as tokio_fs, File};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::contact::Contact;
+
+pub use self::calendar::{EventStore, TodoStore};
+pub use self::fs::FsStore;
+pub use self::memory::MemStore;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{Database, SqliteStore};
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// A stored contact could not be parsed back.
+    Parse(String),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "{}", e),
+            StoreError::Parse(e) => write!(f, "invalid stored contact: {}", e),
+            #[cfg(feature = "sqlite")]
+            StoreError::Sqlite(e) => write!(f, "sqlite: {}", e),
+        }
+    }
+}
+
+impl StoreError {
+    /// Whether the storage ran out of space, which unlike other errors may go
+    /// away once some space is freed.
+    pub fn is_storage_full(&self) -> bool {
+        match self {
+            StoreError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+            ),
+            StoreError::Parse(_) => false,
+            #[cfg(feature = "sqlite")]
+            StoreError::Sqlite(e) => e.sqlite_error_code() == Some(rusqlite::ErrorCode::DiskFull),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+/// Contacts read from a store, along with the number of stored entries that
+/// could not be read back.
+pub struct Listing {
+    pub contacts: Vec<Contact>,
+    pub skipped: usize,
+}
+
+/// A contact in the trash.
+pub struct Trashed {
+    pub contact: Contact,
+    pub deleted: SystemTime,
+}
+
+/// A contact file created, modified or deleted behind the back of the store,
+/// by its ID.
+pub enum ExternalChange {
+    Created(String),
+    Modified(String),
+    Deleted(String),
+}
+
+/// What is wrong with a stored entry, found by [`ContactStore::check`].
+pub enum ProblemKind {
+    /// The file is empty.
+    Empty,
+    /// The file cannot be read or parsed, for the given reason.
+    Unparseable(String),
+    /// The `UID` inside the file, given, does not match its name.
+    UidMismatch(String),
+    /// The index has an entry for a file that does not exist.
+    OrphanedIndexEntry,
+    /// The index entry of an unchanged file holds another contact.
+    StaleIndexEntry,
+}
+
+impl ProblemKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProblemKind::Empty => "empty",
+            ProblemKind::Unparseable(_) => "unparseable",
+            ProblemKind::UidMismatch(_) => "uid_mismatch",
+            ProblemKind::OrphanedIndexEntry => "orphaned_index_entry",
+            ProblemKind::StaleIndexEntry => "stale_index_entry",
+        }
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        match self {
+            ProblemKind::Unparseable(reason) => Some(reason),
+            ProblemKind::UidMismatch(uid) => Some(uid),
+            _ => None,
+        }
+    }
+}
+
+/// A problem found in a store, along with what was done about it when
+/// repairing.
+pub struct Problem {
+    /// Name of the file, relative to the directory of the store.
+    pub file: String,
+    pub kind: ProblemKind,
+    pub repair: Option<&'static str>,
+}
+
+/// Storage backend for contacts.
+#[async_trait]
+pub trait ContactStore: Send + Sync {
+    /// Returns the contact with the given ID, if any.
+    async fn get(&self, id: &str) -> Result<Option<Contact>, StoreError>;
+
+    /// Whether a contact with the given ID is stored, without reading it.
+    async fn exists(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// Creates or replaces a contact. Returns `true` if it did not exist before.
+    async fn put(&self, contact: &Contact) -> Result<bool, StoreError>;
+
+    /// Removes a contact. Returns `false` if it did not exist.
+    async fn delete(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// Stores `put` and removes `delete` in one go, all or nothing on backends
+    /// with transactions. Returns whether each stored contact is new.
+    async fn write_batch(&self, put: &[Contact], delete: &[&str]) -> Result<Vec<bool>, StoreError> {
+        let mut created = Vec::with_capacity(put.len());
+        for contact in put {
+            created.push(self.put(contact).await?);
+        }
+
+        for id in delete {
+            self.delete(id).await?;
+        }
+
+        Ok(created)
+    }
+
+    /// Moves a contact to the trash. Returns `false` if it did not exist.
+    async fn trash(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// Returns every contact in the trash, with the time it was deleted.
+    async fn trashed(&self) -> Result<Vec<Trashed>, StoreError>;
+
+    /// Removes a contact from the trash for good. Returns `false` if it is not
+    /// in the trash.
+    async fn purge(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// Removes for good the contacts deleted before `cutoff`. Returns how many
+    /// were removed.
+    async fn purge_before(&self, cutoff: SystemTime) -> Result<usize, StoreError> {
+        let mut purged = 0;
+        for trashed in self.trashed().await? {
+            if trashed.deleted < cutoff && self.purge(&trashed.contact.id).await? {
+                purged += 1;
+            }
+        }
+
+        Ok(purged)
+    }
+
+    /// Moves a contact back from the trash. Returns `false` if it is not in the
+    /// trash.
+    async fn restore(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// Returns every stored contact.
+    async fn list(&self) -> Result<Vec<Contact>, StoreError>;
+
+    /// Returns a contact picked at random, if there is any.
+    async fn random(&self) -> Result<Option<Contact>, StoreError> {
+        let mut contacts = self.list().await?;
+        if contacts.is_empty() {
+            return Ok(None);
+        }
+
+        let index = random_index(contacts.len())?;
+        Ok(Some(contacts.swap_remove(index)))
+    }
+
+    /// Returns every stored contact, skipping the entries that cannot be read
+    /// instead of failing.
+    async fn scan(&self) -> Result<Listing, StoreError> {
+        Ok(Listing {
+            contacts: self.list().await?,
+            skipped: 0,
+        })
+    }
+
+    /// Rebuilds what the store caches about the stored contacts, after they
+    /// were edited behind its back. Returns the number of contacts.
+    async fn reindex(&self) -> Result<usize, StoreError> {
+        Ok(self.list().await?.len())
+    }
+
+    /// Returns the contacts changed by something else than the store since the
+    /// previous call, the first call only taking note of what is stored.
+    /// Backends that cannot be edited from the outside never report any.
+    async fn external_changes(&self) -> Result<Vec<ExternalChange>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Moves the entries stored in an older layout to the current one.
+    /// Returns how many were moved.
+    async fn migrate_layout(&self) -> Result<usize, StoreError> {
+        Ok(0)
+    }
+
+    /// Looks for stored entries that are broken or disagree with what the store
+    /// caches about them. With `repair`, fixes what can be fixed without
+    /// losing data. Backends keeping their own consistency report nothing.
+    async fn check(&self, _repair: bool) -> Result<Vec<Problem>, StoreError> {
+        Ok(Vec::new())
+    }
+
+    /// Returns the current ETag of a contact, if it exists.
+    async fn etag(&self, id: &str) -> Result<Option<String>, StoreError>;
+
+    /// Returns the ID and the ETag of every stored contact. Backends that can
+    /// tell the ETags without reading the contacts do so.
+    async fn etags(&self) -> Result<Vec<(String, String)>, StoreError> {
+        let mut etags = Vec::new();
+        for contact in self.list().await? {
+            if let Some(etag) = self.etag(&contact.id).await? {
+                etags.push((contact.id, etag));
+            }
+        }
+
+        Ok(etags)
+    }
+
+    /// Returns the time a contact was last written, if it exists.
+    async fn modified(&self, id: &str) -> Result<Option<SystemTime>, StoreError>;
+
+    /// Returns the number of bytes used on disk by the stored contacts.
+    async fn usage(&self) -> Result<u64, StoreError>;
+
+    /// Returns the size in bytes of every stored contact, without reading them.
+    async fn sizes(&self) -> Result<Vec<u64>, StoreError>;
+}
+
+/// Writes a file through a synced temporary file in the same directory, renamed
+/// over the target so it never holds partial content. The temporary file ends
+/// with `.tmp` and is skipped when listing. With `sync_dir`, the directory is
+/// synced as well so the rename itself survives a crash.
+async fn write_atomic(path: &Path, content: &[u8], sync_dir: bool) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dir.join(format!(".{}.{}.tmp", name, Uuid::new_v4().simple()));
+
+    let written = async {
+        let mut file = File::create(&temp_path).await?;
+        file.write_all(content).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        tokio_fs::rename(&temp_path, path).await
+    }
+    .await;
+
+    if let Err(e) = written {
+        // The target is left as it was.
+        let _ = tokio_fs::remove_file(&temp_path).await;
+        return Err(e);
+    }
+
+    if sync_dir {
+        File::open(dir).await?.sync_all().await?;
+    }
+
+    Ok(())
+}
+
+/// Picks an index below `len` from the random number generator of the system.
+fn random_index(len: usize) -> Result<usize, StoreError> {
+    let random = getrandom::u64().map_err(io::Error::other)?;
+    Ok((random % len as u64) as usize)
+}
+
+/// Longest ID in bytes. Files are named `<id>.vcf` and written through a
+/// `.<id>.vcf.<uuid>.tmp` temporary file, which must fit the 255 bytes most
+/// filesystems allow for a file name.
+pub const MAX_ID_LEN: usize = 200;
+
+/// Characters Windows does not allow in file names, rejected everywhere so a
+/// data directory can be copied from one system to another.
+const RESERVED_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Checks that an ID can name a file on any filesystem without escaping its
+/// directory, returning why it cannot.
+pub fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("ID is empty".to_string());
+    }
+
+    if id.len() > MAX_ID_LEN {
+        return Err(format!("ID is longer than {} bytes", MAX_ID_LEN));
+    }
+
+    if id == "." || id == ".." {
+        return Err(format!("ID cannot be '{}'", id));
+    }
+
+    if let Some(c) = id.chars().find(|c| c.is_control()) {
+        return Err(format!("ID contains the control character {:?}", c));
+    }
+
+    if let Some(c) = id.chars().find(|c| RESERVED_CHARS.contains(c)) {
+        return Err(format!(
+            "ID contains '{}', which is not allowed in file names",
+            c
+        ));
+    }
+
+    // Windows drops them from file names, two IDs would share a file.
+    if id.ends_with([' ', '.']) {
+        return Err("ID cannot end with a space or a dot".to_string());
+    }
+
+    Ok(())
+}
+
+/// Computes an ETag from the serialized content of a contact.
+pub fn content_etag(content: &str) -> String {
+    format!("\"{:016x}\"", fnv1a(content))
+}
+
+/// Shard of a contact, two hex digits of the hash of its ID. The low byte is
+/// taken as the high bits of FNV-1a hardly vary between short IDs.
+pub fn shard(id: &str) -> String {
+    format!("{:02x}", fnv1a(id) & 0xff)
+}
+
+/// 64-bit FNV-1a hash, stable across builds unlike `DefaultHasher`.
+fn fnv1a(content: &str) -> u64 {
+    content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{self, Cipher};
+
+    fn contact(id: &str, name: &str) -> Contact {
+        Contact {
+            id: id.to_string(),
+            name: name.to_string(),
+            email: format!("{}@example.com", id),
+            phone: "+1 555 0100".to_string(),
+            categories: vec!["friends".to_string()],
+            ..Contact::default()
+        }
+    }
+
+    /// Every backend, keeping its data under `dir`.
+    async fn stores(dir: &Path) -> Vec<(&'static str, Box<dyn ContactStore>)> {
+        let fs_dir = dir.join("fs");
+        tokio_fs::create_dir_all(&fs_dir).await.unwrap();
+        let encrypted_dir = dir.join("fs-encrypted");
+        tokio_fs::create_dir_all(&encrypted_dir).await.unwrap();
+
+        #[allow(unused_mut)]
+        let mut stores: Vec<(&'static str, Box<dyn ContactStore>)> = vec![
+            ("fs", Box::new(FsStore::new(fs_dir, false, None))),
+            (
+                "fs-encrypted",
+                Box::new(FsStore::new(
+                    encrypted_dir,
+                    false,
+                    Some(Cipher::new(&[7; 32])),
+                )),
+            ),
+            ("memory", Box::new(MemStore::new())),
+        ];
+        #[cfg(feature = "sqlite")]
+        stores.push((
+            "sqlite",
+            Box::new(SqliteStore::open(dir.join("contacts.db")).await.unwrap()),
+        ));
+
+        stores
+    }
+
+    #[tokio::test]
+    async fn put_get_and_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        for (backend, store) in stores(dir.path()).await {
+            assert!(
+                store.put(&contact("alice", "Alice")).await.unwrap(),
+                "{}",
+                backend
+            );
+            assert!(store.exists("alice").await.unwrap(), "{}", backend);
+
+            let stored = store.get("alice").await.unwrap().expect(backend);
+            assert_eq!(stored.name, "Alice", "{}", backend);
+            assert_eq!(stored.email, "alice@example.com", "{}", backend);
+            assert_eq!(stored.phone, "+1 555 0100", "{}", backend);
+            assert_eq!(stored.categories, ["friends"], "{}", backend);
+
+            let etag = store.etag("alice").await.unwrap().expect(backend);
+            assert!(
+                !store.put(&contact("alice", "Alice Liddell")).await.unwrap(),
+                "{}",
+                backend
+            );
+            let stored = store.get("alice").await.unwrap().expect(backend);
+            assert_eq!(stored.name, "Alice Liddell", "{}", backend);
+            assert_ne!(
+                store.etag("alice").await.unwrap().expect(backend),
+                etag,
+                "{}",
+                backend
+            );
+            assert!(
+                store.modified("alice").await.unwrap().is_some(),
+                "{}",
+                backend
+            );
+
+            assert!(store.get("bob").await.unwrap().is_none(), "{}", backend);
+            assert!(store.etag("bob").await.unwrap().is_none(), "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        for (backend, store) in stores(dir.path()).await {
+            store.put(&contact("alice", "Alice")).await.unwrap();
+            store.put(&contact("bob", "Bob")).await.unwrap();
+
+            let mut ids: Vec<_> = store
+                .list()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            ids.sort();
+            assert_eq!(ids, ["alice", "bob"], "{}", backend);
+            assert_eq!(store.etags().await.unwrap().len(), 2, "{}", backend);
+            assert_eq!(store.sizes().await.unwrap().len(), 2, "{}", backend);
+
+            assert!(store.delete("alice").await.unwrap(), "{}", backend);
+            assert!(!store.delete("alice").await.unwrap(), "{}", backend);
+            assert!(!store.exists("alice").await.unwrap(), "{}", backend);
+
+            let ids: Vec<_> = store
+                .list()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            assert_eq!(ids, ["bob"], "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn write_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        for (backend, store) in stores(dir.path()).await {
+            store.put(&contact("alice", "Alice")).await.unwrap();
+
+            let created = store
+                .write_batch(
+                    &[contact("alice", "Alice"), contact("bob", "Bob")],
+                    &["carol"],
+                )
+                .await
+                .unwrap();
+            assert_eq!(created, [false, true], "{}", backend);
+
+            store.write_batch(&[], &["alice"]).await.unwrap();
+            let ids: Vec<_> = store
+                .list()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            assert_eq!(ids, ["bob"], "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn trash_restore_and_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        for (backend, store) in stores(dir.path()).await {
+            store.put(&contact("alice", "Alice")).await.unwrap();
+            store.put(&contact("bob", "Bob")).await.unwrap();
+
+            assert!(store.trash("alice").await.unwrap(), "{}", backend);
+            assert!(!store.trash("carol").await.unwrap(), "{}", backend);
+            assert!(store.get("alice").await.unwrap().is_none(), "{}", backend);
+            let trashed = store.trashed().await.unwrap();
+            assert_eq!(trashed.len(), 1, "{}", backend);
+            assert_eq!(trashed[0].contact.name, "Alice", "{}", backend);
+
+            assert!(store.restore("alice").await.unwrap(), "{}", backend);
+            assert!(!store.restore("alice").await.unwrap(), "{}", backend);
+            assert_eq!(
+                store.get("alice").await.unwrap().expect(backend).name,
+                "Alice"
+            );
+            assert!(store.trashed().await.unwrap().is_empty(), "{}", backend);
+
+            store.trash("bob").await.unwrap();
+            assert!(store.purge("bob").await.unwrap(), "{}", backend);
+            assert!(store.trashed().await.unwrap().is_empty(), "{}", backend);
+            assert!(!store.exists("bob").await.unwrap(), "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypts_the_files_of_the_fs_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = Cipher::new(&[7; 32]);
+        let store = FsStore::new(dir.path().to_path_buf(), false, Some(cipher.clone()));
+        store.put(&contact("alice", "Alice")).await.unwrap();
+        store.trash("alice").await.unwrap();
+        store.put(&contact("bob", "Bob")).await.unwrap();
+
+        let file = dir.path().join(shard("bob")).join("bob.vcf");
+        let content = tokio_fs::read(&file).await.unwrap();
+        assert!(encryption::is_encrypted(&content));
+        assert!(!content.windows(3).any(|window| window == b"Bob"));
+        let trashed = tokio_fs::read(dir.path().join("trash").join("alice.vcf"))
+            .await
+            .unwrap();
+        assert!(encryption::is_encrypted(&trashed));
+        assert!(store.check_encryption().await.is_ok());
+
+        // Without the key, or with another one, the store refuses to start.
+        let plain = FsStore::new(dir.path().to_path_buf(), false, None);
+        let e = plain.check_encryption().await.unwrap_err().to_string();
+        assert!(e.contains("DAV_ENCRYPTION_KEY"), "{}", e);
+        assert!(plain.get("bob").await.is_err());
+        let other = FsStore::new(dir.path().to_path_buf(), false, Some(Cipher::new(&[8; 32])));
+        assert!(other.check_encryption().await.is_err());
+
+        // Plain files are still read with a key, until they are written again.
+        let flat = dir.path().join("carol.vcf");
+        tokio_fs::write(&flat, contact("carol", "Carol").to_string())
+            .await
+            .unwrap();
+        assert!(store.check_encryption().await.is_ok());
+        assert_eq!(store.get("carol").await.unwrap().unwrap().name, "Carol");
+        assert_eq!(store.list().await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_unsafe_ids() {
+        assert!(check_id("alice").is_ok());
+        assert!(check_id("").is_err());
+        assert!(check_id("../alice").is_err());
+        assert!(check_id(&"a".repeat(MAX_ID_LEN + 1)).is_err());
+    }
+}
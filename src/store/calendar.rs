@@ -0,0 +1,137 @@
+use std::fmt::Display;
+use std::io;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use tokio::fs;
+use tracing::warn;
+
+use super::{write_atomic, StoreError};
+use crate::event::Event;
+use crate::todo::Todo;
+
+/// A component stored as its own iCalendar object, such as an event.
+pub trait CalendarObject: FromStr<Err = String> + Display {
+    /// Name of the component in logs.
+    const KIND: &'static str;
+
+    fn id(&self) -> &str;
+}
+
+impl CalendarObject for Event {
+    const KIND: &'static str = "event";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl CalendarObject for Todo {
+    const KIND: &'static str = "task";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+pub type EventStore = CalendarStore<Event>;
+
+pub type TodoStore = CalendarStore<Todo>;
+
+/// Stores every object as an `<id>.ics` file in a directory, the layout of a
+/// CalDAV collection.
+pub struct CalendarStore<T> {
+    dir: PathBuf,
+    /// Whether the directory is synced after every write.
+    sync_dir: bool,
+    objects: PhantomData<T>,
+}
+
+impl<T: CalendarObject> CalendarStore<T> {
+    pub async fn open(dir: PathBuf, sync_dir: bool) -> Result<Self, StoreError> {
+        fs::create_dir_all(&dir).await?;
+        Ok(CalendarStore {
+            dir,
+            sync_dir,
+            objects: PhantomData,
+        })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.ics", id))
+    }
+
+    /// Returns the object with the given ID, if any.
+    pub async fn get(&self, id: &str) -> Result<Option<T>, StoreError> {
+        match fs::read_to_string(self.path(id)).await {
+            Ok(content) => content.parse().map(Some).map_err(StoreError::Parse),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Creates or replaces an object. Returns `true` if it did not exist before.
+    pub async fn put(&self, object: &T) -> Result<bool, StoreError> {
+        let file_path = self.path(object.id());
+        let created = !fs::try_exists(&file_path).await?;
+
+        write_atomic(&file_path, object.to_string().as_bytes(), self.sync_dir).await?;
+        Ok(created)
+    }
+
+    /// Removes an object. Returns `false` if it did not exist.
+    pub async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        match fs::remove_file(self.path(id)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the current ETag of an object, if it exists.
+    pub async fn etag(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let metadata = match fs::metadata(self.path(id)).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        Ok(Some(format!(
+            "\"{:x}-{:x}\"",
+            modified.as_nanos(),
+            metadata.len()
+        )))
+    }
+
+    /// Returns every stored object, skipping the files that cannot be parsed.
+    pub async fn list(&self) -> Result<Vec<T>, StoreError> {
+        let mut objects = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "ics") {
+                continue;
+            }
+
+            match fs::read_to_string(&path)
+                .await
+                .map(|content| content.parse::<T>())
+            {
+                Ok(Ok(object)) => objects.push(object),
+                Ok(Err(e)) => warn!("Skipping unreadable {} {}: {}", T::KIND, path.display(), e),
+                Err(e) => warn!("Skipping unreadable {} {}: {}", T::KIND, path.display(), e),
+            }
+        }
+
+        Ok(objects)
+    }
+}
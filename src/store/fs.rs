@@ -0,0 +1,743 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{
+    random_index, shard, write_atomic, ContactStore, ExternalChange, Listing, Problem, ProblemKind,
+    StoreError, Trashed,
+};
+use crate::contact::Contact;
+use crate::encryption::{self, Cipher};
+
+const TRASH_DIR: &str = "trash";
+/// Subdirectory where the repair of the store moves the files it cannot read.
+const CORRUPT_DIR: &str = "corrupt";
+
+/// A contact as parsed from its file, valid as long as the file keeps the same
+/// modification time and size.
+struct Indexed {
+    modified: SystemTime,
+    len: u64,
+    contact: Contact,
+}
+
+/// Modification time and size of a contact file.
+type Stamp = (SystemTime, u64);
+
+/// Stores every contact as a `<shard>/<id>.vcf` file in a directory, the shard
+/// being two hex digits of a hash of the ID so no subdirectory grows too
+/// large, and trashed contacts in its `trash` subdirectory.
+///
+/// Files of the older flat layout, `<id>.vcf` right in the directory, are still
+/// read until [`ContactStore::migrate_layout`] moves them into their shard.
+///
+/// Listing is served from an in-memory index of the parsed files, so only the
+/// files changed since the previous listing, including by hand, are read again.
+pub struct FsStore {
+    dir: PathBuf,
+    /// Whether the directory is synced after every write.
+    sync_dir: bool,
+    /// Key the files are encrypted with, unset for plain files.
+    cipher: Option<Cipher>,
+    index: Mutex<HashMap<PathBuf, Indexed>>,
+    /// Files as last seen when looking for external changes, kept up to date
+    /// with the writes of the store. `None` until the first look.
+    known: Mutex<Option<HashMap<PathBuf, Stamp>>>,
+}
+
+impl FsStore {
+    pub fn new(dir: PathBuf, sync_dir: bool, cipher: Option<Cipher>) -> Self {
+        FsStore {
+            dir,
+            sync_dir,
+            cipher,
+            index: Mutex::new(HashMap::new()),
+            known: Mutex::new(None),
+        }
+    }
+
+    /// Takes note of a write of the store, so it is not reported as an
+    /// external change.
+    fn know(&self, path: &Path, stamp: Option<Stamp>) {
+        let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(known) = known.as_mut() {
+            match stamp {
+                Some(stamp) => known.insert(path.to_path_buf(), stamp),
+                None => known.remove(path),
+            };
+        }
+    }
+
+    /// Paths of every contact file, in the shards and in the flat layout. A
+    /// flat file is left out when its contact also has a sharded one.
+    async fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut flat = Vec::new();
+        let mut shards = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if is_contact_file(&path) {
+                flat.push(path);
+            } else if is_shard(&entry.file_name()) && entry.file_type().await?.is_dir() {
+                shards.push(path);
+            }
+        }
+
+        let mut files = Vec::new();
+        for shard in shards {
+            let mut entries = match fs::read_dir(&shard).await {
+                Ok(entries) => entries,
+                // Removed since the directory was read.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if is_contact_file(&path) {
+                    files.push(path);
+                }
+            }
+        }
+
+        let sharded: HashSet<_> = files
+            .iter()
+            .filter_map(|path| path.file_name().map(|name| name.to_os_string()))
+            .collect();
+        files.extend(
+            flat.into_iter()
+                .filter(|path| path.file_name().is_some_and(|name| !sharded.contains(name))),
+        );
+
+        Ok(files)
+    }
+
+    /// Modification time and size of every contact file.
+    async fn stamps(&self) -> io::Result<HashMap<PathBuf, Stamp>> {
+        let mut stamps = HashMap::new();
+
+        for path in self.files().await? {
+            match fs::metadata(&path).await {
+                Ok(metadata) => {
+                    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                    stamps.insert(path, (modified, metadata.len()));
+                }
+                // Removed since the directory was read.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(stamps)
+    }
+
+    /// Returns the indexed contact of a file, if the file did not change since.
+    fn indexed(&self, path: &Path, modified: SystemTime, len: u64) -> Option<Contact> {
+        let index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        index
+            .get(path)
+            .filter(|indexed| indexed.modified == modified && indexed.len == len)
+            .map(|indexed| indexed.contact.clone())
+    }
+
+    /// Indexes the contact just written to `path`.
+    async fn index(&self, path: PathBuf, contact: &Contact) -> io::Result<()> {
+        let metadata = fs::metadata(&path).await?;
+        let indexed = Indexed {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+            contact: contact.clone(),
+        };
+        self.know(&path, Some((indexed.modified, indexed.len)));
+
+        self.index
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path, indexed);
+        Ok(())
+    }
+
+    fn unindex(&self, path: &Path) {
+        self.index
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(path);
+        self.know(path, None);
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(shard(id)).join(format!("{}.vcf", id))
+    }
+
+    /// Path of a contact in the older flat layout.
+    fn flat_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.vcf", id))
+    }
+
+    /// Path of the file of a contact, in its shard or else in the flat layout.
+    async fn find(&self, id: &str) -> io::Result<Option<PathBuf>> {
+        for path in [self.path(id), self.flat_path(id)] {
+            if fs::try_exists(&path).await? {
+                return Ok(Some(path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Moves an unreadable file out of the way, to the `corrupt` subdirectory.
+    async fn quarantine(&self, path: &Path) -> io::Result<()> {
+        let dir = self.dir.join(CORRUPT_DIR);
+        fs::create_dir_all(&dir).await?;
+
+        // An earlier corrupt file of the same name is kept as well.
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut target = dir.join(&*name);
+        if fs::try_exists(&target).await? {
+            target = dir.join(format!("{}.{}", name, Uuid::new_v4().simple()));
+        }
+
+        self.unindex(path);
+        fs::rename(path, target).await
+    }
+
+    fn trash_path(&self, id: &str) -> PathBuf {
+        self.dir.join(TRASH_DIR).join(format!("{}.vcf", id))
+    }
+
+    /// Reads a contact file, decrypting it if it is encrypted.
+    async fn read(&self, path: &Path) -> io::Result<String> {
+        encryption::read(path, self.cipher.as_ref()).await
+    }
+
+    /// Checks at startup that the encrypted contact files can be read: that a
+    /// key is set, and that it opens them. Plain files left next to encrypted
+    /// ones are reported, for `dav encrypt` to take care of.
+    pub async fn check_encryption(&self) -> Result<(), StoreError> {
+        let mut encrypted = None;
+        let mut plain = 0;
+        for path in self.files().await? {
+            let mut header = [0; 8];
+            let len = match fs::File::open(&path).await {
+                Ok(mut file) => file.read(&mut header).await?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if encryption::is_encrypted(&header[..len]) {
+                encrypted.get_or_insert(path);
+            } else if len > 0 {
+                plain += 1;
+            }
+        }
+
+        match (encrypted, &self.cipher) {
+            (Some(_), None) => {
+                return Err(StoreError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} holds encrypted contact files but no key is set, set DAV_ENCRYPTION_KEY or DAV_ENCRYPTION_KEY_FILE",
+                        self.dir.display()
+                    ),
+                )))
+            }
+            (Some(path), Some(_)) => {
+                self.read(&path).await.map_err(|e| {
+                    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+                })?;
+            }
+            (None, _) => {}
+        }
+        if plain > 0 && self.cipher.is_some() {
+            warn!(
+                "{} plain contact files left in {}, run dav encrypt to encrypt them",
+                plain,
+                self.dir.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContactStore for FsStore {
+    async fn get(&self, id: &str) -> Result<Option<Contact>, StoreError> {
+        for path in [self.path(id), self.flat_path(id)] {
+            match self.read(&path).await {
+                Ok(content) => return content.parse().map(Some).map_err(StoreError::Parse),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn exists(&self, id: &str) -> Result<bool, StoreError> {
+        Ok(self.find(id).await?.is_some())
+    }
+
+    async fn put(&self, contact: &Contact) -> Result<bool, StoreError> {
+        let file_path = self.path(&contact.id);
+        let created = self.find(&contact.id).await?.is_none();
+
+        if let Some(shard) = file_path.parent() {
+            fs::create_dir_all(shard).await?;
+        }
+        let content = encryption::encode(&contact.to_string(), self.cipher.as_ref())?;
+        write_atomic(&file_path, &content, self.sync_dir).await?;
+        self.index(file_path, contact).await?;
+
+        // The contact moved to its shard, the flat file is outdated.
+        let flat_path = self.flat_path(&contact.id);
+        self.unindex(&flat_path);
+        match fs::remove_file(&flat_path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(created)
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let mut deleted = false;
+
+        for path in [self.path(id), self.flat_path(id)] {
+            self.unindex(&path);
+            match fs::remove_file(&path).await {
+                Ok(()) => deleted = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn trash(&self, id: &str) -> Result<bool, StoreError> {
+        fs::create_dir_all(self.dir.join(TRASH_DIR)).await?;
+        let Some(path) = self.find(id).await? else {
+            return Ok(false);
+        };
+        self.unindex(&path);
+
+        match fs::rename(&path, self.trash_path(id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        // The modification time of a trashed file is its deletion time.
+        let path = self.trash_path(id);
+        tokio::task::spawn_blocking(move || {
+            std::fs::File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(SystemTime::now())
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        Ok(true)
+    }
+
+    async fn trashed(&self) -> Result<Vec<Trashed>, StoreError> {
+        let mut entries = match fs::read_dir(self.dir.join(TRASH_DIR)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut trashed = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "vcf") {
+                continue;
+            }
+
+            let deleted = entry.metadata().await?.modified()?;
+            match self
+                .read(&path)
+                .await
+                .map(|content| content.parse::<Contact>())
+            {
+                Ok(Ok(contact)) => trashed.push(Trashed { contact, deleted }),
+                Ok(Err(e)) => warn!("Skipping unreadable contact {}: {}", path.display(), e),
+                Err(e) => warn!("Skipping unreadable contact {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(trashed)
+    }
+
+    async fn purge(&self, id: &str) -> Result<bool, StoreError> {
+        match fs::remove_file(self.trash_path(id)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn restore(&self, id: &str) -> Result<bool, StoreError> {
+        if !fs::try_exists(self.trash_path(id)).await? {
+            return Ok(false);
+        }
+
+        if let Some(shard) = self.path(id).parent() {
+            fs::create_dir_all(shard).await?;
+        }
+        match fs::rename(self.trash_path(id), self.path(id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        let metadata = fs::metadata(self.path(id)).await?;
+        self.know(&self.path(id), Some((metadata.modified()?, metadata.len())));
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Contact>, StoreError> {
+        Ok(self.scan().await?.contacts)
+    }
+
+    /// Picks a file among the contact files, only reading that one.
+    async fn random(&self) -> Result<Option<Contact>, StoreError> {
+        let paths = self.files().await?;
+        if paths.is_empty() {
+            return Ok(None);
+        }
+
+        let path = &paths[random_index(paths.len())?];
+        match self.read(path).await {
+            Ok(content) => content.parse().map(Some).map_err(StoreError::Parse),
+            // Deleted since the directory was read.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn scan(&self) -> Result<Listing, StoreError> {
+        let mut listing = Listing {
+            contacts: Vec::new(),
+            skipped: 0,
+        };
+        let mut seen = HashSet::new();
+
+        for path in self.files().await? {
+            let metadata = match fs::metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    warn!("Skipping unreadable contact {}: {}", path.display(), e);
+                    listing.skipped += 1;
+                    continue;
+                }
+            };
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            seen.insert(path.clone());
+
+            if let Some(contact) = self.indexed(&path, modified, metadata.len()) {
+                listing.contacts.push(contact);
+                continue;
+            }
+
+            let contact = match self.read(&path).await {
+                Ok(content) => content.parse::<Contact>(),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match contact {
+                Ok(contact) => {
+                    let indexed = Indexed {
+                        modified,
+                        len: metadata.len(),
+                        contact: contact.clone(),
+                    };
+                    self.index
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(path, indexed);
+                    listing.contacts.push(contact);
+                }
+                Err(e) => {
+                    warn!("Skipping unreadable contact {}: {}", path.display(), e);
+                    listing.skipped += 1;
+                }
+            }
+        }
+
+        // Files removed by hand leave the index as well.
+        self.index
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|path, _| seen.contains(path));
+
+        Ok(listing)
+    }
+
+    async fn reindex(&self) -> Result<usize, StoreError> {
+        self.index
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+
+        Ok(self.scan().await?.contacts.len())
+    }
+
+    async fn external_changes(&self) -> Result<Vec<ExternalChange>, StoreError> {
+        let current = self.stamps().await?;
+
+        let previous = {
+            let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+            match known.replace(current.clone()) {
+                Some(previous) => previous,
+                None => return Ok(Vec::new()),
+            }
+        };
+
+        let id = |path: &Path| {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        let mut changes = Vec::new();
+        for (path, stamp) in &current {
+            match previous.get(path) {
+                None => changes.push(ExternalChange::Created(id(path))),
+                Some(previous) if previous != stamp => {
+                    changes.push(ExternalChange::Modified(id(path)))
+                }
+                Some(_) => {}
+            }
+        }
+        for path in previous.keys().filter(|path| !current.contains_key(*path)) {
+            changes.push(ExternalChange::Deleted(id(path)));
+        }
+
+        // The changed files are parsed again right away.
+        if !changes.is_empty() {
+            self.scan().await?;
+        }
+
+        Ok(changes)
+    }
+
+    async fn check(&self, repair: bool) -> Result<Vec<Problem>, StoreError> {
+        let mut problems = Vec::new();
+        let stamps = self.stamps().await?;
+        let name = |path: &Path| {
+            path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        for (path, &(modified, len)) in &stamps {
+            let parsed = if len == 0 {
+                Err(ProblemKind::Empty)
+            } else {
+                match self.read(path).await {
+                    Ok(content) => content.parse::<Contact>().map_err(ProblemKind::Unparseable),
+                    Err(e) => Err(ProblemKind::Unparseable(e.to_string())),
+                }
+            };
+
+            let contact = match parsed {
+                Ok(contact) => contact,
+                Err(kind) => {
+                    let repaired = if repair {
+                        self.quarantine(path).await?;
+                        Some("moved to corrupt/")
+                    } else {
+                        None
+                    };
+                    problems.push(Problem {
+                        file: name(path),
+                        kind,
+                        repair: repaired,
+                    });
+                    continue;
+                }
+            };
+
+            if path.file_stem().is_some_and(|stem| *stem != *contact.id) {
+                // Renaming could overwrite another contact, left to a human.
+                problems.push(Problem {
+                    file: name(path),
+                    kind: ProblemKind::UidMismatch(contact.id.clone()),
+                    repair: None,
+                });
+            }
+
+            // Entries of files changed since they were indexed are refreshed on
+            // the next listing, only unchanged files must match their entry.
+            let stale = self
+                .indexed(path, modified, len)
+                .is_some_and(|indexed| indexed.to_string() != contact.to_string());
+            if stale {
+                let repaired = if repair {
+                    let indexed = Indexed {
+                        modified,
+                        len,
+                        contact,
+                    };
+                    self.index
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(path.clone(), indexed);
+                    Some("index entry rebuilt")
+                } else {
+                    None
+                };
+                problems.push(Problem {
+                    file: name(path),
+                    kind: ProblemKind::StaleIndexEntry,
+                    repair: repaired,
+                });
+            }
+        }
+
+        let orphaned: Vec<PathBuf> = self
+            .index
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .filter(|path| !stamps.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in orphaned {
+            let repaired = if repair {
+                self.index
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(&path);
+                Some("index entry removed")
+            } else {
+                None
+            };
+            problems.push(Problem {
+                file: name(&path),
+                kind: ProblemKind::OrphanedIndexEntry,
+                repair: repaired,
+            });
+        }
+
+        problems.sort_by(|a, b| a.file.cmp(&b.file));
+        Ok(problems)
+    }
+
+    async fn etag(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let Some(path) = self.find(id).await? else {
+            return Ok(None);
+        };
+        let metadata = match fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        Ok(Some(stamp_etag((modified, metadata.len()))))
+    }
+
+    /// Derives the ETags from the modification times and sizes of the files,
+    /// without reading them.
+    async fn etags(&self) -> Result<Vec<(String, String)>, StoreError> {
+        Ok(self
+            .stamps()
+            .await?
+            .into_iter()
+            .filter_map(|(path, stamp)| {
+                let id = path.file_stem()?.to_str()?.to_string();
+                Some((id, stamp_etag(stamp)))
+            })
+            .collect())
+    }
+
+    async fn modified(&self, id: &str) -> Result<Option<SystemTime>, StoreError> {
+        let Some(path) = self.find(id).await? else {
+            return Ok(None);
+        };
+        match fs::metadata(path).await {
+            Ok(metadata) => Ok(Some(metadata.modified()?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn usage(&self) -> Result<u64, StoreError> {
+        Ok(self.stamps().await?.values().map(|&(_, len)| len).sum())
+    }
+
+    async fn sizes(&self) -> Result<Vec<u64>, StoreError> {
+        Ok(self.stamps().await?.values().map(|&(_, len)| len).collect())
+    }
+
+    /// Moves the files of the flat layout into their shard, each with a single
+    /// rename. A flat file whose contact also has a sharded one is outdated
+    /// and removed.
+    async fn migrate_layout(&self) -> Result<usize, StoreError> {
+        let mut moved = 0;
+        let mut entries = fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let flat_path = entry.path();
+            if !is_contact_file(&flat_path) {
+                continue;
+            }
+            let Some(id) = flat_path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let path = self.path(id);
+            self.unindex(&flat_path);
+            if fs::try_exists(&path).await? {
+                fs::remove_file(&flat_path).await?;
+                continue;
+            }
+
+            if let Some(shard) = path.parent() {
+                fs::create_dir_all(shard).await?;
+            }
+            fs::rename(&flat_path, &path).await?;
+            let metadata = fs::metadata(&path).await?;
+            self.know(&path, Some((metadata.modified()?, metadata.len())));
+            moved += 1;
+        }
+
+        Ok(moved)
+    }
+}
+
+/// ETag of a contact file, changing whenever the file is written.
+fn stamp_etag((modified, len): Stamp) -> String {
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), len)
+}
+
+/// Whether a path is a contact file, leaving out the temporary files of the
+/// writes in progress which end with `.tmp`.
+fn is_contact_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "vcf")
+}
+
+/// Whether a directory entry is named like a shard, two lowercase hex digits.
+fn is_shard(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| {
+        name.len() == 2
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    })
+}
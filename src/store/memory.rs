@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use super::{content_etag, ContactStore, StoreError, Trashed};
+use crate::contact::Contact;
+
+struct Stored {
+    contact: Contact,
+    /// Time of the last write, or of the deletion in the trash.
+    modified: SystemTime,
+}
+
+/// Keeps contacts in memory only, for throwaway instances. Everything is lost
+/// when the server stops.
+#[derive(Default)]
+pub struct MemStore {
+    contacts: RwLock<HashMap<String, Stored>>,
+    trash: RwLock<HashMap<String, Stored>>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        MemStore::default()
+    }
+}
+
+#[async_trait]
+impl ContactStore for MemStore {
+    async fn get(&self, id: &str) -> Result<Option<Contact>, StoreError> {
+        let contacts = self.contacts.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts.get(id).map(|stored| stored.contact.clone()))
+    }
+
+    async fn exists(&self, id: &str) -> Result<bool, StoreError> {
+        let contacts = self.contacts.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts.contains_key(id))
+    }
+
+    async fn put(&self, contact: &Contact) -> Result<bool, StoreError> {
+        let mut contacts = self
+            .contacts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let previous = contacts.insert(
+            contact.id.clone(),
+            Stored {
+                contact: contact.clone(),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(previous.is_none())
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let mut contacts = self
+            .contacts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts.remove(id).is_some())
+    }
+
+    async fn trash(&self, id: &str) -> Result<bool, StoreError> {
+        let mut contacts = self
+            .contacts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(stored) = contacts.remove(id) else {
+            return Ok(false);
+        };
+
+        self.trash
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                id.to_string(),
+                Stored {
+                    contact: stored.contact,
+                    modified: SystemTime::now(),
+                },
+            );
+        Ok(true)
+    }
+
+    async fn trashed(&self) -> Result<Vec<Trashed>, StoreError> {
+        let trash = self.trash.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(trash
+            .values()
+            .map(|stored| Trashed {
+                contact: stored.contact.clone(),
+                deleted: stored.modified,
+            })
+            .collect())
+    }
+
+    async fn purge(&self, id: &str) -> Result<bool, StoreError> {
+        let mut trash = self.trash.write().unwrap_or_else(PoisonError::into_inner);
+        Ok(trash.remove(id).is_some())
+    }
+
+    async fn restore(&self, id: &str) -> Result<bool, StoreError> {
+        let mut trash = self.trash.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(stored) = trash.remove(id) else {
+            return Ok(false);
+        };
+
+        self.contacts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                id.to_string(),
+                Stored {
+                    contact: stored.contact,
+                    modified: SystemTime::now(),
+                },
+            );
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Contact>, StoreError> {
+        let contacts = self.contacts.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts
+            .values()
+            .map(|stored| stored.contact.clone())
+            .collect())
+    }
+
+    async fn etag(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let contacts = self.contacts.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts
+            .get(id)
+            .map(|stored| content_etag(&stored.contact.to_string())))
+    }
+
+    async fn modified(&self, id: &str) -> Result<Option<SystemTime>, StoreError> {
+        let contacts = self.contacts.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts.get(id).map(|stored| stored.modified))
+    }
+
+    async fn usage(&self) -> Result<u64, StoreError> {
+        Ok(self.sizes().await?.iter().sum())
+    }
+
+    async fn sizes(&self) -> Result<Vec<u64>, StoreError> {
+        let contacts = self.contacts.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(contacts
+            .values()
+            .map(|stored| stored.contact.to_string().len() as u64)
+            .collect())
+    }
+}
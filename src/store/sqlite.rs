@@ -0,0 +1,628 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+
+use super::{content_etag, ContactStore, StoreError, Trashed};
+use crate::contact::Contact;
+
+/// Schema changes, applied in order. The index of the last applied migration
+/// is stored in the `user_version` pragma.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS contacts (
+        id TEXT PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL,
+        email TEXT NOT NULL,
+        phone TEXT NOT NULL,
+        etag TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS contacts_email ON contacts (email);",
+    // Milliseconds since the Unix epoch.
+    "ALTER TABLE contacts ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;",
+    // Comma-separated list.
+    "ALTER TABLE contacts ADD COLUMN categories TEXT NOT NULL DEFAULT '';",
+    // Serialized vCard, filled for existing rows by `backfill_vcards`.
+    "ALTER TABLE contacts ADD COLUMN vcard TEXT NOT NULL DEFAULT '';",
+    // Trashed contacts, `deleted` in milliseconds since the Unix epoch.
+    "CREATE TABLE IF NOT EXISTS trash (
+        id TEXT PRIMARY KEY NOT NULL,
+        vcard TEXT NOT NULL,
+        deleted INTEGER NOT NULL
+    );",
+    // Revision of the vCard, empty without one.
+    "ALTER TABLE contacts ADD COLUMN rev TEXT NOT NULL DEFAULT '';",
+    // Log of the modifications, see `ChangeLog`.
+    "CREATE TABLE IF NOT EXISTS changes (
+        seq INTEGER PRIMARY KEY NOT NULL,
+        id TEXT NOT NULL,
+        op TEXT NOT NULL,
+        timestamp INTEGER NOT NULL
+    );",
+];
+
+/// Connection to the database, shared by the store and the change log. The
+/// connection is only reachable through [`Database::run`], so no query ever
+/// blocks the async runtime.
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Runs a query on a blocking thread.
+    pub async fn run<T, F>(&self, query: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        self.run_then(query, Ok).await
+    }
+
+    /// Runs a query on a blocking thread, then `then` on its result on the
+    /// same thread once the connection is released, so that parsing a large
+    /// result neither blocks the runtime nor the other queries.
+    async fn run_then<R, T, F, M>(&self, query: F, then: M) -> Result<T, StoreError>
+    where
+        R: Send + 'static,
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<R> + Send + 'static,
+        M: FnOnce(R) -> Result<T, StoreError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let rows = {
+                let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+                query(&mut conn)?
+            };
+            then(rows)
+        })
+        .await
+        .map_err(|e| StoreError::Io(e.into()))?
+    }
+}
+
+/// Stores contacts in an SQLite database, indexed by ID and email. The full
+/// vCard is kept alongside the indexed columns so properties without a column
+/// of their own are preserved.
+pub struct SqliteStore {
+    db: Database,
+}
+
+impl SqliteStore {
+    /// Opens the database and applies the pending migrations, off the async
+    /// runtime since both block.
+    pub async fn open(path: PathBuf) -> Result<Self, StoreError> {
+        let conn = tokio::task::spawn_blocking(move || {
+            let mut conn = Connection::open(path)?;
+            migrate(&mut conn)?;
+            backfill_vcards(&mut conn)?;
+
+            Ok::<_, rusqlite::Error>(conn)
+        })
+        .await
+        .map_err(|e| StoreError::Io(e.into()))??;
+
+        Ok(SqliteStore {
+            db: Database {
+                conn: Arc::new(Mutex::new(conn)),
+            },
+        })
+    }
+
+    /// Database of the store, shared with the change log.
+    pub fn database(&self) -> Database {
+        self.db.clone()
+    }
+
+    async fn run<T, F>(&self, query: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        self.db.run(query).await
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Serializes rows written before the `vcard` column existed.
+fn backfill_vcards(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+
+    let contacts = tx
+        .prepare("SELECT id, name, email, phone, categories FROM contacts WHERE vcard = ''")?
+        .query_map([], contact_from_columns)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for contact in contacts {
+        tx.execute(
+            "UPDATE contacts SET vcard = ?1 WHERE id = ?2",
+            params![contact.to_string(), contact.id],
+        )?;
+    }
+
+    tx.commit()
+}
+
+fn parse_optional(vcard: Option<String>) -> Result<Option<Contact>, StoreError> {
+    vcard
+        .map(|vcard| vcard.parse().map_err(StoreError::Parse))
+        .transpose()
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as i64)
+}
+
+fn contact_from_columns(row: &Row<'_>) -> rusqlite::Result<Contact> {
+    Ok(Contact {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        email: row.get("email")?,
+        phone: row.get("phone")?,
+        categories: row
+            .get::<_, String>("categories")?
+            .split(',')
+            .filter(|category| !category.is_empty())
+            .map(str::to_string)
+            .collect(),
+        ..Contact::default()
+    })
+}
+
+/// Creates or replaces a contact within a transaction. Returns `true` if it did
+/// not exist before.
+fn upsert(tx: &Transaction<'_>, contact: &Contact) -> rusqlite::Result<bool> {
+    let vcard = contact.to_string();
+    let etag = content_etag(&vcard);
+
+    let exists = tx
+        .query_row(
+            "SELECT 1 FROM contacts WHERE id = ?1",
+            params![contact.id],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+
+    tx.execute(
+        "INSERT OR REPLACE INTO contacts
+         (id, name, email, phone, categories, vcard, etag, rev, modified)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            contact.id,
+            contact.name,
+            contact.email,
+            contact.phone,
+            contact.categories.join(","),
+            vcard,
+            etag,
+            contact.rev.as_deref().unwrap_or_default(),
+            now_millis()
+        ],
+    )?;
+
+    Ok(!exists)
+}
+
+#[async_trait]
+impl ContactStore for SqliteStore {
+    async fn get(&self, id: &str) -> Result<Option<Contact>, StoreError> {
+        let id = id.to_string();
+
+        self.db
+            .run_then(
+                move |conn| {
+                    conn.query_row(
+                        "SELECT vcard FROM contacts WHERE id = ?1",
+                        params![id],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                },
+                parse_optional,
+            )
+            .await
+    }
+
+    async fn random(&self) -> Result<Option<Contact>, StoreError> {
+        self.db
+            .run_then(
+                |conn| {
+                    conn.query_row(
+                        "SELECT vcard FROM contacts ORDER BY RANDOM() LIMIT 1",
+                        [],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                },
+                parse_optional,
+            )
+            .await
+    }
+
+    async fn exists(&self, id: &str) -> Result<bool, StoreError> {
+        let id = id.to_string();
+
+        self.run(move |conn| {
+            conn.query_row("SELECT 1 FROM contacts WHERE id = ?1", params![id], |_| {
+                Ok(())
+            })
+            .optional()
+            .map(|row| row.is_some())
+        })
+        .await
+    }
+
+    async fn put(&self, contact: &Contact) -> Result<bool, StoreError> {
+        let contact = contact.clone();
+
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let created = upsert(&tx, &contact)?;
+            tx.commit()?;
+
+            Ok(created)
+        })
+        .await
+    }
+
+    async fn write_batch(&self, put: &[Contact], delete: &[&str]) -> Result<Vec<bool>, StoreError> {
+        let put = put.to_vec();
+        let delete: Vec<String> = delete.iter().map(|id| id.to_string()).collect();
+
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+
+            let created = put
+                .iter()
+                .map(|contact| upsert(&tx, contact))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for id in delete {
+                tx.execute("DELETE FROM contacts WHERE id = ?1", params![id])?;
+            }
+            tx.commit()?;
+
+            Ok(created)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let id = id.to_string();
+
+        self.run(move |conn| {
+            let deleted = conn.execute("DELETE FROM contacts WHERE id = ?1", params![id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn trash(&self, id: &str) -> Result<bool, StoreError> {
+        let id = id.to_string();
+
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let trashed = tx.execute(
+                "INSERT OR REPLACE INTO trash (id, vcard, deleted)
+                 SELECT id, vcard, ?2 FROM contacts WHERE id = ?1",
+                params![id, now_millis()],
+            )?;
+            tx.execute("DELETE FROM contacts WHERE id = ?1", params![id])?;
+            tx.commit()?;
+
+            Ok(trashed > 0)
+        })
+        .await
+    }
+
+    async fn trashed(&self) -> Result<Vec<Trashed>, StoreError> {
+        self.db
+            .run_then(
+                |conn| {
+                    let mut stmt = conn.prepare("SELECT vcard, deleted FROM trash")?;
+                    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                    rows.collect::<rusqlite::Result<Vec<(String, i64)>>>()
+                },
+                |rows| {
+                    rows.iter()
+                        .map(|(vcard, deleted)| {
+                            Ok(Trashed {
+                                contact: vcard.parse().map_err(StoreError::Parse)?,
+                                deleted: UNIX_EPOCH + Duration::from_millis(*deleted as u64),
+                            })
+                        })
+                        .collect()
+                },
+            )
+            .await
+    }
+
+    async fn purge(&self, id: &str) -> Result<bool, StoreError> {
+        let id = id.to_string();
+
+        self.run(move |conn| {
+            let purged = conn.execute("DELETE FROM trash WHERE id = ?1", params![id])?;
+            Ok(purged > 0)
+        })
+        .await
+    }
+
+    async fn purge_before(&self, cutoff: SystemTime) -> Result<usize, StoreError> {
+        let cutoff = cutoff
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as i64);
+
+        self.run(move |conn| conn.execute("DELETE FROM trash WHERE deleted < ?1", params![cutoff]))
+            .await
+    }
+
+    async fn restore(&self, id: &str) -> Result<bool, StoreError> {
+        let contact = {
+            let id = id.to_string();
+            self.db
+                .run_then(
+                    move |conn| {
+                        conn.query_row(
+                            "SELECT vcard FROM trash WHERE id = ?1",
+                            params![id],
+                            |row| row.get(0),
+                        )
+                        .optional()
+                    },
+                    parse_optional,
+                )
+                .await?
+        };
+
+        let Some(contact) = contact else {
+            return Ok(false);
+        };
+
+        // The contact leaves the trash only once it is stored again.
+        self.put(&contact).await?;
+
+        let id = id.to_string();
+        self.run(move |conn| conn.execute("DELETE FROM trash WHERE id = ?1", params![id]))
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn list(&self) -> Result<Vec<Contact>, StoreError> {
+        self.db
+            .run_then(
+                |conn| {
+                    let mut stmt = conn.prepare("SELECT vcard FROM contacts")?;
+                    let vcards = stmt.query_map([], |row| row.get(0))?;
+                    vcards.collect::<rusqlite::Result<Vec<String>>>()
+                },
+                |vcards| {
+                    vcards
+                        .iter()
+                        .map(|vcard| vcard.parse().map_err(StoreError::Parse))
+                        .collect()
+                },
+            )
+            .await
+    }
+
+    async fn etag(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let id = id.to_string();
+
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT etag FROM contacts WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn modified(&self, id: &str) -> Result<Option<SystemTime>, StoreError> {
+        let id = id.to_string();
+
+        let modified: Option<i64> = self
+            .run(move |conn| {
+                conn.query_row(
+                    "SELECT modified FROM contacts WHERE id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        Ok(modified.map(|millis| UNIX_EPOCH + Duration::from_millis(millis as u64)))
+    }
+
+    async fn usage(&self) -> Result<u64, StoreError> {
+        self.run(|conn| {
+            conn.query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            )
+        })
+        .await
+    }
+
+    async fn etags(&self) -> Result<Vec<(String, String)>, StoreError> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare("SELECT id, etag FROM contacts")?;
+            let etags = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            etags.collect()
+        })
+        .await
+    }
+
+    async fn sizes(&self) -> Result<Vec<u64>, StoreError> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare("SELECT length(CAST(vcard AS BLOB)) FROM contacts")?;
+            let sizes = stmt.query_map([], |row| row.get(0))?;
+            sizes.collect()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::changes::{ChangeLog, Operation};
+
+    const HELD: Duration = Duration::from_millis(300);
+
+    fn contact(id: &str) -> Contact {
+        Contact {
+            id: id.to_string(),
+            name: format!("Contact {}", id),
+            email: format!("{}@example.com", id),
+            ..Contact::default()
+        }
+    }
+
+    /// Keeps the connection busy from another thread for [`HELD`], as a long
+    /// query would.
+    fn hold_connection(store: &SqliteStore) -> thread::JoinHandle<()> {
+        let conn = store.db.conn.clone();
+        let (locked, wait) = mpsc::channel();
+        let holder = thread::spawn(move || {
+            let _conn = conn.lock().unwrap();
+            locked.send(()).unwrap();
+            thread::sleep(HELD);
+        });
+        wait.recv().unwrap();
+
+        holder
+    }
+
+    /// Checks that `query` waits for the connection without blocking the
+    /// runtime: a timer set meanwhile still fires on time.
+    async fn assert_runtime_free<T: Send + 'static>(
+        store: &SqliteStore,
+        query: impl std::future::Future<Output = T> + Send + 'static,
+    ) -> T {
+        let holder = hold_connection(store);
+        let query = tokio::spawn(query);
+
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(start.elapsed() < HELD / 2, "runtime blocked");
+        assert!(!query.is_finished());
+
+        let result = query.await.unwrap();
+        holder.join().unwrap();
+        result
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn queries_wait_for_the_connection_off_the_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(
+            SqliteStore::open(dir.path().join("contacts.db"))
+                .await
+                .unwrap(),
+        );
+        store.put(&contact("alice")).await.unwrap();
+        let log = Arc::new(
+            ChangeLog::open_sqlite(
+                store.database(),
+                dir.path().join("changes.jsonl"),
+                Duration::from_secs(3600),
+            )
+            .await
+            .unwrap(),
+        );
+
+        let reader = store.clone();
+        let alice = assert_runtime_free(&store, async move { reader.get("alice").await }).await;
+        assert_eq!(alice.unwrap().unwrap().name, "Contact alice");
+
+        let writer = store.clone();
+        let created =
+            assert_runtime_free(&store, async move { writer.put(&contact("bob")).await }).await;
+        assert!(created.unwrap());
+
+        let reader = store.clone();
+        let listed = assert_runtime_free(&store, async move { reader.list().await }).await;
+        assert_eq!(listed.unwrap().len(), 2);
+
+        let recorder = log.clone();
+        let seq = assert_runtime_free(&store, async move {
+            recorder.record("bob", Operation::Create).await
+        })
+        .await;
+        assert_eq!(seq.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn serves_small_reads_during_large_listings() {
+        const CONTACTS: usize = 3000;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(
+            SqliteStore::open(dir.path().join("contacts.db"))
+                .await
+                .unwrap(),
+        );
+        let contacts: Vec<_> = (0..CONTACTS).map(|n| contact(&n.to_string())).collect();
+        store.write_batch(&contacts, &[]).await.unwrap();
+
+        let mut listings = Vec::new();
+        for _ in 0..4 {
+            let store = store.clone();
+            listings.push(tokio::spawn(async move { store.list().await }));
+        }
+
+        let mut reads = Vec::new();
+        for n in 0..200 {
+            let store = store.clone();
+            let id = (n * 13 % CONTACTS).to_string();
+            reads.push(tokio::spawn(async move {
+                let start = Instant::now();
+                let contact = store.get(&id).await.unwrap().unwrap();
+                assert_eq!(contact.id, id);
+                assert_eq!(contact.name, format!("Contact {}", id));
+                start.elapsed()
+            }));
+        }
+
+        let all = async {
+            let mut slowest = Duration::ZERO;
+            for read in reads {
+                slowest = slowest.max(read.await.unwrap());
+            }
+            for listing in listings {
+                assert_eq!(listing.await.unwrap().unwrap().len(), CONTACTS);
+            }
+            slowest
+        };
+        let slowest = tokio::time::timeout(Duration::from_secs(30), all)
+            .await
+            .expect("reads starved by the listings");
+        assert!(slowest < Duration::from_secs(10), "{:?}", slowest);
+    }
+}
@@ -0,0 +1,225 @@
+//! Server built like `run` builds it, answering requests without a listener,
+//! for the tests of the handlers.
+
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware, Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::sync::watch;
+use tower::ServiceExt;
+use tower_http::normalize_path::NormalizePath;
+
+use crate::audit::{self, AuditLog};
+use crate::config::Config;
+use crate::listener::Addr;
+use crate::{proxy, serve, AppState, Served};
+
+/// Address the requests come from.
+pub const CLIENT: &str = "127.0.0.1:40000";
+
+/// Server with its data in a temporary directory, removed when dropped.
+pub struct TestServer {
+    pub dir: TempDir,
+    pub config: Config,
+    pub states: Vec<Arc<AppState>>,
+    app: NormalizePath<Router>,
+    /// Stops the background tasks when dropped.
+    _stop: watch::Sender<bool>,
+}
+
+impl TestServer {
+    /// Server with the default settings.
+    pub async fn new() -> Self {
+        TestServer::with_config(|_| {}).await
+    }
+
+    /// Server with the default settings changed by `configure`.
+    pub async fn with_config(configure: impl FnOnce(&mut Config)) -> Self {
+        let dir = tempfile::tempdir().expect("failed to create the data directory");
+        let mut config = Config::for_tests();
+        config.data_dir = Some(dir.path().to_path_buf());
+        configure(&mut config);
+        TestServer::open(dir, config).await
+    }
+
+    /// Server over an existing data directory, as when restarting.
+    pub async fn open(dir: TempDir, config: Config) -> Self {
+        let backup_dir = config
+            .backup_dir
+            .clone()
+            .unwrap_or_else(|| dir.path().join("backups"));
+        let (stop, stopping) = watch::channel(false);
+
+        let (audit, entries) = AuditLog::new(
+            dir.path(),
+            config.auth.as_ref().map(|auth| auth.user.clone()),
+        );
+        let audit = Arc::new(audit);
+        tokio::spawn(audit::write(
+            dir.path().to_path_buf(),
+            entries,
+            config.audit_max_size,
+            stopping.clone(),
+        ));
+
+        let Served { app, states, .. } = serve(&config, dir.path(), &backup_dir, &audit, &stopping)
+            .await
+            .expect("failed to open the data directory");
+        let app =
+            NormalizePath::trim_trailing_slash(app.layer(middleware::from_fn(proxy::forwarded)));
+
+        TestServer {
+            dir,
+            config,
+            states,
+            app,
+            _stop: stop,
+        }
+    }
+
+    /// State of the default user.
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.states[0]
+    }
+
+    /// Stops the server, returning its data directory and settings to open it
+    /// again.
+    pub fn stop(self) -> (TempDir, Config) {
+        (self.dir, self.config)
+    }
+
+    /// Answers a request.
+    pub async fn send(&self, mut request: Request<Body>) -> TestResponse {
+        let addr: SocketAddr = CLIENT.parse().expect("invalid client address");
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(Addr::Tcp(addr)));
+
+        let response = self
+            .app
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("infallible service failed");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("failed to read the response body");
+
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// Answers a request without headers.
+    pub async fn call(&self, method: Method, uri: &str, body: impl Into<Body>) -> TestResponse {
+        self.send(request(method, uri).body(body.into()).unwrap())
+            .await
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.call(Method::GET, uri, Body::empty()).await
+    }
+
+    /// Gets the JSON form of a resource.
+    pub async fn get_json(&self, uri: &str) -> TestResponse {
+        self.send(
+            request(Method::GET, uri)
+                .header(header::ACCEPT, "application/json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+    }
+
+    pub async fn delete(&self, uri: &str) -> TestResponse {
+        self.call(Method::DELETE, uri, Body::empty()).await
+    }
+
+    /// Sends `value` as JSON.
+    pub async fn json(&self, method: Method, uri: &str, value: &Value) -> TestResponse {
+        self.send(
+            request(method, uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(value.to_string()))
+                .unwrap(),
+        )
+        .await
+    }
+
+    /// Sends `body` as a vCard.
+    pub async fn vcard(&self, method: Method, uri: &str, body: &str) -> TestResponse {
+        self.send(
+            request(method, uri)
+                .header(header::CONTENT_TYPE, "text/vcard")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+    }
+
+    /// Creates a contact, failing the test if it cannot.
+    pub async fn create(&self, contact: &Value) {
+        let response = self.json(Method::POST, "/contacts", contact).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    }
+}
+
+/// Request to the server, the `Host` header set.
+pub fn request(method: Method, uri: &str) -> axum::http::request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::HOST, "localhost")
+}
+
+/// `Authorization` header of HTTP Basic authentication.
+pub fn basic_auth(user: &str, password: &str) -> String {
+    format!("Basic {}", BASE64.encode(format!("{}:{}", user, password)))
+}
+
+/// JSON of a valid contact.
+pub fn contact(id: &str, name: &str) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "email": format!("{}@example.com", id),
+        "phone": "+1 555 0100",
+    })
+}
+
+/// Response as received by a client.
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("invalid JSON {:?}: {}", self.text(), e))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::contact::{property_name, unfold};
+use crate::event::EventTime;
+
+/// Progress of a task, the `STATUS` of a `VTODO`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    #[default]
+    NeedsAction,
+    InProcess,
+    Completed,
+    Cancelled,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::NeedsAction => "NEEDS-ACTION",
+            Status::InProcess => "IN-PROCESS",
+            Status::Completed => "COMPLETED",
+            Status::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+impl FromStr for Status {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status.to_ascii_uppercase().as_str() {
+            "NEEDS-ACTION" => Ok(Status::NeedsAction),
+            "IN-PROCESS" => Ok(Status::InProcess),
+            "COMPLETED" => Ok(Status::Completed),
+            "CANCELLED" => Ok(Status::Cancelled),
+            _ => Err(format!("invalid task STATUS '{}'", status)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Todo {
+    pub id: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<EventTime>,
+    /// From 1, the highest, to 9, the lowest. 0 means undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent_complete: Option<u8>,
+    /// When the task was completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<DateTime<Utc>>,
+    /// Properties of the task without a field of their own, kept verbatim.
+    #[serde(skip)]
+    pub extras: Vec<String>,
+}
+
+impl Todo {
+    /// Returns why the task cannot be stored, if it cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.priority.is_some_and(|priority| priority > 9) {
+            return Err("priority must be between 0 and 9".to_string());
+        }
+
+        if self.percent_complete.is_some_and(|percent| percent > 100) {
+            return Err("percent_complete must be between 0 and 100".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Marks the task as completed at `time`.
+    pub fn complete(&mut self, time: DateTime<Utc>) {
+        self.status = Status::Completed;
+        self.percent_complete = Some(100);
+        self.completed = Some(time);
+    }
+}
+
+impl FromStr for Todo {
+    type Err = String;
+
+    /// Parses the first `VTODO` of an iCalendar object.
+    fn from_str(ics: &str) -> Result<Self, Self::Err> {
+        let mut id = None;
+        let mut summary = None;
+        let mut due = None;
+        let mut priority = None;
+        let mut status = None;
+        let mut percent_complete = None;
+        let mut completed = None;
+        let mut extras = Vec::new();
+        let mut in_todo = false;
+
+        for line in unfold(ics) {
+            let Some((property, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let name = property_name(property);
+            match (name.as_str(), in_todo) {
+                ("BEGIN", false) if value.eq_ignore_ascii_case("VTODO") => in_todo = true,
+                ("END", true) if value.eq_ignore_ascii_case("VTODO") => break,
+                (_, false) => {}
+                ("UID", true) if id.is_none() => id = Some(value.to_string()),
+                ("SUMMARY", true) if summary.is_none() => summary = Some(value.to_string()),
+                ("DUE", true) if due.is_none() => due = Some(EventTime::parse(property, value)?),
+                ("PRIORITY", true) if priority.is_none() => {
+                    priority = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid task PRIORITY '{}'", value))?,
+                    )
+                }
+                ("STATUS", true) if status.is_none() => status = Some(value.parse()?),
+                ("PERCENT-COMPLETE", true) if percent_complete.is_none() => {
+                    percent_complete = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid task PERCENT-COMPLETE '{}'", value))?,
+                    )
+                }
+                ("COMPLETED", true) if completed.is_none() => {
+                    completed = Some(
+                        value
+                            .strip_suffix('Z')
+                            .and_then(|value| {
+                                NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+                            })
+                            .map(|time| time.and_utc())
+                            .ok_or_else(|| format!("invalid task COMPLETED '{}'", value))?,
+                    )
+                }
+                _ => extras.push(line),
+            }
+        }
+
+        Ok(Todo {
+            id: id.ok_or("task UID is empty")?,
+            summary: summary.unwrap_or_default(),
+            due,
+            priority,
+            status: status.unwrap_or_default(),
+            percent_complete,
+            completed,
+            extras,
+        })
+    }
+}
+
+/// Serializes the task as an iCalendar object with CRLF line endings.
+impl fmt::Display for Todo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//dav//EN\r\nBEGIN:VTODO\r\nUID:{}\r\nSUMMARY:{}\r\nSTATUS:{}\r\n",
+            self.id,
+            self.summary,
+            self.status.as_str()
+        )?;
+
+        if let Some(due) = &self.due {
+            due.write(f, "DUE")?;
+        }
+
+        if let Some(priority) = self.priority {
+            write!(f, "PRIORITY:{}\r\n", priority)?;
+        }
+
+        if let Some(percent) = self.percent_complete {
+            write!(f, "PERCENT-COMPLETE:{}\r\n", percent)?;
+        }
+
+        if let Some(completed) = &self.completed {
+            write!(f, "COMPLETED:{}\r\n", completed.format("%Y%m%dT%H%M%SZ"))?;
+        }
+
+        for extra in &self.extras {
+            write!(f, "{}\r\n", extra)?;
+        }
+
+        write!(f, "END:VTODO\r\nEND:VCALENDAR\r\n")
+    }
+}
@@ -0,0 +1,297 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path as AxumPath, State},
+    http::{HeaderMap, Method, StatusCode},
+    routing::{delete, get},
+    Json, Router,
+};
+use blake2::{Blake2b256, Digest};
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::auth::constant_time_eq;
+use crate::books::DEFAULT_BOOK;
+use crate::dav;
+use crate::read_only::is_read;
+
+/// File of the tokens, in the data directory.
+const TOKENS_FILE: &str = "tokens.json";
+
+/// Prefix of the tokens, telling them apart from passwords in a leak scan.
+const TOKEN_PREFIX: &str = "dav_";
+
+/// Random bytes of a token.
+const TOKEN_BYTES: usize = 32;
+
+/// What a token lets a client do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Only the requests that change nothing.
+    Read,
+    /// Every request but the management of the tokens.
+    ReadWrite,
+}
+
+/// API token, as listed. The token itself is only known to the client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Token {
+    pub id: String,
+    pub label: String,
+    pub scope: Scope,
+    /// Address book the token is limited to, every one when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub book: Option<String>,
+    pub created: DateTime<Utc>,
+    /// Time after which the token is refused, never when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// Token as stored, with a hash of its value. Tokens are long and random, so
+/// a plain BLAKE2b is enough to keep a leaked file from being usable.
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: Token,
+    hash: String,
+}
+
+/// Body of `POST /auth/tokens`.
+#[derive(Deserialize)]
+pub struct NewToken {
+    label: String,
+    scope: Scope,
+    #[serde(default)]
+    book: Option<String>,
+    #[serde(default)]
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Answer of `POST /auth/tokens`, the only one carrying the token.
+#[derive(Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    token: Token,
+    /// Value for the `Authorization: Bearer` header.
+    secret: String,
+}
+
+/// API tokens, kept in `tokens.json` in the data directory.
+pub struct Tokens {
+    path: PathBuf,
+    tokens: RwLock<Vec<StoredToken>>,
+}
+
+impl Tokens {
+    pub async fn open(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(TOKENS_FILE);
+        let tokens = match fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid {}: {}", TOKENS_FILE, e),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Tokens {
+            path,
+            tokens: RwLock::new(tokens),
+        })
+    }
+
+    /// Finds the unexpired token of a `Bearer` header.
+    pub async fn find(&self, secret: &str) -> Option<Token> {
+        let hash = hash(secret);
+        let now = Utc::now();
+        self.tokens
+            .read()
+            .await
+            .iter()
+            .find(|stored| constant_time_eq(stored.hash.as_bytes(), hash.as_bytes()))
+            .map(|stored| stored.token.clone())
+            .filter(|token| token.expires.is_none_or(|expires| expires > now))
+    }
+
+    async fn save(&self, tokens: &[StoredToken]) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(tokens).map_err(io::Error::other)?;
+        fs::write(&self.path, content).await
+    }
+}
+
+impl Token {
+    /// Checks that the token allows a request, returning why it does not.
+    pub fn allows(&self, method: &Method, path: &str, headers: &HeaderMap) -> Result<(), String> {
+        if path == "/auth" || path.starts_with("/auth/") {
+            return Err("tokens cannot manage tokens".to_string());
+        }
+
+        if self.scope == Scope::Read && !is_read(method, path) {
+            return Err("token is read-only".to_string());
+        }
+
+        let Some(allowed) = &self.book else {
+            return Ok(());
+        };
+
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let book = match segments.as_slice() {
+            ["contacts", ..] => DEFAULT_BOOK.to_string(),
+            ["addressbooks", book, ..] => percent_decode_str(book).decode_utf8_lossy().into_owned(),
+            // Discovery of the address book, which lists the others without
+            // their content.
+            []
+            | [".well-known", "carddav"]
+            | ["principals", ..]
+            | ["addressbooks"]
+            | ["openapi.json"]
+                if is_read(method, path) =>
+            {
+                return Ok(());
+            }
+            _ => return Err(format!("token is limited to address book '{}'", allowed)),
+        };
+
+        // A COPY or MOVE must not reach another book either.
+        let destination = dav::destination(headers).ok().map(|(book, _)| book);
+        if &book != allowed || destination.is_some_and(|target| &target != allowed) {
+            return Err(format!("token is limited to address book '{}'", allowed));
+        }
+
+        Ok(())
+    }
+}
+
+/// Routes managing the tokens, which only accept the password.
+pub fn routes(tokens: Arc<Tokens>) -> Router {
+    Router::new()
+        .route("/auth/tokens", get(list_tokens).post(create_token))
+        .route("/auth/tokens/{id}", delete(delete_token))
+        .with_state(tokens)
+}
+
+/// Creates a token, the answer carrying its value this once.
+async fn create_token(
+    State(tokens): State<Arc<Tokens>>,
+    Json(new): Json<NewToken>,
+) -> Result<(StatusCode, Json<CreatedToken>), (StatusCode, String)> {
+    let label = new.label.trim().to_string();
+    if label.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "label is empty".to_string()));
+    }
+    if new.book.as_deref().is_some_and(str::is_empty) {
+        return Err((StatusCode::BAD_REQUEST, "book is empty".to_string()));
+    }
+    let now = Utc::now();
+    if new.expires.is_some_and(|expires| expires <= now) {
+        return Err((StatusCode::BAD_REQUEST, "expiry is in the past".to_string()));
+    }
+
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::fill(&mut bytes).map_err(|e| {
+        error!("failed to generate a token: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to generate a token".to_string(),
+        )
+    })?;
+    let secret = format!("{}{}", TOKEN_PREFIX, hex(&bytes));
+
+    let token = Token {
+        id: Uuid::new_v4().to_string(),
+        label,
+        scope: new.scope,
+        book: new.book,
+        created: now,
+        expires: new.expires,
+    };
+
+    let mut stored = tokens.tokens.write().await;
+    let mut updated = stored.clone();
+    updated.push(StoredToken {
+        token: token.clone(),
+        hash: hash(&secret),
+    });
+    tokens.save(&updated).await.map_err(|e| {
+        error!("failed to save tokens: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to save tokens".to_string(),
+        )
+    })?;
+    *stored = updated;
+
+    info!("Token {} ({}) created", token.id, token.label);
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, secret })))
+}
+
+/// Lists the tokens, oldest first, without their values.
+async fn list_tokens(State(tokens): State<Arc<Tokens>>) -> Json<Vec<Token>> {
+    let mut list: Vec<Token> = tokens
+        .tokens
+        .read()
+        .await
+        .iter()
+        .map(|stored| stored.token.clone())
+        .collect();
+    list.sort_by_key(|token| token.created);
+    Json(list)
+}
+
+/// Revokes a token, refused from its next request on.
+async fn delete_token(
+    State(tokens): State<Arc<Tokens>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut stored = tokens.tokens.write().await;
+    let mut updated = stored.clone();
+    updated.retain(|stored| stored.token.id != id);
+    if updated.len() == stored.len() {
+        return Err((StatusCode::NOT_FOUND, "token not found".to_string()));
+    }
+
+    tokens.save(&updated).await.map_err(|e| {
+        error!("failed to save tokens: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to save tokens".to_string(),
+        )
+    })?;
+    *stored = updated;
+
+    info!("Token {} revoked", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn hash(secret: &str) -> String {
+    hex(&Blake2b256::digest(secret.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_secrets_with_blake2b_256() {
+        // As `printf dav_secret | b2sum -l 256`, so stored tokens keep working.
+        assert_eq!(
+            hash("dav_secret"),
+            "a22681374af5a0bc894a55e90f52333e08b94a5ee7ae52c65b2517c94c667dd7"
+        );
+    }
+}
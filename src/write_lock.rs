@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Async locks keyed by contact, held around the sequences of reads and writes
+/// of a contact so two requests cannot interleave them and lose an update.
+/// Unlike WebDAV locks they are never seen by clients, a second request just
+/// waits for the first one.
+#[derive(Default)]
+pub struct WriteLocks {
+    /// Lock of every key held or waited for, dropped once nobody needs it.
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+/// Keeps the keys locked until dropped.
+pub struct WriteGuard<'a> {
+    locks: &'a WriteLocks,
+    held: Vec<(String, OwnedMutexGuard<()>)>,
+}
+
+impl WriteLocks {
+    /// Locks every key, in sorted order so two callers locking overlapping
+    /// keys cannot deadlock.
+    pub async fn lock(&self, mut keys: Vec<String>) -> WriteGuard<'_> {
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut guard = WriteGuard {
+            locks: self,
+            held: Vec::with_capacity(keys.len()),
+        };
+        for key in keys {
+            let lock = self
+                .locks
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(key.clone())
+                .or_default()
+                .clone();
+            guard.held.push((key, lock.lock_owned().await));
+        }
+
+        guard
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        let mut locks = self
+            .locks
+            .locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        for (key, held) in self.held.drain(..) {
+            drop(held);
+            // Only the map still refers to the lock, nobody waits for it.
+            if locks
+                .get(&key)
+                .is_some_and(|lock| Arc::strong_count(lock) == 1)
+            {
+                locks.remove(&key);
+            }
+        }
+    }
+}